@@ -211,13 +211,55 @@ impl GetResponse {
 
 /// Options for `Delete` operation.
 #[derive(Debug, Default, Clone)]
-pub struct DeleteOptions {}
+pub struct DeleteOptions {
+    pub(crate) range_end: Option<Vec<u8>>,
+    pub(crate) prefix: bool,
+    pub(crate) prev_kv: bool,
+}
+
+impl DeleteOptions {
+    /// Creates a `DeleteOptions`.
+    #[inline]
+    pub const fn new() -> Self {
+        DeleteOptions {
+            range_end: None,
+            prefix: false,
+            prev_kv: false,
+        }
+    }
+
+    /// `end_key` is the key following the last key to delete for the range [key, end_key).
+    /// If `end_key` is `"\0"`, all keys greater than or equal to key are deleted.
+    #[inline]
+    pub fn with_range(mut self, end_key: impl Into<Vec<u8>>) -> Self {
+        self.range_end = Some(end_key.into());
+        self.prefix = false;
+        self
+    }
+
+    /// Deletes all keys prefixed with key.
+    #[inline]
+    pub fn with_prefix(mut self) -> Self {
+        self.range_end = None;
+        self.prefix = true;
+        self
+    }
+
+    /// If `prev_kv` is set, etcd gets the previous key-value pairs before deleting it.
+    /// The previous key-value pairs will be returned in the delete response.
+    #[inline]
+    pub const fn with_prev_key(mut self) -> Self {
+        self.prev_kv = true;
+        self
+    }
+}
 
 /// Response for `Delete` operation.
 #[derive(Debug, Clone)]
 pub struct DeleteResponse {
     pub(crate) header: ResponseHeader,
     pub(crate) deleted: i64,
+    pub(crate) prev_kvs: Vec<KeyValue>,
 }
 
 impl DeleteResponse {
@@ -232,6 +274,12 @@ impl DeleteResponse {
     pub const fn deleted(&self) -> i64 {
         self.deleted
     }
+
+    /// If `prev_kv` is set in the request, the previous key-value pairs will be returned.
+    #[inline]
+    pub fn prev_kvs(&self) -> &[KeyValue] {
+        &self.prev_kvs
+    }
 }
 
 /// Options for `Compact` operation.
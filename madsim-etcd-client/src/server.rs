@@ -1,12 +1,25 @@
-use madsim::net::{Endpoint, Payload};
-use std::{io::Result, net::SocketAddr, sync::Arc};
+use madsim::{
+    net::{Endpoint, Payload},
+    time::Instant,
+};
+use spin::Mutex;
+use std::{collections::HashMap, io::Result, net::SocketAddr, sync::Arc, time::Duration};
 
-use super::{election::*, kv::*, service::EtcdService};
+use super::{
+    election::*,
+    kv::*,
+    lease::*,
+    service::{EtcdService, Snapshot},
+    Error,
+};
 
 /// A simulated etcd server.
 #[derive(Default, Clone)]
 pub struct SimServer {
     timeout_rate: f32,
+    /// The server version. All requests are supported if not set.
+    version: Option<Vec<u32>>,
+    faults: Faults,
 }
 
 impl SimServer {
@@ -22,6 +35,33 @@ impl SimServer {
         self
     }
 
+    /// Set the version of the server, like `"3.2"`.
+    ///
+    /// Requests introduced after this version fail with `Unimplemented`:
+    ///
+    /// - lease `time_to_live` since 3.1
+    /// - the election API since 3.2
+    /// - lease `leases` since 3.3
+    ///
+    /// This can be used to simulate a cluster in the middle of an upgrade.
+    /// All requests are supported by default.
+    pub fn version(mut self, version: &str) -> Self {
+        let version = version
+            .split('.')
+            .map(|n| n.parse().expect("invalid version"))
+            .collect();
+        self.version = Some(version);
+        self
+    }
+
+    /// Returns a handle to inject faults into the server.
+    ///
+    /// The handle is shared by the clones of this builder, and can be used to
+    /// change the faults while the server is running.
+    pub fn faults(&self) -> Faults {
+        self.faults.clone()
+    }
+
     /// Consume this [`SimServer`] creating a future that will execute the server.
     pub async fn serve(self, addr: SocketAddr) -> Result<()> {
         let ep = Endpoint::bind(addr).await?;
@@ -29,22 +69,56 @@ impl SimServer {
         loop {
             let (tx, mut rx, _) = ep.accept1().await?;
             let service = service.clone();
+            let version = self.version.clone();
+            let faults = self.faults.clone();
             madsim::task::spawn(async move {
                 let request = *rx.recv().await?.downcast::<Request>().unwrap();
+                match faults.inject(request.operation()).await {
+                    Some(Fault::Drop) => {
+                        tracing::debug!(?request, "connection dropped");
+                        return Ok(());
+                    }
+                    Some(Fault::Unavailable) => {
+                        let status = tonic::Status::unavailable("etcdserver: unavailable");
+                        tx.send(request.error(Error::GRpcStatus(status))).await?;
+                        return Ok(());
+                    }
+                    None => {}
+                }
+                let stale = faults.stale_snapshot(&service);
+                if let Some(version) = &version {
+                    if let Some(response) = request.check_version(version) {
+                        tx.send(response).await?;
+                        return Ok(());
+                    }
+                }
                 let response: Payload = match request {
                     Request::Put {
                         key,
                         value,
                         options,
                     } => Box::new(service.put(key, value, options).await),
-                    Request::Get { key, options } => Box::new(service.get(key, options).await),
+                    Request::Get { key, options } => match stale {
+                        Some(snapshot) => {
+                            Box::new(service.stale_get(&snapshot, key, options).await)
+                        }
+                        None => Box::new(service.get(key, options).await),
+                    },
                     Request::Delete { key, options } => {
                         Box::new(service.delete(key, options).await)
                     }
                     Request::Txn { txn } => Box::new(service.txn(txn).await),
                     Request::LeaseGrant { ttl, id } => Box::new(service.lease_grant(ttl, id).await),
                     Request::LeaseRevoke { id } => Box::new(service.lease_revoke(id).await),
-                    Request::LeaseKeepAlive { id } => Box::new(service.lease_keep_alive(id).await),
+                    Request::LeaseKeepAlive { mut id } => loop {
+                        // keep-alive requests are streamed on the same connection
+                        let rsp = service.lease_keep_alive(id).await;
+                        tx.send(Box::new(rsp)).await?;
+                        match *rx.recv().await?.downcast::<Request>().unwrap() {
+                            Request::LeaseKeepAlive { id: next } => id = next,
+                            _ => panic!("expect keep-alive request"),
+                        }
+                    },
                     Request::LeaseTimeToLive { id, keys } => {
                         Box::new(service.lease_time_to_live(id, keys).await)
                     }
@@ -56,7 +130,13 @@ impl SimServer {
                         Box::new(service.proclaim(leader, value).await)
                     }
                     Request::Leader { name } => Box::new(service.leader(name).await),
-                    Request::Observe { name: _ } => todo!(),
+                    Request::Observe { name } => {
+                        let mut last = None;
+                        loop {
+                            let rsp = service.observe(&name, &mut last).await;
+                            tx.send(Box::new(Ok(rsp) as super::Result<_>)).await?;
+                        }
+                    }
                     Request::Resign { leader } => Box::new(service.resign(leader).await),
                 };
                 tx.send(response).await?;
@@ -66,6 +146,140 @@ impl SimServer {
     }
 }
 
+/// Server-side faults of a [`SimServer`].
+///
+/// Faults are set by tests while the server is running, and apply to the
+/// requests arriving afterwards. They are deterministic: a fault affects
+/// exactly the requests in its window or count.
+#[derive(Debug, Default, Clone)]
+pub struct Faults {
+    state: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Debug, Default)]
+struct FaultState {
+    latency: Duration,
+    operation_latency: HashMap<Operation, Duration>,
+    unavailable_until: Option<Instant>,
+    drop_requests: usize,
+    stale_reads_until: Option<Instant>,
+    /// The store served to reads, taken at the first request of the window.
+    stale_snapshot: Option<Arc<Snapshot>>,
+}
+
+/// A fault injected into a request.
+enum Fault {
+    /// Close the connection without a response.
+    Drop,
+    /// Fail the request with `Unavailable`.
+    Unavailable,
+}
+
+/// A type of request to a [`SimServer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Put,
+    Get,
+    Delete,
+    Txn,
+    LeaseGrant,
+    LeaseRevoke,
+    LeaseKeepAlive,
+    LeaseTimeToLive,
+    LeaseLeases,
+    Campaign,
+    Proclaim,
+    Leader,
+    Observe,
+    Resign,
+}
+
+impl Faults {
+    /// Delays every request by `latency` before it is processed.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().latency = latency;
+    }
+
+    /// Delays the requests of an operation by `latency` before they are
+    /// processed, in addition to the latency of all requests.
+    ///
+    /// This can slow down writes while keeping reads fast. A zero latency
+    /// removes the delay.
+    pub fn set_operation_latency(&self, operation: Operation, latency: Duration) {
+        let mut state = self.state.lock();
+        if latency.is_zero() {
+            state.operation_latency.remove(&operation);
+        } else {
+            state.operation_latency.insert(operation, latency);
+        }
+    }
+
+    /// Fails requests with `Unavailable` for `duration` from now.
+    pub fn set_unavailable(&self, duration: Duration) {
+        self.state.lock().unavailable_until = Some(Instant::now() + duration);
+    }
+
+    /// Closes the connections of the next `n` requests without processing them.
+    pub fn drop_requests(&self, n: usize) {
+        self.state.lock().drop_requests = n;
+    }
+
+    /// Serves reads from a stale copy of the store for `duration` from now.
+    ///
+    /// This is a buggy mode violating linearizability, as a member partitioned
+    /// away from the cluster would if it answered reads without confirming its
+    /// leadership. The copy is taken when the next request arrives, and `get`
+    /// requests don't see the writes made after it until the window ends.
+    /// It can be used to check that a consistency checker catches stale reads.
+    pub fn serve_stale_reads(&self, duration: Duration) {
+        let mut state = self.state.lock();
+        state.stale_reads_until = Some(Instant::now() + duration);
+        state.stale_snapshot = None;
+    }
+
+    /// Removes all faults.
+    pub fn clear(&self) {
+        *self.state.lock() = FaultState::default();
+    }
+
+    /// Returns the stale copy of the store to serve reads from, if any.
+    fn stale_snapshot(&self, service: &EtcdService) -> Option<Arc<Snapshot>> {
+        let mut state = self.state.lock();
+        if !matches!(state.stale_reads_until, Some(t) if Instant::now() < t) {
+            state.stale_snapshot = None;
+            return None;
+        }
+        let snapshot = state
+            .stale_snapshot
+            .get_or_insert_with(|| Arc::new(service.snapshot()));
+        Some(snapshot.clone())
+    }
+
+    /// Returns the fault to fail a request with, after the injected latency.
+    async fn inject(&self, operation: Operation) -> Option<Fault> {
+        let latency = {
+            let mut state = self.state.lock();
+            if matches!(state.unavailable_until, Some(t) if Instant::now() < t) {
+                return Some(Fault::Unavailable);
+            }
+            if state.drop_requests > 0 {
+                state.drop_requests -= 1;
+                return Some(Fault::Drop);
+            }
+            state.latency
+                + state
+                    .operation_latency
+                    .get(&operation)
+                    .copied()
+                    .unwrap_or_default()
+        };
+        if !latency.is_zero() {
+            madsim::time::sleep(latency).await;
+        }
+        None
+    }
+}
+
 /// A request to etcd server.
 #[derive(Debug)]
 pub(crate) enum Request {
@@ -118,10 +332,74 @@ pub(crate) enum Request {
         name: Vec<u8>,
     },
     Observe {
-        #[allow(dead_code)]
         name: Vec<u8>,
     },
     Resign {
         leader: LeaderKey,
     },
 }
+
+impl Request {
+    /// Returns an error response if the request is not supported by a server
+    /// of `version`.
+    fn check_version(&self, version: &[u32]) -> Option<Payload> {
+        let (since, method): (&[u32], _) = match self {
+            Request::LeaseTimeToLive { .. } => (&[3, 1], "LeaseTimeToLive"),
+            Request::LeaseLeases => (&[3, 3], "LeaseLeases"),
+            Request::Campaign { .. } => (&[3, 2], "Campaign"),
+            Request::Proclaim { .. } => (&[3, 2], "Proclaim"),
+            Request::Leader { .. } => (&[3, 2], "Leader"),
+            Request::Observe { .. } => (&[3, 2], "Observe"),
+            Request::Resign { .. } => (&[3, 2], "Resign"),
+            _ => return None,
+        };
+        if version < since {
+            let status = tonic::Status::unimplemented(format!("unknown method {method}"));
+            Some(self.error(Error::GRpcStatus(status)))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the type of the request.
+    fn operation(&self) -> Operation {
+        match self {
+            Request::Put { .. } => Operation::Put,
+            Request::Get { .. } => Operation::Get,
+            Request::Delete { .. } => Operation::Delete,
+            Request::Txn { .. } => Operation::Txn,
+            Request::LeaseGrant { .. } => Operation::LeaseGrant,
+            Request::LeaseRevoke { .. } => Operation::LeaseRevoke,
+            Request::LeaseKeepAlive { .. } => Operation::LeaseKeepAlive,
+            Request::LeaseTimeToLive { .. } => Operation::LeaseTimeToLive,
+            Request::LeaseLeases => Operation::LeaseLeases,
+            Request::Campaign { .. } => Operation::Campaign,
+            Request::Proclaim { .. } => Operation::Proclaim,
+            Request::Leader { .. } => Operation::Leader,
+            Request::Observe { .. } => Operation::Observe,
+            Request::Resign { .. } => Operation::Resign,
+        }
+    }
+
+    /// Returns a response failing the request with `error`.
+    fn error(&self, error: Error) -> Payload {
+        fn err<T: Send + Sync + 'static>(error: Error) -> Payload {
+            Box::new(Err::<T, _>(error))
+        }
+        match self {
+            Request::Put { .. } => err::<PutResponse>(error),
+            Request::Get { .. } => err::<GetResponse>(error),
+            Request::Delete { .. } => err::<DeleteResponse>(error),
+            Request::Txn { .. } => err::<TxnResponse>(error),
+            Request::LeaseGrant { .. } => err::<LeaseGrantResponse>(error),
+            Request::LeaseRevoke { .. } => err::<LeaseRevokeResponse>(error),
+            Request::LeaseKeepAlive { .. } => err::<LeaseKeepAliveResponse>(error),
+            Request::LeaseTimeToLive { .. } => err::<LeaseTimeToLiveResponse>(error),
+            Request::LeaseLeases => err::<LeaseLeasesResponse>(error),
+            Request::Campaign { .. } => err::<CampaignResponse>(error),
+            Request::Proclaim { .. } => err::<ProclaimResponse>(error),
+            Request::Leader { .. } | Request::Observe { .. } => err::<LeaderResponse>(error),
+            Request::Resign { .. } => err::<ResignResponse>(error),
+        }
+    }
+}
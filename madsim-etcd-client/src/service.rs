@@ -1,6 +1,9 @@
 use super::*;
 use futures_util::future::poll_fn;
-use madsim::rand::{random, thread_rng, Rng};
+use madsim::{
+    rand::{random, thread_rng, Rng},
+    time::Instant,
+};
 use spin::Mutex;
 use std::collections::{btree_map::Range, BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
@@ -15,13 +18,16 @@ pub struct EtcdService {
 
 impl EtcdService {
     pub fn new(timeout_rate: f32) -> Self {
-        let inner = Arc::new(Mutex::new(ServiceInner::default()));
+        let inner = Arc::new(Mutex::new(ServiceInner::new()));
         let weak = Arc::downgrade(&inner);
         madsim::task::spawn(async move {
             while let Some(inner) = weak.upgrade() {
-                inner.lock().tick();
+                let next = inner.lock().tick();
                 drop(inner);
-                madsim::time::sleep(Duration::from_secs(1)).await;
+                // wake up at the next expiry, so that leases expire exactly on
+                // time. new leases live for at least 1 second.
+                let max = Instant::now() + Duration::from_secs(1);
+                madsim::time::sleep_until(next.map_or(max, |t| t.min(max))).await;
             }
         });
         EtcdService {
@@ -47,6 +53,34 @@ impl EtcdService {
         Ok(rsp)
     }
 
+    /// Returns a copy of the key-value store at the current revision.
+    pub fn snapshot(&self) -> Snapshot {
+        let inner = self.inner.lock();
+        Snapshot {
+            header: inner.header(),
+            kv: inner.kv.clone(),
+        }
+    }
+
+    /// Gets the keys from a snapshot instead of the current store.
+    pub async fn stale_get(
+        &self,
+        snapshot: &Snapshot,
+        key: Vec<u8>,
+        options: GetOptions,
+    ) -> Result<GetResponse> {
+        self.timeout().await?;
+        tracing::trace!(
+            key = ?String::from_utf8_lossy(&key),
+            revision = snapshot.header.revision,
+            "stale get"
+        );
+        Ok(GetResponse {
+            header: snapshot.header.clone(),
+            kvs: range(&snapshot.kv, key, &options),
+        })
+    }
+
     pub async fn delete(&self, key: Vec<u8>, options: DeleteOptions) -> Result<DeleteResponse> {
         self.timeout().await?;
         let rsp = self.inner.lock().delete(key, options);
@@ -73,8 +107,7 @@ impl EtcdService {
 
     pub async fn lease_keep_alive(&self, id: i64) -> Result<LeaseKeepAliveResponse> {
         self.timeout().await?;
-        let rsp = self.inner.lock().lease_keep_alive(id);
-        Ok(rsp)
+        self.inner.lock().lease_keep_alive(id)
     }
 
     pub async fn lease_time_to_live(&self, id: i64, keys: bool) -> Result<LeaseTimeToLiveResponse> {
@@ -105,6 +138,11 @@ impl EtcdService {
         self.inner.lock().leader(name)
     }
 
+    /// Waits for the leader of the election to differ from `last`.
+    pub async fn observe(&self, name: &[u8], last: &mut Option<KeyValue>) -> LeaderResponse {
+        poll_fn(|cx| self.inner.lock().poll_observe(name, last, cx)).await
+    }
+
     pub async fn resign(&self, leader: LeaderKey) -> Result<ResignResponse> {
         self.timeout().await?;
         self.inner.lock().resign(leader)
@@ -126,11 +164,23 @@ impl EtcdService {
 
 #[derive(Debug, Default)]
 struct ServiceInner {
+    cluster_id: u64,
+    member_id: u64,
+    /// The revision of the key-value store. It's increased by every change of keys.
     revision: i64,
+    /// The raft term. The simulated server is a single member that never loses leadership.
+    raft_term: u64,
     kv: BTreeMap<Key, Value>,
     lease: HashMap<LeaseId, Lease>,
-    /// Waiters for election.
-    waiting_candidates: Vec<(Key, Waker)>,
+    /// Election candidates and observers waiting for changes on a key prefix.
+    waiters: Vec<(Key, Waker)>,
+}
+
+/// A copy of the key-value store.
+#[derive(Debug)]
+pub struct Snapshot {
+    header: ResponseHeader,
+    kv: BTreeMap<Key, Value>,
 }
 
 type LeaseId = i64;
@@ -139,28 +189,63 @@ type Value = Vec<u8>;
 
 #[derive(Debug)]
 struct Lease {
-    ttl: i64,
     granted_ttl: i64,
+    /// The time when the lease expires.
+    deadline: Instant,
     keys: HashSet<Key>,
 }
 
 impl Lease {
     fn new(ttl: i64) -> Self {
         Lease {
-            ttl,
             granted_ttl: ttl,
+            deadline: Self::deadline(ttl),
             keys: HashSet::new(),
         }
     }
+
+    fn deadline(ttl: i64) -> Instant {
+        Instant::now() + Duration::from_secs(ttl.max(0) as u64)
+    }
+
+    /// Returns the remaining TTL in seconds.
+    fn ttl(&self) -> i64 {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        remaining.as_secs() as i64
+    }
 }
 
 impl ServiceInner {
+    fn new() -> Self {
+        ServiceInner {
+            cluster_id: random(),
+            member_id: random(),
+            raft_term: 1,
+            ..Default::default()
+        }
+    }
+
     fn header(&self) -> ResponseHeader {
         ResponseHeader {
+            cluster_id: self.cluster_id,
+            member_id: self.member_id,
             revision: self.revision,
+            raft_term: self.raft_term,
         }
     }
 
+    /// Wakes up the waiters on prefixes of the changed key.
+    fn notify(&mut self, key: &[u8]) {
+        self.waiters.retain(|(prefix, waker)| {
+            if key.starts_with(prefix) {
+                waker.wake_by_ref();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
     fn put(&mut self, key: Vec<u8>, value: Vec<u8>, options: PutOptions) -> PutResponse {
         tracing::trace!(
             key = ?String::from_utf8_lossy(&key),
@@ -175,6 +260,7 @@ impl ServiceInner {
         let prev_value = self.kv.insert(key.clone(), value);
         // TODO: remove key from previous lease
         self.revision += 1;
+        self.notify(&key);
         PutResponse {
             header: self.header(),
             prev_kv: if options.prev_kv {
@@ -194,56 +280,51 @@ impl ServiceInner {
         if options.revision > 0 {
             todo!("get with revision");
         }
-        let kvs = if options.prefix {
-            self.get_prefix_range(key)
-                .map(|(k, v)| KeyValue {
-                    key: k.clone(),
-                    value: v.clone(),
-                })
-                .collect()
-        } else {
-            self.kv
-                .get(&key)
-                .map(|v| KeyValue {
-                    key: key.clone(),
-                    value: v.clone(),
-                })
-                .into_iter()
-                .collect()
-        };
         GetResponse {
             header: self.header(),
-            kvs,
+            kvs: range(&self.kv, key, &options),
         }
     }
 
     fn get_prefix_range(&self, key: Key) -> Range<'_, Key, Value> {
-        let mut end = key.clone();
-        *end.last_mut().unwrap() += 1;
-        self.kv.range(key..end)
+        prefix_range(&self.kv, key)
     }
 
-    fn delete(&mut self, key: Vec<u8>, _options: DeleteOptions) -> DeleteResponse {
+    fn delete(&mut self, key: Vec<u8>, options: DeleteOptions) -> DeleteResponse {
         tracing::trace!(
             key = ?String::from_utf8_lossy(&key),
+            ?options,
             "delete"
         );
-        let deleted = self.kv.remove(&key).map_or(0, |_| 1);
+        let keys: Vec<Key> = if options.prefix {
+            self.get_prefix_range(key).map(|(k, _)| k.clone()).collect()
+        } else if let Some(end) = options.range_end {
+            if end == b"\0" {
+                // all keys from `key`, as in etcd
+                self.kv.range(key..).map(|(k, _)| k.clone()).collect()
+            } else if end > key {
+                self.kv.range(key..end).map(|(k, _)| k.clone()).collect()
+            } else {
+                vec![]
+            }
+        } else {
+            vec![key]
+        };
+        let prev_kvs: Vec<KeyValue> = keys
+            .into_iter()
+            .filter_map(|key| self.kv.remove(&key).map(|value| KeyValue { key, value }))
+            .collect();
+        let deleted = prev_kvs.len() as i64;
         if deleted > 0 {
             self.revision += 1;
-            // TODO: notify one
-            self.waiting_candidates.retain(|(prefix, waker)| {
-                if key.starts_with(prefix) {
-                    waker.wake_by_ref();
-                    false
-                } else {
-                    true
-                }
-            });
+            for kv in &prev_kvs {
+                self.notify(&kv.key);
+            }
         }
         DeleteResponse {
             header: self.header(),
             deleted,
+            prev_kvs: if options.prev_kv { prev_kvs } else { vec![] },
         }
     }
 
@@ -274,7 +355,19 @@ impl ServiceInner {
             };
             op_responses.push(response);
         }
-        self.revision = revision + 1;
+        // a transaction increases the revision by at most one
+        if self.revision > revision {
+            self.revision = revision + 1;
+        }
+        let header = self.header();
+        for response in &mut op_responses {
+            match response {
+                TxnOpResponse::Get(r) => r.header = header.clone(),
+                TxnOpResponse::Put(r) => r.header = header.clone(),
+                TxnOpResponse::Delete(r) => r.header = header.clone(),
+                TxnOpResponse::Txn(r) => r.header = header.clone(),
+            }
+        }
 
         TxnResponse {
             header: self.header(),
@@ -292,8 +385,7 @@ impl ServiceInner {
             }
         }
         let old = self.lease.insert(id, Lease::new(ttl));
-        assert!(old.is_some(), "lease ID already exists");
-        self.revision += 1;
+        assert!(old.is_none(), "lease ID already exists");
         LeaseGrantResponse {
             header: self.header(),
             id,
@@ -304,35 +396,56 @@ impl ServiceInner {
     fn lease_revoke(&mut self, id: i64) -> LeaseRevokeResponse {
         tracing::trace!(id, "lease_revoke");
         let lease = self.lease.remove(&id).expect("no lease");
-        for key in lease.keys {
-            self.kv.remove(&key);
+        let removed: Vec<Key> = lease
+            .keys
+            .into_iter()
+            .filter(|key| self.kv.remove(key).is_some())
+            .collect();
+        if !removed.is_empty() {
+            self.revision += 1;
+        }
+        for key in removed {
+            self.notify(&key);
         }
-        self.revision += 1;
         LeaseRevokeResponse {
             header: self.header(),
         }
     }
 
-    fn lease_keep_alive(&mut self, id: i64) -> LeaseKeepAliveResponse {
+    fn lease_keep_alive(&mut self, id: i64) -> Result<LeaseKeepAliveResponse> {
         tracing::trace!(id, "lease_keep_alive");
-        let lease = self.lease.get_mut(&id).expect("no lease");
-        let ttl = 30; // TODO: choose default TTL
-        lease.ttl = ttl;
-        lease.granted_ttl = ttl;
-        self.revision += 1;
-        LeaseKeepAliveResponse {
+        let lease = self.lease.get_mut(&id).ok_or_else(|| {
+            Error::GRpcStatus(tonic::Status::not_found(
+                "etcdserver: requested lease not found",
+            ))
+        })?;
+        lease.deadline = Lease::deadline(lease.granted_ttl);
+        let ttl = lease.granted_ttl;
+        Ok(LeaseKeepAliveResponse {
             header: self.header(),
             id,
             ttl,
-        }
+        })
     }
 
     fn lease_time_to_live(&self, id: i64, keys: bool) -> LeaseTimeToLiveResponse {
-        let lease = self.lease.get(&id).expect("no lease");
+        let lease = match self.lease.get(&id) {
+            Some(lease) => lease,
+            // the lease does not exist or has expired
+            None => {
+                return LeaseTimeToLiveResponse {
+                    header: self.header(),
+                    id,
+                    ttl: -1,
+                    granted_ttl: 0,
+                    keys: vec![],
+                }
+            }
+        };
         LeaseTimeToLiveResponse {
             header: self.header(),
             id,
-            ttl: lease.ttl,
+            ttl: lease.ttl(),
             granted_ttl: lease.granted_ttl,
             keys: if keys {
                 lease.keys.iter().cloned().collect()
@@ -349,24 +462,30 @@ impl ServiceInner {
         }
     }
 
-    /// Clears expired lease. This should be called every seconds.
-    fn tick(&mut self) {
-        let origin_len = self.lease.len();
+    /// Clears expired leases, and returns the time when the next lease expires.
+    fn tick(&mut self) -> Option<Instant> {
+        let mut expired_keys = vec![];
+        let now = Instant::now();
         self.lease.retain(|id, lease| {
-            lease.ttl -= 1;
-            if lease.ttl <= 0 {
+            if lease.deadline <= now {
                 tracing::trace!(id, "lease expired");
-                for key in &lease.keys {
-                    self.kv.remove(key);
+                for key in lease.keys.drain() {
+                    if self.kv.remove(&key).is_some() {
+                        expired_keys.push(key);
+                    }
                 }
                 false
             } else {
                 true
             }
         });
-        if self.lease.len() != origin_len {
+        if !expired_keys.is_empty() {
             self.revision += 1;
         }
+        for key in expired_keys {
+            self.notify(&key);
+        }
+        self.lease.values().map(|lease| lease.deadline).min()
     }
 
     fn poll_campaign(
@@ -378,8 +497,7 @@ impl ServiceInner {
     ) -> Poll<CampaignResponse> {
         if self.get_prefix_range(name.to_vec()).next().is_some() {
             // the election name is occupied
-            self.waiting_candidates
-                .push((name.to_vec(), cx.waker().clone()));
+            self.waiters.push((name.to_vec(), cx.waker().clone()));
             return Poll::Pending;
         }
 
@@ -389,7 +507,12 @@ impl ServiceInner {
         key.extend_from_slice(format!("{lease:016x}").as_bytes());
 
         self.kv.insert(key.clone(), value.to_vec());
+        if let Some(lease) = self.lease.get_mut(&lease) {
+            // leadership is lost when the lease expires
+            lease.keys.insert(key.clone());
+        }
         self.revision += 1;
+        self.notify(&key);
 
         tracing::trace!(
             name = ?String::from_utf8_lossy(name),
@@ -414,9 +537,14 @@ impl ServiceInner {
             value = ?String::from_utf8_lossy(&value),
             "proclaim",
         );
-        (self.kv.insert(leader.key, value))
+        // the leader key is gone if the leadership has been lost
+        let current = self
+            .kv
+            .get_mut(&leader.key)
             .ok_or_else(|| Error::ElectError("session expired".into()))?;
+        *current = value;
         self.revision += 1;
+        self.notify(&leader.key);
         Ok(ProclaimResponse {
             header: self.header(),
         })
@@ -432,12 +560,77 @@ impl ServiceInner {
         })
     }
 
+    fn poll_observe(
+        &mut self,
+        name: &[u8],
+        last: &mut Option<KeyValue>,
+        cx: &mut Context<'_>,
+    ) -> Poll<LeaderResponse> {
+        let leader = self
+            .get_prefix_range(name.to_vec())
+            .next()
+            .map(|(k, v)| KeyValue {
+                key: k.clone(),
+                value: v.clone(),
+            });
+        let changed = match (&leader, &*last) {
+            (Some(new), Some(old)) => new.key != old.key || new.value != old.value,
+            (new, _) => new.is_some(),
+        };
+        if !changed {
+            self.waiters.push((name.to_vec(), cx.waker().clone()));
+            return Poll::Pending;
+        }
+        *last = leader.clone();
+        Poll::Ready(LeaderResponse {
+            header: self.header(),
+            kv: leader,
+        })
+    }
+
     fn resign(&mut self, leader: LeaderKey) -> Result<ResignResponse> {
         tracing::trace!(name = ?String::from_utf8_lossy(&leader.name), "resign");
         (self.kv.remove(&leader.key)).ok_or_else(|| Error::ElectError("session expired".into()))?;
+        if let Some(lease) = self.lease.get_mut(&leader.lease) {
+            lease.keys.remove(&leader.key);
+        }
         self.revision += 1;
+        self.notify(&leader.key);
         Ok(ResignResponse {
             header: self.header(),
         })
     }
 }
+
+/// Returns the key-values of a get request.
+fn range(kv: &BTreeMap<Key, Value>, key: Key, options: &GetOptions) -> Vec<KeyValue> {
+    let to_kv = |(k, v): (&Key, &Value)| KeyValue {
+        key: k.clone(),
+        value: v.clone(),
+    };
+    if options.prefix {
+        prefix_range(kv, key).map(to_kv).collect()
+    } else {
+        kv.get_key_value(&key).map(to_kv).into_iter().collect()
+    }
+}
+
+fn prefix_range(kv: &BTreeMap<Key, Value>, key: Key) -> Range<'_, Key, Value> {
+    match prefix_end(&key) {
+        Some(end) => kv.range(key..end),
+        None => kv.range(key..),
+    }
+}
+
+/// Returns the smallest key that is larger than all keys with the given prefix,
+/// or `None` if there is no such key.
+fn prefix_end(prefix: &[u8]) -> Option<Key> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
@@ -12,7 +12,7 @@ pub use self::election::*;
 pub use self::error::{Error, Result};
 pub use self::kv::*;
 pub use self::lease::*;
-pub use self::server::SimServer;
+pub use self::server::{Faults, Operation, SimServer};
 
 /// Asynchronous `etcd` client using v3 API.
 #[derive(Clone)]
@@ -93,13 +93,34 @@ impl ConnectOptions {
 /// General `etcd` response header.
 #[derive(Debug, Clone)]
 pub struct ResponseHeader {
+    pub(crate) cluster_id: u64,
+    pub(crate) member_id: u64,
     pub(crate) revision: i64,
+    pub(crate) raft_term: u64,
 }
 
 impl ResponseHeader {
+    /// The ID of the cluster which sent the response.
+    #[inline]
+    pub const fn cluster_id(&self) -> u64 {
+        self.cluster_id
+    }
+
+    /// The ID of the member which sent the response.
+    #[inline]
+    pub const fn member_id(&self) -> u64 {
+        self.member_id
+    }
+
     /// The key-value store revision when the request was applied.
     #[inline]
     pub const fn revision(&self) -> i64 {
         self.revision
     }
+
+    /// The raft term when the request was applied.
+    #[inline]
+    pub const fn raft_term(&self) -> u64 {
+        self.raft_term
+    }
 }
@@ -0,0 +1,247 @@
+#![cfg(madsim)]
+
+use madsim::{
+    net::NetSim,
+    runtime::{Handle, NodeHandle},
+    time::{sleep, Instant},
+};
+use madsim_etcd_client::{Client, LeaderKey, ProclaimOptions, ResignOptions, SimServer};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const SERVER: &str = "10.0.0.1:2379";
+const ELECTION: &str = "leader";
+const LEASE_TTL: i64 = 3;
+
+/// Leaders in the order they were elected.
+type Leaders = Arc<Mutex<Vec<(String, LeaderKey)>>>;
+
+/// Starts the etcd server.
+async fn start_server(handle: &Handle) {
+    let addr = SERVER.parse::<SocketAddr>().unwrap();
+    handle
+        .create_node()
+        .name("etcd")
+        .ip(addr.ip())
+        .build()
+        .spawn(async move {
+            SimServer::builder().serve(addr).await.unwrap();
+        });
+    sleep(Duration::from_secs(1)).await;
+}
+
+/// Starts a node campaigning for [`ELECTION`] with `candidate-{id}` as the value.
+///
+/// The candidate keeps its lease alive until the node is killed or the lease
+/// is lost, and records itself in `leaders` once elected.
+fn start_candidate(handle: &Handle, id: u8, leaders: Leaders) -> NodeHandle {
+    let name = candidate_name(id);
+    let node = handle
+        .create_node()
+        .name(&name)
+        .ip([10, 0, 1, id].into())
+        .build();
+    node.spawn(async move {
+        let client = Client::connect([SERVER], None).await.unwrap();
+        let mut lease_client = client.lease_client();
+        let lease = lease_client.grant(LEASE_TTL, None).await.unwrap().id();
+        let (mut keeper, mut stream) = lease_client.keep_alive(lease).await.unwrap();
+        madsim::task::spawn(async move {
+            loop {
+                sleep(Duration::from_secs(1)).await;
+                if keeper.keep_alive().await.is_err() || stream.message().await.is_err() {
+                    // the lease has expired
+                    return;
+                }
+            }
+        });
+
+        let mut election = client.election_client();
+        let rsp = election
+            .campaign(ELECTION, name.clone(), lease)
+            .await
+            .unwrap();
+        let leader = rsp.leader().unwrap().clone();
+        leaders.lock().unwrap().push((name, leader));
+    });
+    node
+}
+
+fn candidate_name(id: u8) -> String {
+    format!("candidate-{id}")
+}
+
+/// Returns the value of the current leader.
+async fn current_leader(client: &Client) -> Option<String> {
+    let rsp = client.election_client().leader(ELECTION).await.unwrap();
+    rsp.kv()
+        .map(|kv| String::from_utf8(kv.value().to_vec()).unwrap())
+}
+
+#[madsim::test]
+async fn failover() {
+    let handle = Handle::current();
+    start_server(&handle).await;
+
+    let leaders = Leaders::default();
+    let candidates: Vec<_> = (1..=3)
+        .map(|id| start_candidate(&handle, id, leaders.clone()))
+        .collect();
+    let observed = Arc::new(Mutex::new(vec![]));
+    let observed_ = observed.clone();
+    let observer = handle
+        .create_node()
+        .name("observer")
+        .ip([10, 0, 2, 1].into())
+        .build();
+    observer.spawn(async move {
+        let client = Client::connect([SERVER], None).await.unwrap();
+        let mut stream = client.election_client().observe(ELECTION).await.unwrap();
+        while let Some(rsp) = stream.message().await.unwrap() {
+            let value = rsp.kv().unwrap().value().to_vec();
+            observed_
+                .lock()
+                .unwrap()
+                .push(String::from_utf8(value).unwrap());
+        }
+    });
+    sleep(Duration::from_secs(5)).await;
+
+    // exactly one candidate is elected
+    let first = leaders.lock().unwrap()[0].0.clone();
+    assert_eq!(leaders.lock().unwrap().len(), 1);
+    let leader = observer
+        .spawn(async {
+            let client = Client::connect([SERVER], None).await.unwrap();
+            current_leader(&client).await
+        })
+        .await
+        .unwrap();
+    assert_eq!(leader.as_ref(), Some(&first));
+
+    // kill the leader, another candidate takes over after the lease expires
+    let index = (1..=3).position(|id| candidate_name(id) == first).unwrap();
+    handle.kill(candidates[index].id());
+    sleep(Duration::from_secs(LEASE_TTL as u64 + 2)).await;
+    let second = leaders.lock().unwrap()[1].0.clone();
+    assert_eq!(leaders.lock().unwrap().len(), 2);
+    assert_ne!(first, second);
+
+    assert_eq!(*observed.lock().unwrap(), [first, second]);
+}
+
+#[madsim::test]
+async fn resign_and_proclaim() {
+    let handle = Handle::current();
+    start_server(&handle).await;
+
+    let leaders = Leaders::default();
+    for id in 1..=2 {
+        start_candidate(&handle, id, leaders.clone());
+    }
+    sleep(Duration::from_secs(2)).await;
+    let (first, leader) = leaders.lock().unwrap()[0].clone();
+
+    let client_node = handle
+        .create_node()
+        .name("client")
+        .ip([10, 0, 2, 1].into())
+        .build();
+    let second = client_node
+        .spawn(async move {
+            let client = Client::connect([SERVER], None).await.unwrap();
+            let mut election = client.election_client();
+
+            // the leader announces a new value without another election
+            let options = ProclaimOptions::new().with_leader(leader.clone());
+            election.proclaim("updated", Some(options)).await.unwrap();
+            assert_eq!(current_leader(&client).await.unwrap(), "updated");
+
+            // the other candidate is elected immediately after the leader resigns
+            let options = ResignOptions::new().with_leader(leader);
+            election.resign(Some(options)).await.unwrap();
+            sleep(Duration::from_millis(100)).await;
+            current_leader(&client).await.unwrap()
+        })
+        .await
+        .unwrap();
+
+    assert_ne!(second, first);
+    assert_eq!(leaders.lock().unwrap().len(), 2);
+    assert_eq!(leaders.lock().unwrap()[1].0, second);
+}
+
+#[madsim::test]
+async fn partitioned_leader() {
+    let handle = Handle::current();
+    start_server(&handle).await;
+
+    let leaders = Leaders::default();
+    let candidates: Vec<_> = (1..=3)
+        .map(|id| start_candidate(&handle, id, leaders.clone()))
+        .collect();
+    let observed = Arc::new(Mutex::new(vec![]));
+    let observed_ = observed.clone();
+    let observer = handle
+        .create_node()
+        .name("observer")
+        .ip([10, 0, 2, 1].into())
+        .build();
+    observer.spawn(async move {
+        let client = Client::connect([SERVER], None).await.unwrap();
+        let mut stream = client.election_client().observe(ELECTION).await.unwrap();
+        while let Some(rsp) = stream.message().await.unwrap() {
+            let value = rsp.kv().unwrap().value().to_vec();
+            let value = String::from_utf8(value).unwrap();
+            observed_.lock().unwrap().push((value, Instant::now()));
+        }
+    });
+    sleep(Duration::from_secs(5)).await;
+    let (first, leader) = leaders.lock().unwrap()[0].clone();
+
+    // partition the leader from etcd, so that it can't renew its lease
+    let index = (1..=3).position(|id| candidate_name(id) == first).unwrap();
+    let net = NetSim::current();
+    net.clog_node(candidates[index].id());
+    let t0 = Instant::now();
+    sleep(Duration::from_secs(LEASE_TTL as u64 + 1)).await;
+
+    // a follower wins the election once the lease expires
+    assert_eq!(leaders.lock().unwrap().len(), 2);
+    let second = leaders.lock().unwrap()[1].0.clone();
+    assert_ne!(first, second);
+    let observed = observed.lock().unwrap().clone();
+    assert_eq!(observed.len(), 2);
+    assert_eq!([&observed[0].0, &observed[1].0], [&first, &second]);
+    let failover = observed[1].1 - t0;
+    assert!(
+        failover <= Duration::from_secs(LEASE_TTL as u64),
+        "{failover:?}"
+    );
+
+    // the old leader has lost its leadership after the partition heals
+    net.unclog_node(candidates[index].id());
+    let proclaim = candidates[index]
+        .spawn(async move {
+            let client = Client::connect([SERVER], None).await.unwrap();
+            let options = ProclaimOptions::new().with_leader(leader);
+            client
+                .election_client()
+                .proclaim("stale", Some(options))
+                .await
+        })
+        .await
+        .unwrap();
+    assert!(proclaim.is_err());
+    let leader = observer
+        .spawn(async {
+            let client = Client::connect([SERVER], None).await.unwrap();
+            current_leader(&client).await
+        })
+        .await
+        .unwrap();
+    assert_eq!(leader, Some(second));
+}
@@ -0,0 +1,362 @@
+#![cfg(madsim)]
+
+use madsim::{
+    runtime::{Handle, NodeHandle},
+    time::Instant,
+};
+use madsim_etcd_client::{
+    Client, Compare, CompareOp, DeleteOptions, Error, GetOptions, LeaseTimeToLiveOptions,
+    Operation, PutOptions, ResponseHeader, SimServer, Txn, TxnOp, TxnOpResponse,
+};
+use std::{future::Future, net::SocketAddr, time::Duration};
+
+/// The address of the server in tests.
+const SERVER_ADDR: &str = "10.0.0.1:2379";
+
+/// Starts the server on its own node and waits for it to be ready.
+async fn start_server(server: SimServer) {
+    let addr = SERVER_ADDR.parse::<SocketAddr>().unwrap();
+    Handle::current()
+        .create_node()
+        .name("server")
+        .ip(addr.ip())
+        .build()
+        .spawn(async move {
+            server.serve(addr).await.unwrap();
+        });
+    madsim::time::sleep(Duration::from_secs(1)).await;
+}
+
+/// Creates the node that clients run on.
+fn client_node() -> NodeHandle {
+    Handle::current()
+        .create_node()
+        .name("client")
+        .ip("10.0.1.1".parse().unwrap())
+        .build()
+}
+
+/// Runs `f` on the client node with a client connected to the server.
+async fn run_client<F, Fut>(f: F)
+where
+    F: FnOnce(Client) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    client_node()
+        .spawn(async move {
+            let client = Client::connect([SERVER_ADDR], None).await.unwrap();
+            f(client).await;
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn delete_prefix() {
+    start_server(SimServer::builder()).await;
+    run_client(|client| async move {
+        let mut kv = client.kv_client();
+        for key in ["job/1", "job/2", "job/3", "jobs", "other"] {
+            kv.put(key, "v", None).await.unwrap();
+        }
+        let rev = kv
+            .get("other", None)
+            .await
+            .unwrap()
+            .header()
+            .unwrap()
+            .revision();
+
+        let options = DeleteOptions::new().with_prefix().with_prev_key();
+        let rsp = kv.delete("job/", Some(options)).await.unwrap();
+        assert_eq!(rsp.deleted(), 3);
+        let keys: Vec<_> = rsp.prev_kvs().iter().map(|kv| kv.key()).collect();
+        assert_eq!(keys, [b"job/1", b"job/2", b"job/3"]);
+        assert!(rsp.prev_kvs().iter().all(|kv| kv.value() == b"v"));
+        // a range deletion is one revision
+        assert_eq!(rsp.header().unwrap().revision(), rev + 1);
+
+        // without prev_kv
+        let options = DeleteOptions::new().with_range("jobt");
+        let rsp = kv.delete("jobs", Some(options)).await.unwrap();
+        assert_eq!(rsp.deleted(), 1);
+        assert!(rsp.prev_kvs().is_empty());
+
+        // nothing left to delete
+        let options = DeleteOptions::new().with_prefix();
+        let rsp = kv.delete("job", Some(options)).await.unwrap();
+        assert_eq!(rsp.deleted(), 0);
+        assert_eq!(rsp.header().unwrap().revision(), rev + 2);
+        assert_eq!(kv.get("other", None).await.unwrap().kvs().len(), 1);
+
+        // "\0" as the range end deletes all keys from the key
+        for key in ["a", "b", "c"] {
+            kv.put(key, "v", None).await.unwrap();
+        }
+        let options = DeleteOptions::new().with_range("\0");
+        let rsp = kv.delete("b", Some(options)).await.unwrap();
+        // "b", "c" and "other"
+        assert_eq!(rsp.deleted(), 3);
+        let options = GetOptions::new().with_prefix();
+        let rsp = kv.get("", Some(options)).await.unwrap();
+        let keys: Vec<_> = rsp.kvs().iter().map(|kv| kv.key()).collect();
+        assert_eq!(keys, [b"a"]);
+    })
+    .await;
+}
+
+#[madsim::test]
+async fn response_header() {
+    start_server(SimServer::builder()).await;
+    run_client(|client| async move {
+        let mut kv = client.kv_client();
+        let first = kv.get("k", None).await.unwrap().header().unwrap().clone();
+        let rev = first.revision();
+        let check = |header: &ResponseHeader, revision: i64| {
+            assert_eq!(header.revision(), revision);
+            assert_eq!(header.raft_term(), first.raft_term());
+            assert_eq!(header.cluster_id(), first.cluster_id());
+            assert_eq!(header.member_id(), first.member_id());
+        };
+
+        // every put increases the revision by one
+        for i in 1..=5 {
+            let rsp = kv.put("k", format!("v{i}"), None).await.unwrap();
+            check(rsp.header().unwrap(), rev + i);
+        }
+        // reads and no-op deletes don't change the revision
+        check(kv.get("k", None).await.unwrap().header().unwrap(), rev + 5);
+        let rsp = kv.delete("none", None).await.unwrap();
+        check(rsp.header().unwrap(), rev + 5);
+        // granting a lease doesn't change the key-value store
+        let rsp = client.lease_client().grant(10, None).await.unwrap();
+        check(rsp.header().unwrap(), rev + 5);
+
+        // a transaction with multiple writes increases the revision by one
+        let txn = Txn::new()
+            .when([Compare::value("k", CompareOp::Equal, "v5")])
+            .and_then([
+                TxnOp::put("a", "1", None),
+                TxnOp::put("b", "1", None),
+                TxnOp::get("k", None),
+            ]);
+        let rsp = kv.txn(txn).await.unwrap();
+        assert!(rsp.succeeded());
+        check(rsp.header().unwrap(), rev + 6);
+        for op in rsp.op_responses() {
+            let header = match &op {
+                TxnOpResponse::Put(r) => r.header(),
+                TxnOpResponse::Get(r) => r.header(),
+                _ => unreachable!(),
+            };
+            check(header.unwrap(), rev + 6);
+        }
+        // a read-only transaction doesn't change the revision
+        let txn = Txn::new().and_then([TxnOp::get("k", None)]);
+        check(kv.txn(txn).await.unwrap().header().unwrap(), rev + 6);
+    })
+    .await;
+}
+
+#[madsim::test]
+async fn lease_time_to_live() {
+    start_server(SimServer::builder()).await;
+    run_client(|client| async move {
+        let mut kv = client.kv_client();
+        let mut lease = client.lease_client();
+        let id = lease.grant(10, None).await.unwrap().id();
+        for key in ["a", "b"] {
+            let options = PutOptions::new().with_lease(id);
+            kv.put(key, "v", Some(options)).await.unwrap();
+        }
+
+        madsim::time::sleep(Duration::from_secs(3)).await;
+        let options = LeaseTimeToLiveOptions::new().with_keys();
+        let rsp = lease.time_to_live(id, Some(options.clone())).await.unwrap();
+        assert_eq!(rsp.id(), id);
+        assert_eq!(rsp.granted_ttl(), 10);
+        assert_eq!(rsp.ttl(), 6);
+        let mut keys = rsp.keys().to_vec();
+        keys.sort();
+        assert_eq!(keys, [b"a", b"b"]);
+
+        // keys are only returned on request
+        let rsp = lease.time_to_live(id, None).await.unwrap();
+        assert!(rsp.keys().is_empty());
+
+        // an unknown lease
+        let rsp = lease.time_to_live(id + 1, None).await.unwrap();
+        assert_eq!(rsp.ttl(), -1);
+
+        // an expired lease
+        madsim::time::sleep(Duration::from_secs(10)).await;
+        let rsp = lease.time_to_live(id, Some(options)).await.unwrap();
+        assert_eq!(rsp.ttl(), -1);
+        assert!(rsp.keys().is_empty());
+    })
+    .await;
+}
+
+#[madsim::test]
+async fn mixed_version_cluster() {
+    let handle = Handle::current();
+    // a cluster in the middle of an upgrade
+    let servers = [("10.0.0.1:2379", "3.1"), ("10.0.0.2:2379", "3.3")];
+    for (addr, version) in servers {
+        let addr = addr.parse::<SocketAddr>().unwrap();
+        handle
+            .create_node()
+            .name(format!("server-{version}"))
+            .ip(addr.ip())
+            .build()
+            .spawn(async move {
+                SimServer::builder()
+                    .version(version)
+                    .serve(addr)
+                    .await
+                    .unwrap();
+            });
+    }
+    madsim::time::sleep(Duration::from_secs(1)).await;
+
+    client_node()
+        .spawn(async move {
+            let mut ttls = vec![];
+            for (addr, _) in servers {
+                let client = Client::connect([addr], None).await.unwrap();
+                let mut lease = client.lease_client();
+                let id = lease.grant(10, None).await.unwrap().id();
+                // list the leases, or fall back to querying the known ones
+                let ids = match lease.leases().await {
+                    Ok(rsp) => rsp.leases().iter().map(|l| l.id()).collect(),
+                    Err(Error::GRpcStatus(status)) => {
+                        assert_eq!(status.message(), "unknown method LeaseLeases");
+                        vec![id]
+                    }
+                    Err(e) => panic!("unexpected error: {e}"),
+                };
+                for id in ids {
+                    ttls.push(lease.time_to_live(id, None).await.unwrap().granted_ttl());
+                }
+            }
+            assert_eq!(ttls, [10, 10]);
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn server_faults() {
+    let server = SimServer::builder();
+    let faults = server.faults();
+    start_server(server).await;
+    run_client(|client| async move {
+        let mut kv = client.kv_client();
+        let rsp = kv.put("k", "v1", None).await.unwrap();
+        let rev = rsp.header().unwrap().revision();
+
+        // the server is unavailable for a while, then drops a connection
+        // and recovers slowly
+        faults.set_unavailable(Duration::from_secs(3));
+        faults.drop_requests(1);
+        faults.set_latency(Duration::from_millis(500));
+        let t0 = Instant::now();
+        let mut errors = vec![];
+        let mut backoff = Duration::from_millis(100);
+        let rsp = loop {
+            match kv.put("k", "v2", None).await {
+                Ok(rsp) => break rsp,
+                Err(e) => errors.push(e),
+            }
+            madsim::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(1));
+        };
+        assert!(t0.elapsed() >= Duration::from_millis(3500));
+        let (last, unavailable) = errors.split_last().unwrap();
+        assert!(!unavailable.is_empty());
+        for e in unavailable {
+            assert!(
+                matches!(e, Error::GRpcStatus(s) if s.code() == tonic::Code::Unavailable),
+                "{e}"
+            );
+        }
+        assert!(matches!(last, Error::IoError(_)), "{last}");
+
+        // failed requests are not applied
+        assert_eq!(rsp.header().unwrap().revision(), rev + 1);
+        faults.clear();
+        let rsp = kv.get("k", None).await.unwrap();
+        assert_eq!(rsp.kvs()[0].value(), b"v2");
+    })
+    .await;
+}
+
+#[madsim::test]
+async fn stale_reads() {
+    let server = SimServer::builder();
+    let faults = server.faults();
+    start_server(server).await;
+    run_client(|client| async move {
+        let mut kv = client.kv_client();
+        kv.put("k", "v1", None).await.unwrap();
+
+        // a read after a completed write misses it
+        faults.serve_stale_reads(Duration::from_secs(5));
+        let rsp = kv.put("k", "v2", None).await.unwrap();
+        let rev = rsp.header().unwrap().revision();
+        let rsp = kv.get("k", None).await.unwrap();
+        assert_eq!(rsp.kvs()[0].value(), b"v1");
+        assert!(rsp.header().unwrap().revision() < rev);
+
+        // reads are linearizable again after the window
+        madsim::time::sleep(Duration::from_secs(5)).await;
+        let rsp = kv.get("k", None).await.unwrap();
+        assert_eq!(rsp.kvs()[0].value(), b"v2");
+        assert_eq!(rsp.header().unwrap().revision(), rev);
+    })
+    .await;
+}
+
+#[madsim::test]
+async fn operation_latency() {
+    let server = SimServer::builder();
+    let faults = server.faults();
+    start_server(server).await;
+    run_client(|client| async move {
+        let mut kv = client.kv_client();
+        let write_timeout = Duration::from_secs(3);
+        let read_timeout = Duration::from_millis(100);
+
+        // writes are slowed down close to their timeout
+        faults.set_operation_latency(Operation::Put, Duration::from_millis(2500));
+        faults.set_operation_latency(Operation::Txn, Duration::from_millis(2500));
+        let t0 = Instant::now();
+        madsim::time::timeout(write_timeout, kv.put("k", "v", None))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(t0.elapsed() >= Duration::from_millis(2500));
+        let t0 = Instant::now();
+        let txn = Txn::new().and_then([TxnOp::put("k", "v2", None)]);
+        madsim::time::timeout(write_timeout, kv.txn(txn))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(t0.elapsed() >= Duration::from_millis(2500));
+
+        // while reads stay fast
+        let rsp = madsim::time::timeout(read_timeout, kv.get("k", None))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rsp.kvs()[0].value(), b"v2");
+
+        // a zero latency removes the delay
+        faults.set_operation_latency(Operation::Put, Duration::ZERO);
+        madsim::time::timeout(read_timeout, kv.put("k", "v3", None))
+            .await
+            .unwrap()
+            .unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,202 @@
+//! HTTP Client
+//!
+//! Only HTTP/1 over plain TCP is supported in the simulation.
+//! Connections are not pooled: each request opens a new connection.
+
+use futures_util::{future::BoxFuture, FutureExt};
+use hyper::{
+    client::conn,
+    header::{HeaderValue, HOST},
+    http::uri::{PathAndQuery, Scheme},
+    Body, Request, Response, Uri,
+};
+use madsim::net::TcpStream;
+use std::{
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::*;
+
+/// A simulated HTTP client.
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    http1_title_case_headers: bool,
+}
+
+impl Client {
+    /// Create a new `Client` with the default config.
+    pub fn new() -> Client {
+        Client::default()
+    }
+
+    /// Create a builder to configure a new `Client`.
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// Send a `GET` request to the supplied `Uri`.
+    pub fn get(&self, uri: Uri) -> ResponseFuture {
+        let mut req = Request::new(Body::empty());
+        *req.uri_mut() = uri;
+        self.request(req)
+    }
+
+    /// Send a constructed `Request` using this `Client`.
+    pub fn request(&self, req: Request<Body>) -> ResponseFuture {
+        let client = self.clone();
+        ResponseFuture {
+            inner: async move { client.send_request(req).await }.boxed(),
+        }
+    }
+
+    async fn send_request(self, mut req: Request<Body>) -> hyper::Result<Response<Body>> {
+        let uri = req.uri().clone();
+        debug!(method = %req.method(), %uri, "send request");
+        let stream = match self.connect(&uri).await {
+            Ok(stream) => stream,
+            Err(e) => return Err(io_error(e).await),
+        };
+        // the request should be sent in origin-form
+        if !req.headers().contains_key(HOST) {
+            let host = match uri.port() {
+                Some(port) => format!("{}:{}", uri.host().unwrap(), port),
+                None => uri.host().unwrap().to_string(),
+            };
+            req.headers_mut()
+                .insert(HOST, HeaderValue::from_str(&host).unwrap());
+        }
+        *req.uri_mut() = uri
+            .path_and_query()
+            .cloned()
+            .unwrap_or_else(|| PathAndQuery::from_static("/"))
+            .into();
+
+        let (mut sender, conn) = conn::Builder::new()
+            .http1_title_case_headers(self.http1_title_case_headers)
+            .handshake(stream)
+            .await?;
+        madsim::task::spawn(async move {
+            if let Err(e) = conn.await {
+                debug!("connection error: {}", e);
+            }
+        });
+        sender.send_request(req).await
+    }
+
+    async fn connect(&self, uri: &Uri) -> io::Result<TcpStream> {
+        if uri.scheme() != Some(&Scheme::HTTP) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported scheme: {uri}"),
+            ));
+        }
+        let host = uri
+            .host()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing host"))?;
+        // remove brackets of IPv6 address
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = uri.port_u16().unwrap_or(80);
+        TcpStream::connect((host, port)).await
+    }
+}
+
+/// A builder to configure a new [`Client`].
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    http1_title_case_headers: bool,
+}
+
+impl Builder {
+    /// Set whether HTTP/1 connections will write header names as title case at the socket level.
+    ///
+    /// Default is false.
+    pub fn http1_title_case_headers(&mut self, val: bool) -> &mut Self {
+        self.http1_title_case_headers = val;
+        self
+    }
+
+    /// Sets whether HTTP/2 is required.
+    ///
+    /// HTTP/1 is always used in the simulation.
+    pub fn http2_only(&mut self, _val: bool) -> &mut Self {
+        self
+    }
+
+    /// Builds a [`Client`].
+    pub fn build_http(&self) -> Client {
+        Client {
+            http1_title_case_headers: self.http1_title_case_headers,
+        }
+    }
+}
+
+/// A `Future` that will resolve to an HTTP Response.
+#[must_use = "futures do nothing unless polled"]
+pub struct ResponseFuture {
+    inner: BoxFuture<'static, hyper::Result<Response<Body>>>,
+}
+
+impl Future for ResponseFuture {
+    type Output = hyper::Result<Response<Body>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.inner.poll_unpin(cx)
+    }
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("Future<Response>")
+    }
+}
+
+/// Converts an IO error to `hyper::Error`.
+///
+/// `hyper::Error` can not be constructed outside hyper.
+/// So we send a request on a broken connection to get one.
+async fn io_error(error: io::Error) -> hyper::Error {
+    let (mut sender, conn) = conn::handshake(BrokenIo(Some(error)))
+        .await
+        .expect("handshake should not fail");
+    let (res, _) = futures_util::join!(sender.send_request(Request::new(Body::empty())), conn);
+    res.expect_err("request on a broken connection should fail")
+}
+
+/// An IO that fails on the first write.
+struct BrokenIo(Option<io::Error>);
+
+impl AsyncRead for BrokenIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for BrokenIo {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let e = self
+            .0
+            .take()
+            .unwrap_or_else(|| io::ErrorKind::BrokenPipe.into());
+        Poll::Ready(Err(e))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
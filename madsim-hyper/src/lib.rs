@@ -0,0 +1,8 @@
+#[cfg(madsim)]
+#[path = "sim.rs"]
+mod sim;
+
+#[cfg(not(madsim))]
+pub use hyper::*;
+#[cfg(madsim)]
+pub use sim::*;
@@ -0,0 +1,282 @@
+//! HTTP Server
+//!
+//! Only HTTP/1 is supported in the simulation.
+
+use futures_util::{
+    future::{poll_fn, BoxFuture},
+    select_biased, FutureExt,
+};
+use hyper::{body::HttpBody, server::conn::Http, service::Service, Body, Request, Response};
+use madsim::net::{TcpListener, TcpStream};
+use std::{
+    error::Error as StdError,
+    fmt,
+    future::{pending, Future},
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tracing::*;
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// A listening HTTP server that accepts connections.
+///
+/// `Server` is a `Future` mapping a bound listener with a set of service
+/// handlers. It is built using the [`Builder`], and the future
+/// completes when the server has been shutdown. It should be run by an executor.
+pub struct Server<S> {
+    builder: Builder,
+    make_service: Option<S>,
+    future: Option<BoxFuture<'static, hyper::Result<()>>>,
+}
+
+/// A builder for a [`Server`].
+#[derive(Debug, Clone)]
+pub struct Builder {
+    addr: SocketAddr,
+    http1_keepalive: bool,
+    http1_half_close: bool,
+    http1_max_buf_size: Option<usize>,
+}
+
+/// Lower-level server connection API.
+pub mod conn {
+    pub use super::AddrStream;
+}
+
+/// The connection information passed to the `MakeService`.
+#[derive(Debug, Clone, Copy)]
+pub struct AddrStream {
+    local_addr: SocketAddr,
+    remote_addr: SocketAddr,
+}
+
+impl AddrStream {
+    /// Returns the remote (peer) address of this connection.
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    /// Returns the local address of this connection.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Server<()> {
+    /// Binds to the provided address, and returns a [`Builder`].
+    ///
+    /// # Panics
+    ///
+    /// The server panics if binding to the address fails.
+    pub fn bind(addr: &SocketAddr) -> Builder {
+        Builder {
+            addr: *addr,
+            http1_keepalive: true,
+            http1_half_close: false,
+            http1_max_buf_size: None,
+        }
+    }
+}
+
+impl Builder {
+    /// Sets whether HTTP/1 keep-alive is enabled.
+    ///
+    /// Default is `true`.
+    pub fn http1_keepalive(mut self, val: bool) -> Self {
+        self.http1_keepalive = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections should support half-closures.
+    ///
+    /// Default is `false`.
+    pub fn http1_half_close(mut self, val: bool) -> Self {
+        self.http1_half_close = val;
+        self
+    }
+
+    /// Set the maximum buffer size.
+    pub fn http1_max_buf_size(mut self, val: usize) -> Self {
+        self.http1_max_buf_size = Some(val);
+        self
+    }
+
+    /// Sets whether HTTP/1 is required.
+    ///
+    /// HTTP/1 is always used in the simulation.
+    pub fn http1_only(self, _val: bool) -> Self {
+        self
+    }
+
+    /// Consume this `Builder`, creating a [`Server`].
+    pub fn serve<S, Svc, E, F, B>(self, make_service: S) -> Server<S>
+    where
+        S: for<'a> Service<&'a AddrStream, Response = Svc, Error = E, Future = F> + Send + 'static,
+        E: Into<BoxError> + Send + 'static,
+        F: Future<Output = Result<Svc, E>> + Send + 'static,
+        Svc: Service<Request<Body>, Response = Response<B>> + Send + 'static,
+        Svc::Error: Into<BoxError>,
+        Svc::Future: Send + 'static,
+        B: HttpBody + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>,
+    {
+        Server {
+            builder: self,
+            make_service: Some(make_service),
+            future: None,
+        }
+    }
+
+    fn http(&self) -> Http {
+        let mut http = Http::new();
+        http.http1_only(true)
+            .http1_keep_alive(self.http1_keepalive)
+            .http1_half_close(self.http1_half_close);
+        if let Some(max) = self.http1_max_buf_size {
+            http.max_buf_size(max);
+        }
+        http
+    }
+}
+
+impl<S, Svc, E, F, B> Server<S>
+where
+    S: for<'a> Service<&'a AddrStream, Response = Svc, Error = E, Future = F> + Send + 'static,
+    E: Into<BoxError> + Send + 'static,
+    F: Future<Output = Result<Svc, E>> + Send + 'static,
+    Svc: Service<Request<Body>, Response = Response<B>> + Send + 'static,
+    Svc::Error: Into<BoxError>,
+    Svc::Future: Send + 'static,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    /// Prepares a server to handle graceful shutdown when the provided future completes.
+    ///
+    /// The server stops accepting new connections once the signal completes.
+    pub fn with_graceful_shutdown<Fut>(self, signal: Fut) -> Graceful
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Graceful {
+            future: self.builder.run(self.make_service.unwrap(), signal).boxed(),
+        }
+    }
+}
+
+impl Builder {
+    async fn run<S, Svc, E, F, B>(
+        self,
+        mut make_service: S,
+        signal: impl Future<Output = ()> + Send + 'static,
+    ) -> hyper::Result<()>
+    where
+        S: for<'a> Service<&'a AddrStream, Response = Svc, Error = E, Future = F> + Send + 'static,
+        E: Into<BoxError> + Send + 'static,
+        F: Future<Output = Result<Svc, E>> + Send + 'static,
+        Svc: Service<Request<Body>, Response = Response<B>> + Send + 'static,
+        Svc::Error: Into<BoxError>,
+        Svc::Future: Send + 'static,
+        B: HttpBody + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>,
+    {
+        let listener = TcpListener::bind(self.addr)
+            .await
+            .unwrap_or_else(|e| panic!("error binding to {}: {}", self.addr, e));
+        let local_addr = listener.local_addr().unwrap();
+        let http = self.http();
+        let mut signal = Box::pin(signal.fuse());
+        loop {
+            let (stream, remote_addr): (TcpStream, SocketAddr) = select_biased! {
+                _ = signal => return Ok(()),
+                res = listener.accept().fuse() => match res {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        warn!("accept error: {}", e);
+                        continue;
+                    }
+                },
+            };
+            let conn = AddrStream {
+                local_addr,
+                remote_addr,
+            };
+            if let Err(e) = poll_fn(|cx| make_service.poll_ready(cx)).await {
+                error!("make service error: {}", e.into());
+                continue;
+            }
+            let service = match make_service.call(&conn).await {
+                Ok(service) => service,
+                Err(e) => {
+                    error!("make service error: {}", e.into());
+                    continue;
+                }
+            };
+            let http = http.clone();
+            madsim::task::spawn(async move {
+                if let Err(e) = http.serve_connection(stream, service).await {
+                    debug!(?remote_addr, "connection error: {}", e);
+                }
+            });
+        }
+    }
+}
+
+impl<S, Svc, E, F, B> Future for Server<S>
+where
+    S: for<'a> Service<&'a AddrStream, Response = Svc, Error = E, Future = F>
+        + Send
+        + Unpin
+        + 'static,
+    E: Into<BoxError> + Send + 'static,
+    F: Future<Output = Result<Svc, E>> + Send + 'static,
+    Svc: Service<Request<Body>, Response = Response<B>> + Send + 'static,
+    Svc::Error: Into<BoxError>,
+    Svc::Future: Send + 'static,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Output = hyper::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.future.is_none() {
+            let make_service = self.make_service.take().unwrap();
+            let future = self.builder.clone().run(make_service, pending());
+            self.future = Some(future.boxed());
+        }
+        self.future.as_mut().unwrap().poll_unpin(cx)
+    }
+}
+
+impl<S> fmt::Debug for Server<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("addr", &self.builder.addr)
+            .finish()
+    }
+}
+
+/// A listening HTTP server that stops accepting connections when a signal completes.
+#[must_use = "futures do nothing unless polled"]
+pub struct Graceful {
+    future: BoxFuture<'static, hyper::Result<()>>,
+}
+
+impl Future for Graceful {
+    type Output = hyper::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.future.poll_unpin(cx)
+    }
+}
+
+impl fmt::Debug for Graceful {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Graceful").finish()
+    }
+}
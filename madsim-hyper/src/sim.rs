@@ -0,0 +1,10 @@
+pub use hyper::{
+    body, header, http, rt, service, upgrade, Body, Error, HeaderMap, Method, Request, Response,
+    Result, StatusCode, Uri, Version,
+};
+
+pub use self::client::Client;
+pub use self::server::Server;
+
+pub mod client;
+pub mod server;
@@ -0,0 +1,152 @@
+#![cfg(madsim)]
+
+use madsim::{runtime::Handle, time::sleep};
+use madsim_hyper::{
+    body::to_bytes,
+    service::{make_service_fn, service_fn},
+    Body, Client, Method, Request, Response, Server, StatusCode, Uri,
+};
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+use tokio::sync::oneshot;
+
+async fn serve_req(
+    remote_addr: SocketAddr,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let res = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/") => Response::new(Body::from(remote_addr.to_string())),
+        (&Method::POST, "/echo") => Response::new(req.into_body()),
+        (&Method::GET, "/stream") => {
+            let (mut sender, body) = Body::channel();
+            madsim::task::spawn(async move {
+                for i in 0..3 {
+                    sleep(Duration::from_secs(1)).await;
+                    sender.send_data(format!("{i}").into()).await.unwrap();
+                }
+            });
+            Response::new(body)
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    };
+    Ok(res)
+}
+
+#[madsim::test]
+async fn basic() {
+    let handle = Handle::current();
+    let addr: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+
+    handle
+        .create_node()
+        .name("server")
+        .ip(addr.ip())
+        .build()
+        .spawn(async move {
+            let make_service = make_service_fn(|conn: &madsim_hyper::server::conn::AddrStream| {
+                let remote_addr = conn.remote_addr();
+                async move { Ok::<_, Infallible>(service_fn(move |req| serve_req(remote_addr, req))) }
+            });
+            Server::bind(&addr).serve(make_service).await.unwrap();
+        });
+
+    let client_node = handle
+        .create_node()
+        .name("client")
+        .ip("10.0.0.2".parse().unwrap())
+        .build();
+    client_node
+        .spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            let client = Client::new();
+
+            // GET
+            let res = client
+                .get("http://10.0.0.1:8080/".parse().unwrap())
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = to_bytes(res.into_body()).await.unwrap();
+            assert!(body.starts_with(b"10.0.0.2:"));
+
+            // POST
+            let req = Request::post("http://10.0.0.1:8080/echo")
+                .body(Body::from("hello"))
+                .unwrap();
+            let res = client.request(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(to_bytes(res.into_body()).await.unwrap(), "hello");
+
+            // status code
+            let res = client
+                .get("http://10.0.0.1:8080/404".parse().unwrap())
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+            // streaming body
+            let t0 = madsim::time::Instant::now();
+            let res = client
+                .get("http://10.0.0.1:8080/stream".parse().unwrap())
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(to_bytes(res.into_body()).await.unwrap(), "012");
+            assert!(t0.elapsed() >= Duration::from_secs(3));
+
+            // connection refused
+            client
+                .get("http://10.0.0.1:80/".parse().unwrap())
+                .await
+                .unwrap_err();
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn graceful_shutdown() {
+    let handle = Handle::current();
+    let addr: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+    let (tx, rx) = oneshot::channel();
+
+    let server = handle
+        .create_node()
+        .name("server")
+        .ip(addr.ip())
+        .build()
+        .spawn(async move {
+            let make_service = make_service_fn(|_| async {
+                Ok::<_, Infallible>(service_fn(|_| async {
+                    Ok::<_, Infallible>(Response::new(Body::from("ok")))
+                }))
+            });
+            Server::bind(&addr)
+                .serve(make_service)
+                .with_graceful_shutdown(async { rx.await.unwrap() })
+                .await
+                .unwrap();
+        });
+
+    let client_node = handle
+        .create_node()
+        .name("client")
+        .ip("10.0.0.2".parse().unwrap())
+        .build();
+    client_node
+        .spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            let client = Client::new();
+            let uri: Uri = "http://10.0.0.1:8080/".parse().unwrap();
+            let res = client.get(uri.clone()).await.unwrap();
+            assert_eq!(to_bytes(res.into_body()).await.unwrap(), "ok");
+
+            tx.send(()).unwrap();
+            server.await.unwrap();
+            client.get(uri).await.unwrap_err();
+        })
+        .await
+        .unwrap();
+}
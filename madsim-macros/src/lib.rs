@@ -84,6 +84,27 @@ pub fn main(args: TokenStream, item: TokenStream) -> TokenStream {
 ///     If any non-determinism detected, it will panic as soon as possible.
 ///
 ///     By default, it is disabled.
+///
+/// - `MADSIM_TEST_EPOCH`: Set the wall-clock time when the simulation starts,
+///   in seconds since the Unix epoch.
+///
+///     By default, the simulation starts at a random time in 2022.
+///
+/// A test can also start with its own configuration by naming a function that
+/// returns a `madsim::Config`. It takes precedence over `MADSIM_TEST_CONFIG`.
+///
+/// ```ignore
+/// fn lossy() -> madsim::Config {
+///     let mut config = madsim::Config::default();
+///     config.net.packet_loss_rate = 0.5;
+///     config
+/// }
+///
+/// #[madsim::test(config = "lossy")]
+/// async fn my_test() {
+///     assert!(true);
+/// }
+/// ```
 #[proc_macro_attribute]
 pub fn test(args: TokenStream, item: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(item as syn::ItemFn);
@@ -112,9 +133,12 @@ pub fn tokio_test(args: TokenStream, item: TokenStream) -> TokenStream {
     parse(input, args, true, true).unwrap_or_else(|e| e.to_compile_error().into())
 }
 
+/// The arguments of `#[tokio::main]` and `#[tokio::test]`.
+const TOKIO_ARGS: &[&str] = &["flavor", "worker_threads", "start_paused", "crate"];
+
 fn parse(
     mut input: syn::ItemFn,
-    _args: syn::AttributeArgs,
+    args: syn::AttributeArgs,
     is_test: bool,
     is_tokio: bool,
 ) -> Result<TokenStream, syn::Error> {
@@ -123,6 +147,34 @@ fn parse(
         return Err(syn::Error::new_spanned(input.sig.fn_token, msg));
     }
 
+    let mut config = None;
+    for arg in args {
+        match arg {
+            syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("config") => {
+                let path = match &nv.lit {
+                    syn::Lit::Str(s) => s.parse::<syn::ExprPath>()?,
+                    lit => {
+                        let msg = "expected a string naming a function that returns `Config`";
+                        return Err(syn::Error::new_spanned(lit, msg));
+                    }
+                };
+                config = Some(path);
+            }
+            // arguments for tokio are ignored
+            syn::NestedMeta::Meta(syn::Meta::NameValue(nv))
+                if is_tokio && TOKIO_ARGS.iter().any(|arg| nv.path.is_ident(arg)) => {}
+            arg => {
+                let expected = if is_tokio {
+                    "`config`, `flavor`, `worker_threads`, `start_paused` or `crate`"
+                } else {
+                    "`config`"
+                };
+                let msg = format!("unknown argument, expected {expected}");
+                return Err(syn::Error::new_spanned(arg, msg));
+            }
+        }
+    }
+
     let body = &input.block;
     let brace_token = input.block.brace_token;
     let tokio = if is_tokio {
@@ -130,10 +182,17 @@ fn parse(
     } else {
         quote! {}
     };
+    let set_config = config.map(|path| {
+        quote! {
+            let builder = #tokio::madsim::runtime::Builder { config: #path(), ..builder };
+        }
+    });
     input.block = syn::parse2(quote! {
         {
             #tokio::madsim::runtime::init_logger();
-            #tokio::madsim::runtime::Builder::from_env().run(|| async #body)
+            let builder = #tokio::madsim::runtime::Builder::from_env();
+            #set_config
+            builder.run(|| async #body)
         }
     })
     .expect("Parsing failure");
@@ -87,11 +87,17 @@ where
             let req = Request::CreateTopic {
                 name: topic.name.to_string(),
                 partitions: topic.num_partitions as usize,
+                config: topic
+                    .config
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
             };
             let (tx, mut rx) = self.ep.connect1(self.addr).await?;
             tx.send(Box::new(req)).await?;
             let res = match *rx.recv().await?.downcast::<KafkaResult<()>>().unwrap() {
                 Ok(()) => Ok(topic.name.to_string()),
+                Err(KafkaError::AdminOp(code)) => Err((topic.name.to_string(), code)),
                 Err(e) => todo!("failed to create topic: {}", e),
             };
             results.push(res);
@@ -5,21 +5,61 @@ use crate::{
     message::{OwnedHeaders, OwnedMessage, Timestamp, ToBytes},
     metadata::{Metadata, MetadataPartition, MetadataTopic},
     producer::BaseRecord,
+    util::current_time_millis,
     Message, Offset, TopicPartitionList,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use tracing::*;
 
 #[derive(Debug, Default)]
 pub struct Broker {
     topics: HashMap<String, Topic>,
+    /// The last allocated producer ID.
+    last_producer_id: i64,
+    /// The state of each idempotent producer on each partition.
+    ///
+    /// Records sent without a partition are partitioned by the broker, so
+    /// they are sequenced together under the `None` partition of the topic.
+    sequences: HashMap<(i64, String, Option<i32>), ProducerState>,
 }
 
+/// The number of recent records of an idempotent producer whose offsets are
+/// remembered for duplicates. Kafka keeps the last 5 batches.
+const DEDUP_WINDOW: usize = 5;
+
+/// The state of an idempotent producer on a partition.
+#[derive(Debug, Default)]
+struct ProducerState {
+    /// The next expected sequence number.
+    next: i32,
+    /// The sequence number and the result of the recently produced records.
+    recent: VecDeque<(i32, Produced)>,
+}
+
+/// The partition and offset of an appended record, or the error if rejected.
+type Produced = std::result::Result<(i32, i64), ErrorCode>;
+
 #[derive(Debug)]
 struct Topic {
     name: String,
     partitions: Vec<Partition>,
     last_partition: usize,
+    /// The log compaction policy if `cleanup.policy` is `compact`.
+    compaction: Option<Compaction>,
+    /// The largest record size allowed.
+    ///
+    /// Configured by `max.message.bytes`. Default: 1048588
+    max_message_bytes: usize,
+}
+
+/// Log compaction policy of a topic.
+#[derive(Debug, Clone, Copy)]
+struct Compaction {
+    /// Compact a partition when the ratio of records appended since the last
+    /// compaction reaches this value.
+    ///
+    /// Configured by `min.cleanable.dirty.ratio`. Default: 0.5
+    min_cleanable_dirty_ratio: f64,
 }
 
 #[derive(Debug)]
@@ -29,6 +69,8 @@ struct Partition {
     low_watermark: i64,
     high_watermark: i64,
     msgs: Vec<OwnedMessage>,
+    /// Records before this offset have been compacted.
+    cleaned_offset: i64,
 }
 
 impl Partition {
@@ -40,7 +82,40 @@ impl Partition {
             low_watermark: 0,
             high_watermark: 0,
             msgs: vec![],
+            cleaned_offset: 0,
+        }
+    }
+
+    /// Compacts the log if enough records have been appended since the last compaction.
+    fn maybe_compact(&mut self, compaction: Compaction) {
+        let dirty = self.msgs.len()
+            - self
+                .msgs
+                .partition_point(|m| m.offset() < self.cleaned_offset);
+        if dirty as f64 >= self.msgs.len() as f64 * compaction.min_cleanable_dirty_ratio {
+            self.compact();
+        }
+    }
+
+    /// Removes records that are superseded by a later record with the same key.
+    ///
+    /// Offsets of the remaining records are unchanged. Tombstones, records
+    /// with a null payload, are kept as the latest value of their keys.
+    fn compact(&mut self) {
+        let mut latest = HashMap::new();
+        for msg in &self.msgs {
+            // records in compacted topics always have keys
+            latest.insert(msg.key().unwrap(), msg.offset());
         }
+        let keep: HashSet<i64> = latest.into_values().collect();
+        let before = self.msgs.len();
+        self.msgs.retain(|msg| keep.contains(&msg.offset()));
+        trace!(
+            partition = self.id,
+            removed = before - self.msgs.len(),
+            "compact"
+        );
+        self.cleaned_offset = self.log_end_offset;
     }
 
     /// Looks up the offset by timestamp.
@@ -48,66 +123,177 @@ impl Partition {
     /// The returned offset is the earliest offset whose timestamp
     /// is greater than or equal to the given timestamp.
     fn offset_for_time(&self, timestamp: i64) -> Option<i64> {
-        let idx = self
-            .msgs
-            .partition_point(|msg| match msg.timestamp() {
-                Timestamp::NotAvailable => 0,
-                Timestamp::CreateTime(t) => t,
-                Timestamp::LogAppendTime(t) => t,
-            } < timestamp);
-        self.msgs.get(idx).map(|msg| msg.offset())
+        // timestamps are not necessarily monotonic since they can be set by producers
+        self.msgs
+            .iter()
+            .find(|msg| matches!(msg.timestamp().to_millis(), Some(t) if t >= timestamp))
+            .map(|msg| msg.offset())
     }
 }
 
 impl Broker {
     /// Creates a new topic.
-    pub fn create_topic(&mut self, name: String, partitions: usize) -> Result<()> {
-        debug!(?name, partitions, "create_topic");
-        self.topics
-            .insert(name.clone(), Topic::new(name, partitions));
+    pub fn create_topic(
+        &mut self,
+        name: String,
+        partitions: usize,
+        config: Vec<(String, String)>,
+    ) -> Result<()> {
+        debug!(?name, partitions, ?config, "create_topic");
+        let mut topic = Topic::new(name.clone(), partitions);
+        let mut compact = false;
+        let mut min_cleanable_dirty_ratio = 0.5;
+        for (key, value) in &config {
+            match key.as_str() {
+                "cleanup.policy" => match value.as_str() {
+                    "delete" => {}
+                    "compact" => compact = true,
+                    _ => return Err(Error::AdminOp(ErrorCode::InvalidConfig)),
+                },
+                "min.cleanable.dirty.ratio" => {
+                    min_cleanable_dirty_ratio = match value.parse() {
+                        Ok(ratio) if (0.0..=1.0).contains(&ratio) => ratio,
+                        _ => return Err(Error::AdminOp(ErrorCode::InvalidConfig)),
+                    }
+                }
+                "max.message.bytes" => {
+                    topic.max_message_bytes = value
+                        .parse()
+                        .map_err(|_| Error::AdminOp(ErrorCode::InvalidConfig))?;
+                }
+                _ => warn!(key, value, "unsupported topic config"),
+            }
+        }
+        topic.compaction = compact.then_some(Compaction {
+            min_cleanable_dirty_ratio,
+        });
+        self.topics.insert(name, topic);
         Ok(())
     }
 
+    /// Allocates a new producer ID for an idempotent producer.
+    pub fn init_producer_id(&mut self) -> Result<i64> {
+        self.last_producer_id += 1;
+        debug!(id = self.last_producer_id, "init_producer_id");
+        Ok(self.last_producer_id)
+    }
+
     /// Produces records.
-    pub fn produce(&mut self, records: Vec<OwnedRecord>) -> Result<()> {
+    ///
+    /// Each record is appended independently, and the result of each record
+    /// is returned in order. On success, the appended message is returned.
+    ///
+    /// If `producer_id` is set, records with duplicate sequence numbers are dropped.
+    ///
+    /// When attempts of the same record race, e.g. a retry and the original
+    /// request whose response was lost, the one processed first by the broker
+    /// is appended. Requests are processed one at a time in the order their
+    /// handlers take the broker, which is decided by the simulated network and
+    /// scheduler, so the winner is the same for a given seed. The other attempts
+    /// return the partition and offset of the winner, as long as it is one of
+    /// the last few records of the producer.
+    pub fn produce(
+        &mut self,
+        records: Vec<OwnedRecord>,
+        producer_id: Option<i64>,
+    ) -> Vec<Result<OwnedMessage>> {
         debug!("produce {} records", records.len());
-        for record in records {
-            self.produce_one(record)?;
-        }
-        Ok(())
+        records
+            .into_iter()
+            .map(|record| self.produce_one(record, producer_id))
+            .collect()
     }
 
     /// Produces a record.
-    fn produce_one(&mut self, record: OwnedRecord) -> Result<()> {
+    ///
+    /// The sequence number of an idempotent producer is consumed even if the
+    /// record is rejected, so that the following records are not out of order.
+    fn produce_one(
+        &mut self,
+        record: OwnedRecord,
+        producer_id: Option<i64>,
+    ) -> Result<OwnedMessage> {
+        let producer = match (producer_id, record.sequence) {
+            (Some(id), Some(sequence)) => {
+                let key = (id, record.topic.clone(), record.partition);
+                let state = self.sequences.entry(key.clone()).or_default();
+                if sequence < state.next {
+                    debug!(producer_id = id, sequence, "drop duplicate record");
+                    let result = state
+                        .recent
+                        .iter()
+                        .find(|(seq, _)| *seq == sequence)
+                        .map_or(Ok((-1, -1)), |&(_, result)| result);
+                    return match result {
+                        Ok((partition, offset)) => Ok(record.into_message(partition, offset)),
+                        Err(code) => Err(Error::MessageProduction(code)),
+                    };
+                } else if sequence > state.next {
+                    return Err(Error::MessageProduction(
+                        ErrorCode::OutOfOrderSequenceNumber,
+                    ));
+                }
+                state.next += 1;
+                Some((key, sequence))
+            }
+            _ => None,
+        };
+
+        let result = self.append(record);
+        if let Some((key, sequence)) = producer {
+            let state = self.sequences.get_mut(&key).unwrap();
+            if state.recent.len() == DEDUP_WINDOW {
+                state.recent.pop_front();
+            }
+            let result = result
+                .as_ref()
+                .map(|msg| (msg.partition(), msg.offset()))
+                .map_err(|&code| code);
+            state.recent.push_back((sequence, result));
+        }
+        result.map_err(Error::MessageProduction)
+    }
+
+    /// Validates a record and appends it to the log.
+    fn append(&mut self, record: OwnedRecord) -> std::result::Result<OwnedMessage, ErrorCode> {
         let topic = self
             .topics
             .get_mut(&record.topic)
-            .ok_or(Error::MessageProduction(ErrorCode::UnknownTopic))?;
-
-        let partition_idx = topic.last_partition;
-        topic.last_partition += 1;
-        if topic.last_partition >= topic.partitions.len() {
-            topic.last_partition = 0;
+            .ok_or(ErrorCode::UnknownTopic)?;
+        if matches!(record.partition, Some(p) if p < 0 || p as usize >= topic.partitions.len()) {
+            return Err(ErrorCode::UnknownPartition);
+        }
+        if record.size() > topic.max_message_bytes {
+            return Err(ErrorCode::MessageSizeTooLarge);
         }
 
+        let partition_idx = match (record.partition, &topic.compaction, &record.key) {
+            // records in compacted topics must have keys
+            (_, Some(_), None) => return Err(ErrorCode::InvalidRecord),
+            (Some(p), _, _) => p as usize,
+            // records of the same key must be in the same partition to be compacted
+            (None, Some(_), Some(key)) => (fnv1a(key) % topic.partitions.len() as u64) as usize,
+            (None, None, _) => {
+                let idx = topic.last_partition;
+                topic.last_partition += 1;
+                if topic.last_partition >= topic.partitions.len() {
+                    topic.last_partition = 0;
+                }
+                idx
+            }
+        };
+
         let partition = &mut topic.partitions[partition_idx];
 
-        let msg = OwnedMessage::new(
-            record.payload,
-            record.key,
-            record.topic,
-            record
-                .timestamp
-                .map_or(Timestamp::NotAvailable, Timestamp::CreateTime),
-            partition_idx as _,
-            partition.log_end_offset,
-            record.headers,
-        );
+        let msg = record.into_message(partition_idx as _, partition.log_end_offset);
         trace!(?msg, "produce");
-        partition.msgs.push(msg);
+        partition.msgs.push(msg.clone());
         partition.log_end_offset += 1;
         partition.high_watermark = partition.log_end_offset;
-        Ok(())
+        if let Some(compaction) = topic.compaction {
+            partition.maybe_compact(compaction);
+        }
+        Ok(msg)
     }
 
     /// Fetch records.
@@ -141,7 +327,8 @@ impl Broker {
                 if msg.offset() >= partition.high_watermark {
                     continue;
                 }
-                if total_bytes + size > opts.fetch_max_bytes as usize
+                if rets.len() >= opts.max_records
+                    || total_bytes + size > opts.fetch_max_bytes as usize
                     || total_bytes_in_partition + size > opts.max_partition_fetch_bytes as usize
                 {
                     return Ok(rets);
@@ -189,9 +376,10 @@ impl Broker {
                 Offset::Offset(ts) => ts,
                 _ => return Err(Error::OffsetFetch(ErrorCode::InvalidTimestamp)),
             };
+            // if there is no such message, the end offset is returned
             let offset = partition
                 .offset_for_time(timestamp)
-                .map_or(Offset::Invalid, Offset::Offset);
+                .map_or(Offset::End, Offset::Offset);
             ret.add_partition_offset(&e.topic, e.partition, offset)
                 .unwrap();
         }
@@ -219,6 +407,8 @@ impl Topic {
             name,
             partitions: (0..partitions).map(|id| Partition::new(id as _)).collect(),
             last_partition: 0,
+            compaction: None,
+            max_message_bytes: 1048588,
         }
     }
 
@@ -235,7 +425,14 @@ impl Topic {
     }
 }
 
-#[derive(Debug)]
+/// The 64-bit FNV-1a hash, used to assign keys to partitions deterministically.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Debug, Clone)]
 pub struct OwnedRecord {
     /// Required destination topic.
     pub topic: String,
@@ -252,17 +449,44 @@ pub struct OwnedRecord {
     pub timestamp: Option<i64>,
     /// Optional message headers.
     pub headers: Option<OwnedHeaders>,
+    /// Sequence number assigned by an idempotent producer.
+    pub sequence: Option<i32>,
 }
 
-impl<'a, K: ToBytes + ?Sized, P: ToBytes + ?Sized> BaseRecord<'a, K, P> {
+impl OwnedRecord {
+    /// Returns the size of the key and payload in bytes.
+    pub(crate) fn size(&self) -> usize {
+        self.key.as_ref().map_or(0, |k| k.len()) + self.payload.as_ref().map_or(0, |p| p.len())
+    }
+
+    /// Converts the record to a message at the given position.
+    pub(crate) fn into_message(self, partition: i32, offset: i64) -> OwnedMessage {
+        OwnedMessage::new(
+            self.payload,
+            self.key,
+            self.topic,
+            match self.timestamp {
+                Some(t) => Timestamp::CreateTime(t),
+                None => Timestamp::LogAppendTime(current_time_millis()),
+            },
+            partition,
+            offset,
+            self.headers,
+        )
+    }
+}
+
+impl<'a, K: ToBytes + ?Sized, P: ToBytes + ?Sized, D> BaseRecord<'a, K, P, D> {
     pub(crate) fn to_owned(&self) -> OwnedRecord {
         OwnedRecord {
             topic: self.topic.to_owned(),
             partition: self.partition,
             payload: self.payload.map(|p| p.to_bytes().to_owned()),
             key: self.key.map(|k| k.to_bytes().to_owned()),
-            timestamp: self.timestamp,
+            // like librdkafka, use the current time if the timestamp is not set
+            timestamp: Some(self.timestamp.unwrap_or_else(current_time_millis)),
             headers: self.headers.clone(),
+            sequence: None,
         }
     }
 }
@@ -278,6 +502,11 @@ pub struct FetchOptions {
     ///
     /// Default: 52428800 (50 mebibytes)
     pub fetch_max_bytes: u32,
+
+    /// The maximum number of records returned in a single fetch request.
+    ///
+    /// Default: 500
+    pub max_records: usize,
 }
 
 impl Default for FetchOptions {
@@ -285,6 +514,7 @@ impl Default for FetchOptions {
         Self {
             max_partition_fetch_bytes: 1048576,
             fetch_max_bytes: 52428800,
+            max_records: 500,
         }
     }
 }
@@ -188,6 +188,7 @@ where
                 opts: FetchOptions {
                     fetch_max_bytes: self.config.fetch_max_bytes,
                     max_partition_fetch_bytes: self.config.max_partition_fetch_bytes,
+                    max_records: self.config.max_poll_records,
                 },
             };
             let (tx, mut rx) = self.ep.connect1(self.addr).await?;
@@ -339,6 +340,14 @@ struct ConsumerConfig {
     )]
     max_partition_fetch_bytes: u32,
 
+    /// The maximum number of records returned from the broker in a single fetch.
+    #[serde(
+        rename = "max.poll.records",
+        deserialize_with = "super::from_str",
+        default = "default_max_poll_records"
+    )]
+    max_poll_records: usize,
+
     /// What to do when there is no initial offset in Kafka or if the current offset does not exist
     /// any more on the server (e.g. because that data has been deleted)
     #[serde(rename = "auto.offset.reset", default = "default_auto_offset_reset")]
@@ -373,6 +382,9 @@ const fn default_fetch_max_bytes() -> u32 {
 const fn default_max_partition_fetch_bytes() -> u32 {
     1048576
 }
+const fn default_max_poll_records() -> usize {
+    500
+}
 fn default_auto_offset_reset() -> AutoOffsetResetStrategy {
     AutoOffsetResetStrategy::Latest
 }
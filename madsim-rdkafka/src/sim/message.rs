@@ -1,3 +1,4 @@
+use crate::error::KafkaError;
 use std::marker::PhantomData;
 
 /// A cheap conversion from a byte slice to typed data.
@@ -128,6 +129,23 @@ pub enum Timestamp {
     LogAppendTime(i64),
 }
 
+impl Timestamp {
+    /// Convert the timestamp to milliseconds since epoch.
+    pub fn to_millis(self) -> Option<i64> {
+        match self {
+            Timestamp::NotAvailable | Timestamp::CreateTime(-1) | Timestamp::LogAppendTime(-1) => {
+                None
+            }
+            Timestamp::CreateTime(t) | Timestamp::LogAppendTime(t) => Some(t),
+        }
+    }
+
+    /// Creates a new `Timestamp::CreateTime` representing the current time.
+    pub fn now() -> Timestamp {
+        Timestamp::CreateTime(crate::util::current_time_millis())
+    }
+}
+
 /// A Kafka message that owns its backing data.
 #[derive(Debug, Clone)]
 pub struct OwnedMessage {
@@ -217,6 +235,14 @@ impl Message for OwnedMessage {
     }
 }
 
+/// The result of a message production.
+///
+/// If message production is successful `DeliveryResult` will contain the sent
+/// message, which can be used to find which partition and offset the message
+/// was sent to. If message production is not successful, the `DeliveryResult`
+/// will contain an error and the message that failed to be sent.
+pub type DeliveryResult<'a> = Result<BorrowedMessage<'a>, (KafkaError, BorrowedMessage<'a>)>;
+
 /// A zero-copy Kafka message.
 pub struct BorrowedMessage<'a> {
     msg: OwnedMessage,
@@ -1,16 +1,18 @@
-use std::{net::SocketAddr, ops::Deref, sync::Arc, time::Duration};
+use std::{collections::HashMap, net::SocketAddr, ops::Deref, sync::Arc, time::Duration};
 
 use madsim::net::Endpoint;
 use serde::Deserialize;
 use spin::Mutex;
 use tracing::*;
 
+pub use crate::message::DeliveryResult;
+
 use crate::{
     broker::OwnedRecord,
     client::ClientContext,
     config::{FromClientConfig, FromClientConfigAndContext},
     error::{KafkaError, KafkaResult, RDKafkaError, RDKafkaErrorCode},
-    message::{OwnedHeaders, ToBytes},
+    message::{OwnedHeaders, OwnedMessage, ToBytes},
     sim_broker::Request,
     util::Timeout,
     ClientConfig,
@@ -18,7 +20,7 @@ use crate::{
 
 /// A record for the [`BaseProducer`] and [`ThreadedProducer`].
 #[derive(Debug)]
-pub struct BaseRecord<'a, K: ToBytes + ?Sized = (), P: ToBytes + ?Sized = ()> {
+pub struct BaseRecord<'a, K: ToBytes + ?Sized = (), P: ToBytes + ?Sized = (), D = ()> {
     /// Required destination topic.
     pub topic: &'a str,
     /// Optional destination partition.
@@ -35,24 +37,37 @@ pub struct BaseRecord<'a, K: ToBytes + ?Sized = (), P: ToBytes + ?Sized = ()> {
     /// Optional message headers.
     pub headers: Option<OwnedHeaders>,
     /// Required delivery opaque (defaults to `()` if not required).
-    pub delivery_opaque: (),
+    pub delivery_opaque: D,
 }
 
-impl<'a, K: ToBytes + ?Sized, P: ToBytes + ?Sized> BaseRecord<'a, K, P> {
+impl<'a, K: ToBytes + ?Sized, P: ToBytes + ?Sized, D> BaseRecord<'a, K, P, D> {
+    /// Creates a new record with the specified topic name and delivery opaque.
+    pub fn with_opaque_to(topic: &'a str, delivery_opaque: D) -> BaseRecord<'a, K, P, D> {
+        BaseRecord {
+            topic,
+            partition: None,
+            payload: None,
+            key: None,
+            timestamp: None,
+            headers: None,
+            delivery_opaque,
+        }
+    }
+
     /// Sets the destination partition of the record.
-    pub fn partition(mut self, partition: i32) -> BaseRecord<'a, K, P> {
+    pub fn partition(mut self, partition: i32) -> BaseRecord<'a, K, P, D> {
         self.partition = Some(partition);
         self
     }
 
     /// Sets the payload of the record.
-    pub fn payload(mut self, payload: &'a P) -> BaseRecord<'a, K, P> {
+    pub fn payload(mut self, payload: &'a P) -> BaseRecord<'a, K, P, D> {
         self.payload = Some(payload);
         self
     }
 
     /// Sets the key of the record.
-    pub fn key(mut self, key: &'a K) -> BaseRecord<'a, K, P> {
+    pub fn key(mut self, key: &'a K) -> BaseRecord<'a, K, P, D> {
         self.key = Some(key);
         self
     }
@@ -61,28 +76,22 @@ impl<'a, K: ToBytes + ?Sized, P: ToBytes + ?Sized> BaseRecord<'a, K, P> {
     ///
     /// Note that Kafka represents timestamps as the number of milliseconds
     /// since the Unix epoch.
-    pub fn timestamp(mut self, timestamp: i64) -> BaseRecord<'a, K, P> {
+    pub fn timestamp(mut self, timestamp: i64) -> BaseRecord<'a, K, P, D> {
         self.timestamp = Some(timestamp);
         self
     }
 
     /// Sets the headers of the record.
-    pub fn headers(mut self, headers: OwnedHeaders) -> BaseRecord<'a, K, P> {
+    pub fn headers(mut self, headers: OwnedHeaders) -> BaseRecord<'a, K, P, D> {
         self.headers = Some(headers);
         self
     }
+}
 
+impl<'a, K: ToBytes + ?Sized, P: ToBytes + ?Sized> BaseRecord<'a, K, P> {
     /// Creates a new record with the specified topic name.
     pub fn to(topic: &'a str) -> BaseRecord<'a, K, P> {
-        BaseRecord {
-            topic,
-            partition: None,
-            payload: None,
-            key: None,
-            timestamp: None,
-            headers: None,
-            delivery_opaque: (),
-        }
+        BaseRecord::with_opaque_to(topic, ())
     }
 }
 
@@ -94,7 +103,20 @@ where
 }
 
 /// Producer-specific context.
-pub trait ProducerContext: ClientContext {}
+///
+/// It can be used to specify the `delivery` callback that will be called when
+/// the acknowledgement for a delivered message is received.
+pub trait ProducerContext: ClientContext {
+    /// A `DeliveryOpaque` is a user-defined structure that will be passed to
+    /// the producer when producing a message, and returned to the `delivery`
+    /// method once the message has been delivered, or failed to.
+    type DeliveryOpaque: Send + Sync;
+
+    /// This method will be called once the message has been delivered (or
+    /// failed to). The `DeliveryOpaque` will be the one provided by the user
+    /// when calling send.
+    fn delivery(&self, delivery_result: &DeliveryResult<'_>, delivery_opaque: Self::DeliveryOpaque);
+}
 
 /// An inert producer context that can be used when customizations are not
 /// required.
@@ -102,7 +124,11 @@ pub trait ProducerContext: ClientContext {}
 pub struct DefaultProducerContext;
 
 impl ClientContext for DefaultProducerContext {}
-impl ProducerContext for DefaultProducerContext {}
+impl ProducerContext for DefaultProducerContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, _: &DeliveryResult<'_>, _: Self::DeliveryOpaque) {}
+}
 
 #[async_trait::async_trait]
 impl FromClientConfig for BaseProducer {
@@ -116,7 +142,7 @@ impl<C> FromClientConfigAndContext<C> for BaseProducer<C>
 where
     C: ProducerContext,
 {
-    async fn from_config_and_context(config: &ClientConfig, _context: C) -> KafkaResult<Self> {
+    async fn from_config_and_context(config: &ClientConfig, context: C) -> KafkaResult<Self> {
         let config_json = serde_json::to_string(&config.conf_map)
             .map_err(|e| KafkaError::ClientCreation(e.to_string()))?;
         let config: ProducerConfig = serde_json::from_str(&config_json)
@@ -126,13 +152,15 @@ where
             .parse::<SocketAddr>()
             .map_err(|e| KafkaError::ClientCreation(e.to_string()))?;
         let p = BaseProducer {
-            _context,
+            context,
             config,
             ep: Endpoint::bind("0.0.0.0:0")
                 .await
                 .map_err(|e| KafkaError::ClientCreation(e.to_string()))?,
             addr,
             inner: Mutex::new(Inner::default()),
+            producer_id: Mutex::new(None),
+            sequences: Mutex::new(HashMap::new()),
         };
         Ok(p)
     }
@@ -143,25 +171,35 @@ pub struct BaseProducer<C = DefaultProducerContext>
 where
     C: ProducerContext,
 {
-    _context: C,
+    context: C,
     config: ProducerConfig,
     ep: Endpoint,
     addr: SocketAddr,
-    inner: Mutex<Inner>,
+    inner: Mutex<Inner<C::DeliveryOpaque>>,
+    /// The producer ID allocated by broker if idempotence is enabled.
+    producer_id: Mutex<Option<i64>>,
+    /// The next sequence number of each topic and partition if idempotence is enabled.
+    ///
+    /// Records without a partition are partitioned by the broker in the simulation,
+    /// so they are sequenced under the `None` partition of their topic.
+    sequences: Mutex<HashMap<(String, Option<i32>), i32>>,
 }
 
-#[derive(Debug, Default)]
-enum Inner {
+/// Records with their delivery opaques.
+type Buffer<D> = Vec<(OwnedRecord, D)>;
+
+#[derive(Default)]
+enum Inner<D> {
     #[default]
     Init,
     NonTxn {
-        buffer: Vec<OwnedRecord>,
+        buffer: Buffer<D>,
     },
     Txn {
         /// Indicate whether the producer is in a transaction.
         in_txn: bool,
         // We simulate transaction by buffering all records and sending them in a batch.
-        buffer: Vec<OwnedRecord>,
+        buffer: Buffer<D>,
     },
 }
 
@@ -170,10 +208,11 @@ where
     C: ProducerContext,
 {
     /// Sends a message to Kafka.
+    #[allow(clippy::type_complexity)]
     pub fn send<'a, K, P>(
         &self,
-        record: BaseRecord<'a, K, P>,
-    ) -> Result<(), (KafkaError, BaseRecord<'a, K, P>)>
+        record: BaseRecord<'a, K, P, C::DeliveryOpaque>,
+    ) -> Result<(), (KafkaError, BaseRecord<'a, K, P, C::DeliveryOpaque>)>
     where
         K: ToBytes + ?Sized,
         P: ToBytes + ?Sized,
@@ -191,20 +230,79 @@ where
                         record,
                     ));
                 }
-                buffer.push(record.to_owned());
+                buffer.push((self.to_owned_record(&record), record.delivery_opaque));
             }
             Inner::Txn { in_txn, buffer } => {
                 assert!(
                     *in_txn,
                     "messages should only be sent when a transaction is active"
                 );
-                buffer.push(record.to_owned());
+                buffer.push((self.to_owned_record(&record), record.delivery_opaque));
             }
             Inner::Init => unreachable!(),
         }
         Ok(())
     }
 
+    /// Converts the record to an owned one, assigning a sequence number if idempotence is enabled.
+    fn to_owned_record<K, P>(&self, record: &BaseRecord<'_, K, P, C::DeliveryOpaque>) -> OwnedRecord
+    where
+        K: ToBytes + ?Sized,
+        P: ToBytes + ?Sized,
+    {
+        let mut owned = record.to_owned();
+        if self.config.enable_idempotence {
+            let mut sequences = self.sequences.lock();
+            let next = sequences
+                .entry((owned.topic.clone(), owned.partition))
+                .or_insert(0);
+            owned.sequence = Some(*next);
+            *next += 1;
+        }
+        owned
+    }
+
+    /// Returns the producer ID if idempotence is enabled.
+    ///
+    /// The ID is allocated by broker on the first call.
+    async fn producer_id(&self) -> KafkaResult<Option<i64>> {
+        if !self.config.enable_idempotence {
+            return Ok(None);
+        }
+        if let Some(id) = *self.producer_id.lock() {
+            return Ok(Some(id));
+        }
+        let req = Request::InitProducerId;
+        let (tx, mut rx) = self.ep.connect1(self.addr).await?;
+        tx.send(Box::new(req)).await?;
+        let id = (*rx.recv().await?.downcast::<KafkaResult<i64>>().unwrap())?;
+        debug!(id, "init producer id");
+        Ok(Some(*self.producer_id.lock().get_or_insert(id)))
+    }
+
+    /// Resets the idempotent producer state after an out-of-order sequence error
+    /// or an aborted transaction.
+    ///
+    /// Like the epoch bump of librdkafka, a new producer ID is allocated on the
+    /// next produce request and the sequence numbers of the queued records are
+    /// reassigned from zero.
+    fn reset_sequences(&self) {
+        debug!("reset idempotent producer state");
+        let mut inner = self.inner.lock();
+        let mut sequences = self.sequences.lock();
+        *self.producer_id.lock() = None;
+        sequences.clear();
+        if let Inner::NonTxn { buffer } | Inner::Txn { buffer, .. } = &mut *inner {
+            for (record, _) in buffer {
+                let next = sequences
+                    .entry((record.topic.clone(), record.partition))
+                    .or_insert(0);
+                record.sequence = Some(*next);
+                *next += 1;
+            }
+        }
+    }
+
     /// Polls the producer, returning the number of events served.
     pub async fn poll<T: Into<Timeout>>(&self, timeout: T) -> i32 {
         self.flush(timeout).await;
@@ -212,15 +310,64 @@ where
     }
 
     async fn flush_internal(&self) -> KafkaResult<()> {
-        let records = match &mut *self.inner.lock() {
+        let buffer = match &mut *self.inner.lock() {
             Inner::NonTxn { buffer } if !buffer.is_empty() => std::mem::take(buffer),
             _ => return Ok(()),
         };
-        debug!("flushing {} records", records.len());
-        let req = Request::Produce { records };
+        debug!("flushing {} records", buffer.len());
+        // put records back if no response is received, so that they will be retried
+        let mut guard = RetryGuard {
+            inner: &self.inner,
+            buffer: Some(buffer),
+        };
+        let results = self.produce(guard.buffer.as_ref().unwrap()).await?;
+        self.deliver(guard.buffer.take().unwrap(), results);
+        Ok(())
+    }
+
+    /// Sends records to the broker and returns the result of each record.
+    async fn produce(
+        &self,
+        buffer: &Buffer<C::DeliveryOpaque>,
+    ) -> KafkaResult<Vec<KafkaResult<OwnedMessage>>> {
+        let producer_id = self.producer_id().await?;
+        let req = Request::Produce {
+            records: buffer.iter().map(|(record, _)| record.clone()).collect(),
+            producer_id,
+        };
         let (tx, mut rx) = self.ep.connect1(self.addr).await?;
         tx.send(Box::new(req)).await?;
-        *rx.recv().await?.downcast::<KafkaResult<()>>().unwrap()
+        let results = *rx
+            .recv()
+            .await?
+            .downcast::<Vec<KafkaResult<OwnedMessage>>>()
+            .unwrap();
+        Ok(results)
+    }
+
+    /// Reports the delivery results to the context.
+    fn deliver(&self, buffer: Buffer<C::DeliveryOpaque>, results: Vec<KafkaResult<OwnedMessage>>) {
+        if results.iter().any(|r| {
+            matches!(
+                r,
+                Err(KafkaError::MessageProduction(
+                    RDKafkaErrorCode::OutOfOrderSequenceNumber
+                ))
+            )
+        }) {
+            self.reset_sequences();
+        }
+        for ((record, opaque), result) in buffer.into_iter().zip(results) {
+            let result = match result {
+                Ok(msg) => Ok(msg.borrow()),
+                Err(e) => {
+                    debug!(topic = record.topic, "failed to deliver: {e}");
+                    let partition = record.partition.unwrap_or(-1);
+                    Err((e, record.into_message(partition, -1001).borrow()))
+                }
+            };
+            self.context.delivery(&result, opaque);
+        }
     }
 
     /// Flushes any pending messages.
@@ -272,14 +419,20 @@ where
     /// Commits the current transaction.
     pub async fn commit_transaction<T: Into<Timeout>>(&self, _timeout: T) -> KafkaResult<()> {
         debug!("commit transaction");
-        let records = match &mut *self.inner.lock() {
+        let buffer = match &mut *self.inner.lock() {
             Inner::Txn { in_txn, buffer } if *in_txn => std::mem::take(buffer),
             _ => return Err(invalid_transaction_state("no opened transaction")),
         };
-        let req = Request::Produce { records };
-        let (tx, mut rx) = self.ep.connect1(self.addr).await?;
-        tx.send(Box::new(req)).await?;
-        let res = *rx.recv().await?.downcast::<KafkaResult<()>>().unwrap();
+        let results = self.produce(&buffer).await?;
+        // the broker only returns production errors
+        let res = match results.iter().find_map(|r| match r {
+            Err(KafkaError::MessageProduction(code)) => Some(*code),
+            _ => None,
+        }) {
+            Some(code) => Err(KafkaError::MessageProduction(code)),
+            None => Ok(()),
+        };
+        self.deliver(buffer, results);
         // TODO: simulate transaction aborted
         match &mut *self.inner.lock() {
             Inner::Txn { in_txn, .. } if *in_txn => *in_txn = false,
@@ -298,10 +451,31 @@ where
             }
             _ => return Err(invalid_transaction_state("no opened transaction")),
         }
+        // the aborted records never reach the broker, so their sequence numbers are skipped
+        if self.config.enable_idempotence {
+            self.reset_sequences();
+        }
         Ok(())
     }
 }
 
+/// Puts the records back to the front of buffer on drop.
+struct RetryGuard<'a, D> {
+    inner: &'a Mutex<Inner<D>>,
+    buffer: Option<Buffer<D>>,
+}
+
+impl<D> Drop for RetryGuard<'_, D> {
+    fn drop(&mut self) {
+        if let (Some(records), Inner::NonTxn { buffer }) =
+            (self.buffer.take(), &mut *self.inner.lock())
+        {
+            debug!("{} records will be retried", records.len());
+            buffer.splice(0..0, records);
+        }
+    }
+}
+
 fn invalid_transaction_state(msg: &str) -> KafkaError {
     KafkaError::Transaction(RDKafkaError::new(
         RDKafkaErrorCode::InvalidTransactionalState,
@@ -368,6 +542,15 @@ struct ProducerConfig {
     #[serde(rename = "transactional.id")]
     transactional_id: Option<String>,
 
+    /// When set to `true`, the producer will ensure that messages are successfully
+    /// produced exactly once and in the original produce order.
+    #[serde(
+        rename = "enable.idempotence",
+        deserialize_with = "super::from_str",
+        default
+    )]
+    enable_idempotence: bool,
+
     /// Local message timeout.
     #[serde(
         rename = "message.timeout.ms",
@@ -1,51 +1,243 @@
 use crate::{
     broker::{Broker, FetchOptions, OwnedRecord},
+    error::{KafkaError, KafkaResult, RDKafkaErrorCode},
     metadata::Metadata,
-    TopicPartitionList,
+    Message, TopicPartitionList,
 };
-use madsim::net::{Endpoint, Payload};
+use futures_util::{future::BoxFuture, select_biased, FutureExt};
+use madsim::net::{Endpoint, Payload, Receiver, Sender};
 use spin::Mutex;
-use std::{io::Result, net::SocketAddr, sync::Arc};
+use std::{collections::HashMap, io::Result, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+use tracing::*;
 
+/// A simulated Kafka broker.
 #[derive(Default)]
-pub struct SimBroker {}
+pub struct SimBroker {
+    accept_latency: Duration,
+    process_latency: Duration,
+    accept_queue: Option<usize>,
+    /// The backlog beyond which fetches are cold, and their extra latency.
+    cold_fetch: Option<(i64, Duration)>,
+    /// The broker version. All requests are supported if not set.
+    version: Option<Version>,
+    /// The maximum number of in-flight requests per connection.
+    max_in_flight: Option<usize>,
+}
+
+/// A version number like `0.10.2`.
+type Version = Vec<u32>;
+
+/// The queue of requests of a connection.
+type RequestQueue = async_channel::Sender<BoxFuture<'static, Result<()>>>;
 
 impl SimBroker {
+    /// Set the time to accept a connection.
+    ///
+    /// Connections are accepted one at a time, so they wait in the accept queue
+    /// when arriving faster than this.
+    pub fn accept_latency(mut self, latency: Duration) -> Self {
+        self.accept_latency = latency;
+        self
+    }
+
+    /// Set the time to process a request after it is accepted.
+    pub fn process_latency(mut self, latency: Duration) -> Self {
+        self.process_latency = latency;
+        self
+    }
+
+    /// Set the capacity of the accept queue.
+    ///
+    /// Connections arriving when the queue is full are rejected.
+    /// The queue is unbounded by default.
+    pub fn accept_queue(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "accept queue capacity must be positive");
+        self.accept_queue = Some(capacity);
+        self
+    }
+
+    /// Set the extra latency of fetching cold data.
+    ///
+    /// A fetch returning records more than `backlog` records behind the end of
+    /// their partition reads them from disk instead of the page cache, and
+    /// takes `latency` longer. Consumers keeping up with the producers are not
+    /// affected.
+    pub fn cold_fetch(mut self, backlog: i64, latency: Duration) -> Self {
+        self.cold_fetch = Some((backlog, latency));
+        self
+    }
+
+    /// Set the version of the broker, like `"0.10.2"`.
+    ///
+    /// Requests introduced after this version are rejected with
+    /// [`RDKafkaErrorCode::UnsupportedVersion`]:
+    ///
+    /// - creating topics and `offsets_for_times` since 0.10.1
+    /// - the idempotent producer since 0.11.0
+    ///
+    /// This can be used to simulate a cluster in the middle of an upgrade,
+    /// where brokers of different versions serve the same clients.
+    /// All requests are supported by default.
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = Some(parse_version(version));
+        self
+    }
+
+    /// Set the maximum number of in-flight requests per connection.
+    ///
+    /// Requests of a client beyond this wait until an earlier one completes,
+    /// and are processed in the order they arrive. Requests from the same
+    /// address are on the same connection. Unlimited by default.
+    pub fn max_in_flight(mut self, max: usize) -> Self {
+        assert!(max > 0, "max in-flight requests must be positive");
+        self.max_in_flight = Some(max);
+        self
+    }
+
     pub async fn serve(self, addr: SocketAddr) -> Result<()> {
         let ep = Endpoint::bind(addr).await?;
         let service = Arc::new(Mutex::new(Broker::default()));
-        loop {
-            let (tx, mut rx, _) = ep.accept1().await?;
-            let service = service.clone();
-            madsim::task::spawn(async move {
-                let request = *rx.recv().await?.downcast::<Request>().unwrap();
-                let response: Payload = match request {
-                    Request::CreateTopic { name, partitions } => {
-                        Box::new(service.lock().create_topic(name, partitions))
+        let (queue_tx, queue_rx) = match self.accept_queue {
+            Some(capacity) => async_channel::bounded(capacity),
+            None => async_channel::unbounded(),
+        };
+        let accept_latency = self.accept_latency;
+        let process_latency = self.process_latency;
+        let cold_fetch = self.cold_fetch;
+        let version = self.version;
+        let max_in_flight = self.max_in_flight;
+        // accept connections from the queue one at a time
+        madsim::task::spawn(async move {
+            let mut connections = HashMap::new();
+            while let Ok((tx, rx, peer)) = queue_rx.recv().await {
+                if !accept_latency.is_zero() {
+                    madsim::time::sleep(accept_latency).await;
+                }
+                let service = service.clone();
+                let request = Self::handle(
+                    service,
+                    tx,
+                    rx,
+                    process_latency,
+                    cold_fetch,
+                    version.clone(),
+                );
+                match max_in_flight {
+                    Some(max) => {
+                        // forget the connections whose queue has drained
+                        connections.retain(|_, queue: &mut RequestQueue| !queue.is_closed());
+                        let queue = connections
+                            .entry(peer)
+                            .or_insert_with(|| Self::in_flight_queue(max));
+                        let _ = queue.try_send(Box::pin(request));
                     }
-                    Request::Produce { records } => Box::new(service.lock().produce(records)),
-                    Request::Fetch { mut tpl, opts } => {
-                        let ret = service.lock().fetch(&mut tpl, opts);
-                        Box::new(ret.map(|msgs| (msgs, tpl)))
+                    None => {
+                        madsim::task::spawn(request);
                     }
-                    Request::FetchMetadata { topic } => Box::new(match topic {
-                        Some(topic) => service
-                            .lock()
-                            .metadata_of_topic(&topic)
-                            .map(|m| Metadata { topics: vec![m] }),
-                        None => service.lock().metadata(),
-                    }),
-                    Request::FetchWatermarks { topic, partition } => {
-                        Box::new(service.lock().fetch_watermarks(&topic, partition))
-                    }
-                    Request::OffsetsForTimes { tpl } => {
-                        Box::new(service.lock().offsets_for_times(&tpl))
+                }
+            }
+        });
+        loop {
+            let (tx, rx, peer) = ep.accept1().await?;
+            if queue_tx.try_send((tx, rx, peer)).is_err() {
+                debug!(?peer, "accept queue is full, connection rejected");
+            }
+        }
+    }
+
+    /// Spawns a task running the requests of a connection, at most `max` at a
+    /// time and in the order they are queued.
+    ///
+    /// The task exits, closing the queue, once all queued requests are done.
+    fn in_flight_queue(max: usize) -> RequestQueue {
+        let (tx, rx) = async_channel::unbounded::<BoxFuture<'static, Result<()>>>();
+        let semaphore = Arc::new(Semaphore::new(max));
+        madsim::task::spawn(async move {
+            loop {
+                let request = select_biased! {
+                    request = rx.recv().fuse() => request.unwrap(),
+                    _ = semaphore.acquire_many(max as u32).fuse() => {
+                        if rx.is_empty() {
+                            return;
+                        }
+                        continue;
                     }
                 };
+                let permit = semaphore.clone().acquire_owned().await.unwrap();
+                madsim::task::spawn(async move {
+                    let _ = request.await;
+                    drop(permit);
+                });
+            }
+        });
+        tx
+    }
+
+    async fn handle(
+        service: Arc<Mutex<Broker>>,
+        tx: Sender,
+        mut rx: Receiver,
+        process_latency: Duration,
+        cold_fetch: Option<(i64, Duration)>,
+        version: Option<Version>,
+    ) -> Result<()> {
+        let request = *rx.recv().await?.downcast::<Request>().unwrap();
+        if !process_latency.is_zero() {
+            madsim::time::sleep(process_latency).await;
+        }
+        if let Some(version) = &version {
+            if let Some(response) = request.check_version(version) {
+                debug!(?request, "unsupported by broker version");
                 tx.send(response).await?;
-                Ok(()) as Result<()>
-            });
+                return Ok(());
+            }
         }
+        let mut extra_latency = Duration::ZERO;
+        let response: Payload = match request {
+            Request::CreateTopic {
+                name,
+                partitions,
+                config,
+            } => Box::new(service.lock().create_topic(name, partitions, config)),
+            Request::InitProducerId => Box::new(service.lock().init_producer_id()),
+            Request::Produce {
+                records,
+                producer_id,
+            } => Box::new(service.lock().produce(records, producer_id)),
+            Request::Fetch { mut tpl, opts } => {
+                let broker = service.lock();
+                let ret = broker.fetch(&mut tpl, opts);
+                if let (Ok(msgs), Some((backlog, latency))) = (&ret, cold_fetch) {
+                    let cold = msgs.iter().any(|msg| {
+                        let (_, high) = broker
+                            .fetch_watermarks(msg.topic(), msg.partition())
+                            .unwrap();
+                        high - msg.offset() > backlog
+                    });
+                    if cold {
+                        extra_latency = latency;
+                    }
+                }
+                Box::new(ret.map(|msgs| (msgs, tpl)))
+            }
+            Request::FetchMetadata { topic } => Box::new(match topic {
+                Some(topic) => service
+                    .lock()
+                    .metadata_of_topic(&topic)
+                    .map(|m| Metadata { topics: vec![m] }),
+                None => service.lock().metadata(),
+            }),
+            Request::FetchWatermarks { topic, partition } => {
+                Box::new(service.lock().fetch_watermarks(&topic, partition))
+            }
+            Request::OffsetsForTimes { tpl } => Box::new(service.lock().offsets_for_times(&tpl)),
+        };
+        if !extra_latency.is_zero() {
+            madsim::time::sleep(extra_latency).await;
+        }
+        tx.send(response).await?;
+        Ok(())
     }
 }
 
@@ -55,9 +247,13 @@ pub enum Request {
     CreateTopic {
         name: String,
         partitions: usize,
+        config: Vec<(String, String)>,
     },
+    InitProducerId,
     Produce {
         records: Vec<OwnedRecord>,
+        /// The producer ID if idempotence is enabled.
+        producer_id: Option<i64>,
     },
     Fetch {
         tpl: TopicPartitionList,
@@ -74,3 +270,41 @@ pub enum Request {
         tpl: TopicPartitionList,
     },
 }
+
+impl Request {
+    /// Returns an error response if the request is not supported by a broker
+    /// of `version`.
+    fn check_version(&self, version: &[u32]) -> Option<Payload> {
+        let code = RDKafkaErrorCode::UnsupportedVersion;
+        let (since, response): (&[u32], Payload) = match self {
+            Request::CreateTopic { .. } => (
+                &[0, 10, 1],
+                Box::new(KafkaResult::<()>::Err(KafkaError::AdminOp(code))),
+            ),
+            Request::InitProducerId => (
+                &[0, 11, 0],
+                Box::new(KafkaResult::<i64>::Err(KafkaError::MessageProduction(code))),
+            ),
+            Request::OffsetsForTimes { .. } => (
+                &[0, 10, 1],
+                Box::new(KafkaResult::<TopicPartitionList>::Err(
+                    KafkaError::OffsetFetch(code),
+                )),
+            ),
+            _ => return None,
+        };
+        if version < since {
+            Some(response)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parses a version number like `0.10.2`.
+fn parse_version(version: &str) -> Version {
+    version
+        .split('.')
+        .map(|n| n.parse().expect("invalid version"))
+        .collect()
+}
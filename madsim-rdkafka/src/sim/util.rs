@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Specifies a timeout for a Kafka operation.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
@@ -33,3 +33,15 @@ impl From<Option<Duration>> for Timeout {
         }
     }
 }
+
+/// Converts the given time to the number of milliseconds since the Unix epoch.
+pub fn millis_to_epoch(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_secs(0))
+        .as_millis() as i64
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+pub fn current_time_millis() -> i64 {
+    millis_to_epoch(SystemTime::now())
+}
@@ -1,12 +1,18 @@
 #![cfg(madsim)]
 
 use futures_util::StreamExt;
-use madsim::runtime::Handle;
+use madsim::{
+    net::NetSim,
+    runtime::{Handle, NodeHandle, Runtime},
+    time::TimeHandle,
+};
 use madsim_rdkafka::{
     admin::*,
     consumer::{BaseConsumer, StreamConsumer},
-    producer::{BaseProducer, BaseRecord},
-    ClientConfig, Message, SimBroker, TopicPartitionList,
+    error::KafkaError,
+    error::RDKafkaErrorCode,
+    producer::{BaseProducer, BaseRecord, DefaultProducerContext, DeliveryResult, ProducerContext},
+    ClientConfig, ClientContext, Message, Offset, SimBroker, Timestamp, TopicPartitionList,
 };
 use std::{
     net::SocketAddr,
@@ -14,22 +20,63 @@ use std::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
+/// The address of the broker started by [`start_broker`].
+fn broker_addr() -> SocketAddr {
+    "10.0.0.1:50051".parse().unwrap()
+}
+
+/// Starts the broker on a new node, and waits for it to be ready.
+async fn start_broker(broker: SimBroker) -> NodeHandle {
+    let addr = broker_addr();
+    let node = (Handle::current().create_node())
+        .name("broker")
+        .ip(addr.ip())
+        .build();
+    node.spawn(async move {
+        broker.serve(addr).await.unwrap();
+    });
+    madsim::time::sleep(Duration::from_secs(1)).await;
+    node
+}
+
+/// Returns the config of a client of the broker with extra settings.
+fn client_config(settings: &[(&str, &str)]) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config.set("bootstrap.servers", broker_addr().to_string());
+    for (key, value) in settings {
+        config.set(*key, *value);
+    }
+    config
+}
+
+/// Creates a topic on the broker.
+async fn create_topic(topic: NewTopic<'_>) {
+    let admin = client_config(&[])
+        .create::<AdminClient<_>>()
+        .await
+        .expect("failed to create admin client");
+    let results = admin
+        .create_topics(&[topic], &AdminOptions::new())
+        .await
+        .expect("failed to create topic");
+    results[0].as_ref().expect("failed to create topic");
+}
+
+/// Creates a producer of the broker with extra settings.
+async fn producer<C: ProducerContext>(context: C, settings: &[(&str, &str)]) -> BaseProducer<C> {
+    client_config(settings)
+        .create_with_context(context)
+        .await
+        .expect("failed to create producer")
+}
+
 #[madsim::test]
 async fn test() {
     let handle = Handle::current();
-    let broker_addr = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
-    handle
-        .create_node()
-        .name("broker")
-        .ip(broker_addr.ip())
-        .build()
-        .spawn(async move {
-            SimBroker::default().serve(broker_addr).await.unwrap();
-        });
-    madsim::time::sleep(Duration::from_secs(1)).await;
+    start_broker(SimBroker::default()).await;
 
     handle
         .create_node()
@@ -37,18 +84,7 @@ async fn test() {
         .ip("10.0.0.2".parse().unwrap())
         .build()
         .spawn(async move {
-            let admin = ClientConfig::new()
-                .set("bootstrap.servers", broker_addr.to_string())
-                .create::<AdminClient<_>>()
-                .await
-                .expect("failed to create admin client");
-            admin
-                .create_topics(
-                    &[NewTopic::new("topic", 3, TopicReplication::Fixed(1))],
-                    &AdminOptions::new(),
-                )
-                .await
-                .expect("failed to create topic");
+            create_topic(NewTopic::new("topic", 3, TopicReplication::Fixed(1))).await;
         })
         .await
         .unwrap();
@@ -59,11 +95,7 @@ async fn test() {
         .ip("10.0.1.1".parse().unwrap())
         .build()
         .spawn(async move {
-            let producer = ClientConfig::new()
-                .set("bootstrap.servers", broker_addr.to_string())
-                .create::<BaseProducer>()
-                .await
-                .expect("failed to create producer");
+            let producer = producer(DefaultProducerContext, &[]).await;
 
             // generate an element every 0.1s
             for i in 1..=30 {
@@ -84,11 +116,7 @@ async fn test() {
         .ip("10.0.1.2".parse().unwrap())
         .build()
         .spawn(async move {
-            let producer = ClientConfig::new()
-                .set("bootstrap.servers", broker_addr.to_string())
-                .create::<BaseProducer>()
-                .await
-                .expect("failed to create producer");
+            let producer = producer(DefaultProducerContext, &[]).await;
 
             // generate an element every 0.2s
             for i in 1..=30 {
@@ -113,13 +141,13 @@ async fn test() {
         .ip("10.0.2.1".parse().unwrap())
         .build()
         .spawn(async move {
-            let consumer = ClientConfig::new()
-                .set("bootstrap.servers", broker_addr.to_string())
-                .set("enable.auto.commit", "false")
-                .set("auto.offset.reset", "earliest")
-                .create::<BaseConsumer>()
-                .await
-                .expect("failed to create consumer");
+            let consumer = client_config(&[
+                ("enable.auto.commit", "false"),
+                ("auto.offset.reset", "earliest"),
+            ])
+            .create::<BaseConsumer>()
+            .await
+            .expect("failed to create consumer");
 
             let mut assignment = TopicPartitionList::new();
             assignment.add_partition("topic", 0);
@@ -145,13 +173,13 @@ async fn test() {
         .ip("10.0.2.2".parse().unwrap())
         .build()
         .spawn(async move {
-            let consumer = ClientConfig::new()
-                .set("bootstrap.servers", broker_addr.to_string())
-                .set("enable.auto.commit", "false")
-                .set("auto.offset.reset", "earliest")
-                .create::<StreamConsumer>()
-                .await
-                .expect("failed to create consumer");
+            let consumer = client_config(&[
+                ("enable.auto.commit", "false"),
+                ("auto.offset.reset", "earliest"),
+            ])
+            .create::<StreamConsumer>()
+            .await
+            .expect("failed to create consumer");
 
             let mut assignment = TopicPartitionList::new();
             assignment.add_partition("topic", 2);
@@ -167,3 +195,850 @@ async fn test() {
     madsim::time::sleep(Duration::from_secs(10)).await;
     assert_eq!(sum.load(Ordering::Relaxed), (1..=30).sum::<usize>() * 2);
 }
+
+#[madsim::test]
+async fn offsets_for_times() {
+    let handle = Handle::current();
+    start_broker(SimBroker::default()).await;
+
+    let node = handle
+        .create_node()
+        .name("client")
+        .ip("10.0.1.1".parse().unwrap())
+        .build();
+    node.spawn(async move {
+        create_topic(NewTopic::new("topic", 1, TopicReplication::Fixed(1))).await;
+
+        // produce records at distinct times
+        let producer = producer(DefaultProducerContext, &[]).await;
+        let mut times = vec![];
+        for i in 0..5u8 {
+            times.push(Timestamp::now().to_millis().unwrap());
+            let payload = [i];
+            let record = BaseRecord::<(), _>::to("topic").payload(&payload);
+            producer.send(record).expect("failed to send message");
+            madsim::time::sleep(Duration::from_secs(1)).await;
+        }
+        // producer-supplied timestamp
+        let record = BaseRecord::<(), _>::to("topic")
+            .payload(&[5])
+            .timestamp(times[0] - 1000);
+        producer.send(record).expect("failed to send message");
+        producer.flush(None).await;
+
+        // fetched records carry the timestamps
+        let consumer = client_config(&[
+            ("enable.auto.commit", "false"),
+            ("auto.offset.reset", "earliest"),
+        ])
+        .create::<BaseConsumer>()
+        .await
+        .expect("failed to create consumer");
+        let mut assignment = TopicPartitionList::new();
+        assignment.add_partition("topic", 0);
+        consumer.assign(&assignment).expect("failed to assign");
+        let mut timestamps = vec![];
+        while timestamps.len() < 6 {
+            match consumer.poll().await {
+                Some(msg) => timestamps.push(msg.unwrap().timestamp()),
+                None => madsim::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+        let mut expected: Vec<_> = times.iter().map(|&t| Timestamp::CreateTime(t)).collect();
+        expected.push(Timestamp::CreateTime(times[0] - 1000));
+        assert_eq!(timestamps, expected);
+
+        // look up offsets by timestamp
+        let lookup = |timestamp: i64| {
+            let consumer = &consumer;
+            async move {
+                let mut tpl = TopicPartitionList::new();
+                tpl.add_partition_offset("topic", 0, Offset::Offset(timestamp))
+                    .unwrap();
+                let ret = consumer.offsets_for_times(tpl, None).await.unwrap();
+                ret.elements_for_topic("topic")[0].offset()
+            }
+        };
+        assert_eq!(lookup(times[0] - 2000).await, Offset::Offset(0));
+        assert_eq!(lookup(times[2]).await, Offset::Offset(2));
+        assert_eq!(lookup(times[2] + 1).await, Offset::Offset(3));
+        assert_eq!(lookup(times[4] + 1).await, Offset::End);
+    })
+    .await
+    .unwrap();
+}
+
+#[madsim::test]
+async fn idempotent_producer() {
+    let handle = Handle::current();
+    let broker = start_broker(SimBroker::default()).await;
+
+    let client = handle
+        .create_node()
+        .name("client")
+        .ip("10.0.1.1".parse().unwrap())
+        .build();
+    let (broker_id, client_id) = (broker.id(), client.id());
+    client
+        .spawn(async move {
+            create_topic(NewTopic::new("topic", 1, TopicReplication::Fixed(1))).await;
+
+            let producer =
+                producer(DefaultProducerContext, &[("enable.idempotence", "true")]).await;
+            let record = BaseRecord::<(), _>::to("topic").payload("1");
+            producer.send(record).expect("failed to send message");
+            producer.flush(None).await;
+
+            // the response is lost, so the producer will retry
+            let net = NetSim::current();
+            net.clog_link(broker_id, client_id);
+            let record = BaseRecord::<(), _>::to("topic").payload("2");
+            producer.send(record).expect("failed to send message");
+            producer.flush(Duration::from_secs(1)).await;
+            net.unclog_link(broker_id, client_id);
+            producer.flush(None).await;
+
+            let consumer = client_config(&[])
+                .create::<BaseConsumer>()
+                .await
+                .expect("failed to create consumer");
+            let watermarks = consumer.fetch_watermarks("topic", 0, None).await.unwrap();
+            assert_eq!(watermarks, (0, 2));
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn idempotent_transaction_abort() {
+    let handle = Handle::current();
+    start_broker(SimBroker::default()).await;
+
+    handle
+        .create_node()
+        .name("client")
+        .ip("10.0.1.1".parse().unwrap())
+        .build()
+        .spawn(async move {
+            create_topic(NewTopic::new("topic", 2, TopicReplication::Fixed(1))).await;
+
+            let producer = producer(
+                DefaultProducerContext,
+                &[("enable.idempotence", "true"), ("transactional.id", "txn")],
+            )
+            .await;
+            producer.init_transactions(None).await.unwrap();
+
+            // partitions are sequenced independently
+            producer.begin_transaction().unwrap();
+            for partition in [0, 1, 1] {
+                let record = BaseRecord::<(), _>::to("topic")
+                    .payload("1")
+                    .partition(partition);
+                producer.send(record).expect("failed to send message");
+            }
+            producer.commit_transaction(None).await.unwrap();
+
+            // the sequence numbers of aborted records are not expected by the broker
+            producer.begin_transaction().unwrap();
+            let record = BaseRecord::<(), _>::to("topic").payload("2").partition(0);
+            producer.send(record).expect("failed to send message");
+            producer.abort_transaction(None).await.unwrap();
+
+            producer.begin_transaction().unwrap();
+            let record = BaseRecord::<(), _>::to("topic").payload("3").partition(0);
+            producer.send(record).expect("failed to send message");
+            producer.commit_transaction(None).await.unwrap();
+
+            let consumer = client_config(&[])
+                .create::<BaseConsumer>()
+                .await
+                .expect("failed to create consumer");
+            let watermarks = consumer.fetch_watermarks("topic", 0, None).await.unwrap();
+            assert_eq!(watermarks, (0, 2));
+            let watermarks = consumer.fetch_watermarks("topic", 1, None).await.unwrap();
+            assert_eq!(watermarks, (0, 2));
+        })
+        .await
+        .unwrap();
+}
+
+/// A producer context that collects the offsets of delivered messages.
+#[derive(Clone, Default)]
+struct Offsets(Arc<spin::Mutex<Vec<i64>>>);
+
+impl ClientContext for Offsets {}
+impl ProducerContext for Offsets {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        if let Ok(msg) = result {
+            self.0.lock().push(msg.offset());
+        }
+    }
+}
+
+#[test]
+fn idempotent_producer_racing_retry() {
+    /// Returns the delivered offsets and the watermarks of the partition.
+    fn run(seed: u64) -> (Vec<i64>, (i64, i64)) {
+        let runtime = Runtime::with_seed_and_config(seed, madsim::Config::default());
+        runtime.block_on(async move {
+            let handle = Handle::current();
+            start_broker(SimBroker::default().process_latency(Duration::from_secs(2))).await;
+
+            handle
+                .create_node()
+                .name("client")
+                .ip("10.0.1.1".parse().unwrap())
+                .build()
+                .spawn(async move {
+                    create_topic(NewTopic::new("topic", 1, TopicReplication::Fixed(1))).await;
+
+                    let context = Offsets::default();
+                    let producer =
+                        producer(context.clone(), &[("enable.idempotence", "true")]).await;
+                    let record = BaseRecord::<(), _>::to("topic").payload("1");
+                    producer.send(record).expect("failed to send message");
+                    producer.flush(None).await;
+
+                    // the flush gives up while the broker is still processing,
+                    // so the retry races with the original request
+                    let record = BaseRecord::<(), _>::to("topic").payload("2");
+                    producer.send(record).expect("failed to send message");
+                    producer.flush(Duration::from_secs(1)).await;
+                    producer.flush(None).await;
+
+                    let consumer = client_config(&[])
+                        .create::<BaseConsumer>()
+                        .await
+                        .expect("failed to create consumer");
+                    let watermarks = consumer.fetch_watermarks("topic", 0, None).await.unwrap();
+                    let offsets = context.0.lock().clone();
+                    (offsets, watermarks)
+                })
+                .await
+                .unwrap()
+        })
+    }
+
+    for seed in 0..5 {
+        // the record is appended once, and the retry reports the same offset
+        assert_eq!(run(seed), (vec![0, 1], (0, 2)), "seed: {seed}");
+    }
+}
+
+#[madsim::test]
+async fn max_in_flight() {
+    let handle = Handle::current();
+    let process_latency = Duration::from_secs(2);
+    start_broker(
+        SimBroker::default()
+            .process_latency(process_latency)
+            .max_in_flight(1),
+    )
+    .await;
+
+    handle
+        .create_node()
+        .name("client")
+        .ip("10.0.1.1".parse().unwrap())
+        .build()
+        .spawn(async move {
+            create_topic(NewTopic::new("topic", 1, TopicReplication::Fixed(1))).await;
+
+            let context = Offsets::default();
+            let producer = producer(
+                context.clone(),
+                &[
+                    ("enable.idempotence", "true"),
+                    ("max.in.flight.requests.per.connection", "1"),
+                ],
+            )
+            .await;
+
+            // each flush gives up before the broker responds, so the records
+            // are retried along with the new ones while earlier requests are
+            // still in flight
+            let t0 = madsim::time::Instant::now();
+            for i in 0..3 {
+                let payload = i.to_string();
+                let record = BaseRecord::<(), _>::to("topic").payload(&payload);
+                producer.send(record).expect("failed to send message");
+                producer.flush(Duration::from_secs(1)).await;
+            }
+            producer.flush(None).await;
+            // the requests are processed one at a time
+            assert!(t0.elapsed() >= process_latency * 4, "{:?}", t0.elapsed());
+            assert_eq!(*context.0.lock(), vec![0, 1, 2]);
+
+            // the records are appended once, in the order they were sent
+            let consumer = client_config(&[
+                ("enable.auto.commit", "false"),
+                ("auto.offset.reset", "earliest"),
+            ])
+            .create::<BaseConsumer>()
+            .await
+            .expect("failed to create consumer");
+            let mut assignment = TopicPartitionList::new();
+            assignment.add_partition("topic", 0);
+            consumer.assign(&assignment).expect("failed to assign");
+            let mut payloads = vec![];
+            while payloads.len() < 3 {
+                match consumer.poll().await {
+                    Some(msg) => payloads.push(msg.unwrap().payload().unwrap().to_vec()),
+                    None => madsim::time::sleep(Duration::from_millis(100)).await,
+                }
+            }
+            assert_eq!(payloads, [b"0", b"1", b"2"]);
+            let watermarks = consumer.fetch_watermarks("topic", 0, None).await.unwrap();
+            assert_eq!(watermarks, (0, 3));
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn max_poll_records() {
+    let handle = Handle::current();
+    start_broker(SimBroker::default()).await;
+
+    handle
+        .create_node()
+        .name("client")
+        .ip("10.0.1.1".parse().unwrap())
+        .build()
+        .spawn(async move {
+            create_topic(NewTopic::new("topic", 1, TopicReplication::Fixed(1))).await;
+
+            let producer = producer(DefaultProducerContext, &[]).await;
+            for i in 0..100u8 {
+                let payload = [i];
+                let record = BaseRecord::<(), _>::to("topic").payload(&payload);
+                producer.send(record).expect("failed to send message");
+                if i % 10 == 9 {
+                    producer.flush(None).await;
+                }
+            }
+
+            let consumer = client_config(&[
+                ("enable.auto.commit", "false"),
+                ("auto.offset.reset", "earliest"),
+                ("max.poll.records", "16"),
+            ])
+            .create::<StreamConsumer>()
+            .await
+            .expect("failed to create consumer");
+            let mut assignment = TopicPartitionList::new();
+            assignment.add_partition("topic", 0);
+            consumer.assign(&assignment).expect("failed to assign");
+
+            // records fetched in one request are received at the same time
+            let mut stream = consumer.stream();
+            let mut chunks: Vec<(madsim::time::Instant, Vec<u8>)> = vec![];
+            while chunks.iter().map(|(_, c)| c.len()).sum::<usize>() < 100 {
+                let msg = stream.next().await.unwrap().unwrap();
+                let now = madsim::time::Instant::now();
+                let value = msg.payload().unwrap()[0];
+                match chunks.last_mut() {
+                    Some((t, chunk)) if *t == now => chunk.push(value),
+                    _ => chunks.push((now, vec![value])),
+                }
+            }
+            let sizes: Vec<usize> = chunks.iter().map(|(_, c)| c.len()).collect();
+            assert_eq!(sizes, [16, 16, 16, 16, 16, 16, 4]);
+            let values: Vec<u8> = chunks.into_iter().flat_map(|(_, c)| c).collect();
+            assert_eq!(values, (0..100).collect::<Vec<u8>>());
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn cold_fetch() {
+    let handle = Handle::current();
+    start_broker(SimBroker::default().cold_fetch(20, Duration::from_secs(1))).await;
+
+    handle
+        .create_node()
+        .name("client")
+        .ip("10.0.1.1".parse().unwrap())
+        .build()
+        .spawn(async move {
+            create_topic(NewTopic::new("topic", 1, TopicReplication::Fixed(1))).await;
+
+            let producer = producer(DefaultProducerContext, &[]).await;
+            for i in 0..100u8 {
+                let payload = [i];
+                let record = BaseRecord::<(), _>::to("topic").payload(&payload);
+                producer.send(record).expect("failed to send message");
+                if i % 10 == 9 {
+                    producer.flush(None).await;
+                }
+            }
+
+            let consumer = client_config(&[
+                ("enable.auto.commit", "false"),
+                ("auto.offset.reset", "earliest"),
+                ("max.poll.records", "16"),
+            ])
+            .create::<StreamConsumer>()
+            .await
+            .expect("failed to create consumer");
+            let mut assignment = TopicPartitionList::new();
+            assignment.add_partition("topic", 0);
+            consumer.assign(&assignment).expect("failed to assign");
+
+            // measure how long each fetch takes while catching up
+            let mut stream = consumer.stream();
+            let mut last = madsim::time::Instant::now();
+            let mut fetches = vec![];
+            for _ in 0..100 {
+                stream.next().await.unwrap().unwrap();
+                let now = madsim::time::Instant::now();
+                if now != last {
+                    fetches.push(now - last);
+                    last = now;
+                }
+            }
+            // fetches starting at offset 0, 16, 32, 48 and 64 are more than 20
+            // records behind the end, those at 80 and 96 read hot data
+            let cold: Vec<bool> = fetches
+                .iter()
+                .map(|d| *d >= Duration::from_secs(1))
+                .collect();
+            assert_eq!(cold, [true, true, true, true, true, false, false]);
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn overloaded_broker() {
+    let handle = Handle::current();
+    let accept_latency = Duration::from_millis(10);
+    let process_latency = Duration::from_millis(50);
+    start_broker(
+        SimBroker::default()
+            .accept_latency(accept_latency)
+            .process_latency(process_latency)
+            .accept_queue(4),
+    )
+    .await;
+
+    // flood the broker with metadata requests from many clients at the same time
+    let mut tasks = vec![];
+    for i in 1..=20 {
+        let task = handle
+            .create_node()
+            .name(format!("client-{i}"))
+            .ip(format!("10.0.1.{i}").parse().unwrap())
+            .build()
+            .spawn(async move {
+                let consumer = client_config(&[])
+                    .create::<BaseConsumer>()
+                    .await
+                    .expect("failed to create consumer");
+                let t0 = madsim::time::Instant::now();
+                let ret = consumer.fetch_metadata(None, None).await;
+                (ret.is_ok(), t0.elapsed())
+            });
+        tasks.push(task);
+    }
+    let mut latencies = vec![];
+    let mut rejected = 0;
+    for task in tasks {
+        match task.await.unwrap() {
+            (true, latency) => latencies.push(latency),
+            (false, _) => rejected += 1,
+        }
+    }
+
+    // the accept queue is full, so some connections are rejected
+    assert!(rejected > 0);
+    assert!(latencies.len() >= 5, "accepted: {}", latencies.len());
+    // accepted requests are delayed by accepting one at a time
+    assert!(latencies
+        .iter()
+        .all(|l| *l >= accept_latency + process_latency));
+    let max = *latencies.iter().max().unwrap();
+    assert!(max >= accept_latency * latencies.len() as u32 + process_latency);
+
+    // requests time out on the client side if the broker is too slow
+    handle
+        .create_node()
+        .name("client")
+        .ip("10.0.2.1".parse().unwrap())
+        .build()
+        .spawn(async move {
+            let consumer = client_config(&[])
+                .create::<BaseConsumer>()
+                .await
+                .expect("failed to create consumer");
+            let timeout = Duration::from_millis(30);
+            let ret = madsim::time::timeout(timeout, consumer.fetch_metadata(None, None)).await;
+            assert!(ret.is_err());
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn compacted_topic() {
+    let handle = Handle::current();
+    start_broker(SimBroker::default()).await;
+
+    handle
+        .create_node()
+        .name("client")
+        .ip("10.0.1.1".parse().unwrap())
+        .build()
+        .spawn(async move {
+            let admin = client_config(&[])
+                .create::<AdminClient<_>>()
+                .await
+                .expect("failed to create admin client");
+            let topic = NewTopic::new("state", 2, TopicReplication::Fixed(1))
+                .set("cleanup.policy", "compact")
+                .set("min.cleanable.dirty.ratio", "0");
+            let invalid = NewTopic::new("invalid", 1, TopicReplication::Fixed(1))
+                .set("cleanup.policy", "unknown");
+            let results = admin
+                .create_topics(&[topic, invalid], &AdminOptions::new())
+                .await
+                .expect("failed to create topic");
+            assert_eq!(results[0], Ok("state".to_string()));
+            assert_eq!(
+                results[1],
+                Err(("invalid".to_string(), RDKafkaErrorCode::InvalidConfig))
+            );
+
+            let producer = producer(DefaultProducerContext, &[]).await;
+            let updates = [("a", "1"), ("b", "1"), ("a", "2"), ("c", "1"), ("a", "3")];
+            for (key, value) in updates {
+                let record = BaseRecord::to("state").key(key).payload(value);
+                producer.send(record).expect("failed to send message");
+            }
+            // delete "b"
+            let record = BaseRecord::<_, ()>::to("state").key("b");
+            producer.send(record).expect("failed to send message");
+            producer.flush(None).await;
+
+            // restore the state from the compacted topic
+            let consumer = client_config(&[
+                ("enable.auto.commit", "false"),
+                ("auto.offset.reset", "earliest"),
+            ])
+            .create::<BaseConsumer>()
+            .await
+            .expect("failed to create consumer");
+            let mut assignment = TopicPartitionList::new();
+            assignment.add_partition("state", 0);
+            assignment.add_partition("state", 1);
+            consumer.assign(&assignment).expect("failed to assign");
+            let mut records = vec![];
+            while let Some(msg) = consumer.poll().await {
+                let msg = msg.unwrap();
+                let key = String::from_utf8(msg.key().unwrap().to_vec()).unwrap();
+                let value = msg
+                    .payload()
+                    .map(|v| String::from_utf8(v.to_vec()).unwrap());
+                records.push((key, value));
+            }
+            records.sort();
+            // only the latest value of each key is kept, including the tombstone
+            assert_eq!(
+                records,
+                [
+                    ("a".to_string(), Some("3".to_string())),
+                    ("b".to_string(), None),
+                    ("c".to_string(), Some("1".to_string())),
+                ]
+            );
+        })
+        .await
+        .unwrap();
+}
+
+/// A producer context that collects delivery errors.
+#[derive(Clone, Default)]
+struct DeadLetters(Arc<spin::Mutex<Vec<(String, RDKafkaErrorCode)>>>);
+
+impl ClientContext for DeadLetters {}
+impl ProducerContext for DeadLetters {
+    type DeliveryOpaque = String;
+
+    fn delivery(&self, result: &DeliveryResult<'_>, opaque: String) {
+        if let Err((KafkaError::MessageProduction(code), _)) = result {
+            self.0.lock().push((opaque, *code));
+        }
+    }
+}
+
+#[madsim::test]
+async fn produce_errors() {
+    let handle = Handle::current();
+    start_broker(SimBroker::default()).await;
+
+    handle
+        .create_node()
+        .name("client")
+        .ip("10.0.1.1".parse().unwrap())
+        .build()
+        .spawn(async move {
+            let topic = NewTopic::new("topic", 2, TopicReplication::Fixed(1))
+                .set("max.message.bytes", "100");
+            create_topic(topic).await;
+
+            let context = DeadLetters::default();
+            let producer = producer(context.clone(), &[]).await;
+            let small = [0u8; 10];
+            let large = [0u8; 200];
+            let records = [
+                BaseRecord::<(), _, _>::with_opaque_to("topic", "ok".into()).payload(&small[..]),
+                BaseRecord::with_opaque_to("topic", "too large".into()).payload(&large[..]),
+                BaseRecord::with_opaque_to("topic", "partition 1".into())
+                    .payload(&small[..])
+                    .partition(1),
+                BaseRecord::with_opaque_to("topic", "partition 2".into())
+                    .payload(&small[..])
+                    .partition(2),
+            ];
+            for record in records {
+                producer.send(record).expect("failed to send message");
+            }
+            producer.flush(None).await;
+            assert_eq!(
+                *context.0.lock(),
+                [
+                    (
+                        "too large".to_string(),
+                        RDKafkaErrorCode::MessageSizeTooLarge
+                    ),
+                    (
+                        "partition 2".to_string(),
+                        RDKafkaErrorCode::UnknownPartition
+                    ),
+                ]
+            );
+
+            // only the valid records are appended
+            let consumer = client_config(&[
+                ("enable.auto.commit", "false"),
+                ("auto.offset.reset", "earliest"),
+            ])
+            .create::<BaseConsumer>()
+            .await
+            .expect("failed to create consumer");
+            assert_eq!(
+                consumer.fetch_watermarks("topic", 0, None).await.unwrap(),
+                (0, 1)
+            );
+            assert_eq!(
+                consumer.fetch_watermarks("topic", 1, None).await.unwrap(),
+                (0, 1)
+            );
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn idempotent_producer_rejected_records() {
+    let handle = Handle::current();
+    start_broker(SimBroker::default()).await;
+
+    handle
+        .create_node()
+        .name("client")
+        .ip("10.0.1.1".parse().unwrap())
+        .build()
+        .spawn(async move {
+            let topic = NewTopic::new("topic", 1, TopicReplication::Fixed(1))
+                .set("cleanup.policy", "compact")
+                .set("max.message.bytes", "100");
+            create_topic(topic).await;
+
+            let context = DeadLetters::default();
+            let producer = producer(context.clone(), &[("enable.idempotence", "true")]).await;
+            let large = [0u8; 200];
+            let records = [
+                BaseRecord::<_, [u8], _>::with_opaque_to("topic", "too large".into())
+                    .key("1")
+                    .payload(&large[..]),
+                BaseRecord::with_opaque_to("topic", "no key".into()).payload(&b"2"[..]),
+            ];
+            for record in records {
+                producer.send(record).expect("failed to send message");
+            }
+            producer.flush(None).await;
+
+            // the rejected records consume their sequence numbers
+            let record = BaseRecord::with_opaque_to("topic", "ok".into())
+                .key("3")
+                .payload("3");
+            producer.send(record).expect("failed to send message");
+            producer.flush(None).await;
+            assert_eq!(
+                *context.0.lock(),
+                [
+                    (
+                        "too large".to_string(),
+                        RDKafkaErrorCode::MessageSizeTooLarge
+                    ),
+                    ("no key".to_string(), RDKafkaErrorCode::InvalidRecord),
+                ]
+            );
+
+            let consumer = client_config(&[])
+                .create::<BaseConsumer>()
+                .await
+                .expect("failed to create consumer");
+            let watermarks = consumer.fetch_watermarks("topic", 0, None).await.unwrap();
+            assert_eq!(watermarks, (0, 1));
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn mixed_version_cluster() {
+    let handle = Handle::current();
+    // a cluster in the middle of an upgrade
+    let old_addr = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+    let new_addr = "10.0.0.2:50051".parse::<SocketAddr>().unwrap();
+    for (addr, version) in [(old_addr, "0.10.0"), (new_addr, "0.11.0")] {
+        handle
+            .create_node()
+            .name(format!("broker-{version}"))
+            .ip(addr.ip())
+            .build()
+            .spawn(async move {
+                SimBroker::default()
+                    .version(version)
+                    .serve(addr)
+                    .await
+                    .unwrap();
+            });
+    }
+    madsim::time::sleep(Duration::from_secs(1)).await;
+
+    handle
+        .create_node()
+        .name("client")
+        .ip("10.0.1.1".parse().unwrap())
+        .build()
+        .spawn(async move {
+            // create the topic on the first broker supporting it
+            let mut created = None;
+            for addr in [old_addr, new_addr] {
+                let admin = ClientConfig::new()
+                    .set("bootstrap.servers", addr.to_string())
+                    .create::<AdminClient<_>>()
+                    .await
+                    .expect("failed to create admin client");
+                let topic = NewTopic::new("topic", 1, TopicReplication::Fixed(1));
+                let results = admin
+                    .create_topics(&[topic], &AdminOptions::new())
+                    .await
+                    .expect("failed to create topic");
+                match &results[0] {
+                    Ok(_) => {
+                        created = Some(addr);
+                        break;
+                    }
+                    Err((_, code)) => assert_eq!(*code, RDKafkaErrorCode::UnsupportedVersion),
+                }
+            }
+            assert_eq!(created, Some(new_addr));
+
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", new_addr.to_string())
+                .set("enable.idempotence", "true")
+                .create::<BaseProducer>()
+                .await
+                .expect("failed to create producer");
+            let time = Timestamp::now().to_millis().unwrap();
+            let record = BaseRecord::<(), _>::to("topic").payload(&[0]);
+            producer.send(record).expect("failed to send message");
+            producer.flush(None).await;
+
+            // look up offsets on the old broker, then fall back to the new one
+            let mut offset = None;
+            for addr in [old_addr, new_addr] {
+                let consumer = ClientConfig::new()
+                    .set("bootstrap.servers", addr.to_string())
+                    .set("enable.auto.commit", "false")
+                    .create::<BaseConsumer>()
+                    .await
+                    .expect("failed to create consumer");
+                let mut tpl = TopicPartitionList::new();
+                tpl.add_partition_offset("topic", 0, Offset::Offset(time))
+                    .unwrap();
+                match consumer.offsets_for_times(tpl, None).await {
+                    Ok(ret) => {
+                        offset = Some(ret.elements_for_topic("topic")[0].offset());
+                        break;
+                    }
+                    Err(KafkaError::OffsetFetch(code)) => {
+                        assert_eq!(code, RDKafkaErrorCode::UnsupportedVersion)
+                    }
+                    Err(e) => panic!("unexpected error: {e}"),
+                }
+            }
+            assert_eq!(offset, Some(Offset::Offset(0)));
+        })
+        .await
+        .unwrap();
+}
+
+#[test]
+fn epoch() {
+    let epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut rt = Runtime::new();
+    rt.set_epoch(epoch);
+    rt.block_on(async move {
+        let handle = Handle::current();
+        start_broker(SimBroker::default()).await;
+
+        let node = handle
+            .create_node()
+            .name("client")
+            .ip("10.0.1.1".parse().unwrap())
+            .build();
+        node.spawn(async move {
+            create_topic(NewTopic::new("topic", 1, TopicReplication::Fixed(1))).await;
+            let producer = producer(DefaultProducerContext, &[]).await;
+            madsim::time::sleep(Duration::from_secs(10)).await;
+
+            let epoch_millis = 1_700_000_000_000;
+            let elapsed_millis = || TimeHandle::current().elapsed().as_millis() as i64;
+            let before = epoch_millis + elapsed_millis();
+            let record = BaseRecord::<(), _>::to("topic").payload(&[0]);
+            producer.send(record).expect("failed to send message");
+            let after = epoch_millis + elapsed_millis();
+            producer.flush(None).await;
+
+            let consumer = client_config(&[
+                ("enable.auto.commit", "false"),
+                ("auto.offset.reset", "earliest"),
+            ])
+            .create::<BaseConsumer>()
+            .await
+            .expect("failed to create consumer");
+            let mut assignment = TopicPartitionList::new();
+            assignment.add_partition("topic", 0);
+            consumer.assign(&assignment).expect("failed to assign");
+            let timestamp = loop {
+                match consumer.poll().await {
+                    Some(msg) => break msg.unwrap().timestamp().to_millis().unwrap(),
+                    None => madsim::time::sleep(Duration::from_millis(100)).await,
+                }
+            };
+            // the record time is the epoch plus the elapsed simulated time
+            assert!((before..=after).contains(&timestamp));
+        })
+        .await
+        .unwrap();
+    });
+}
@@ -0,0 +1,12 @@
+//! The Redis simulator on madsim.
+//!
+//! Outside the simulation, this crate re-exports the `redis` crate.
+
+#[cfg(madsim)]
+mod sim;
+
+#[cfg(madsim)]
+pub use sim::*;
+
+#[cfg(not(madsim))]
+pub use redis::*;
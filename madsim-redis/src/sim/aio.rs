@@ -0,0 +1,161 @@
+//! Async connections.
+
+use crate::{
+    Cmd, ConnectionInfo, ErrorKind, FromRedisValue, RedisError, RedisFuture, RedisResult, Request,
+    ToRedisArgs, Value,
+};
+use futures_util::{stream, FutureExt, Stream};
+use madsim::net::{lookup_host, Endpoint, Receiver};
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+
+/// An async abstraction over connections.
+pub trait ConnectionLike {
+    /// Sends an already encoded (packed) command into the TCP socket and
+    /// reads the single response from it.
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value>;
+
+    /// Returns the database this connection is bound to.  Note that this
+    /// information might be unreliable because it's initially cached and
+    /// also might be incorrect if the connection like object is not
+    /// actually connected.
+    fn get_db(&self) -> i64;
+}
+
+/// An async connection to a redis server.
+#[derive(Clone)]
+pub struct Connection {
+    ep: Arc<Endpoint>,
+    addr: SocketAddr,
+    db: i64,
+}
+
+/// A connection object which can be cloned, allowing requests to be sent concurrently.
+///
+/// Connections are not multiplexed in the simulation, so this is the same as [`Connection`].
+pub type MultiplexedConnection = Connection;
+
+impl Connection {
+    pub(crate) async fn connect(info: &ConnectionInfo) -> RedisResult<Connection> {
+        let addr = lookup_host(info.addr.to_string())
+            .await?
+            .next()
+            .ok_or_else(|| {
+                RedisError::new(ErrorKind::InvalidClientConfig, "No address found for host")
+            })?;
+        let conn = Connection {
+            ep: Arc::new(Endpoint::bind("0.0.0.0:0").await?),
+            addr,
+            db: info.redis.db,
+        };
+        // make sure the server is reachable
+        conn.request(Request::Ping).await?;
+        Ok(conn)
+    }
+
+    /// Sends a request to the server and returns the response.
+    async fn request(&self, request: Request) -> RedisResult<Value> {
+        let (tx, mut rx) = self.ep.connect1(self.addr).await?;
+        tx.send(Box::new(request)).await?;
+        *rx.recv().await?.downcast().unwrap()
+    }
+
+    /// Converts this connection into a pub/sub connection.
+    pub fn into_pubsub(self) -> PubSub {
+        PubSub {
+            conn: self,
+            channels: BTreeMap::new(),
+        }
+    }
+}
+
+impl ConnectionLike for Connection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        async move { self.request(Request::from_cmd(cmd)?).await }.boxed()
+    }
+
+    fn get_db(&self) -> i64 {
+        self.db
+    }
+}
+
+/// A connection dedicated to pub/sub.
+pub struct PubSub {
+    conn: Connection,
+    /// The connection of each subscribed channel.
+    channels: BTreeMap<String, Receiver>,
+}
+
+impl PubSub {
+    /// Subscribes to a new channel.
+    pub async fn subscribe<T: ToRedisArgs>(&mut self, channel: T) -> RedisResult<()> {
+        for channel in channel.to_redis_args() {
+            let channel = String::from_utf8(channel).map_err(|_| {
+                RedisError::new(ErrorKind::ClientError, "Channel name must be UTF-8")
+            })?;
+            if self.channels.contains_key(&channel) {
+                continue;
+            }
+            let (tx, mut rx) = self.conn.ep.connect1(self.conn.addr).await?;
+            let request = Request::Subscribe {
+                channel: channel.clone(),
+            };
+            tx.send(Box::new(request)).await?;
+            let rsp = *rx.recv().await?.downcast::<RedisResult<Value>>().unwrap();
+            rsp?;
+            self.channels.insert(channel, rx);
+        }
+        Ok(())
+    }
+
+    /// Unsubscribes from a channel.
+    pub async fn unsubscribe<T: ToRedisArgs>(&mut self, channel: T) -> RedisResult<()> {
+        for channel in channel.to_redis_args() {
+            // dropping the connection removes the subscription on the server
+            self.channels
+                .remove(String::from_utf8_lossy(&channel).as_ref());
+        }
+        Ok(())
+    }
+
+    /// Returns a stream of messages received from the subscribed channels.
+    ///
+    /// The stream ends when all connections are closed.
+    pub fn on_message(&mut self) -> impl Stream<Item = Msg> + '_ {
+        stream::select_all(self.channels.values_mut().map(|rx| {
+            Box::pin(stream::unfold(rx, |rx| async move {
+                let payload = rx.recv().await.ok()?;
+                let (channel, payload) = *payload.downcast::<(String, Vec<u8>)>().unwrap();
+                Some((Msg { channel, payload }, rx))
+            }))
+        }))
+    }
+
+    /// Exits from the pub/sub mode and converts back into a regular connection.
+    pub async fn into_connection(self) -> Connection {
+        self.conn
+    }
+}
+
+/// Represents a pubsub message.
+#[derive(Debug, Clone)]
+pub struct Msg {
+    channel: String,
+    payload: Vec<u8>,
+}
+
+impl Msg {
+    /// Returns the channel this message came on.
+    pub fn get_channel_name(&self) -> &str {
+        &self.channel
+    }
+
+    /// Returns the message's payload in a specific format.
+    pub fn get_payload<T: FromRedisValue>(&self) -> RedisResult<T> {
+        T::from_redis_value(&Value::Data(self.payload.clone()))
+    }
+
+    /// Returns the bytes that are the message's payload.
+    pub fn get_payload_bytes(&self) -> &[u8] {
+        &self.payload
+    }
+}
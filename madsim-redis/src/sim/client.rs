@@ -0,0 +1,39 @@
+use crate::{
+    aio::{Connection, MultiplexedConnection},
+    ConnectionInfo, IntoConnectionInfo, RedisResult,
+};
+
+/// The client type.
+#[derive(Debug, Clone)]
+pub struct Client {
+    connection_info: ConnectionInfo,
+}
+
+impl Client {
+    /// Connects to a redis server and returns a client.
+    ///
+    /// The url should be in the format of `redis://host[:port][/db]`.
+    /// The connection is not established until a connection is requested.
+    pub fn open<T: IntoConnectionInfo>(params: T) -> RedisResult<Client> {
+        Ok(Client {
+            connection_info: params.into_connection_info()?,
+        })
+    }
+
+    /// Returns an async connection from the client.
+    pub async fn get_async_connection(&self) -> RedisResult<Connection> {
+        Connection::connect(&self.connection_info).await
+    }
+
+    /// Returns an async connection from the client.
+    ///
+    /// Connections are not multiplexed in the simulation.
+    pub async fn get_multiplexed_async_connection(&self) -> RedisResult<MultiplexedConnection> {
+        Connection::connect(&self.connection_info).await
+    }
+
+    /// Returns a reference of client connection info object.
+    pub fn get_connection_info(&self) -> &ConnectionInfo {
+        &self.connection_info
+    }
+}
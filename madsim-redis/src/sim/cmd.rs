@@ -0,0 +1,47 @@
+use crate::{aio::ConnectionLike, FromRedisValue, RedisResult, RedisWrite, ToRedisArgs};
+
+/// Represents redis commands.
+#[derive(Debug, Clone, Default)]
+pub struct Cmd {
+    args: Vec<Vec<u8>>,
+}
+
+impl RedisWrite for Cmd {
+    fn write_arg(&mut self, arg: &[u8]) {
+        self.args.push(arg.to_owned());
+    }
+}
+
+impl Cmd {
+    /// Creates a new empty command.
+    pub fn new() -> Cmd {
+        Cmd::default()
+    }
+
+    /// Appends an argument to the command.
+    pub fn arg<T: ToRedisArgs>(&mut self, arg: T) -> &mut Cmd {
+        arg.write_redis_args(self);
+        self
+    }
+
+    /// Async version of `query`.
+    pub async fn query_async<C, T: FromRedisValue>(&self, con: &mut C) -> RedisResult<T>
+    where
+        C: ConnectionLike,
+    {
+        let val = con.req_packed_command(self).await?;
+        T::from_redis_value(&val)
+    }
+
+    /// Returns the name and arguments of the command.
+    pub(crate) fn args(&self) -> &[Vec<u8>] {
+        &self.args
+    }
+}
+
+/// Shortcut function to creating a command with a single argument.
+pub fn cmd(name: &str) -> Cmd {
+    let mut rv = Cmd::new();
+    rv.arg(name);
+    rv
+}
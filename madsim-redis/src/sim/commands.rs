@@ -0,0 +1,95 @@
+use crate::{aio::ConnectionLike, cmd, FromRedisValue, RedisFuture, ToRedisArgs};
+
+/// Implements common redis commands over asynchronous connections.
+pub trait AsyncCommands: ConnectionLike + Send + Sized {
+    /// Get the value of a key.
+    fn get<'a, K, RV>(&'a mut self, key: K) -> RedisFuture<'a, RV>
+    where
+        K: ToRedisArgs + Send + Sync + 'a,
+        RV: FromRedisValue,
+    {
+        Box::pin(async move { cmd("GET").arg(key).query_async(self).await })
+    }
+
+    /// Set the string value of a key.
+    fn set<'a, K, V, RV>(&'a mut self, key: K, value: V) -> RedisFuture<'a, RV>
+    where
+        K: ToRedisArgs + Send + Sync + 'a,
+        V: ToRedisArgs + Send + Sync + 'a,
+        RV: FromRedisValue,
+    {
+        Box::pin(async move { cmd("SET").arg(key).arg(value).query_async(self).await })
+    }
+
+    /// Set the value and expiration of a key.
+    fn set_ex<'a, K, V, RV>(&'a mut self, key: K, value: V, seconds: usize) -> RedisFuture<'a, RV>
+    where
+        K: ToRedisArgs + Send + Sync + 'a,
+        V: ToRedisArgs + Send + Sync + 'a,
+        RV: FromRedisValue,
+    {
+        Box::pin(async move {
+            cmd("SETEX")
+                .arg(key)
+                .arg(seconds)
+                .arg(value)
+                .query_async(self)
+                .await
+        })
+    }
+
+    /// Delete one or more keys.
+    fn del<'a, K, RV>(&'a mut self, key: K) -> RedisFuture<'a, RV>
+    where
+        K: ToRedisArgs + Send + Sync + 'a,
+        RV: FromRedisValue,
+    {
+        Box::pin(async move { cmd("DEL").arg(key).query_async(self).await })
+    }
+
+    /// Set a key's time to live in seconds.
+    fn expire<'a, K, RV>(&'a mut self, key: K, seconds: usize) -> RedisFuture<'a, RV>
+    where
+        K: ToRedisArgs + Send + Sync + 'a,
+        RV: FromRedisValue,
+    {
+        Box::pin(async move { cmd("EXPIRE").arg(key).arg(seconds).query_async(self).await })
+    }
+
+    /// Get the time to live for a key in seconds.
+    fn ttl<'a, K, RV>(&'a mut self, key: K) -> RedisFuture<'a, RV>
+    where
+        K: ToRedisArgs + Send + Sync + 'a,
+        RV: FromRedisValue,
+    {
+        Box::pin(async move { cmd("TTL").arg(key).query_async(self).await })
+    }
+
+    /// Increment the numeric value of a key by the given amount.
+    fn incr<'a, K, V, RV>(&'a mut self, key: K, delta: V) -> RedisFuture<'a, RV>
+    where
+        K: ToRedisArgs + Send + Sync + 'a,
+        V: ToRedisArgs + Send + Sync + 'a,
+        RV: FromRedisValue,
+    {
+        Box::pin(async move { cmd("INCRBY").arg(key).arg(delta).query_async(self).await })
+    }
+
+    /// Posts a message to the given channel.
+    fn publish<'a, K, E, RV>(&'a mut self, channel: K, message: E) -> RedisFuture<'a, RV>
+    where
+        K: ToRedisArgs + Send + Sync + 'a,
+        E: ToRedisArgs + Send + Sync + 'a,
+        RV: FromRedisValue,
+    {
+        Box::pin(async move {
+            cmd("PUBLISH")
+                .arg(channel)
+                .arg(message)
+                .query_async(self)
+                .await
+        })
+    }
+}
+
+impl<T: ConnectionLike + Send + Sized> AsyncCommands for T {}
@@ -0,0 +1,108 @@
+use crate::{ErrorKind, RedisError, RedisResult};
+use std::{fmt, str::FromStr};
+
+/// Defines the connection address.
+///
+/// Only TCP is supported in the simulation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionAddr {
+    /// Format for this is `(host, port)`.
+    Tcp(String, u16),
+}
+
+impl fmt::Display for ConnectionAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionAddr::Tcp(host, port) => write!(f, "{host}:{port}"),
+        }
+    }
+}
+
+/// Holds the connection information that redis should use for connecting.
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    /// A connection address for where to connect to.
+    pub addr: ConnectionAddr,
+
+    /// A boxed connection address for where to connect to.
+    pub redis: RedisConnectionInfo,
+}
+
+/// Redis specific/connection independent information used to establish a connection to redis.
+#[derive(Clone, Debug, Default)]
+pub struct RedisConnectionInfo {
+    /// The database number to use.  This is usually `0`.
+    pub db: i64,
+    /// Optionally a username that should be used for connection.
+    pub username: Option<String>,
+    /// Optionally a password that should be used for connection.
+    pub password: Option<String>,
+}
+
+impl FromStr for ConnectionInfo {
+    type Err = RedisError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.into_connection_info()
+    }
+}
+
+/// Converts an object into a connection info struct.  This allows the
+/// constructor of the client to accept connection information in a
+/// range of different formats.
+pub trait IntoConnectionInfo {
+    /// Converts the object into a connection info object.
+    fn into_connection_info(self) -> RedisResult<ConnectionInfo>;
+}
+
+impl IntoConnectionInfo for ConnectionInfo {
+    fn into_connection_info(self) -> RedisResult<ConnectionInfo> {
+        Ok(self)
+    }
+}
+
+impl IntoConnectionInfo for &str {
+    /// Parses a url in the format of `redis://[username[:password]@]host[:port][/db]`.
+    fn into_connection_info(self) -> RedisResult<ConnectionInfo> {
+        let invalid = || {
+            RedisError::new(ErrorKind::InvalidClientConfig, "Redis URL did not parse")
+                .with_detail(self)
+        };
+        let rest = self.strip_prefix("redis://").ok_or_else(invalid)?;
+        let (authority, db) = match rest.split_once('/') {
+            Some((authority, "")) => (authority, 0),
+            Some((authority, db)) => (authority, db.parse().map_err(|_| invalid())?),
+            None => (rest, 0),
+        };
+        let (user_info, host_port) = match authority.rsplit_once('@') {
+            Some((user_info, host_port)) => (Some(user_info), host_port),
+            None => (None, authority),
+        };
+        let (username, password) = match user_info.map(|s| s.split_once(':')) {
+            Some(Some((username, password))) => (username, Some(password.to_string())),
+            Some(None) => (user_info.unwrap(), None),
+            None => ("", None),
+        };
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (host_port, 6379),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(ConnectionInfo {
+            addr: ConnectionAddr::Tcp(host.to_string(), port),
+            redis: RedisConnectionInfo {
+                db,
+                username: (!username.is_empty()).then(|| username.to_string()),
+                password,
+            },
+        })
+    }
+}
+
+impl IntoConnectionInfo for String {
+    fn into_connection_info(self) -> RedisResult<ConnectionInfo> {
+        self.as_str().into_connection_info()
+    }
+}
@@ -0,0 +1,18 @@
+pub mod aio;
+mod client;
+mod cmd;
+mod commands;
+mod connection;
+pub(crate) mod sim_server;
+pub(crate) mod store;
+mod types;
+
+pub use self::aio::Msg;
+pub use self::client::Client;
+pub use self::cmd::{cmd, Cmd};
+pub use self::commands::AsyncCommands;
+pub use self::connection::{
+    ConnectionAddr, ConnectionInfo, IntoConnectionInfo, RedisConnectionInfo,
+};
+pub use self::sim_server::{Request, SimRedis};
+pub use self::types::*;
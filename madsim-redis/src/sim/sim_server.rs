@@ -0,0 +1,175 @@
+use crate::{store::Store, Cmd, ErrorKind, RedisError, RedisResult, Value};
+use madsim::net::{Endpoint, Payload, Receiver, Sender};
+use spin::Mutex;
+use std::{io::Result, net::SocketAddr, sync::Arc, time::Duration};
+use tracing::*;
+
+/// A simulated Redis server.
+#[derive(Default)]
+pub struct SimRedis {
+    _private: (),
+}
+
+impl SimRedis {
+    /// Serve on the given address.
+    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+        let ep = Endpoint::bind(addr).await?;
+        let store = Arc::new(Mutex::new(Store::default()));
+        loop {
+            let (tx, rx, peer) = ep.accept1().await?;
+            let store = store.clone();
+            madsim::task::spawn(async move {
+                if let Err(e) = Self::handle(store, tx, rx).await {
+                    debug!(?peer, "connection error: {e}");
+                }
+            });
+        }
+    }
+
+    async fn handle(store: Arc<Mutex<Store>>, tx: Sender, mut rx: Receiver) -> Result<()> {
+        let request = *rx.recv().await?.downcast::<Request>().unwrap();
+        trace!(?request, "request");
+        let response: RedisResult<Value> = match request {
+            Request::Ping => Ok(Value::Status("PONG".into())),
+            Request::Get { key } => Ok(store.lock().get(&key)),
+            Request::Set { key, value, expire } => Ok(store.lock().set(key, value, expire)),
+            Request::Del { keys } => Ok(store.lock().del(&keys)),
+            Request::Expire { key, seconds } => Ok(store.lock().expire(&key, seconds)),
+            Request::Ttl { key } => Ok(store.lock().ttl(&key)),
+            Request::Incr { key, delta } => store.lock().incr(key, delta),
+            Request::Publish { channel, message } => {
+                let subscribers = store.lock().subscribers(&channel);
+                let mut count = 0;
+                for subscriber in subscribers {
+                    let msg: Payload = Box::new((channel.clone(), message.clone()));
+                    if subscriber.send(msg).await.is_ok() {
+                        count += 1;
+                    } else {
+                        store.lock().unsubscribe(&channel, &subscriber);
+                    }
+                }
+                Ok(Value::Int(count))
+            }
+            Request::Subscribe { channel } => {
+                // the connection is kept open to push messages
+                let tx = Arc::new(tx);
+                tx.send(Box::new(Ok::<_, crate::RedisError>(Value::Okay)))
+                    .await?;
+                store.lock().subscribe(channel, tx);
+                return Ok(());
+            }
+        };
+        tx.send(Box::new(response)).await?;
+        Ok(())
+    }
+}
+
+/// Request to `SimRedis`.
+#[derive(Debug)]
+pub enum Request {
+    /// `PING`. Replies `PONG`.
+    Ping,
+    /// `GET`. Replies the value of the key, or nil if it doesn't exist.
+    Get { key: Vec<u8> },
+    /// `SET` or `SETEX`. Replaces the value and the time to live of the key.
+    Set {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        /// The time to live of the key.
+        expire: Option<Duration>,
+    },
+    /// `DEL`. Replies the number of keys removed.
+    Del { keys: Vec<Vec<u8>> },
+    /// `EXPIRE`. Replies 1 if the key exists, or 0 otherwise.
+    ///
+    /// The key is removed at once if `seconds` is 0.
+    Expire { key: Vec<u8>, seconds: u64 },
+    /// `TTL`. Replies the remaining seconds to live of the key, -1 if it
+    /// doesn't expire, or -2 if it doesn't exist.
+    Ttl { key: Vec<u8> },
+    /// `INCR` or `INCRBY`. Replies the value of the key after the increment.
+    ///
+    /// A missing key counts as 0, and the time to live is kept.
+    Incr { key: Vec<u8>, delta: i64 },
+    /// `PUBLISH`. Replies the number of subscribers that received the message.
+    Publish { channel: String, message: Vec<u8> },
+    /// `SUBSCRIBE`. Subscribes to a channel.
+    ///
+    /// After the acknowledgement, messages published to the channel are sent
+    /// on the same connection as `(String, Vec<u8>)`.
+    Subscribe { channel: String },
+}
+
+impl Request {
+    /// Parses a command into a request.
+    pub(crate) fn from_cmd(cmd: &Cmd) -> RedisResult<Request> {
+        let (name, args) = cmd
+            .args()
+            .split_first()
+            .ok_or_else(|| RedisError::new(ErrorKind::ClientError, "Empty command"))?;
+        let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+        let request = match (name.as_str(), args) {
+            ("ping", []) => Request::Ping,
+            ("get", [key]) => Request::Get { key: key.clone() },
+            ("set", [key, value]) => Request::Set {
+                key: key.clone(),
+                value: value.clone(),
+                expire: None,
+            },
+            ("setex", [key, seconds, value]) => Request::Set {
+                key: key.clone(),
+                value: value.clone(),
+                expire: Some(Duration::from_secs(int_arg(seconds)? as u64)),
+            },
+            ("del", [_, ..]) => Request::Del {
+                keys: args.to_vec(),
+            },
+            ("expire", [key, seconds]) => Request::Expire {
+                key: key.clone(),
+                seconds: int_arg(seconds)? as u64,
+            },
+            ("ttl", [key]) => Request::Ttl { key: key.clone() },
+            ("incr", [key]) => Request::Incr {
+                key: key.clone(),
+                delta: 1,
+            },
+            ("incrby", [key, delta]) => Request::Incr {
+                key: key.clone(),
+                delta: int_arg(delta)?,
+            },
+            ("publish", [channel, message]) => Request::Publish {
+                channel: String::from_utf8(channel.clone()).map_err(|_| {
+                    RedisError::new(ErrorKind::ClientError, "Channel name must be UTF-8")
+                })?,
+                message: message.clone(),
+            },
+            (
+                "ping" | "get" | "set" | "setex" | "del" | "expire" | "ttl" | "incr" | "incrby"
+                | "publish",
+                _,
+            ) => {
+                return Err(server_error(format!(
+                    "wrong number of arguments for '{name}' command"
+                )))
+            }
+            _ => return Err(server_error(format!("unknown command '{name}'"))),
+        };
+        Ok(request)
+    }
+}
+
+/// Parses an integer argument.
+fn int_arg(arg: &[u8]) -> RedisResult<i64> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| server_error("value is not an integer or out of range"))
+}
+
+fn server_error(detail: impl Into<String>) -> RedisError {
+    RedisError::new(
+        ErrorKind::ResponseError,
+        "An error was signalled by the server",
+    )
+    .with_detail(detail)
+}
@@ -0,0 +1,137 @@
+use crate::{ErrorKind, RedisError, RedisResult, Value};
+use madsim::{net::Sender, time::Instant};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+/// The in-memory data of a Redis server.
+#[derive(Default)]
+pub(crate) struct Store {
+    data: HashMap<Vec<u8>, Entry>,
+    /// Subscribers of each channel.
+    channels: BTreeMap<String, Vec<Arc<Sender>>>,
+}
+
+struct Entry {
+    value: Vec<u8>,
+    /// The time when the key expires.
+    expire_at: Option<Instant>,
+}
+
+impl Store {
+    /// Returns the entry of the key, removing it if expired.
+    fn entry(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        let expired = match self.data.get(key) {
+            Some(entry) => matches!(entry.expire_at, Some(t) if t <= Instant::now()),
+            None => return None,
+        };
+        if expired {
+            self.data.remove(key);
+            return None;
+        }
+        self.data.get_mut(key)
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Value {
+        match self.entry(key) {
+            Some(entry) => Value::Data(entry.value.clone()),
+            None => Value::Nil,
+        }
+    }
+
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>, expire: Option<Duration>) -> Value {
+        let expire_at = expire.map(|d| Instant::now() + d);
+        self.data.insert(key, Entry { value, expire_at });
+        Value::Okay
+    }
+
+    pub fn del(&mut self, keys: &[Vec<u8>]) -> Value {
+        let mut count = 0;
+        for key in keys {
+            if self.entry(key).is_some() {
+                self.data.remove(key);
+                count += 1;
+            }
+        }
+        Value::Int(count)
+    }
+
+    pub fn expire(&mut self, key: &[u8], seconds: u64) -> Value {
+        if self.entry(key).is_none() {
+            return Value::Int(0);
+        }
+        if seconds == 0 {
+            self.data.remove(key);
+        } else {
+            let entry = self.data.get_mut(key).unwrap();
+            entry.expire_at = Some(Instant::now() + Duration::from_secs(seconds));
+        }
+        Value::Int(1)
+    }
+
+    pub fn ttl(&mut self, key: &[u8]) -> Value {
+        match self.entry(key) {
+            None => Value::Int(-2),
+            Some(Entry {
+                expire_at: None, ..
+            }) => Value::Int(-1),
+            Some(Entry {
+                expire_at: Some(t), ..
+            }) => {
+                // round up like Redis does
+                let remaining = t.duration_since(Instant::now()).as_millis() as i64;
+                Value::Int((remaining + 999) / 1000)
+            }
+        }
+    }
+
+    pub fn incr(&mut self, key: Vec<u8>, delta: i64) -> RedisResult<Value> {
+        // remove the key if expired
+        self.entry(&key);
+        let entry = self.data.entry(key).or_insert(Entry {
+            value: b"0".to_vec(),
+            expire_at: None,
+        });
+        let current: i64 = std::str::from_utf8(&entry.value)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| {
+                RedisError::new(
+                    ErrorKind::ResponseError,
+                    "An error was signalled by the server",
+                )
+                .with_detail("value is not an integer or out of range")
+            })?;
+        let value = current.checked_add(delta).ok_or_else(|| {
+            RedisError::new(
+                ErrorKind::ResponseError,
+                "An error was signalled by the server",
+            )
+            .with_detail("increment or decrement would overflow")
+        })?;
+        // the TTL is preserved
+        entry.value = value.to_string().into_bytes();
+        Ok(Value::Int(value))
+    }
+
+    pub fn subscribe(&mut self, channel: String, tx: Arc<Sender>) {
+        self.channels.entry(channel).or_default().push(tx);
+    }
+
+    /// Returns the subscribers of the channel.
+    pub fn subscribers(&self, channel: &str) -> Vec<Arc<Sender>> {
+        self.channels.get(channel).cloned().unwrap_or_default()
+    }
+
+    /// Removes a subscriber whose connection has been closed.
+    pub fn unsubscribe(&mut self, channel: &str, tx: &Arc<Sender>) {
+        if let Some(subscribers) = self.channels.get_mut(channel) {
+            subscribers.retain(|s| !Arc::ptr_eq(s, tx));
+            if subscribers.is_empty() {
+                self.channels.remove(channel);
+            }
+        }
+    }
+}
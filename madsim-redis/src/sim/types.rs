@@ -0,0 +1,334 @@
+use futures_util::future::BoxFuture;
+use std::{fmt, io};
+
+/// Library generic result type.
+pub type RedisResult<T> = Result<T, RedisError>;
+
+/// Library generic future type.
+pub type RedisFuture<'a, T> = BoxFuture<'a, RedisResult<T>>;
+
+/// Internal low-level redis value enum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// A nil response from the server.
+    Nil,
+    /// An integer response.
+    Int(i64),
+    /// An arbitrary binary data.
+    Data(Vec<u8>),
+    /// A bulk response of more data.
+    Bulk(Vec<Value>),
+    /// A status response.
+    Status(String),
+    /// A status response which represents the string "OK".
+    Okay,
+}
+
+/// An enum of all error kinds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The server generated an invalid response.
+    ResponseError,
+    /// The authentication with the server failed.
+    AuthenticationFailed,
+    /// Operation failed because of a type mismatch.
+    TypeError,
+    /// An error that was caused because the parameter to the client were wrong.
+    InvalidClientConfig,
+    /// This kind is returned if the redis error is one that is not native to the system.
+    IoError,
+    /// An error raised that was identified on the client before execution.
+    ClientError,
+}
+
+/// Represents a redis error.
+#[derive(Debug, Clone)]
+pub struct RedisError {
+    kind: ErrorKind,
+    desc: &'static str,
+    detail: Option<String>,
+}
+
+impl RedisError {
+    pub(crate) fn new(kind: ErrorKind, desc: &'static str) -> Self {
+        RedisError {
+            kind,
+            desc,
+            detail: None,
+        }
+    }
+
+    pub(crate) fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Returns the kind of the error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the error detail.
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_deref()
+    }
+
+    /// Returns the raw error code if available.
+    pub fn code(&self) -> Option<&str> {
+        match self.kind {
+            ErrorKind::ResponseError => Some("ERR"),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the error category for display purposes.
+    pub fn category(&self) -> &str {
+        match self.kind {
+            ErrorKind::ResponseError => "response error",
+            ErrorKind::AuthenticationFailed => "authentication failed",
+            ErrorKind::TypeError => "type error",
+            ErrorKind::InvalidClientConfig => "invalid client config",
+            ErrorKind::IoError => "I/O error",
+            ErrorKind::ClientError => "client error",
+        }
+    }
+
+    /// Indicates that this failure is an IO failure.
+    pub fn is_io_error(&self) -> bool {
+        self.kind == ErrorKind::IoError
+    }
+}
+
+impl fmt::Display for RedisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.desc)?;
+        if let Some(detail) = &self.detail {
+            write!(f, ": {detail}")?;
+        }
+        write!(f, " - {}", self.category())
+    }
+}
+
+impl std::error::Error for RedisError {}
+
+impl From<io::Error> for RedisError {
+    fn from(e: io::Error) -> Self {
+        RedisError::new(ErrorKind::IoError, "I/O error").with_detail(e.to_string())
+    }
+}
+
+impl From<(ErrorKind, &'static str)> for RedisError {
+    fn from((kind, desc): (ErrorKind, &'static str)) -> Self {
+        RedisError::new(kind, desc)
+    }
+}
+
+/// Abstraction trait for redis command abstractions.
+pub trait RedisWrite {
+    /// Accepts a serialized redis command.
+    fn write_arg(&mut self, arg: &[u8]);
+
+    /// Accepts a serialized redis command.
+    fn write_arg_fmt(&mut self, arg: impl fmt::Display) {
+        self.write_arg(arg.to_string().as_bytes())
+    }
+}
+
+impl RedisWrite for Vec<Vec<u8>> {
+    fn write_arg(&mut self, arg: &[u8]) {
+        self.push(arg.to_owned());
+    }
+}
+
+/// Used to convert a value into one or multiple redis argument strings.
+pub trait ToRedisArgs {
+    /// Appends the arguments of this value to `out`.
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + RedisWrite;
+
+    /// Converts the value into a vector of redis arguments.
+    fn to_redis_args(&self) -> Vec<Vec<u8>> {
+        let mut out = vec![];
+        self.write_redis_args(&mut out);
+        out
+    }
+}
+
+impl ToRedisArgs for str {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + RedisWrite,
+    {
+        out.write_arg(self.as_bytes());
+    }
+}
+
+impl ToRedisArgs for String {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + RedisWrite,
+    {
+        out.write_arg(self.as_bytes());
+    }
+}
+
+impl ToRedisArgs for [u8] {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + RedisWrite,
+    {
+        out.write_arg(self);
+    }
+}
+
+impl ToRedisArgs for Vec<u8> {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + RedisWrite,
+    {
+        out.write_arg(self);
+    }
+}
+
+impl<T: ToRedisArgs> ToRedisArgs for [T] {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + RedisWrite,
+    {
+        for item in self {
+            item.write_redis_args(out);
+        }
+    }
+}
+
+impl<T: ToRedisArgs> ToRedisArgs for Vec<T> {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + RedisWrite,
+    {
+        self.as_slice().write_redis_args(out);
+    }
+}
+
+impl<T: ToRedisArgs, const N: usize> ToRedisArgs for [T; N] {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + RedisWrite,
+    {
+        self.as_slice().write_redis_args(out);
+    }
+}
+
+impl<T: ToRedisArgs + ?Sized> ToRedisArgs for &T {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + RedisWrite,
+    {
+        (*self).write_redis_args(out);
+    }
+}
+
+macro_rules! to_redis_args_for_int {
+    ($($t:ty),*) => {$(
+        impl ToRedisArgs for $t {
+            fn write_redis_args<W>(&self, out: &mut W)
+            where
+                W: ?Sized + RedisWrite,
+            {
+                out.write_arg_fmt(self);
+            }
+        }
+    )*};
+}
+to_redis_args_for_int!(i8, i16, u16, i32, u32, i64, u64, isize, usize);
+
+/// This trait is used to convert a redis value into a more appropriate type.
+pub trait FromRedisValue: Sized {
+    /// Given a redis `Value` this attempts to convert it into the given destination type.
+    fn from_redis_value(v: &Value) -> RedisResult<Self>;
+}
+
+fn invalid_type(v: &Value, target: &str) -> RedisError {
+    RedisError::new(ErrorKind::TypeError, "Response was of incompatible type")
+        .with_detail(format!("{v:?} (response was not convertible to {target})"))
+}
+
+impl FromRedisValue for Value {
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        Ok(v.clone())
+    }
+}
+
+impl FromRedisValue for () {
+    fn from_redis_value(_v: &Value) -> RedisResult<Self> {
+        Ok(())
+    }
+}
+
+impl FromRedisValue for String {
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        match v {
+            Value::Data(data) => {
+                String::from_utf8(data.clone()).map_err(|_| invalid_type(v, "string"))
+            }
+            Value::Int(i) => Ok(i.to_string()),
+            Value::Status(s) => Ok(s.clone()),
+            Value::Okay => Ok("OK".into()),
+            Value::Nil | Value::Bulk(_) => Err(invalid_type(v, "string")),
+        }
+    }
+}
+
+impl FromRedisValue for Vec<u8> {
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        match v {
+            Value::Data(data) => Ok(data.clone()),
+            Value::Int(i) => Ok(i.to_string().into_bytes()),
+            Value::Status(s) => Ok(s.clone().into_bytes()),
+            Value::Okay => Ok(b"OK".to_vec()),
+            Value::Nil | Value::Bulk(_) => Err(invalid_type(v, "bytes")),
+        }
+    }
+}
+
+impl FromRedisValue for bool {
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        match v {
+            Value::Nil => Ok(false),
+            Value::Int(i) => Ok(*i != 0),
+            Value::Data(data) if data == b"1" => Ok(true),
+            Value::Data(data) if data == b"0" => Ok(false),
+            Value::Okay => Ok(true),
+            _ => Err(invalid_type(v, "bool")),
+        }
+    }
+}
+
+impl<T: FromRedisValue> FromRedisValue for Option<T> {
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        match v {
+            Value::Nil => Ok(None),
+            _ => T::from_redis_value(v).map(Some),
+        }
+    }
+}
+
+macro_rules! from_redis_value_for_int {
+    ($($t:ty),*) => {$(
+        impl FromRedisValue for $t {
+            fn from_redis_value(v: &Value) -> RedisResult<Self> {
+                match v {
+                    Value::Int(i) => <$t>::try_from(*i).map_err(|_| invalid_type(v, stringify!($t))),
+                    Value::Data(data) => std::str::from_utf8(data)
+                        .ok()
+                        .and_then(|s| s.parse().ok())
+                        .ok_or_else(|| invalid_type(v, stringify!($t))),
+                    _ => Err(invalid_type(v, stringify!($t))),
+                }
+            }
+        }
+    )*};
+}
+from_redis_value_for_int!(i8, i16, u16, i32, u32, i64, u64, isize, usize);
@@ -0,0 +1,188 @@
+#![cfg(madsim)]
+
+use futures_util::StreamExt;
+use madsim::{
+    net::NetSim,
+    runtime::{Handle, NodeHandle},
+    time::sleep,
+};
+use madsim_redis::{cmd, AsyncCommands, Client, ErrorKind, SimRedis};
+use std::{net::SocketAddr, time::Duration};
+
+const SERVER: &str = "10.0.0.1:6379";
+const URL: &str = "redis://10.0.0.1:6379/";
+
+/// Starts the Redis server.
+async fn start_server(handle: &Handle) {
+    let addr = SERVER.parse::<SocketAddr>().unwrap();
+    handle
+        .create_node()
+        .name("redis")
+        .ip(addr.ip())
+        .build()
+        .spawn(async move {
+            SimRedis::default().serve(addr).await.unwrap();
+        });
+    sleep(Duration::from_secs(1)).await;
+}
+
+fn client_node(handle: &Handle, id: u8) -> NodeHandle {
+    handle
+        .create_node()
+        .name(format!("client-{id}"))
+        .ip([10, 0, 1, id].into())
+        .build()
+}
+
+#[madsim::test]
+async fn commands() {
+    let handle = Handle::current();
+    start_server(&handle).await;
+
+    client_node(&handle, 1)
+        .spawn(async move {
+            let client = Client::open(URL).unwrap();
+            let mut conn = client.get_async_connection().await.unwrap();
+
+            let value: Option<String> = conn.get("key").await.unwrap();
+            assert_eq!(value, None);
+            conn.set::<_, _, ()>("key", "value").await.unwrap();
+            let value: String = conn.get("key").await.unwrap();
+            assert_eq!(value, "value");
+
+            let value: i64 = conn.incr("counter", 1).await.unwrap();
+            assert_eq!(value, 1);
+            let value: i64 = conn.incr("counter", 41).await.unwrap();
+            assert_eq!(value, 42);
+            let err = conn.incr::<_, _, i64>("key", 1).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ResponseError);
+
+            // low-level commands
+            let value: i64 = cmd("INCRBY")
+                .arg("counter")
+                .arg(-2)
+                .query_async(&mut conn)
+                .await
+                .unwrap();
+            assert_eq!(value, 40);
+            let err = cmd("GET")
+                .query_async::<_, ()>(&mut conn)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ResponseError);
+            let err = cmd("FLUSHALL")
+                .query_async::<_, ()>(&mut conn)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ResponseError);
+
+            let deleted: i64 = conn.del(["key", "counter", "missing"]).await.unwrap();
+            assert_eq!(deleted, 2);
+            let exists: bool = conn.get("key").await.unwrap();
+            assert!(!exists);
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn expire() {
+    let handle = Handle::current();
+    start_server(&handle).await;
+
+    client_node(&handle, 1)
+        .spawn(async move {
+            let client = Client::open(URL).unwrap();
+            let mut conn = client.get_async_connection().await.unwrap();
+
+            conn.set_ex::<_, _, ()>("lock", "owner", 10).await.unwrap();
+            conn.set::<_, _, ()>("key", "value").await.unwrap();
+            let ttl: i64 = conn.ttl("lock").await.unwrap();
+            assert_eq!(ttl, 10);
+            let ttl: i64 = conn.ttl("key").await.unwrap();
+            assert_eq!(ttl, -1);
+            let set: bool = conn.expire("key", 5).await.unwrap();
+            assert!(set);
+            let set: bool = conn.expire("missing", 5).await.unwrap();
+            assert!(!set);
+
+            // keys expire with the simulated time
+            sleep(Duration::from_secs(6)).await;
+            let value: Option<String> = conn.get("key").await.unwrap();
+            assert_eq!(value, None);
+            let value: Option<String> = conn.get("lock").await.unwrap();
+            assert_eq!(value.as_deref(), Some("owner"));
+            sleep(Duration::from_secs(5)).await;
+            let value: Option<String> = conn.get("lock").await.unwrap();
+            assert_eq!(value, None);
+            let ttl: i64 = conn.ttl("lock").await.unwrap();
+            assert_eq!(ttl, -2);
+        })
+        .await
+        .unwrap();
+}
+
+#[madsim::test]
+async fn pubsub() {
+    let handle = Handle::current();
+    start_server(&handle).await;
+
+    let subscriber = client_node(&handle, 1).spawn(async move {
+        let client = Client::open(URL).unwrap();
+        let mut pubsub = client.get_async_connection().await.unwrap().into_pubsub();
+        pubsub.subscribe("events").await.unwrap();
+        let mut stream = pubsub.on_message();
+        let mut received = vec![];
+        for _ in 0..3 {
+            let msg = stream.next().await.unwrap();
+            assert_eq!(msg.get_channel_name(), "events");
+            received.push(msg.get_payload::<String>().unwrap());
+        }
+        received
+    });
+    sleep(Duration::from_secs(1)).await;
+
+    client_node(&handle, 2)
+        .spawn(async move {
+            let client = Client::open(URL).unwrap();
+            let mut conn = client.get_async_connection().await.unwrap();
+            for i in 0..3 {
+                let receivers: i64 = conn.publish("events", format!("event-{i}")).await.unwrap();
+                assert_eq!(receivers, 1);
+            }
+            let receivers: i64 = conn.publish("other", "ignored").await.unwrap();
+            assert_eq!(receivers, 0);
+        })
+        .await
+        .unwrap();
+
+    let received = subscriber.await.unwrap();
+    assert_eq!(received, ["event-0", "event-1", "event-2"]);
+}
+
+#[madsim::test]
+async fn partition() {
+    let handle = Handle::current();
+    start_server(&handle).await;
+
+    let client = client_node(&handle, 1);
+    let client_id = client.id();
+    client
+        .spawn(async move {
+            let client = Client::open(URL).unwrap();
+            let mut conn = client.get_async_connection().await.unwrap();
+            conn.set::<_, _, ()>("key", "value").await.unwrap();
+
+            // requests fail while the client is partitioned from the server
+            NetSim::current().clog_node(client_id);
+            let result =
+                madsim::time::timeout(Duration::from_secs(5), conn.get::<_, String>("key")).await;
+            assert!(!matches!(result, Ok(Ok(_))));
+
+            NetSim::current().unclog_node(client_id);
+            let value: String = conn.get("key").await.unwrap();
+            assert_eq!(value, "value");
+        })
+        .await
+        .unwrap();
+}
@@ -363,7 +363,14 @@ fn generate_server_streaming<T: Method>(
             request.set_remote_addr(remote_addr);
             let res: Result<tonic::Response<_>, tonic::Status> = (*inner).#method_ident(request).await;
             match res {
-                Ok(rsp) => Ok(rsp.into_inner().map(|res| res.map(|rsp| Box::new(rsp) as BoxMessage)).boxed()),
+                Ok(mut rsp) => {
+                    // response headers, followed by the messages
+                    let metadata = std::mem::take(rsp.metadata_mut());
+                    let headers = stream::once(async move { Ok(Box::new(metadata) as BoxMessage) });
+                    let messages = rsp.into_inner().map(|res| res.map(|rsp| Box::new(rsp) as BoxMessage));
+                    Ok(headers.chain(messages).boxed())
+                }
+                // trailers-only response
                 Err(err) => Ok(stream::once(async move { Err(err) }).boxed()),
             }
         })
@@ -382,10 +389,14 @@ fn generate_client_streaming<T: Method>(
     quote! {
         let inner = self.inner.clone();
         Box::pin(async move {
+            let StreamHead(head) = *req.next().await.unwrap().unwrap()
+                .downcast::<StreamHead>()
+                .unwrap();
             let stream = req
                 .map(|res| res.map(|msg| *msg.downcast::<#request>().unwrap()))
                 .boxed();
-            let mut request = tonic::Request::new(tonic::Streaming::from_stream(stream));
+            let (metadata, extensions, ()) = head.into_parts();
+            let mut request = tonic::Request::from_parts(metadata, extensions, tonic::Streaming::from_stream(stream));
             request.set_remote_addr(remote_addr);
             let res: Result<tonic::Response<_>, tonic::Status> = (*inner).#method_ident(request).await;
             Ok(stream::once(async move { res.map(|rsp| Box::new(rsp) as BoxMessage) }).boxed())
@@ -405,14 +416,25 @@ fn generate_streaming<T: Method>(
     quote! {
         let inner = self.inner.clone();
         Box::pin(async move {
+            let StreamHead(head) = *req.next().await.unwrap().unwrap()
+                .downcast::<StreamHead>()
+                .unwrap();
             let stream = req
                 .map(|res| res.map(|msg| *msg.downcast::<#request>().unwrap()))
                 .boxed();
-            let mut request = tonic::Request::new(tonic::Streaming::from_stream(stream));
+            let (metadata, extensions, ()) = head.into_parts();
+            let mut request = tonic::Request::from_parts(metadata, extensions, tonic::Streaming::from_stream(stream));
             request.set_remote_addr(remote_addr);
             let res: Result<tonic::Response<_>, tonic::Status> = (*inner).#method_ident(request).await;
             match res {
-                Ok(rsp) => Ok(rsp.into_inner().map(|res| res.map(|rsp| Box::new(rsp) as BoxMessage)).boxed()),
+                Ok(mut rsp) => {
+                    // response headers, followed by the messages
+                    let metadata = std::mem::take(rsp.metadata_mut());
+                    let headers = stream::once(async move { Ok(Box::new(metadata) as BoxMessage) });
+                    let messages = rsp.into_inner().map(|res| res.map(|rsp| Box::new(rsp) as BoxMessage));
+                    Ok(headers.chain(messages).boxed())
+                }
+                // trailers-only response
                 Err(err) => Ok(stream::once(async move { Err(err) }).boxed()),
             }
         })
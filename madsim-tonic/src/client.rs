@@ -2,9 +2,22 @@
 
 use futures_util::{pin_mut, Stream, StreamExt};
 use tonic::codegen::http::uri::PathAndQuery;
-use tracing::instrument;
+use tracing::{debug, instrument};
 
-use crate::{codegen::BoxMessage, Request, Response, Status, Streaming};
+use crate::{
+    codec::EndOfStream,
+    codegen::{BoxMessage, StreamHead},
+    deadline,
+    flow::RecvWindow,
+    keepalive,
+    metadata::MetadataMap,
+    propagate,
+    simulation::WaitForReady,
+    status::from_io_error,
+    Request, Response, Status, Streaming,
+};
+use std::{io, time::Duration};
+use tokio::sync::OwnedSemaphorePermit;
 
 #[derive(Debug, Clone)]
 pub struct Grpc<T> {
@@ -28,7 +41,7 @@ impl Grpc<crate::transport::Channel> {
     #[instrument(name = "rpc", skip_all, fields(?path))]
     pub async fn unary<M1, M2, C>(
         &mut self,
-        request: Request<M1>,
+        mut request: Request<M1>,
         path: PathAndQuery,
         _codec: C,
     ) -> Result<Response<M2>, Status>
@@ -36,27 +49,41 @@ impl Grpc<crate::transport::Channel> {
         M1: Send + Sync + 'static,
         M2: Send + Sync + 'static,
     {
-        let addr = self.inner.ep.peer_addr().unwrap();
-        let (tx, mut rx) = self.inner.ep.connect1(addr).await?;
-        // send request
-        tx.send(Box::new((path, Box::new(request) as BoxMessage)))
-            .await?;
-        // receive response
-        let rsp = rx.recv().await?;
-        let rsp = *rsp
-            .downcast::<Result<BoxMessage, Status>>()
-            .expect("message type mismatch");
-        let rsp = *rsp?
-            .downcast::<Response<M2>>()
-            .expect("message type mismatch");
-        Ok(rsp)
+        let timeout = deadline::request_timeout(&mut request);
+        propagate::inject(&mut request);
+        let call = deadline::with_timeout(timeout, async {
+            let _slot = self.buffer_slot().await;
+            let (tx, mut rx) = self.connect(timeout, wait_for_ready(&request)).await?;
+            // send request
+            self.attach_identity(&mut request);
+            let metadata = request.metadata().clone();
+            tx.send(Box::new((
+                path,
+                timeout,
+                None::<u32>,
+                metadata,
+                Box::new(request) as BoxMessage,
+            )))
+            .await
+            .map_err(from_io_error)?;
+            // receive response
+            let rsp = rx.recv().await.map_err(from_io_error)?;
+            let rsp = *rsp
+                .downcast::<Result<BoxMessage, Status>>()
+                .expect("message type mismatch");
+            let rsp = *rsp?
+                .downcast::<Response<M2>>()
+                .expect("message type mismatch");
+            Ok(rsp)
+        });
+        keepalive::watch(&self.inner.ep, self.inner.keep_alive, call).await
     }
 
     /// Send a client side streaming gRPC request.
     #[instrument(name = "rpc", skip_all, fields(?path))]
     pub async fn client_streaming<M1, M2, C>(
         &mut self,
-        request: Request<impl Stream<Item = M1> + Send + 'static>,
+        mut request: Request<impl Stream<Item = M1> + Send + 'static>,
         path: PathAndQuery,
         _codec: C,
     ) -> Result<Response<M2>, Status>
@@ -64,26 +91,31 @@ impl Grpc<crate::transport::Channel> {
         M1: Send + Sync + 'static,
         M2: Send + Sync + 'static,
     {
-        let addr = self.inner.ep.peer_addr().unwrap();
-        let (tx, mut rx) = self.inner.ep.connect1(addr).await?;
-        // send requests
-        self.send_request_stream(request, tx, path).await?;
-        // receive response
-        let rsp = rx.recv().await?;
-        let rsp = *rsp
-            .downcast::<Result<BoxMessage, Status>>()
-            .expect("message type mismatch");
-        let rsp = *rsp?
-            .downcast::<Response<M2>>()
-            .expect("message type mismatch");
-        Ok(rsp)
+        let timeout = deadline::request_timeout(&mut request);
+        propagate::inject(&mut request);
+        let call = deadline::with_timeout(timeout, async {
+            let _slot = self.buffer_slot().await;
+            let (tx, mut rx) = self.connect(timeout, wait_for_ready(&request)).await?;
+            // send requests
+            self.send_request_stream(request, tx, path, timeout).await?;
+            // receive response
+            let rsp = rx.recv().await.map_err(from_io_error)?;
+            let rsp = *rsp
+                .downcast::<Result<BoxMessage, Status>>()
+                .expect("message type mismatch");
+            let rsp = *rsp?
+                .downcast::<Response<M2>>()
+                .expect("message type mismatch");
+            Ok(rsp)
+        });
+        keepalive::watch(&self.inner.ep, self.inner.keep_alive, call).await
     }
 
     /// Send a server side streaming gRPC request.
     #[instrument(name = "rpc", skip_all, fields(?path))]
     pub async fn server_streaming<M1, M2, C>(
         &mut self,
-        request: Request<M1>,
+        mut request: Request<M1>,
         path: PathAndQuery,
         _codec: C,
     ) -> Result<Response<Streaming<M2>>, Status>
@@ -91,20 +123,38 @@ impl Grpc<crate::transport::Channel> {
         M1: Send + Sync + 'static,
         M2: Send + Sync + 'static,
     {
-        let addr = self.inner.ep.peer_addr().unwrap();
-        let (tx, rx) = self.inner.ep.connect1(addr).await?;
+        let timeout = deadline::request_timeout(&mut request);
+        propagate::inject(&mut request);
+        let slot = self.buffer_slot().await;
+        let (tx, mut rx) = self.connect(timeout, wait_for_ready(&request)).await?;
         // send request
-        tx.send(Box::new((path, Box::new(request) as BoxMessage)))
-            .await?;
+        self.attach_identity(&mut request);
+        let metadata = request.metadata().clone();
+        let window = self.inner.stream_window;
+        tx.send(Box::new((
+            path,
+            timeout,
+            window,
+            metadata,
+            Box::new(request) as BoxMessage,
+        )))
+        .await
+        .map_err(from_io_error)?;
+        // receive response headers
+        let metadata = recv_headers(&mut rx).await?;
+        drop(slot);
         // receive responses
-        Ok(Response::new(Streaming::new(rx, None)))
+        let window = window.map(|size| RecvWindow::new(size, tx));
+        let mut response = Response::new(Streaming::new(rx, None, window));
+        *response.metadata_mut() = metadata;
+        Ok(response)
     }
 
     /// Send a bi-directional streaming gRPC request.
     #[instrument(name = "rpc", skip_all, fields(?path))]
     pub async fn streaming<M1, M2, C>(
         &mut self,
-        request: Request<impl Stream<Item = M1> + Send + 'static>,
+        mut request: Request<impl Stream<Item = M1> + Send + 'static>,
         path: PathAndQuery,
         _codec: C,
     ) -> Result<Response<Streaming<M2>>, Status>
@@ -112,36 +162,124 @@ impl Grpc<crate::transport::Channel> {
         M1: Send + Sync + 'static,
         M2: Send + Sync + 'static,
     {
-        let addr = self.inner.ep.peer_addr().unwrap();
-        let (tx, rx) = self.inner.ep.connect1(addr).await?;
+        let timeout = deadline::request_timeout(&mut request);
+        propagate::inject(&mut request);
+        let slot = self.buffer_slot().await;
+        let (tx, mut rx) = self.connect(timeout, wait_for_ready(&request)).await?;
         // send requests in a background task
         let this = self.clone();
         let task = madsim::task::spawn(async move {
-            this.send_request_stream(request, tx, path).await.unwrap();
+            this.send_request_stream(request, tx, path, timeout)
+                .await
+                .unwrap();
         });
+        // receive response headers
+        let metadata = recv_headers(&mut rx).await?;
+        drop(slot);
         // receive responses
-        Ok(Response::new(Streaming::new(rx, Some(task))))
+        let mut response = Response::new(Streaming::new(rx, Some(task), None));
+        *response.metadata_mut() = metadata;
+        Ok(response)
+    }
+
+    /// Opens a connection to the server for a call.
+    ///
+    /// If the call waits for ready, a refused connection is retried with
+    /// backoff until the call times out.
+    async fn connect(
+        &self,
+        timeout: Option<Duration>,
+        wait_for_ready: bool,
+    ) -> Result<(madsim::net::Sender, madsim::net::Receiver), Status> {
+        let addr = self.inner.ep.peer_addr().unwrap();
+        if !wait_for_ready {
+            return self.inner.ep.connect1(addr).await.map_err(from_io_error);
+        }
+        deadline::with_timeout(timeout, async {
+            let mut wait = Duration::from_millis(1);
+            loop {
+                match self.inner.ep.connect1(addr).await {
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        debug!(?addr, ?wait, "server not ready, retrying");
+                        madsim::time::sleep(wait).await;
+                        wait = (wait * 2).min(Duration::from_secs(1));
+                    }
+                    ret => return ret.map_err(from_io_error),
+                }
+            }
+        })
+        .await
     }
 
     async fn send_request_stream<M1>(
         &self,
-        request: Request<impl Stream<Item = M1> + Send + 'static>,
+        mut request: Request<impl Stream<Item = M1> + Send + 'static>,
         tx: madsim::net::Sender,
         path: PathAndQuery,
+        timeout: Option<Duration>,
     ) -> Result<(), Status>
     where
         M1: Send + Sync + 'static,
     {
-        // TODO: send `Request` metadata
-        // send stream start message
-        tx.send(Box::new((path, Box::new(()) as BoxMessage)))
-            .await?;
+        // send stream start message with the metadata and extensions
+        self.attach_identity(&mut request);
+        let (metadata, extensions, stream) = request.into_parts();
+        let head = Request::from_parts(metadata.clone(), extensions, ());
+        tx.send(Box::new((
+            path,
+            timeout,
+            None::<u32>,
+            metadata,
+            Box::new(StreamHead(head)) as BoxMessage,
+        )))
+        .await
+        .map_err(from_io_error)?;
         // send requests
-        let stream = request.into_inner();
         pin_mut!(stream);
         while let Some(request) = stream.next().await {
-            tx.send(Box::new(request) as BoxMessage).await?;
+            tx.send(Box::new(request) as BoxMessage)
+                .await
+                .map_err(from_io_error)?;
         }
+        tx.send(Box::new(EndOfStream))
+            .await
+            .map_err(from_io_error)?;
         Ok(())
     }
+
+    /// Waits for a free slot in the request buffer of the channel.
+    async fn buffer_slot(&self) -> Option<OwnedSemaphorePermit> {
+        let buffer = self.inner.buffer.clone()?;
+        Some(buffer.acquire_owned().await.expect("semaphore closed"))
+    }
+
+    /// Attaches the identity of the channel to the request.
+    #[allow(unused_variables)]
+    fn attach_identity<T>(&self, request: &mut Request<T>) {
+        #[cfg(feature = "tls")]
+        if let Some(cert) = &self.inner.client_cert {
+            let certs = crate::simulation::PeerCerts(std::sync::Arc::new(vec![cert.clone()]));
+            request.extensions_mut().insert(certs);
+        }
+    }
+}
+
+/// Returns whether the call waits for the server to become ready.
+fn wait_for_ready<T>(request: &Request<T>) -> bool {
+    request.extensions().get::<WaitForReady>().is_some()
+}
+
+/// Receives the headers of a streaming response.
+///
+/// If the server rejects the call before sending any message, a trailers-only
+/// response is received and returned as the error.
+async fn recv_headers(rx: &mut madsim::net::Receiver) -> Result<MetadataMap, Status> {
+    let msg = rx.recv().await.map_err(from_io_error)?;
+    let headers = *msg
+        .downcast::<Result<BoxMessage, Status>>()
+        .expect("message type mismatch");
+    let metadata = *headers?
+        .downcast::<MetadataMap>()
+        .expect("message type mismatch");
+    Ok(metadata)
 }
@@ -1,4 +1,4 @@
-use crate::{codegen::BoxMessage, Status};
+use crate::{codegen::BoxMessage, flow::RecvWindow, status::from_io_error, Status};
 use async_stream::try_stream;
 use futures_util::{Stream, StreamExt};
 use madsim::task::JoinHandle;
@@ -9,6 +9,11 @@ use std::{
 };
 use tonic::codegen::BoxStream;
 
+/// Sent after the last message of a stream.
+///
+/// A stream closed without this marker was broken, e.g. by a crash of the peer.
+pub(crate) struct EndOfStream;
+
 /// Streaming requests and responses.
 pub struct Streaming<T> {
     stream: BoxStream<T>,
@@ -19,9 +24,11 @@ impl<T: Send + 'static> Streaming<T> {
     ///
     /// The elements will be received from the endpoint starting with the given tag.
     /// If this is a bi-directional streaming RPC, `request_sending_task` is required.
+    /// If the stream is flow-controlled, the window is released as messages are read.
     pub(crate) fn new(
         mut rx: madsim::net::Receiver,
         request_sending_task: Option<JoinHandle<()>>,
+        mut window: Option<RecvWindow>,
     ) -> Self {
         Streaming {
             stream: try_stream! {
@@ -29,9 +36,18 @@ impl<T: Send + 'static> Streaming<T> {
                 // This is used to cancel the task when the stream is dropped.
                 let _task = request_sending_task.map(|t| t.cancel_on_drop());
                 // receive messages
-                while let Ok(msg) = rx.recv().await {
+                loop {
+                    let msg = rx.recv().await.map_err(from_io_error)?;
+                    if msg.is::<EndOfStream>() {
+                        break;
+                    }
                     let msg = *msg.downcast::<Result<BoxMessage, Status>>().unwrap();
-                    yield *msg?.downcast::<T>().unwrap();
+                    let msg = *msg?.downcast::<T>().unwrap();
+                    if let Some(window) = &mut window {
+                        // the server may have gone
+                        let _ = window.release().await;
+                    }
+                    yield msg;
                 }
             }
             .boxed(),
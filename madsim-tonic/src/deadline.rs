@@ -0,0 +1,130 @@
+//! Deadline propagation.
+//!
+//! When a request with `grpc-timeout` arrives at the server, the deadline is
+//! stored in a task-local slot while its handler runs. Any outbound call made
+//! by the handler is then bounded by the remaining time, and the remaining
+//! time is sent to the downstream service as its `grpc-timeout`.
+
+use crate::{Request, Status};
+use futures_util::future::BoxFuture;
+use madsim::time::{timeout, Instant};
+use std::{
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+thread_local! {
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Returns the deadline of the request being handled by the current task.
+fn current() -> Option<Instant> {
+    DEADLINE.with(|d| d.get())
+}
+
+/// Runs the future with the deadline set in the task-local slot.
+pub(crate) fn scope<F>(deadline: Option<Instant>, future: F) -> Scope<F::Output>
+where
+    F: Future + Send + 'static,
+{
+    Scope {
+        deadline,
+        future: Box::pin(future),
+    }
+}
+
+/// The future returned by [`scope`].
+pub(crate) struct Scope<T> {
+    deadline: Option<Instant>,
+    future: BoxFuture<'static, T>,
+}
+
+impl<T> Future for Scope<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let prev = DEADLINE.with(|d| d.replace(self.deadline));
+        let ret = self.future.as_mut().poll(cx);
+        DEADLINE.with(|d| d.set(prev));
+        ret
+    }
+}
+
+/// Returns the timeout of an outbound request.
+///
+/// This is the smaller one of the timeout set on the request and the remaining
+/// time of the current request context. The result is written back to the
+/// `grpc-timeout` header of the request.
+pub(crate) fn request_timeout<T>(request: &mut Request<T>) -> Option<Duration> {
+    let timeout = request
+        .metadata()
+        .get(GRPC_TIMEOUT_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_grpc_timeout);
+    let remaining = current().map(|d| d.saturating_duration_since(Instant::now()));
+    let timeout = match (timeout, remaining) {
+        (Some(t), Some(r)) => Some(t.min(r)),
+        (t, r) => t.or(r),
+    };
+    if let Some(t) = timeout {
+        let value = format_grpc_timeout(t).parse().unwrap();
+        request.metadata_mut().insert(GRPC_TIMEOUT_HEADER, value);
+    }
+    timeout
+}
+
+/// Runs the future with an optional timeout.
+pub(crate) async fn with_timeout<T>(
+    duration: Option<Duration>,
+    future: impl Future<Output = Result<T, Status>>,
+) -> Result<T, Status> {
+    match duration {
+        Some(duration) => timeout(duration, future)
+            .await
+            .map_err(|_| Status::deadline_exceeded("Timeout expired"))?,
+        None => future.await,
+    }
+}
+
+/// Parses a `grpc-timeout` header value.
+fn parse_grpc_timeout(s: &str) -> Option<Duration> {
+    if s.is_empty() || s.len() > 9 {
+        return None;
+    }
+    let (value, unit) = s.split_at(s.len() - 1);
+    let value: u64 = value.parse().ok()?;
+    let duration = match unit {
+        "H" => Duration::from_secs(value * 60 * 60),
+        "M" => Duration::from_secs(value * 60),
+        "S" => Duration::from_secs(value),
+        "m" => Duration::from_millis(value),
+        "u" => Duration::from_micros(value),
+        "n" => Duration::from_nanos(value),
+        _ => return None,
+    };
+    Some(duration)
+}
+
+/// Formats a duration as a `grpc-timeout` header value in the finest unit
+/// that fits in 8 digits.
+fn format_grpc_timeout(duration: Duration) -> String {
+    const MAX: u128 = 99_999_999;
+    let units = [
+        ('n', duration.as_nanos()),
+        ('u', duration.as_micros()),
+        ('m', duration.as_millis()),
+        ('S', duration.as_secs() as u128),
+        ('M', duration.as_secs() as u128 / 60),
+        ('H', duration.as_secs() as u128 / 60 / 60),
+    ];
+    let (unit, value) = units
+        .into_iter()
+        .find(|&(_, v)| v <= MAX)
+        .expect("duration is unrealistically large");
+    format!("{value}{unit}")
+}
@@ -0,0 +1,77 @@
+//! HTTP/2 flow control of streaming responses.
+//!
+//! A client with a stream window lets the server of a server streaming call
+//! send at most that many messages ahead of those it has read. As the client
+//! reads the messages, it grants the server more with window updates. A server
+//! that has used up the window waits for an update before sending the next
+//! message, so a slow reader backpressures the stream.
+//!
+//! The window is counted in messages rather than bytes. The response headers
+//! and trailers are not flow-controlled.
+
+use madsim::net::{Receiver, Sender};
+use std::io;
+
+/// A window update, granting the server to send more messages.
+pub(crate) struct WindowUpdate(u32);
+
+/// The send window of a response stream on the server.
+pub(crate) struct SendWindow {
+    available: u32,
+    rx: Receiver,
+}
+
+impl SendWindow {
+    /// Creates a window of `size` messages, receiving updates from `rx`.
+    pub fn new(size: u32, rx: Receiver) -> Self {
+        SendWindow {
+            available: size,
+            rx,
+        }
+    }
+
+    /// Takes a slot to send a message, waiting for a window update if the
+    /// window is used up.
+    pub async fn acquire(&mut self) -> io::Result<()> {
+        while self.available == 0 {
+            let msg = self.rx.recv().await?;
+            let update = msg
+                .downcast::<WindowUpdate>()
+                .expect("message type mismatch");
+            self.available += update.0;
+        }
+        self.available -= 1;
+        Ok(())
+    }
+}
+
+/// The receive window of a response stream on the client.
+pub(crate) struct RecvWindow {
+    size: u32,
+    consumed: u32,
+    tx: Sender,
+}
+
+impl RecvWindow {
+    /// Creates a window of `size` messages, sending updates to `tx`.
+    pub fn new(size: u32, tx: Sender) -> Self {
+        RecvWindow {
+            size,
+            consumed: 0,
+            tx,
+        }
+    }
+
+    /// Releases the slot of a message read by the application.
+    ///
+    /// An update is sent once half of the window has been consumed, as hyper
+    /// does.
+    pub async fn release(&mut self) -> io::Result<()> {
+        self.consumed += 1;
+        if self.consumed >= (self.size / 2).max(1) {
+            let update = WindowUpdate(std::mem::take(&mut self.consumed));
+            self.tx.send(Box::new(update)).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+//! HTTP/2 keep-alive.
+//!
+//! While a call is in flight on a channel with keep-alive enabled, the client
+//! pings the server periodically. If a ping is not acknowledged in time, e.g.
+//! because the server has been partitioned away, the connection is considered
+//! dead and the call fails with `Unavailable` instead of hanging.
+
+use crate::Status;
+use futures_util::{select_biased, FutureExt};
+use madsim::{
+    net::Endpoint,
+    time::{sleep, timeout},
+};
+use std::{future::Future, io, time::Duration};
+use tracing::warn;
+
+/// The ping message. The server echoes it back.
+pub(crate) struct Ping;
+
+/// Keep-alive settings of a channel.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct KeepAlive {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+/// Runs the call while pinging the server.
+pub(crate) async fn watch<T>(
+    ep: &Endpoint,
+    keep_alive: Option<KeepAlive>,
+    call: impl Future<Output = Result<T, Status>>,
+) -> Result<T, Status> {
+    let keep_alive = match keep_alive {
+        Some(keep_alive) => keep_alive,
+        None => return call.await,
+    };
+    select_biased! {
+        res = call.fuse() => res,
+        _ = ping_until_dead(ep, keep_alive).fuse() => Err(Status::unavailable("keep-alive timed out")),
+    }
+}
+
+/// Returns when a ping fails.
+async fn ping_until_dead(ep: &Endpoint, keep_alive: KeepAlive) {
+    loop {
+        sleep(keep_alive.interval).await;
+        match timeout(keep_alive.timeout, ping(ep)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return warn!("keep-alive ping failed: {e}"),
+            Err(_) => return warn!(timeout = ?keep_alive.timeout, "keep-alive ping timed out"),
+        }
+    }
+}
+
+async fn ping(ep: &Endpoint) -> io::Result<()> {
+    let (tx, mut rx) = ep.connect1(ep.peer_addr()?).await?;
+    tx.send(Box::new(Ping)).await?;
+    rx.recv().await?;
+    Ok(())
+}
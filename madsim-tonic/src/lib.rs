@@ -6,3 +6,6 @@ mod sim;
 pub use sim::*;
 #[cfg(not(madsim))]
 pub use tonic::*;
+
+#[cfg(not(madsim))]
+pub mod testing;
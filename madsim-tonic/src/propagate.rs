@@ -0,0 +1,111 @@
+//! Header propagation.
+//!
+//! A service wrapped in [`PropagateHeaders`] propagates some headers of its
+//! requests, such as a request ID used for tracing. While a handler runs, the
+//! values of these headers are stored in a task-local slot, and added to every
+//! outbound call made by the handler that doesn't set them itself. So a header
+//! set at the edge reaches every service down the call chain.
+//!
+//! [`PropagateHeaders`]: crate::simulation::PropagateHeaders
+
+use crate::{
+    metadata::{Ascii, MetadataMap, MetadataValue},
+    Request,
+};
+use futures_util::Stream;
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// The propagated headers of a request.
+pub(crate) type Headers = Arc<[(&'static str, MetadataValue<Ascii>)]>;
+
+thread_local! {
+    static INBOUND: RefCell<Option<MetadataMap>> = const { RefCell::new(None) };
+    static HEADERS: RefCell<Option<Headers>> = const { RefCell::new(None) };
+}
+
+/// Calls the service with the metadata of the inbound request set, so that
+/// [`extract`] can find it.
+pub(crate) fn with_inbound<R>(metadata: &MetadataMap, f: impl FnOnce() -> R) -> R {
+    let prev = INBOUND.with(|m| m.replace(Some(metadata.clone())));
+    let ret = f();
+    INBOUND.with(|m| *m.borrow_mut() = prev);
+    ret
+}
+
+/// Extracts the headers in `names` from the metadata of the inbound request.
+pub(crate) fn extract(names: &[&'static str]) -> Option<Headers> {
+    let headers: Vec<_> = INBOUND.with(|m| {
+        let m = m.borrow();
+        let metadata = m.as_ref()?;
+        Some(
+            names
+                .iter()
+                .filter_map(|&name| Some((name, metadata.get(name)?.clone())))
+                .collect(),
+        )
+    })?;
+    if headers.is_empty() {
+        return None;
+    }
+    Some(headers.into())
+}
+
+/// Polls the future or stream with the headers set in the task-local slot.
+pub(crate) fn scope<T>(headers: Option<Headers>, inner: T) -> Scope<T> {
+    Scope {
+        headers,
+        inner: Box::pin(inner),
+    }
+}
+
+/// The future or stream returned by [`scope`].
+pub(crate) struct Scope<T> {
+    headers: Option<Headers>,
+    inner: Pin<Box<T>>,
+}
+
+impl<T> Scope<T> {
+    fn enter<R>(&mut self, f: impl FnOnce(Pin<&mut T>) -> R) -> R {
+        let prev = HEADERS.with(|h| h.replace(self.headers.clone()));
+        let ret = f(self.inner.as_mut());
+        HEADERS.with(|h| *h.borrow_mut() = prev);
+        ret
+    }
+}
+
+impl<T: Future> Future for Scope<T> {
+    type Output = T::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T::Output> {
+        self.enter(|inner| inner.poll(cx))
+    }
+}
+
+impl<T: Stream> Stream for Scope<T> {
+    type Item = T::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T::Item>> {
+        self.enter(|inner| inner.poll_next(cx))
+    }
+}
+
+/// Adds the headers of the current request context to an outbound request.
+///
+/// The headers already set on the request are kept.
+pub(crate) fn inject<T>(request: &mut Request<T>) {
+    let headers = match HEADERS.with(|h| h.borrow().clone()) {
+        Some(headers) => headers,
+        None => return,
+    };
+    for (name, value) in headers.iter() {
+        if !request.metadata().contains_key(*name) {
+            request.metadata_mut().insert(*name, value.clone());
+        }
+    }
+}
@@ -12,6 +12,13 @@ macro_rules! include_proto {
 
 pub mod client;
 pub mod codec;
+pub(crate) mod deadline;
+pub(crate) mod flow;
+pub(crate) mod keepalive;
+pub(crate) mod propagate;
+pub mod simulation;
+pub(crate) mod status;
+pub mod testing;
 pub(crate) mod tower;
 pub mod transport;
 
@@ -28,6 +35,10 @@ pub mod codegen {
     /// A type-erased stream of messages.
     pub type BoxMessageStream = BoxStream<BoxMessage>;
 
+    /// The first message of a request stream, carrying the request metadata.
+    #[doc(hidden)]
+    pub struct StreamHead(pub tonic::Request<()>);
+
     pub trait RequestExt {
         fn set_remote_addr(&mut self, addr: SocketAddr);
     }
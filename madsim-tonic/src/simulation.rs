@@ -0,0 +1,222 @@
+//! Extensions for simulation.
+//!
+//! These have no counterpart in `tonic`. Code using them should be gated with
+//! `#[cfg(madsim)]`.
+
+use crate::codegen::{
+    http::uri::PathAndQuery, BoxFuture, BoxMessageStream, Context, Poll, Service,
+};
+use crate::{metadata::MetadataMap, propagate, transport::NamedService, Status};
+use async_stream::stream;
+use futures_util::{FutureExt, StreamExt};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tracing::debug;
+
+/// A service wrapper that propagates some headers of the requests to the
+/// outbound calls made by their handlers, e.g. a request ID used to
+/// correlate traces.
+///
+/// While a request is handled, the headers are added to every call made by
+/// the handler that doesn't set them itself. If the downstream services
+/// propagate the headers too, they are carried along the whole call chain:
+///
+/// ```ignore
+/// Server::builder()
+///     .add_service(PropagateHeaders::new(svc, &["x-request-id"]))
+///     .serve(addr)
+///     .await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct PropagateHeaders<S> {
+    inner: S,
+    names: Vec<&'static str>,
+}
+
+impl<S> PropagateHeaders<S> {
+    /// Wraps the service to propagate the headers in `names`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a name is not a valid lowercase ASCII metadata key.
+    pub fn new(inner: S, names: &[&'static str]) -> Self {
+        for &name in names {
+            crate::metadata::AsciiMetadataKey::from_static(name);
+        }
+        PropagateHeaders {
+            inner,
+            names: names.to_vec(),
+        }
+    }
+}
+
+impl<S> Service<(SocketAddr, PathAndQuery, BoxMessageStream)> for PropagateHeaders<S>
+where
+    S: Service<
+        (SocketAddr, PathAndQuery, BoxMessageStream),
+        Response = BoxMessageStream,
+        Error = Infallible,
+        Future = BoxFuture<BoxMessageStream, Infallible>,
+    >,
+{
+    type Response = BoxMessageStream;
+    type Error = Infallible;
+    type Future = BoxFuture<BoxMessageStream, Infallible>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: (SocketAddr, PathAndQuery, BoxMessageStream)) -> Self::Future {
+        let headers = propagate::extract(&self.names);
+        let future = self.inner.call(req);
+        let stream_headers = headers.clone();
+        propagate::scope(headers, future)
+            .map(|res| res.map(|stream| propagate::scope(stream_headers, stream).boxed()))
+            .boxed()
+    }
+}
+
+impl<S: NamedService> NamedService for PropagateHeaders<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+/// A request extension that makes the call wait for the server to become
+/// ready instead of failing fast, like the `WaitForReady` call option of gRPC.
+///
+/// A call made while the server is unreachable, e.g. restarting, is retried
+/// with backoff until the server is reachable or the call times out:
+///
+/// ```ignore
+/// let mut request = Request::new(message);
+/// request.extensions_mut().insert(WaitForReady);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct WaitForReady;
+
+/// A request extension with the certificates of the client identity, set by
+/// `ClientTlsConfig::identity`.
+///
+/// `Request::peer_certs` is not supported in the simulation, and always
+/// returns `None`. Servers read the client certificates from this extension
+/// instead:
+///
+/// ```ignore
+/// let certs = request.extensions().get::<PeerCerts>();
+/// ```
+#[cfg(feature = "tls")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tls")))]
+#[derive(Debug, Clone)]
+pub struct PeerCerts(pub Arc<Vec<crate::transport::Certificate>>);
+
+/// Server-side faults of gRPC services.
+///
+/// Faults apply to the services wrapped by [`Faults::service`], and can be
+/// changed while the server is running. They are deterministic: a fault
+/// affects exactly the calls in its count.
+///
+/// ```ignore
+/// let faults = Faults::default();
+/// faults.fail_encode("/helloworld.Greeter/SayHello", 1);
+/// Server::builder()
+///     .add_service(faults.service(svc))
+///     .serve(addr)
+///     .await?;
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Faults {
+    /// The number of calls left to fail encoding, by method path.
+    encode_failures: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl Faults {
+    /// Wraps the service to inject the faults into its calls.
+    pub fn service<S>(&self, inner: S) -> WithFaults<S> {
+        WithFaults {
+            inner,
+            faults: self.clone(),
+        }
+    }
+
+    /// Fails to encode the responses of the next `n` calls to a method, like
+    /// `"/helloworld.Greeter/SayHello"`.
+    ///
+    /// The handlers of these calls run as usual, but their first response
+    /// message is replaced with an `Internal` status, as if it could not be
+    /// encoded, and the call ends there.
+    pub fn fail_encode(&self, path: &str, n: usize) {
+        self.encode_failures.lock().unwrap().insert(path.into(), n);
+    }
+
+    /// Removes all faults.
+    pub fn clear(&self) {
+        self.encode_failures.lock().unwrap().clear();
+    }
+
+    /// Returns whether the encoding of a call to the method should fail.
+    fn take_encode_failure(&self, path: &str) -> bool {
+        match self.encode_failures.lock().unwrap().get_mut(path) {
+            Some(n) if *n > 0 => {
+                *n -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A service wrapper that injects [`Faults`].
+#[derive(Debug, Clone)]
+pub struct WithFaults<S> {
+    inner: S,
+    faults: Faults,
+}
+
+impl<S> Service<(SocketAddr, PathAndQuery, BoxMessageStream)> for WithFaults<S>
+where
+    S: Service<
+        (SocketAddr, PathAndQuery, BoxMessageStream),
+        Response = BoxMessageStream,
+        Error = Infallible,
+        Future = BoxFuture<BoxMessageStream, Infallible>,
+    >,
+{
+    type Response = BoxMessageStream;
+    type Error = Infallible;
+    type Future = BoxFuture<BoxMessageStream, Infallible>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: (SocketAddr, PathAndQuery, BoxMessageStream)) -> Self::Future {
+        let path = req.1.clone();
+        let future = self.inner.call(req);
+        if !self.faults.take_encode_failure(path.path()) {
+            return future;
+        }
+        Box::pin(async move {
+            let mut stream = future.await?;
+            let stream = stream! {
+                while let Some(rsp) = stream.next().await {
+                    // the headers of a streaming response are sent as usual
+                    if matches!(&rsp, Ok(msg) if !msg.is::<MetadataMap>()) {
+                        debug!(?path, "failed to encode response");
+                        yield Err(Status::internal("Error encoding: injected failure"));
+                        break;
+                    }
+                    yield rsp;
+                }
+            };
+            Ok(stream.boxed())
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for WithFaults<S> {
+    const NAME: &'static str = S::NAME;
+}
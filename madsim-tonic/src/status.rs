@@ -0,0 +1,25 @@
+//! Status codes of transport errors.
+//!
+//! The errors of the simulated network are mapped to gRPC status codes as
+//! follows, so that clients can make the same retry decisions as on a real
+//! network:
+//!
+//! - connection refused, e.g. the server is down or partitioned away: `Unavailable`
+//! - connection reset, e.g. the server crashed during a call: `Unavailable`
+//! - connection timed out, or a keep-alive ping not acknowledged: `Unavailable`
+//! - the deadline of the call exceeded: `DeadlineExceeded`
+//!
+//! Other I/O errors are converted as by tonic.
+
+use crate::Status;
+use std::io;
+
+/// Converts an error of the simulated network to a status.
+pub(crate) fn from_io_error(err: io::Error) -> Status {
+    use io::ErrorKind::*;
+    match err.kind() {
+        ConnectionRefused | ConnectionReset | ConnectionAborted | NotConnected | BrokenPipe
+        | TimedOut | UnexpectedEof => Status::unavailable(err.to_string()),
+        _ => Status::from(err),
+    }
+}
@@ -0,0 +1,52 @@
+//! Utilities for testing gRPC services.
+//!
+//! They work both in simulation and on a real tokio runtime.
+
+use crate::{Response, Status};
+use futures_util::{Stream, StreamExt};
+#[cfg(madsim)]
+use madsim::time::{timeout, Instant};
+use std::{future::Future, time::Duration};
+#[cfg(not(madsim))]
+use tokio::time::{timeout, Instant};
+
+/// Drives a server-streaming call and collects all its messages.
+///
+/// Returns the messages received, and the status the stream ended with:
+/// `Ok(())` if it ended normally, or the error of the call or of the stream.
+/// If the call has not finished within `limit`, the messages received so far
+/// are returned with `DeadlineExceeded`. In simulation, the limit is measured
+/// on the simulated clock.
+///
+/// # Example
+///
+/// ```ignore
+/// let (replies, status) = collect_stream(
+///     client.lots_of_replies(request),
+///     Duration::from_secs(10),
+/// )
+/// .await;
+/// ```
+pub async fn collect_stream<F, S, T>(call: F, limit: Duration) -> (Vec<T>, Result<(), Status>)
+where
+    F: Future<Output = Result<Response<S>, Status>>,
+    S: Stream<Item = Result<T, Status>> + Unpin,
+{
+    let deadline = Instant::now() + limit;
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+    let elapsed = || Status::deadline_exceeded("stream timed out");
+    let mut messages = vec![];
+    let mut stream = match timeout(remaining(), call).await {
+        Ok(Ok(response)) => response.into_inner(),
+        Ok(Err(status)) => return (messages, Err(status)),
+        Err(_) => return (messages, Err(elapsed())),
+    };
+    loop {
+        match timeout(remaining(), stream.next()).await {
+            Ok(Some(Ok(message))) => messages.push(message),
+            Ok(Some(Err(status))) => return (messages, Err(status)),
+            Ok(None) => return (messages, Ok(())),
+            Err(_) => return (messages, Err(elapsed())),
+        }
+    }
+}
@@ -1,17 +1,33 @@
 //! Client implementation and builder.
 
 use super::Error;
-use std::{fmt, net::IpAddr, sync::Arc, time::Duration};
+#[cfg(feature = "tls")]
+use super::{Certificate, ClientTlsConfig};
+use crate::keepalive::KeepAlive;
+use std::{
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::Semaphore;
 use tonic::{
     codegen::{http::HeaderValue, Bytes, StdError},
     transport::Uri,
 };
+use tracing::debug;
 
 /// Channel builder.
 #[derive(Debug, Clone)]
 pub struct Endpoint {
     uri: Uri,
     timeout: Option<Duration>,
+    http2_keep_alive_interval: Option<Duration>,
+    http2_keep_alive_timeout: Option<Duration>,
+    #[cfg(feature = "tls")]
+    client_cert: Option<Certificate>,
+    buffer_size: Option<usize>,
+    stream_window: Option<u32>,
 }
 
 impl Endpoint {
@@ -41,6 +57,10 @@ impl Endpoint {
     /// Apply a timeout to connecting to the uri.
     ///
     /// Defaults to no timeout.
+    ///
+    /// In the simulation, a connection that can not be established, e.g. because
+    /// the server is partitioned away, is retried with backoff until the timeout
+    /// expires. Without a timeout, connecting fails immediately instead.
     pub fn connect_timeout(mut self, dur: Duration) -> Self {
         self.timeout = Some(dur);
         self
@@ -51,16 +71,50 @@ impl Endpoint {
         let host = self.uri.host().ok_or_else(Error::new_invalid_uri)?;
         let addr: IpAddr = host.parse().map_err(|e| Error::new_invalid_uri().with(e))?;
         let port = self.uri.port_u16().ok_or_else(Error::new_invalid_uri)?;
-        let ep = madsim::net::Endpoint::connect((addr, port))
+        let addr = SocketAddr::from((addr, port));
+        let ep = madsim::net::Endpoint::connect(addr)
             .await
             .map_err(Error::from_source)?;
 
         // handshake
-        ep.connect1((addr, port).into())
-            .await
-            .map_err(Error::from_source)?;
+        match self.timeout {
+            Some(timeout) => madsim::time::timeout(timeout, handshake_with_retry(&ep, addr))
+                .await
+                .map_err(Error::from_source)?
+                .map_err(Error::from_source)?,
+            None => {
+                ep.connect1(addr).await.map_err(Error::from_source)?;
+            }
+        }
 
-        Ok(Channel { ep: Arc::new(ep) })
+        let keep_alive = self.http2_keep_alive_interval.map(|interval| KeepAlive {
+            interval,
+            // the default of `hyper`
+            timeout: self
+                .http2_keep_alive_timeout
+                .unwrap_or(Duration::from_secs(20)),
+        });
+        Ok(Channel {
+            ep: Arc::new(ep),
+            keep_alive,
+            #[cfg(feature = "tls")]
+            client_cert: self.client_cert.clone(),
+            buffer: self.buffer_size.map(|size| Arc::new(Semaphore::new(size))),
+            stream_window: self.stream_window,
+        })
+    }
+
+    /// Configures TLS for the endpoint.
+    ///
+    /// In the simulation, only the client identity is used. Its certificate
+    /// is delivered to the server in the [`PeerCerts`] request extension.
+    ///
+    /// [`PeerCerts`]: crate::simulation::PeerCerts
+    #[cfg(feature = "tls")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "tls")))]
+    pub fn tls_config(mut self, tls_config: ClientTlsConfig) -> Result<Self, Error> {
+        self.client_cert = tls_config.client_cert();
+        Ok(self)
     }
 
     /// Set a custom user-agent header.
@@ -84,6 +138,17 @@ impl Endpoint {
         self
     }
 
+    /// Sets the tower service default internal buffer size.
+    ///
+    /// In the simulation, at most `sz` calls on the channel and its clones can
+    /// wait for a response at the same time. A call waits for a free slot
+    /// before sending its request, in the order of arrival. A streaming call
+    /// frees its slot once the response headers arrive. Defaults to unlimited.
+    pub fn buffer_size(mut self, sz: impl Into<Option<usize>>) -> Self {
+        self.buffer_size = sz.into();
+        self
+    }
+
     /// Apply a concurrency limit to each request.
     pub fn concurrency_limit(self, _limit: usize) -> Self {
         // ignore this setting
@@ -98,8 +163,16 @@ impl Endpoint {
 
     /// Sets the `SETTINGS_INITIAL_WINDOW_SIZE` option for HTTP2
     /// stream-level flow control.
-    pub fn initial_stream_window_size(self, _sz: impl Into<Option<u32>>) -> Self {
-        // ignore this setting
+    ///
+    /// In the simulation, the window is counted in messages instead of bytes,
+    /// and applies to the responses of server streaming calls. The server can
+    /// send at most `sz` messages ahead of those read from the stream, and
+    /// waits for the client to read more. Defaults to unlimited.
+    pub fn initial_stream_window_size(mut self, sz: impl Into<Option<u32>>) -> Self {
+        self.stream_window = sz.into();
+        if let Some(sz) = self.stream_window {
+            assert!(sz > 0, "stream window size must be positive");
+        }
         self
     }
 
@@ -116,14 +189,18 @@ impl Endpoint {
     }
 
     /// Set http2 KEEP_ALIVE_INTERVAL. Uses `hyper`'s default otherwise.
-    pub fn http2_keep_alive_interval(self, _interval: Duration) -> Self {
-        // ignore this setting
+    ///
+    /// In the simulation, the server is pinged at this interval while a unary or
+    /// client streaming call is in flight. If a ping is not acknowledged within
+    /// the keep-alive timeout, the call fails with `Unavailable`.
+    pub fn http2_keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.http2_keep_alive_interval = Some(interval);
         self
     }
 
     /// Set http2 KEEP_ALIVE_TIMEOUT. Uses `hyper`'s default otherwise.
-    pub fn keep_alive_timeout(self, _duration: Duration) -> Self {
-        // ignore this setting
+    pub fn keep_alive_timeout(mut self, duration: Duration) -> Self {
+        self.http2_keep_alive_timeout = Some(duration);
         self
     }
 
@@ -140,9 +217,34 @@ impl Endpoint {
     }
 }
 
+/// Performs the handshake, retrying with backoff while the connection is refused.
+async fn handshake_with_retry(ep: &madsim::net::Endpoint, addr: SocketAddr) -> io::Result<()> {
+    let mut wait = Duration::from_millis(1);
+    loop {
+        match ep.connect1(addr).await {
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                debug!(?addr, ?wait, "connection refused, retrying");
+                madsim::time::sleep(wait).await;
+                wait = (wait * 2).min(Duration::from_secs(1));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 impl From<Uri> for Endpoint {
     fn from(uri: Uri) -> Self {
-        Self { uri, timeout: None }
+        Self {
+            uri,
+            timeout: None,
+            http2_keep_alive_interval: None,
+            http2_keep_alive_timeout: None,
+            #[cfg(feature = "tls")]
+            client_cert: None,
+            buffer_size: None,
+            stream_window: None,
+        }
     }
 }
 
@@ -166,6 +268,14 @@ impl TryFrom<String> for Endpoint {
 #[derive(Clone)]
 pub struct Channel {
     pub(crate) ep: Arc<madsim::net::Endpoint>,
+    pub(crate) keep_alive: Option<KeepAlive>,
+    /// The certificate presented to the server.
+    #[cfg(feature = "tls")]
+    pub(crate) client_cert: Option<Certificate>,
+    /// Slots of the request buffer.
+    pub(crate) buffer: Option<Arc<Semaphore>>,
+    /// The window size of response streams.
+    pub(crate) stream_window: Option<u32>,
 }
 
 impl fmt::Debug for Channel {
@@ -3,11 +3,17 @@
 pub use self::channel::{Channel, Endpoint};
 pub use self::error::Error;
 pub use self::server::Server;
+#[cfg(feature = "tls")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tls")))]
+pub use self::tls::{ClientTlsConfig, Identity, ServerTlsConfig};
 pub use tonic::codegen::http::Uri;
+pub use tonic::transport::Certificate;
 
 pub mod channel;
 mod error;
 pub mod server;
+#[cfg(feature = "tls")]
+pub(crate) mod tls;
 
 /// A trait to provide a static reference to the service's
 /// name. This is used for routing service's within the router.
@@ -1,34 +1,47 @@
 //! Server implementation and builder.
 
+#[cfg(feature = "tls")]
+use super::ServerTlsConfig;
 use super::{Error, NamedService};
-use crate::codegen::{BoxMessage, BoxMessageStream};
+use crate::codec::EndOfStream;
+use crate::codegen::{BoxMessage, BoxMessageStream, StreamHead};
+use crate::deadline;
+use crate::flow::SendWindow;
+use crate::keepalive::Ping;
+use crate::metadata::MetadataMap;
+use crate::propagate;
+use crate::status::from_io_error;
 use crate::tower::layer::util::{Identity, Stack};
 use async_stream::try_stream;
 use futures_util::{future::poll_fn, select_biased, FutureExt, StreamExt};
-use madsim::net::Endpoint;
+use madsim::{net::Endpoint, time::Instant};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     convert::Infallible,
     future::{pending, Future},
     marker::PhantomData,
     net::SocketAddr,
+    sync::{Arc, Mutex},
     time::Duration,
 };
+use tokio::sync::oneshot;
 use tonic::codegen::{http::uri::PathAndQuery, BoxFuture, Service};
-#[cfg(feature = "tls")]
-use tonic::transport::ServerTlsConfig;
 use tracing::*;
 
 /// A default batteries included `transport` server.
 #[derive(Clone, Debug)]
 pub struct Server<L = Identity> {
+    max_concurrent_streams: Option<u32>,
     _mark: PhantomData<L>,
 }
 
 #[allow(clippy::derivable_impls)]
 impl Default for Server {
     fn default() -> Self {
-        Self { _mark: PhantomData }
+        Self {
+            max_concurrent_streams: None,
+            _mark: PhantomData,
+        }
     }
 }
 
@@ -63,7 +76,10 @@ impl<L> Server<L> {
     /// Set the Tower Layer all services will be wrapped in.
     pub fn layer<NewLayer>(self, _new_layer: NewLayer) -> Server<Stack<NewLayer, L>> {
         tracing::warn!("layer is unimplemented and ignored");
-        Server { _mark: PhantomData }
+        Server {
+            max_concurrent_streams: self.max_concurrent_streams,
+            _mark: PhantomData,
+        }
     }
 
     /// Configure TLS for this server.
@@ -103,9 +119,12 @@ impl<L> Server<L> {
     }
 
     /// Sets the `SETTINGS_MAX_CONCURRENT_STREAMS` option for HTTP2 connections.
+    ///
+    /// In the simulation, calls from a channel beyond the limit wait until one
+    /// of its running calls completes, and start in the order they arrived.
     #[must_use]
-    pub fn max_concurrent_streams(self, _max: impl Into<Option<u32>>) -> Self {
-        // ignore this setting
+    pub fn max_concurrent_streams(mut self, max: impl Into<Option<u32>>) -> Self {
+        self.max_concurrent_streams = max.into();
         self
     }
 
@@ -155,7 +174,6 @@ impl<L> Server<L> {
 /// A stack based `Service` router.
 pub struct Router<L = Identity> {
     // TODO: support layers
-    #[allow(dead_code)]
     server: Server<L>,
 
     #[allow(clippy::type_complexity)]
@@ -206,6 +224,8 @@ impl<L> Router<L> {
     ) -> Result<(), Error> {
         let ep = Endpoint::bind(addr).await.map_err(Error::from_source)?;
         let mut signal = Box::pin(signal).fuse();
+        // stream limits of connections, by the address of the client
+        let mut limits: HashMap<SocketAddr, Arc<StreamLimit>> = HashMap::new();
         loop {
             // receive a request
             let (tx, mut rx, addr) = select_biased! {
@@ -216,37 +236,81 @@ impl<L> Router<L> {
                 Ok(msg) => msg,
                 Err(_) => continue, // maybe handshake or error
             };
-            let (path, msg) = *msg
-                .downcast::<(PathAndQuery, BoxMessage)>()
+            if msg.is::<Ping>() {
+                let _ = tx.send(msg).await;
+                continue;
+            }
+            let (path, timeout, window, metadata, msg) = *msg
+                .downcast::<(
+                    PathAndQuery,
+                    Option<Duration>,
+                    Option<u32>,
+                    MetadataMap,
+                    BoxMessage,
+                )>()
                 .expect("invalid type");
+            let deadline = timeout.map(|t| Instant::now() + t);
             let span = debug_span!("request", ?addr, ?path);
             debug!(parent: &span, "received");
 
-            let requests: BoxMessageStream = if msg.downcast_ref::<()>().is_none() {
-                // single request
-                futures_util::stream::once(async move { Ok(msg) }).boxed()
+            let (requests, mut window): (BoxMessageStream, _) = if !msg.is::<StreamHead>() {
+                // single request, followed by window updates if flow-controlled
+                let window = window.map(|size| SendWindow::new(size, rx));
+                (
+                    futures_util::stream::once(async move { Ok(msg) }).boxed(),
+                    window,
+                )
             } else {
-                // request stream
-                try_stream! {
-                    while let Ok(msg) = rx.recv().await {
+                // request stream, led by the request without message
+                let requests = try_stream! {
+                    yield msg;
+                    loop {
+                        let msg = rx.recv().await.map_err(from_io_error)?;
+                        if msg.is::<EndOfStream>() {
+                            break;
+                        }
                         yield msg;
                     }
-                }
-                .boxed()
+                };
+                (requests.boxed(), None)
             };
 
+            // take a stream slot of the connection now to keep the arrival order
+            let slot = self.server.max_concurrent_streams.map(|max| {
+                if !limits.contains_key(&addr) {
+                    // forget the idle connections
+                    limits.retain(|_, limit| Arc::strong_count(limit) > 1);
+                }
+                let limit = limits.entry(addr).or_insert_with(|| StreamLimit::new(max));
+                limit.enter()
+            });
+
             // call the service in a new spawned task
+            // the handler is not cancelled on deadline, but outbound calls made by it are bounded.
             // TODO: handle error
             let svc_name = path.path().split('/').nth(1).unwrap();
             let svc = &mut self.services.get_mut(svc_name).unwrap();
             poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
-            let rsp_future = svc.call((addr, path, requests));
-            madsim::task::spawn(async move {
+            let rsp_future =
+                propagate::with_inbound(&metadata, || svc.call((addr, path, requests)));
+            let handler = deadline::scope(deadline, async move {
+                let _permit = match slot {
+                    Some(slot) => Some(slot.await),
+                    None => None,
+                };
                 let mut stream = rsp_future.instrument(span.clone()).await.unwrap();
                 // send the response
                 let mut count = 0;
                 while let Some(rsp) = stream.next().await {
                     // rsp: Result<BoxMessage, Status>
+                    // the headers and trailers are not flow-controlled
+                    let is_data = matches!(&rsp, Ok(msg) if !msg.is::<MetadataMap>());
+                    if let (Some(window), true) = (&mut window, is_data) {
+                        if window.acquire().await.is_err() {
+                            // client has closed the stream
+                            break;
+                        }
+                    }
                     let res = tx.send(Box::new(rsp)).await;
                     if res.is_err() {
                         // client has closed the stream
@@ -254,8 +318,75 @@ impl<L> Router<L> {
                     }
                     count += 1;
                 }
+                // mark the end, so that the client can tell it from a crash
+                let _ = tx.send(Box::new(EndOfStream)).await;
                 debug!(parent: &span, "completed {count}");
             });
+            madsim::task::spawn(handler);
+        }
+    }
+}
+
+/// Limits the number of concurrent streams on a connection.
+///
+/// Streams beyond the limit wait in a queue, and take over the slots of the
+/// finished ones in the order they arrived.
+struct StreamLimit {
+    max: usize,
+    state: Mutex<LimitState>,
+}
+
+struct LimitState {
+    active: usize,
+    waiters: VecDeque<oneshot::Sender<()>>,
+}
+
+impl StreamLimit {
+    fn new(max: u32) -> Arc<Self> {
+        Arc::new(StreamLimit {
+            max: (max as usize).max(1),
+            state: Mutex::new(LimitState {
+                active: 0,
+                waiters: VecDeque::new(),
+            }),
+        })
+    }
+
+    /// Registers a new stream, and returns a future that resolves when the
+    /// stream can start.
+    fn enter(self: &Arc<Self>) -> impl Future<Output = StreamPermit> + Send + 'static {
+        let mut state = self.state.lock().unwrap();
+        let wait = if state.active < self.max {
+            state.active += 1;
+            None
+        } else {
+            let (tx, rx) = oneshot::channel();
+            state.waiters.push_back(tx);
+            Some(rx)
+        };
+        drop(state);
+        let limit = self.clone();
+        async move {
+            if let Some(rx) = wait {
+                rx.await.expect("stream limit dropped");
+            }
+            StreamPermit(limit)
+        }
+    }
+}
+
+/// A running stream. The slot is released on drop.
+struct StreamPermit(Arc<StreamLimit>);
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        // hand over the slot to the next waiting stream
+        while let Some(tx) = state.waiters.pop_front() {
+            if tx.send(()).is_ok() {
+                return;
+            }
         }
+        state.active -= 1;
     }
 }
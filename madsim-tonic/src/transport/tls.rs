@@ -0,0 +1,88 @@
+//! TLS configurations.
+//!
+//! No handshake is simulated. The certificate of the client identity is
+//! delivered with each request in the [`PeerCerts`] extension.
+//!
+//! [`PeerCerts`]: crate::simulation::PeerCerts
+
+pub use tonic::transport::Certificate;
+
+/// Represents a private key and X509 certificate.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    cert: Certificate,
+    _key: Vec<u8>,
+}
+
+impl Identity {
+    /// Parse a PEM encoded certificate and private key.
+    ///
+    /// The provided cert must contain at least one PEM encoded certificate.
+    pub fn from_pem(cert: impl AsRef<[u8]>, key: impl AsRef<[u8]>) -> Self {
+        Identity {
+            cert: Certificate::from_pem(cert),
+            _key: key.as_ref().into(),
+        }
+    }
+}
+
+/// Configures TLS settings for endpoints.
+#[derive(Debug, Clone, Default)]
+pub struct ClientTlsConfig {
+    identity: Option<Identity>,
+}
+
+impl ClientTlsConfig {
+    /// Creates a new `ClientTlsConfig`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the domain name against which to verify the server's TLS certificate.
+    pub fn domain_name(self, _domain_name: impl Into<String>) -> Self {
+        // ignore this setting
+        self
+    }
+
+    /// Sets the CA Certificate against which to verify the server's TLS certificate.
+    pub fn ca_certificate(self, _ca_certificate: Certificate) -> Self {
+        // ignore this setting
+        self
+    }
+
+    /// Sets the client identity to present to the server.
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Returns the certificate of the client identity.
+    pub(crate) fn client_cert(&self) -> Option<Certificate> {
+        self.identity.as_ref().map(|identity| identity.cert.clone())
+    }
+}
+
+/// Configures TLS settings for servers.
+#[derive(Debug, Clone, Default)]
+pub struct ServerTlsConfig {
+    _private: (),
+}
+
+impl ServerTlsConfig {
+    /// Creates a new `ServerTlsConfig`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the [`Identity`] of the server.
+    pub fn identity(self, _identity: Identity) -> Self {
+        // ignore this setting
+        self
+    }
+
+    /// Sets a certificate against which to validate client TLS certificates.
+    pub fn client_ca_root(self, _cert: Certificate) -> Self {
+        // ignore this setting
+        self
+    }
+}
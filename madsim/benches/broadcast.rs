@@ -0,0 +1,74 @@
+//! Compares sending a message to many nodes with `broadcast` and with a loop
+//! of `send_to` in the simulation.
+//!
+//! Run with `RUSTFLAGS="--cfg madsim" cargo bench --bench broadcast`.
+
+#[cfg(madsim)]
+mod sim {
+    use criterion::*;
+    use madsim::{
+        net::Endpoint,
+        runtime::{NodeHandle, Runtime},
+    };
+    use std::net::{IpAddr, SocketAddr};
+
+    /// Creates a sender and `n` receivers that drop the messages.
+    fn setup(n: usize) -> (Runtime, NodeHandle, Vec<SocketAddr>) {
+        let runtime = Runtime::new();
+        let sender = runtime.create_node().ip([10, 1, 0, 1].into()).build();
+        let mut dsts = vec![];
+        for i in 0..n {
+            let ip = IpAddr::from([10, 0, (i / 250) as u8, (i % 250 + 1) as u8]);
+            dsts.push(SocketAddr::new(ip, 1));
+            let node = runtime.create_node().ip(ip).build();
+            node.spawn(async move {
+                let ep = Endpoint::bind("0.0.0.0:1").await.unwrap();
+                let mut buf = vec![0; 0x10];
+                loop {
+                    ep.recv_from(1, &mut buf).await.unwrap();
+                }
+            });
+        }
+        (runtime, sender, dsts)
+    }
+
+    fn fan_out(c: &mut Criterion) {
+        let mut group = c.benchmark_group("fan-out");
+        for n in [10, 100, 1000] {
+            group.throughput(Throughput::Elements(n as u64));
+            group.bench_with_input(BenchmarkId::new("broadcast", n), &n, |b, &n| {
+                let (runtime, sender, dsts) = setup(n);
+                b.iter(|| {
+                    let dsts = dsts.clone();
+                    let f = sender.spawn(async move {
+                        let ep = Endpoint::bind("0.0.0.0:0").await.unwrap();
+                        ep.broadcast(&dsts, 1, b"hello").await.unwrap();
+                    });
+                    runtime.block_on(f).unwrap();
+                });
+            });
+            group.bench_with_input(BenchmarkId::new("send_to", n), &n, |b, &n| {
+                let (runtime, sender, dsts) = setup(n);
+                b.iter(|| {
+                    let dsts = dsts.clone();
+                    let f = sender.spawn(async move {
+                        let ep = Endpoint::bind("0.0.0.0:0").await.unwrap();
+                        for dst in dsts {
+                            ep.send_to(dst, 1, b"hello").await.unwrap();
+                        }
+                    });
+                    runtime.block_on(f).unwrap();
+                });
+            });
+        }
+        group.finish();
+    }
+
+    criterion_group!(benches, fan_out);
+}
+
+#[cfg(madsim)]
+criterion::criterion_main!(sim::benches);
+
+#[cfg(not(madsim))]
+fn main() {}
@@ -0,0 +1,105 @@
+//! Guarded arithmetic on [`Instant`], shared by the simulation and std.
+
+use crate::time::{Duration, Instant};
+
+/// Extension methods for diffing [`Instant`]s.
+///
+/// Subtracting a later `Instant` from an earlier one is usually a bug,
+/// e.g. timestamps compared in the wrong order or taken on different nodes.
+/// These methods make the expected behavior explicit at the call site.
+pub trait InstantExt {
+    /// Returns the amount of time elapsed from `earlier` to `self`,
+    /// or `None` if `earlier` is later than `self`.
+    fn checked_since(&self, earlier: Instant) -> Option<Duration>;
+
+    /// Returns the amount of time elapsed from `earlier` to `self`,
+    /// or zero if `earlier` is later than `self`.
+    ///
+    /// Going backwards in time is logged as a warning with both instants and
+    /// the caller in debug builds.
+    fn saturating_since(&self, earlier: Instant) -> Duration;
+}
+
+impl InstantExt for Instant {
+    fn checked_since(&self, earlier: Instant) -> Option<Duration> {
+        self.checked_duration_since(earlier)
+    }
+
+    #[track_caller]
+    fn saturating_since(&self, earlier: Instant) -> Duration {
+        match self.checked_duration_since(earlier) {
+            Some(duration) => duration,
+            None => {
+                #[cfg(debug_assertions)]
+                tracing::warn!(
+                    instant = ?self,
+                    ?earlier,
+                    location = %std::panic::Location::caller(),
+                    "time went backwards, saturating to zero"
+                );
+                Duration::ZERO
+            }
+        }
+    }
+}
+
+#[cfg(all(test, madsim))]
+mod tests {
+    use super::*;
+    use crate::{runtime::Runtime, time::sleep};
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    #[test]
+    fn since() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let t0 = Instant::now();
+            sleep(Duration::from_secs(1)).await;
+            let t1 = Instant::now();
+            assert!(t1.checked_since(t0).unwrap() >= Duration::from_secs(1));
+            assert_eq!(t0.checked_since(t1), None);
+            assert!(t1.saturating_since(t0) >= Duration::from_secs(1));
+            assert_eq!(t0.saturating_since(t1), Duration::ZERO);
+        });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn backwards_detected() {
+        #[derive(Clone, Default)]
+        struct Logs(Arc<Mutex<Vec<u8>>>);
+
+        impl io::Write for Logs {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            Runtime::new().block_on(async {
+                let t0 = Instant::now();
+                sleep(Duration::from_secs(1)).await;
+                let t1 = Instant::now();
+                assert_eq!(t1.saturating_since(t0).as_secs(), 1);
+                assert_eq!(t0.saturating_since(t1), Duration::ZERO);
+            });
+        });
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(logs.matches("time went backwards").count(), 1, "{logs}");
+        assert!(logs.contains("src/instant.rs"), "{logs}");
+    }
+}
@@ -11,6 +11,8 @@
 #[cfg_attr(docsrs, doc(cfg(all(feature = "rpc", feature = "macros"))))]
 pub use madsim_macros::{service, Request};
 
+mod instant;
+mod macros;
 #[cfg(madsim)]
 mod sim;
 #[cfg(madsim)]
@@ -0,0 +1,146 @@
+/// Waits on multiple concurrent branches, returning when the **first** branch
+/// completes, cancelling the remaining branches.
+///
+/// This is a drop-in replacement for [`tokio::select!`] with the same syntax:
+///
+/// ```text
+/// <pattern> = <async expression> (, if <precondition>)? => <handler>,
+/// ```
+///
+/// plus an optional `else => <expression>` branch and an optional leading `biased;`.
+///
+/// # Differences from `tokio::select!`
+///
+/// - When multiple branches are ready, the one to take is chosen by the global
+///   RNG of madsim. Branch choices are therefore reproducible with the same seed,
+///   and different seeds explore different interleavings.
+///   Outside the simulation, the thread-local RNG is used.
+/// - Tokio polls branches starting from a random one. Here the polling order is
+///   shuffled on every poll, so each ready branch is equally likely to be taken.
+/// - There is no limit on the number of branches.
+///
+/// # Example
+///
+/// ```
+/// # async fn example() {
+/// use madsim::time::sleep;
+/// use std::time::Duration;
+///
+/// let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<u32>();
+/// tx.send(1).unwrap();
+/// let value = madsim::select! {
+///     Some(v) = rx.recv() => v,
+///     _ = sleep(Duration::from_secs(1)) => 0,
+/// };
+/// assert_eq!(value, 1);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! select {
+    // Branches are normalized into the accumulator as
+    // `[<future> <output> <disabled> (<pattern>) (<future expr>) (<precondition>) (<handler>)]`.
+    // The identifiers are introduced by a separate expansion for each branch,
+    // so they never collide thanks to hygiene.
+
+    (@ $biased:tt { $($acc:tt)* } else => $else:expr $(,)?) => {
+        $crate::select!(@expand $biased { $($acc)* } $else)
+    };
+    (@ $biased:tt { $($acc:tt)* }) => {
+        $crate::select!(@expand $biased { $($acc)* }
+            ::core::panic!("all branches are disabled and there is no else branch"))
+    };
+    (@ $biased:tt { $($acc:tt)* } $p:pat = $f:expr, if $c:expr => $h:block, $($t:tt)*) => {
+        $crate::select!(@ $biased { $($acc)* [fut out disabled ($p) ($f) ($c) ($h)] } $($t)*)
+    };
+    (@ $biased:tt { $($acc:tt)* } $p:pat = $f:expr, if $c:expr => $h:block $($t:tt)*) => {
+        $crate::select!(@ $biased { $($acc)* [fut out disabled ($p) ($f) ($c) ($h)] } $($t)*)
+    };
+    (@ $biased:tt { $($acc:tt)* } $p:pat = $f:expr, if $c:expr => $h:expr, $($t:tt)*) => {
+        $crate::select!(@ $biased { $($acc)* [fut out disabled ($p) ($f) ($c) ($h)] } $($t)*)
+    };
+    (@ $biased:tt { $($acc:tt)* } $p:pat = $f:expr, if $c:expr => $h:expr) => {
+        $crate::select!(@ $biased { $($acc)* [fut out disabled ($p) ($f) ($c) ($h)] })
+    };
+    (@ $biased:tt { $($acc:tt)* } $p:pat = $f:expr => $h:block, $($t:tt)*) => {
+        $crate::select!(@ $biased { $($acc)* [fut out disabled ($p) ($f) (true) ($h)] } $($t)*)
+    };
+    (@ $biased:tt { $($acc:tt)* } $p:pat = $f:expr => $h:block $($t:tt)*) => {
+        $crate::select!(@ $biased { $($acc)* [fut out disabled ($p) ($f) (true) ($h)] } $($t)*)
+    };
+    (@ $biased:tt { $($acc:tt)* } $p:pat = $f:expr => $h:expr, $($t:tt)*) => {
+        $crate::select!(@ $biased { $($acc)* [fut out disabled ($p) ($f) (true) ($h)] } $($t)*)
+    };
+    (@ $biased:tt { $($acc:tt)* } $p:pat = $f:expr => $h:expr) => {
+        $crate::select!(@ $biased { $($acc)* [fut out disabled ($p) ($f) (true) ($h)] })
+    };
+
+    (@expand $biased:tt {
+        $( [$fut:ident $out:ident $disabled:ident ($p:pat) ($f:expr) ($c:expr) ($h:expr)] )+
+    } $else:expr) => {{
+        $( let mut $out = ::core::option::Option::None; )+
+        {
+            $( let mut $disabled = !$c; )+
+            $(
+                let $fut = ::core::future::IntoFuture::into_future($f);
+                $crate::export::futures::pin_mut!($fut);
+            )+
+            // each branch returns `Ready(true)` if it's taken, `Ready(false)` if disabled
+            let mut branches = [$(
+                &mut (|cx: &mut ::core::task::Context<'_>| {
+                    if $disabled {
+                        return ::core::task::Poll::Ready(false);
+                    }
+                    match ::core::future::Future::poll($fut.as_mut(), cx) {
+                        ::core::task::Poll::Ready(out) => {
+                            $disabled = true;
+                            #[allow(unused_variables, unused_mut, unreachable_patterns)]
+                            match &out {
+                                $p => {}
+                                _ => return ::core::task::Poll::Ready(false),
+                            }
+                            $out = ::core::option::Option::Some(out);
+                            ::core::task::Poll::Ready(true)
+                        }
+                        ::core::task::Poll::Pending => ::core::task::Poll::Pending,
+                    }
+                }) as &mut dyn ::core::ops::FnMut(&mut ::core::task::Context<'_>) -> ::core::task::Poll<bool>
+            ),+];
+            $crate::export::futures::future::poll_fn(|cx| {
+                if !$biased {
+                    $crate::rand::shuffle(&mut branches);
+                }
+                let mut pending = false;
+                for branch in branches.iter_mut() {
+                    match branch(cx) {
+                        ::core::task::Poll::Ready(true) => return ::core::task::Poll::Ready(()),
+                        ::core::task::Poll::Ready(false) => {}
+                        ::core::task::Poll::Pending => pending = true,
+                    }
+                }
+                if pending {
+                    ::core::task::Poll::Pending
+                } else {
+                    ::core::task::Poll::Ready(())
+                }
+            })
+            .await;
+        }
+        $(
+            if let ::core::option::Option::Some(out) = $out {
+                #[allow(unreachable_patterns)]
+                match out {
+                    $p => $h,
+                    _ => ::core::unreachable!(),
+                }
+            } else
+        )+
+        { $else }
+    }};
+
+    (biased; $($t:tt)*) => {
+        $crate::select!(@ true {} $($t)*)
+    };
+    ($($t:tt)*) => {
+        $crate::select!(@ false {} $($t)*)
+    };
+}
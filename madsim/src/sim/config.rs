@@ -5,13 +5,17 @@ use std::{
     str::FromStr,
 };
 
-use crate::net::{self, tcp};
+use crate::{
+    fs,
+    net::{self, tcp},
+    sync, time,
+};
 use ahash::AHasher;
 use serde::{Deserialize, Serialize};
 
 /// Simulation configuration.
 #[cfg_attr(docsrs, doc(cfg(madsim)))]
-#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Hash, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
 pub struct Config {
     /// Network configurations.
     #[serde(default)]
@@ -20,6 +24,36 @@ pub struct Config {
     /// Tcp Configurations
     #[serde(default)]
     pub tcp: tcp::TcpConfig,
+
+    /// File system configurations.
+    #[serde(default)]
+    pub fs: fs::Config,
+
+    /// Synchronization configurations.
+    #[serde(default)]
+    pub sync: sync::Config,
+
+    /// Time configurations.
+    #[serde(default)]
+    pub time: time::Config,
+}
+
+/// Sections with default settings are not hashed, so that the hash of a config
+/// doesn't change when a new section is added.
+impl Hash for Config {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.net.hash(state);
+        self.tcp.hash(state);
+        if self.fs != fs::Config::default() {
+            self.fs.hash(state);
+        }
+        if self.sync != sync::Config::default() {
+            self.sync.hash(state);
+        }
+        if self.time != time::Config::default() {
+            self.time.hash(state);
+        }
+    }
 }
 
 impl Config {
@@ -43,7 +77,9 @@ impl FromStr for Config {
 /// Print the config into TOML.
 impl ToString for Config {
     fn to_string(&self) -> String {
-        toml::to_string_pretty(self).unwrap()
+        // go through a value, which puts plain values before tables as TOML requires
+        let value = toml::Value::try_from(self).unwrap();
+        toml::to_string_pretty(&value).unwrap()
     }
 }
 
@@ -59,6 +95,7 @@ mod tests {
         [net]
         packet_loss_rate = 0.1
         send_latency = { start = { secs = 0, nanos = 1000000 }, end = { secs = 0, nanos = 10000000 } }
+        latency_distribution = { type = "exponential", mean = { secs = 0, nanos = 5000000 } }
         
         [tcp]
         "#
@@ -69,10 +106,44 @@ mod tests {
             Config {
                 net: net::Config {
                     packet_loss_rate: 0.1,
-                    send_latency: Duration::from_millis(1)..Duration::from_millis(10)
+                    send_latency: Duration::from_millis(1)..Duration::from_millis(10),
+                    latency_distribution: net::LatencyDistribution::Exponential {
+                        mean: Duration::from_millis(5)
+                    },
+                    serialize_rpc: false,
                 },
-                tcp: tcp::TcpConfig {}
+                tcp: tcp::TcpConfig::default(),
+                fs: fs::Config::default(),
+                sync: sync::Config::default(),
+                time: time::Config::default(),
             }
         );
     }
+
+    #[test]
+    fn to_string() {
+        let mut config = Config::default();
+        config.net.serialize_rpc = true;
+        config.tcp.stall_timeout = Some(Duration::from_secs(1));
+        config.tcp.partial_read = true;
+        let text = config.to_string();
+        assert_eq!(text.parse::<Config>().unwrap(), config);
+    }
+
+    #[test]
+    fn hash() {
+        // the hash of a default config is the same as before any settings
+        // were added to the original ones
+        let mut hasher = AHasher::new_with_keys(0, 0);
+        0.0f64.to_bits().hash(&mut hasher);
+        (Duration::from_millis(1)..Duration::from_millis(10)).hash(&mut hasher);
+        assert_eq!(Config::default().hash(), hasher.finish());
+
+        let mut config = Config::default();
+        config.tcp.partial_read = true;
+        assert_ne!(config.hash(), Config::default().hash());
+        let mut config = Config::default();
+        config.sync.random_lock_order = true;
+        assert_ne!(config.hash(), Config::default().hash());
+    }
 }
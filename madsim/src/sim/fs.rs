@@ -1,33 +1,53 @@
 //! Asynchronous file system.
 
+use serde::{Deserialize, Serialize};
 use spin::{Mutex, RwLock};
 use std::{
     collections::HashMap,
     fmt,
     io::{Error, ErrorKind, Result},
+    ops::Range,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 use tracing::*;
 
 use crate::{
     plugin::{node, simulator, Simulator},
-    rand::GlobalRng,
+    rand::{GlobalRng, Rng},
     task::NodeId,
     time::TimeHandle,
-    Config,
 };
 
 /// File system simulator.
 #[cfg_attr(docsrs, doc(cfg(madsim)))]
-#[derive(Default)]
 pub struct FsSim {
     handles: Mutex<HashMap<NodeId, FsNodeHandle>>,
+    rand: GlobalRng,
+    time: TimeHandle,
+    config: Mutex<Config>,
+}
+
+/// File system configurations.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+pub struct Config {
+    /// The latency range of `sync_all` and `sync_data`.
+    ///
+    /// If the range is empty, the latency is always `start`. Default is zero.
+    #[serde(default)]
+    pub sync_latency: Range<Duration>,
 }
 
 impl Simulator for FsSim {
-    fn new(_rand: &GlobalRng, _time: &TimeHandle, _config: &Config) -> Self {
-        Default::default()
+    fn new(rand: &GlobalRng, time: &TimeHandle, config: &crate::Config) -> Self {
+        FsSim {
+            handles: Default::default(),
+            rand: rand.clone(),
+            time: time.clone(),
+            config: Mutex::new(config.fs.clone()),
+        }
     }
 
     fn create_node(&self, id: NodeId) {
@@ -47,6 +67,48 @@ impl FsSim {
         handles[&id].clone()
     }
 
+    /// Update file system configurations.
+    pub fn update_config(&self, f: impl FnOnce(&mut Config)) {
+        f(&mut self.config.lock());
+    }
+
+    /// Set the latency range of syncing the given file, overriding the global configuration.
+    ///
+    /// If `latency` is `None`, the file will use the global configuration again.
+    pub fn set_sync_latency(
+        &self,
+        node: NodeId,
+        path: impl AsRef<Path>,
+        latency: Option<Range<Duration>>,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let handle = self.handles.lock()[&node].clone();
+        let fs = handle.fs.lock();
+        let inode = fs.get(path).ok_or_else(|| {
+            Error::new(ErrorKind::NotFound, format!("file not found: {:?}", path))
+        })?;
+        *inode.sync_latency.lock() = latency;
+        Ok(())
+    }
+
+    /// Wait for the latency of syncing the file.
+    async fn sync_delay(&self, inode: &INode) {
+        let range = inode
+            .sync_latency
+            .lock()
+            .clone()
+            .unwrap_or_else(|| self.config.lock().sync_latency.clone());
+        let latency = if range.is_empty() {
+            range.start
+        } else {
+            self.rand.with(|rng| rng.gen_range(range))
+        };
+        if !latency.is_zero() {
+            trace!(?latency, "sync delay");
+            self.time.sleep(latency).await;
+        }
+    }
+
     /// Simulate a power failure. All data that does not reach the disk will be lost.
     pub fn power_fail(&self, _id: NodeId) {
         // TODO
@@ -123,6 +185,8 @@ impl FsNodeHandle {
 struct INode {
     path: PathBuf,
     data: RwLock<Vec<u8>>,
+    /// Overrides the global sync latency if set.
+    sync_latency: Mutex<Option<Range<Duration>>>,
 }
 
 impl INode {
@@ -130,6 +194,7 @@ impl INode {
         INode {
             path: path.into(),
             data: RwLock::new(Vec::new()),
+            sync_latency: Mutex::new(None),
         }
     }
 
@@ -217,7 +282,15 @@ impl File {
     /// Attempts to sync all OS-internal metadata to disk.
     #[instrument]
     pub async fn sync_all(&self) -> Result<()> {
-        // TODO: random delay
+        simulator::<FsSim>().sync_delay(&self.inode).await;
+        Ok(())
+    }
+
+    /// This function is similar to `sync_all`, except that it may not synchronize file metadata
+    /// to the filesystem.
+    #[instrument]
+    pub async fn sync_data(&self) -> Result<()> {
+        simulator::<FsSim>().sync_delay(&self.inode).await;
         Ok(())
     }
 
@@ -259,7 +332,7 @@ impl Metadata {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::runtime::Runtime;
+    use crate::{runtime::Runtime, time::Instant};
 
     #[test]
     fn create_open_read_write() {
@@ -293,4 +366,39 @@ mod tests {
         });
         runtime.block_on(f).unwrap();
     }
+
+    #[test]
+    fn sync_latency() {
+        let mut config = crate::Config::default();
+        config.fs.sync_latency = Duration::from_millis(5)..Duration::from_millis(20);
+        let runtime = Runtime::with_seed_and_config(1, config);
+        let node = runtime.create_node().build();
+        let node_id = node.id();
+        let f = node.spawn(async move {
+            let file = File::create("file").await.unwrap();
+            for _ in 0..10 {
+                let t0 = Instant::now();
+                file.sync_all().await.unwrap();
+                let elapsed = t0.elapsed();
+                assert!(elapsed >= Duration::from_millis(5), "{elapsed:?}");
+                assert!(elapsed < Duration::from_millis(21), "{elapsed:?}");
+            }
+
+            // per-file latency
+            let fs = simulator::<FsSim>();
+            fs.set_sync_latency(node_id, "file", Some(Duration::ZERO..Duration::ZERO))
+                .unwrap();
+            let t0 = Instant::now();
+            file.sync_data().await.unwrap();
+            assert_eq!(t0.elapsed(), Duration::ZERO);
+
+            // back to global config
+            fs.set_sync_latency(node_id, "file", None).unwrap();
+            fs.update_config(|c| c.sync_latency = Duration::from_secs(1)..Duration::from_secs(1));
+            let t0 = Instant::now();
+            file.sync_data().await.unwrap();
+            assert!(t0.elapsed() >= Duration::from_secs(1));
+        });
+        runtime.block_on(f).unwrap();
+    }
 }
@@ -15,6 +15,16 @@ pub mod plugin;
 pub mod rand;
 #[cfg_attr(docsrs, doc(cfg(madsim)))]
 pub mod runtime;
+pub mod sync;
 pub mod task;
 pub mod time;
 mod utils;
+
+/// Returns `true` if the current thread is running inside a simulation runtime.
+///
+/// Prefer `#[cfg(madsim)]` where possible. This is for shared code that can't be
+/// gated at compile time, e.g. a library built once and used both in simulation
+/// tests and in production.
+pub fn is_simulated() -> bool {
+    context::try_current(|_| ()).is_some()
+}
@@ -29,6 +29,9 @@ use std::io;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 
 /// Performs a DNS resolution.
+///
+/// Hostnames other than `localhost` are resolved by the simulated DNS. See
+/// [`NetSim::set_dns_record`](super::NetSim::set_dns_record).
 pub async fn lookup_host(host: impl ToSocketAddrs) -> io::Result<impl Iterator<Item = SocketAddr>> {
     to_socket_addrs(host).await
 }
@@ -242,7 +245,16 @@ impl sealed::ToSocketAddrsPriv for (&str, u16) {
 
             return MaybeReady(sealed::State::Ready(Some(addr)));
         }
-        todo!("simulate DNS lookup: {host}");
+
+        let net = crate::plugin::simulator::<super::NetSim>();
+        let res = match net.resolve(host) {
+            Some(ips) => Ok(ips.into_iter().map(|ip| (ip, port).into()).collect()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("failed to lookup address information: {host}"),
+            )),
+        };
+        MaybeReady(sealed::State::Resolved(Some(res)))
     }
 }
 
@@ -304,6 +316,7 @@ pub(crate) mod sealed {
     #[derive(Debug)]
     pub(super) enum State {
         Ready(Option<SocketAddr>),
+        Resolved(Option<io::Result<Vec<SocketAddr>>>),
     }
 
     #[doc(hidden)]
@@ -322,6 +335,10 @@ pub(crate) mod sealed {
                     let iter = OneOrMore::One(i.take().into_iter());
                     Poll::Ready(Ok(iter))
                 }
+                State::Resolved(ref mut res) => {
+                    let res = res.take().expect("polled after completion");
+                    Poll::Ready(res.map(|addrs| OneOrMore::More(addrs.into_iter())))
+                }
             }
         }
     }
@@ -0,0 +1,173 @@
+use crate::{
+    task::{JoinHandle, NodeId, Spawner},
+    time::TimeHandle,
+};
+use spin::Mutex;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{oneshot, Notify};
+
+/// Bandwidth-limited links.
+///
+/// The capacity of a link is shared by the flows on it with start-time fair
+/// queuing: each message is tagged with the virtual time at which its flow
+/// would start sending it, and the message with the smallest tag is sent next.
+/// A flow sending large messages therefore can't starve one sending small
+/// messages, and backlogged flows get an equal share of bytes.
+pub(crate) struct Links {
+    time: TimeHandle,
+    task: Spawner,
+    links: Mutex<HashMap<(NodeId, NodeId), LinkEntry>>,
+    next_flow: AtomicU64,
+}
+
+/// A limited link and the task transmitting on it.
+type LinkEntry = (Arc<Link>, JoinHandle<()>);
+
+struct Link {
+    state: Mutex<LinkState>,
+    /// Wakes up the driver when a message is queued.
+    notify: Notify,
+}
+
+struct LinkState {
+    /// Bytes per second.
+    bandwidth: u64,
+    /// The start tag of the message in transmission.
+    vtime: u64,
+    /// The finish tag of the last message of each flow.
+    finish: HashMap<u64, u64>,
+    /// Messages waiting for transmission.
+    queue: Vec<Pending>,
+    next_seq: u64,
+}
+
+struct Pending {
+    start: u64,
+    /// Breaks ties in the arrival order.
+    seq: u64,
+    size: usize,
+    done: oneshot::Sender<()>,
+}
+
+impl Links {
+    pub fn new(time: TimeHandle, task: Spawner) -> Self {
+        Links {
+            time,
+            task,
+            links: Default::default(),
+            next_flow: Default::default(),
+        }
+    }
+
+    /// Allocates an ID for a new flow.
+    pub fn new_flow(&self) -> u64 {
+        self.next_flow.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Sets the bandwidth of the link from `src` to `dst`, or removes the limit.
+    pub fn set_bandwidth(&self, src: NodeId, dst: NodeId, bandwidth: Option<u64>) {
+        let mut links = self.links.lock();
+        match bandwidth {
+            Some(bandwidth) => {
+                assert!(bandwidth > 0, "bandwidth must be positive");
+                if let Some((link, _)) = links.get(&(src, dst)) {
+                    link.state.lock().bandwidth = bandwidth;
+                    return;
+                }
+                let link = Arc::new(Link {
+                    state: Mutex::new(LinkState {
+                        bandwidth,
+                        vtime: 0,
+                        finish: HashMap::new(),
+                        queue: vec![],
+                        next_seq: 0,
+                    }),
+                    notify: Notify::new(),
+                });
+                let handle = self.task.spawn(link.clone().drive(self.time.clone()));
+                links.insert((src, dst), (link, handle));
+            }
+            None => {
+                if let Some((link, handle)) = links.remove(&(src, dst)) {
+                    handle.abort();
+                    // release all waiting messages
+                    for pending in link.state.lock().queue.drain(..) {
+                        let _ = pending.done.send(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Transmits a message of `size` bytes of `flow` on the link.
+    ///
+    /// Returns a receiver that completes when the message has been sent, or
+    /// `None` if the link is unlimited.
+    pub fn transmit(
+        &self,
+        src: NodeId,
+        dst: NodeId,
+        flow: u64,
+        size: usize,
+    ) -> Option<oneshot::Receiver<()>> {
+        let link = self.links.lock().get(&(src, dst))?.0.clone();
+        let (done, rx) = oneshot::channel();
+        let mut state = link.state.lock();
+        let last_finish = state.finish.get(&flow).copied().unwrap_or(0);
+        let start = state.vtime.max(last_finish);
+        state.finish.insert(flow, start + size as u64);
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.queue.push(Pending {
+            start,
+            seq,
+            size,
+            done,
+        });
+        drop(state);
+        link.notify.notify_one();
+        Some(rx)
+    }
+}
+
+impl Link {
+    /// Transmits the queued messages one by one.
+    async fn drive(self: Arc<Self>, time: TimeHandle) {
+        loop {
+            let next = self.state.lock().next();
+            match next {
+                Some((duration, done)) => {
+                    time.sleep(duration).await;
+                    let _ = done.send(());
+                }
+                None => self.notify.notified().await,
+            }
+        }
+    }
+}
+
+impl LinkState {
+    /// Takes the next message to transmit and returns its transmission time.
+    fn next(&mut self) -> Option<(Duration, oneshot::Sender<()>)> {
+        // skip messages of closed flows
+        self.queue.retain(|p| !p.done.is_closed());
+        let (i, _) = self
+            .queue
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, p)| (p.start, p.seq))?;
+        let pending = self.queue.swap_remove(i);
+        self.vtime = pending.start;
+        let vtime = self.vtime;
+        self.finish.retain(|_, finish| *finish > vtime);
+        let duration = Duration::from_secs_f64(pending.size as f64 / self.bandwidth as f64);
+        Some((duration, pending.done))
+    }
+}
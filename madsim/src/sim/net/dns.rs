@@ -0,0 +1,157 @@
+use crate::{rand::Rng, task::NodeId, time::Instant};
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+
+/// A simulated DNS server with a resolver cache on each node.
+#[derive(Default)]
+pub(crate) struct Dns {
+    hosts: Records<Vec<IpAddr>>,
+    services: Records<Vec<SrvRecord>>,
+}
+
+/// Records of one type, and the results cached by each node.
+struct Records<T> {
+    records: HashMap<String, Record<T>>,
+    caches: HashMap<NodeId, HashMap<String, Cached<T>>>,
+}
+
+struct Record<T> {
+    value: T,
+    ttl: Duration,
+}
+
+struct Cached<T> {
+    value: T,
+    expire: Instant,
+}
+
+/// A DNS SRV record, locating a server of a service.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SrvRecord {
+    /// Servers with a lower priority are tried first.
+    pub priority: u16,
+    /// The relative weight among servers of the same priority.
+    pub weight: u16,
+    /// The hostname of the server.
+    pub target: String,
+    /// The port of the service on the server.
+    pub port: u16,
+}
+
+impl<T> Default for Records<T> {
+    fn default() -> Self {
+        Records {
+            records: HashMap::new(),
+            caches: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone> Records<T> {
+    fn set(&mut self, name: &str, value: T, ttl: Duration) {
+        self.records.insert(name.into(), Record { value, ttl });
+    }
+
+    fn remove(&mut self, name: &str) {
+        self.records.remove(name);
+    }
+
+    /// Resolves a name on `node`.
+    ///
+    /// A cached result is returned until its TTL expires, even if the record
+    /// has been changed since.
+    fn resolve(&mut self, node: NodeId, name: &str, now: Instant) -> Option<T> {
+        let cache = self.caches.entry(node).or_default();
+        if let Some(cached) = cache.get(name) {
+            if now < cached.expire {
+                return Some(cached.value.clone());
+            }
+            cache.remove(name);
+        }
+        let record = self.records.get(name)?;
+        cache.insert(
+            name.into(),
+            Cached {
+                value: record.value.clone(),
+                expire: now + record.ttl,
+            },
+        );
+        Some(record.value.clone())
+    }
+}
+
+impl Dns {
+    /// Sets the addresses of a hostname.
+    pub fn set(&mut self, host: &str, ips: Vec<IpAddr>, ttl: Duration) {
+        self.hosts.set(host, ips, ttl);
+    }
+
+    /// Removes the record of a hostname.
+    pub fn remove(&mut self, host: &str) {
+        self.hosts.remove(host);
+    }
+
+    /// Resolves a hostname on `node`.
+    pub fn resolve(&mut self, node: NodeId, host: &str, now: Instant) -> Option<Vec<IpAddr>> {
+        self.hosts.resolve(node, host, now)
+    }
+
+    /// Sets the SRV records of a service.
+    pub fn set_srv(&mut self, name: &str, records: Vec<SrvRecord>, ttl: Duration) {
+        self.services.set(name, records, ttl);
+    }
+
+    /// Removes the SRV records of a service.
+    pub fn remove_srv(&mut self, name: &str) {
+        self.services.remove(name);
+    }
+
+    /// Resolves the SRV records of a service on `node`, in the order they
+    /// should be tried.
+    pub fn resolve_srv(
+        &mut self,
+        node: NodeId,
+        name: &str,
+        now: Instant,
+        rng: &mut impl Rng,
+    ) -> Option<Vec<SrvRecord>> {
+        let mut records = self.services.resolve(node, name, now)?;
+        records.sort_by_key(|r| r.priority);
+        let mut ordered = Vec::with_capacity(records.len());
+        for group in records.chunk_by(|a, b| a.priority == b.priority) {
+            ordered.extend(weighted_order(group.to_vec(), rng));
+        }
+        Some(ordered)
+    }
+
+    /// Clears the cache of a node.
+    pub fn reset_node(&mut self, node: NodeId) {
+        self.hosts.caches.remove(&node);
+        self.services.caches.remove(&node);
+    }
+}
+
+/// Orders records of the same priority by weighted random selection.
+///
+/// Each record is chosen with a probability proportional to its weight among
+/// the remaining ones. Records of zero weight have a small chance to be chosen
+/// before the others.
+fn weighted_order(mut records: Vec<SrvRecord>, rng: &mut impl Rng) -> Vec<SrvRecord> {
+    // zero-weight records go first, as in RFC 2782
+    records.sort_by_key(|r| r.weight != 0);
+    let mut ordered = Vec::with_capacity(records.len());
+    while !records.is_empty() {
+        let total: u32 = records.iter().map(|r| r.weight as u32).sum();
+        let pick = rng.gen_range(0..=total);
+        let mut sum = 0;
+        let i = records
+            .iter()
+            .position(|r| {
+                sum += r.weight as u32;
+                sum >= pick
+            })
+            .unwrap();
+        ordered.push(records.remove(i));
+    }
+    ordered
+}
@@ -25,6 +25,7 @@ impl Endpoint {
         let socket = Arc::new(EndpointSocket {
             mailbox: Mutex::new(Mailbox::default()),
             conn_tx,
+            timeouts: Mutex::new(Timeouts::default()),
         });
         let guard = Arc::new(BindGuard::bind(addr, Udp, socket.clone()).await?);
         Ok(Endpoint {
@@ -54,6 +55,46 @@ impl Endpoint {
             .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "not connected"))
     }
 
+    /// Sets the read timeout of the socket.
+    ///
+    /// Receiving a message, on the socket or on a connection set up afterwards,
+    /// fails with [`io::ErrorKind::TimedOut`] if no message arrives within the
+    /// timeout on the simulated clock. `None` means waiting indefinitely, which
+    /// is the default.
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidInput`] is returned if the
+    /// duration is zero.
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        check_timeout(dur)?;
+        self.socket.timeouts.lock().read = dur;
+        Ok(())
+    }
+
+    /// Returns the read timeout of the socket.
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.socket.timeouts.lock().read)
+    }
+
+    /// Sets the write timeout of the socket.
+    ///
+    /// Sending a message fails with [`io::ErrorKind::TimedOut`] if it can not
+    /// be sent within the timeout, e.g. on a node with a limited packet rate.
+    /// Sends on a connection never wait. `None` means waiting indefinitely,
+    /// which is the default.
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidInput`] is returned if the
+    /// duration is zero.
+    pub fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        check_timeout(dur)?;
+        self.socket.timeouts.lock().write = dur;
+        Ok(())
+    }
+
+    /// Returns the write timeout of the socket.
+    pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.socket.timeouts.lock().write)
+    }
+
     /// Sends data with tag on the socket to the given address.
     ///
     /// # Example
@@ -67,9 +108,35 @@ impl Endpoint {
     /// ```
     pub async fn send_to(&self, dst: impl ToSocketAddrs, tag: u64, buf: &[u8]) -> io::Result<()> {
         let dst = lookup_host(dst).await?.next().unwrap();
+        if !self
+            .guard
+            .net
+            .check_mtu(self.guard.node.id, dst, buf.len())?
+        {
+            return Ok(());
+        }
         self.send_to_raw(dst, tag, Box::new(Vec::from(buf))).await
     }
 
+    /// Sends data with tag on the socket to many destinations.
+    ///
+    /// This is faster than calling [`send_to`](Self::send_to) for each destination.
+    /// The data is shared by all messages instead of being copied.
+    pub async fn broadcast(&self, dsts: &[SocketAddr], tag: u64, buf: &[u8]) -> io::Result<()> {
+        trace!(
+            "broadcast: {} -> {} peers, tag={tag}",
+            self.guard.addr,
+            dsts.len()
+        );
+        let data = Bytes::copy_from_slice(buf);
+        self.guard
+            .net
+            .send_many(self.guard.node.id, self.guard.addr, dsts, Udp, || {
+                Box::new((tag, Box::new(data.clone()) as Payload))
+            })
+            .await
+    }
+
     /// Receives a single message with given tag on the socket.
     /// On success, returns the number of bytes read and the origin.
     ///
@@ -85,11 +152,16 @@ impl Endpoint {
     /// ```
     pub async fn recv_from(&self, tag: u64, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
         let (data, from) = self.recv_from_raw(tag).await?;
-        // copy to buffer
-        let data = data.downcast::<Vec<u8>>().expect("message is not data");
-        let len = buf.len().min(data.len());
-        buf[..len].copy_from_slice(&data[..len]);
-        Ok((len, from))
+        Ok((copy_data(data, buf), from))
+    }
+
+    /// Tries to receive a single message with given tag on the socket without waiting.
+    ///
+    /// If no message is queued, returns an error of kind [`io::ErrorKind::WouldBlock`].
+    /// On success, returns the number of bytes read and the origin.
+    pub fn try_recv_from(&self, tag: u64, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (data, from) = self.try_recv_from_raw(tag)?;
+        Ok((copy_data(data, buf), from))
     }
 
     /// Sends data on the socket to the remote address to which it is connected.
@@ -110,6 +182,19 @@ impl Endpoint {
         Ok(len)
     }
 
+    /// Tries to receive a single datagram message from the connected remote address without waiting.
+    ///
+    /// If no message is queued, returns an error of kind [`io::ErrorKind::WouldBlock`].
+    pub fn try_recv(&self, tag: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let peer = self.peer_addr()?;
+        let (len, from) = self.try_recv_from(tag, buf)?;
+        assert_eq!(
+            from, peer,
+            "receive a message but not from the connected address"
+        );
+        Ok(len)
+    }
+
     /// Sends a raw message.
     ///
     /// NOTE: Applications should not use this function!
@@ -117,16 +202,15 @@ impl Endpoint {
     #[cfg_attr(docsrs, doc(cfg(madsim)))]
     pub async fn send_to_raw(&self, dst: SocketAddr, tag: u64, data: Payload) -> io::Result<()> {
         trace!("send: {} -> {dst}, tag={tag}", self.guard.addr);
-        self.guard
-            .net
-            .send(
-                self.guard.node.id,
-                self.guard.addr.port(),
-                dst,
-                Udp,
-                Box::new((tag, data)),
-            )
-            .await?;
+        let send = self.guard.net.send(
+            self.guard.node.id,
+            self.guard.addr,
+            dst,
+            Udp,
+            Box::new((tag, data)),
+        );
+        let timeout = self.socket.timeouts.lock().write;
+        with_timeout(timeout, "write timed out", send).await?;
         Ok(())
     }
 
@@ -137,15 +221,36 @@ impl Endpoint {
     #[cfg_attr(docsrs, doc(cfg(madsim)))]
     pub async fn recv_from_raw(&self, tag: u64) -> io::Result<(Payload, SocketAddr)> {
         let recver = self.socket.mailbox.lock().recv(tag);
-        let msg = recver
-            .await
-            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "network is down"))?;
+        let timeout = self.socket.timeouts.lock().read;
+        let recver = async {
+            recver
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "network is down"))
+        };
+        // a message delivered to a dropped receiver is kept in the mailbox
+        let msg = with_timeout(timeout, "read timed out", recver).await?;
         self.guard.net.rand_delay().await?;
 
         trace!("recv: {} <- {}, tag={}", self.guard.addr, msg.from, msg.tag);
         Ok((msg.data, msg.from))
     }
 
+    /// Tries to receive a raw message without waiting.
+    ///
+    /// NOTE: Applications should not use this function!
+    /// It is provided for use by other simulators.
+    #[cfg_attr(docsrs, doc(cfg(madsim)))]
+    pub fn try_recv_from_raw(&self, tag: u64) -> io::Result<(Payload, SocketAddr)> {
+        let msg = self
+            .socket
+            .mailbox
+            .lock()
+            .try_recv(tag)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WouldBlock, "no message available"))?;
+        trace!("recv: {} <- {}, tag={}", self.guard.addr, msg.from, msg.tag);
+        Ok((msg.data, msg.from))
+    }
+
     /// Sends a raw message. to the connected remote address.
     ///
     /// NOTE: Applications should not use this function!
@@ -177,7 +282,7 @@ impl Endpoint {
         let (tx, rx, _) = self
             .guard
             .net
-            .connect1(self.guard.node.id, self.guard.addr.port(), addr, Udp)
+            .connect1(self.guard.node.id, self.guard.addr, addr, Udp)
             .await?;
         let sender = Sender {
             _guard: self.guard.clone(),
@@ -186,6 +291,7 @@ impl Endpoint {
         let recver = Receiver {
             _guard: self.guard.clone(),
             rx,
+            read_timeout: self.socket.timeouts.lock().read,
         };
         Ok((sender, recver))
     }
@@ -204,6 +310,7 @@ impl Endpoint {
         let recver = Receiver {
             _guard: self.guard.clone(),
             rx,
+            read_timeout: self.socket.timeouts.lock().read,
         };
         Ok((sender, recver, addr))
     }
@@ -233,6 +340,39 @@ pub struct Sender {
 pub struct Receiver {
     _guard: Arc<BindGuard>,
     rx: PayloadReceiver,
+    /// The read timeout of the endpoint when the connection was set up.
+    read_timeout: Option<Duration>,
+}
+
+/// The error of a connection rejected by the peer after it was accepted, e.g.
+/// because the handshake failed.
+///
+/// It is returned as the inner error of an [`io::Error`] of kind
+/// `ConnectionAborted` when receiving on the connection, after the messages
+/// sent before the rejection.
+#[doc(hidden)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionRejected {
+    /// The reason given by the peer.
+    pub reason: String,
+}
+
+impl fmt::Display for ConnectionRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connection rejected: {}", self.reason)
+    }
+}
+
+impl std::error::Error for ConnectionRejected {}
+
+impl ConnectionRejected {
+    /// Converts a rejection received on a connection to an error.
+    fn check(payload: Payload) -> io::Result<Payload> {
+        match payload.downcast::<ConnectionRejected>() {
+            Ok(rejected) => Err(io::Error::new(io::ErrorKind::ConnectionAborted, *rejected)),
+            Err(payload) => Ok(payload),
+        }
+    }
 }
 
 impl Sender {
@@ -241,13 +381,47 @@ impl Sender {
         (self.tx.send(value))
             .map_err(|_| io::Error::new(io::ErrorKind::ConnectionReset, "connection reset"))
     }
+
+    /// Rejects an accepted connection with an application error, e.g. when
+    /// the handshake fails.
+    ///
+    /// The peer receives a [`ConnectionRejected`] error instead of a
+    /// connection reset.
+    #[doc(hidden)]
+    pub fn reject(self, reason: impl Into<String>) {
+        let rejected = ConnectionRejected {
+            reason: reason.into(),
+        };
+        let _ = self.tx.send(Box::new(rejected));
+    }
 }
 
 impl Receiver {
+    /// Receives a message, failing with `TimedOut` after the read timeout.
     #[doc(hidden)]
     pub async fn recv(&mut self) -> io::Result<Payload> {
-        (self.rx.recv().await)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionReset, "connection reset"))
+        let recv = async {
+            (self.rx.recv().await)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionReset, "connection reset"))
+        };
+        with_timeout(self.read_timeout, "read timed out", recv)
+            .await
+            .and_then(ConnectionRejected::check)
+    }
+
+    /// Receives a message if one is available, without waiting.
+    ///
+    /// Returns `Ok(None)` if no message is queued.
+    #[doc(hidden)]
+    pub fn try_recv(&mut self) -> io::Result<Option<Payload>> {
+        match self.rx.try_recv() {
+            Ok(value) => ConnectionRejected::check(value).map(Some),
+            Err(mpsc::error::TryRecvError::Empty) => Ok(None),
+            Err(mpsc::error::TryRecvError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "connection reset",
+            )),
+        }
     }
 }
 
@@ -256,7 +430,7 @@ impl Stream for Receiver {
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         match self.rx.poll_recv(cx) {
-            Poll::Ready(Some(value)) => Poll::Ready(Some(Ok(value))),
+            Poll::Ready(Some(value)) => Poll::Ready(Some(ConnectionRejected::check(value))),
             Poll::Ready(None) => Poll::Ready(None),
             Poll::Pending => Poll::Pending,
         }
@@ -295,6 +469,38 @@ struct Mailbox {
 struct EndpointSocket {
     mailbox: Mutex<Mailbox>,
     conn_tx: async_channel::Sender<(PayloadSender, PayloadReceiver, SocketAddr)>,
+    timeouts: Mutex<Timeouts>,
+}
+
+/// The read and write timeouts of an endpoint.
+#[derive(Default)]
+struct Timeouts {
+    read: Option<Duration>,
+    write: Option<Duration>,
+}
+
+fn check_timeout(dur: Option<Duration>) -> io::Result<()> {
+    if dur == Some(Duration::ZERO) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot set a 0 duration timeout",
+        ));
+    }
+    Ok(())
+}
+
+/// Runs an I/O operation, failing with `TimedOut` if it doesn't complete
+/// within `timeout`.
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    msg: &'static str,
+    future: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match timeout {
+        Some(timeout) => (crate::time::timeout(timeout, future).await)
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, msg))?,
+        None => future.await,
+    }
 }
 
 impl Socket for EndpointSocket {
@@ -342,20 +548,38 @@ impl Mailbox {
 
     fn recv(&mut self, tag: u64) -> oneshot::Receiver<Message> {
         let (tx, rx) = oneshot::channel();
-        if let Some(idx) = self.msgs.iter().position(|msg| tag == msg.tag) {
-            let msg = self.msgs.swap_remove(idx);
+        if let Some(msg) = self.try_recv(tag) {
             tx.send(msg).ok().unwrap();
         } else {
             self.registered.push((tag, tx));
         }
         rx
     }
+
+    fn try_recv(&mut self, tag: u64) -> Option<Message> {
+        let idx = self.msgs.iter().position(|msg| tag == msg.tag)?;
+        Some(self.msgs.swap_remove(idx))
+    }
+}
+
+/// Copies the data sent by `send_to` or `broadcast` to the buffer.
+///
+/// Returns the number of bytes copied.
+fn copy_data(data: Payload, buf: &mut [u8]) -> usize {
+    let data: &[u8] = match data.downcast_ref::<Vec<u8>>() {
+        Some(data) => data,
+        None => data.downcast_ref::<Bytes>().expect("message is not data"),
+    };
+    let len = buf.len().min(data.len());
+    buf[..len].copy_from_slice(&data[..len]);
+    len
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{plugin::simulator, runtime::Runtime, time::*};
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use tokio::sync::Barrier;
 
     #[test]
@@ -397,6 +621,210 @@ mod tests {
         runtime.block_on(f).unwrap();
     }
 
+    #[test]
+    fn packet_rate() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).packet_rate(10).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+
+        let barrier_ = barrier.clone();
+        node1.spawn(async move {
+            let net = Endpoint::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            // a burst of 20 packets
+            for i in 0..20 {
+                net.send_to(addr2, 1, &[i]).await.unwrap();
+            }
+        });
+
+        let f = node2.spawn(async move {
+            let net = Endpoint::bind(addr2).await.unwrap();
+            barrier.wait().await;
+            let start = Instant::now();
+            let mut buf = vec![0; 0x10];
+            let mut arrivals = vec![];
+            for i in 0..20 {
+                net.recv_from(1, &mut buf).await.unwrap();
+                assert_eq!(buf[0], i, "packets should stay in order");
+                arrivals.push(start.elapsed());
+            }
+            arrivals
+        });
+
+        let arrivals = runtime.block_on(f).unwrap();
+        // the burst is queued and drains at 10 packets per second
+        assert!(arrivals[0] < Duration::from_millis(100));
+        assert!(arrivals[19] >= Duration::from_millis(1900));
+        assert!(arrivals[19] < Duration::from_millis(2100));
+        for pair in arrivals.windows(2) {
+            assert!(pair[1] - pair[0] > Duration::from_millis(80));
+        }
+    }
+
+    #[test]
+    fn reject_connection() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+
+        let barrier_ = barrier.clone();
+        node1.spawn(async move {
+            let ep = Endpoint::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            loop {
+                let (tx, mut rx, _) = ep.accept1().await.unwrap();
+                // the handshake carries a token
+                let token = *rx.recv().await.unwrap().downcast::<&str>().unwrap();
+                if token == "secret" {
+                    tx.send(Box::new("welcome")).await.unwrap();
+                } else {
+                    tx.send(Box::new("bye")).await.unwrap();
+                    tx.reject("invalid token");
+                }
+            }
+        });
+
+        let f = node2.spawn(async move {
+            let ep = Endpoint::connect(addr1).await.unwrap();
+            barrier.wait().await;
+
+            let (tx, mut rx) = ep.connect1(addr1).await.unwrap();
+            tx.send(Box::new("secret")).await.unwrap();
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(*msg.downcast::<&str>().unwrap(), "welcome");
+
+            let (tx, mut rx) = ep.connect1(addr1).await.unwrap();
+            tx.send(Box::new("guess")).await.unwrap();
+            // messages sent before the rejection are received
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(*msg.downcast::<&str>().unwrap(), "bye");
+            let err = rx.recv().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+            let inner = err.get_ref().unwrap().downcast_ref::<ConnectionRejected>();
+            assert_eq!(inner.unwrap().reason, "invalid token");
+            // then the connection is closed
+            let err = rx.recv().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        });
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn read_timeout() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+
+        let barrier_ = barrier.clone();
+        node1.spawn(async move {
+            let ep = Endpoint::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            // the peer accepts a connection but never responds
+            let (_tx, mut rx, _) = ep.accept1().await.unwrap();
+            rx.recv().await.unwrap();
+            // and replies to a datagram too late
+            sleep(Duration::from_secs(5)).await;
+            ep.send_to(addr2, 1, &[1]).await.unwrap();
+            sleep(Duration::from_secs(100)).await;
+        });
+
+        let f = node2.spawn(async move {
+            let ep = Endpoint::bind(addr2).await.unwrap();
+            assert_eq!(ep.read_timeout().unwrap(), None);
+            let err = ep.set_read_timeout(Some(Duration::ZERO)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            ep.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            assert_eq!(ep.read_timeout().unwrap(), Some(Duration::from_secs(2)));
+            barrier.wait().await;
+
+            let (tx, mut rx) = ep.connect1(addr1).await.unwrap();
+            tx.send(Box::new(())).await.unwrap();
+            let t0 = Instant::now();
+            let err = rx.recv().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert_eq!(t0.elapsed().as_secs(), 2);
+
+            let t0 = Instant::now();
+            let err = ep.recv_from(1, &mut [0]).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert_eq!(t0.elapsed().as_secs(), 2);
+
+            // the late message is not lost
+            ep.set_read_timeout(None).unwrap();
+            let mut buf = [0];
+            ep.recv_from(1, &mut buf).await.unwrap();
+            assert_eq!(buf, [1]);
+        });
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn path_mtu_discovery() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let (id1, id2) = (node1.id(), node2.id());
+
+        let barrier_ = barrier.clone();
+        let sender = node1.spawn(async move {
+            simulator::<NetSim>().set_link_mtu(id1, id2, Some(1200));
+            let net = Endpoint::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+
+            // the oversized datagram vanishes
+            net.send_to(addr2, 1, &[1; 1500]).await.unwrap();
+            sleep(Duration::from_secs(1)).await;
+
+            // until the error arrives
+            let err = net.send_to(addr2, 1, &[1; 1500]).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            let inner = err.get_ref().unwrap().downcast_ref::<FragmentationNeeded>();
+            let mtu = inner.unwrap().mtu;
+            assert_eq!(mtu, 1200);
+            for chunk in [1; 1500].chunks(mtu) {
+                net.send_to(addr2, 1, chunk).await.unwrap();
+            }
+
+            // the path MTU is forgotten after a while and probed again
+            sleep(Duration::from_secs(600)).await;
+            net.send_to(addr2, 1, &[1; 1500]).await.unwrap();
+            sleep(Duration::from_secs(1)).await;
+            let err = net.send_to(addr2, 1, &[1; 1500]).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        });
+
+        let f = node2.spawn(async move {
+            let net = Endpoint::bind(addr2).await.unwrap();
+            barrier.wait().await;
+            let mut buf = vec![0; 2000];
+            let mut lens = vec![];
+            for _ in 0..2 {
+                lens.push(net.recv_from(1, &mut buf).await.unwrap().0);
+            }
+            lens.sort();
+            lens
+        });
+
+        // only the fragments resent after learning the path MTU arrive
+        let lens = runtime.block_on(async move {
+            sender.await.unwrap();
+            f.await.unwrap()
+        });
+        assert_eq!(lens, [300, 1200]);
+    }
+
     #[test]
     fn receiver_drop() {
         let runtime = Runtime::new();
@@ -456,6 +884,51 @@ mod tests {
         });
     }
 
+    #[test]
+    fn try_recv() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+
+        let barrier_ = barrier.clone();
+        node1.spawn(async move {
+            let net = Endpoint::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            net.send_to(addr2, 1, &[1]).await.unwrap();
+        });
+
+        let f = node2.spawn(async move {
+            let net = Endpoint::bind(addr2).await.unwrap();
+            let mut buf = vec![0; 0x10];
+
+            // nothing queued
+            let t0 = Instant::now();
+            let err = net.try_recv_from(1, &mut buf).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+            assert_eq!(Instant::now(), t0);
+
+            barrier.wait().await;
+            sleep(Duration::from_secs(1)).await;
+
+            // wrong tag
+            let err = net.try_recv_from(2, &mut buf).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+
+            let (len, from) = net.try_recv_from(1, &mut buf).unwrap();
+            assert_eq!(from, addr1);
+            assert_eq!(&buf[..len], &[1]);
+
+            // consumed
+            let err = net.try_recv_from(1, &mut buf).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+        });
+
+        runtime.block_on(f).unwrap();
+    }
+
     #[test]
     fn bind() {
         let runtime = Runtime::new();
@@ -502,6 +975,208 @@ mod tests {
         runtime.block_on(f).unwrap();
     }
 
+    #[test]
+    fn bind_privileged_port() {
+        let runtime = Runtime::new();
+        let node = runtime
+            .create_node()
+            .ip("10.0.0.1".parse().unwrap())
+            .build();
+        let unprivileged = runtime
+            .create_node()
+            .ip("10.0.0.2".parse().unwrap())
+            .unprivileged()
+            .build();
+
+        let f1 = node.spawn(async move {
+            Endpoint::bind("10.0.0.1:80").await.unwrap();
+        });
+        let f2 = unprivileged.spawn(async move {
+            let err = Endpoint::bind("10.0.0.2:80").await.err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+            let err = Endpoint::bind("0.0.0.0:1023").await.err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+
+            Endpoint::bind("10.0.0.2:1024").await.unwrap();
+            // ephemeral ports are never privileged
+            let ep = Endpoint::bind("10.0.0.2:0").await.unwrap();
+            assert!(ep.local_addr().unwrap().port() >= 1024);
+        });
+        runtime.block_on(f1).unwrap();
+        runtime.block_on(f2).unwrap();
+    }
+
+    #[test]
+    fn inject_bind_error() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let id1 = node1.id();
+
+        let server = node1.spawn(async move {
+            simulator::<NetSim>().inject_bind_error(id1, 1, io::ErrorKind::AddrInUse, 1);
+            let mut failures = 0;
+            let ep = loop {
+                match Endpoint::bind(addr1).await {
+                    Ok(ep) => break ep,
+                    Err(e) => {
+                        assert_eq!(e.kind(), io::ErrorKind::AddrInUse);
+                        failures += 1;
+                        sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            };
+            let mut buf = vec![0; 0x10];
+            let (len, from) = ep.recv_from(1, &mut buf).await.unwrap();
+            assert_eq!(from, addr2);
+            assert_eq!(&buf[..len], b"ping");
+            failures
+        });
+        let client = node2.spawn(async move {
+            let ep = Endpoint::bind(addr2).await.unwrap();
+            sleep(Duration::from_secs(2)).await;
+            ep.send_to(addr1, 1, b"ping").await.unwrap();
+        });
+        runtime.block_on(client).unwrap();
+        assert_eq!(runtime.block_on(server).unwrap(), 1);
+    }
+
+    #[test]
+    fn ephemeral_port_deterministic() {
+        fn run(seed: u64) -> Vec<(usize, u16)> {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            let node = runtime
+                .create_node()
+                .ip("10.0.0.1".parse().unwrap())
+                .build();
+            let f = node.spawn(async move {
+                let tasks: Vec<_> = (0..10)
+                    .map(|i| {
+                        crate::task::spawn(async move {
+                            let ep = Endpoint::bind("0.0.0.0:0").await.unwrap();
+                            (i, ep)
+                        })
+                    })
+                    .collect();
+                // keep all endpoints alive until every port is assigned
+                let mut eps = vec![];
+                for task in tasks {
+                    eps.push(task.await.unwrap());
+                }
+                eps.iter()
+                    .map(|(i, ep)| (*i, ep.local_addr().unwrap().port()))
+                    .collect::<Vec<_>>()
+            });
+            runtime.block_on(f).unwrap()
+        }
+        for seed in 0..5 {
+            let ports = run(seed);
+            let mut distinct: Vec<_> = ports.iter().map(|(_, port)| *port).collect();
+            distinct.sort();
+            distinct.dedup();
+            assert_eq!(distinct.len(), ports.len());
+            assert_eq!(ports, run(seed), "seed={seed}");
+        }
+    }
+
+    #[test]
+    fn broadcast_many_nodes() {
+        const N: usize = 500;
+        let runtime = Runtime::new();
+        let sender = runtime.create_node().ip([10, 1, 0, 1].into()).build();
+        let mut dsts = vec![];
+        let mut receivers = vec![];
+        for i in 0..N {
+            let ip = IpAddr::from([10, 0, (i / 250) as u8, (i % 250 + 1) as u8]);
+            dsts.push(SocketAddr::new(ip, 1));
+            let node = runtime.create_node().ip(ip).build();
+            receivers.push(node.spawn(async move {
+                let ep = Endpoint::bind("0.0.0.0:1").await.unwrap();
+                let mut buf = vec![0; 0x10];
+                let (len, from) = ep.recv_from(1, &mut buf).await.unwrap();
+                assert_eq!(&buf[..len], b"hello");
+                assert_eq!(from.to_string(), "10.1.0.1:1");
+                // no duplicate
+                sleep(Duration::from_secs(1)).await;
+                ep.try_recv_from(1, &mut buf).unwrap_err();
+            }));
+        }
+        let f = sender.spawn(async move {
+            let ep = Endpoint::bind("0.0.0.0:1").await.unwrap();
+            // wait for all receivers to be ready
+            sleep(Duration::from_secs(1)).await;
+            ep.broadcast(&dsts, 1, b"hello").await.unwrap();
+        });
+        runtime.block_on(f).unwrap();
+        // each destination receives the message exactly once
+        for receiver in receivers {
+            runtime.block_on(receiver).unwrap();
+        }
+    }
+
+    #[test]
+    fn bind_multi_ip() {
+        let runtime = Runtime::new();
+        let node1 = runtime
+            .create_node()
+            .ip("10.0.0.1".parse().unwrap())
+            .ip("10.0.0.2".parse().unwrap())
+            .build();
+        let node2 = runtime
+            .create_node()
+            .ip("10.0.0.3".parse().unwrap())
+            .build();
+        let barrier = Arc::new(Barrier::new(2));
+
+        let barrier_ = barrier.clone();
+        let f1 = node1.spawn(async move {
+            let ep1 = Endpoint::bind("10.0.0.1:53").await.unwrap();
+            let ep2 = Endpoint::bind("10.0.0.2:53").await.unwrap();
+            // IP not owned by this node
+            let err = Endpoint::bind("10.0.0.3:53").await.err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::AddrNotAvailable);
+            barrier_.wait().await;
+
+            let mut buf = vec![0; 0x10];
+            let (len, from) = ep1.recv_from(1, &mut buf).await.unwrap();
+            assert_eq!(&buf[..len], b"to 1");
+            assert_eq!(from.to_string(), "10.0.0.3:1");
+            let (len, from) = ep2.recv_from(1, &mut buf).await.unwrap();
+            assert_eq!(&buf[..len], b"to 2");
+            assert_eq!(from.to_string(), "10.0.0.3:1");
+
+            // reply from the IP that each socket is bound to
+            ep1.send_to(from, 1, b"from 1").await.unwrap();
+            ep2.send_to(from, 1, b"from 2").await.unwrap();
+        });
+        let f2 = node2.spawn(async move {
+            let ep = Endpoint::bind("0.0.0.0:1").await.unwrap();
+            barrier.wait().await;
+
+            ep.send_to("10.0.0.1:53", 1, b"to 1").await.unwrap();
+            ep.send_to("10.0.0.2:53", 1, b"to 2").await.unwrap();
+
+            let mut replies = vec![];
+            for _ in 0..2 {
+                let mut buf = vec![0; 0x10];
+                let (len, from) = ep.recv_from(1, &mut buf).await.unwrap();
+                replies.push((buf[..len].to_vec(), from.to_string()));
+            }
+            replies.sort();
+            assert_eq!(
+                replies,
+                [
+                    (b"from 1".to_vec(), "10.0.0.1:53".into()),
+                    (b"from 2".to_vec(), "10.0.0.2:53".into()),
+                ]
+            );
+        });
+        runtime.block_on(f1).unwrap();
+        runtime.block_on(f2).unwrap();
+    }
+
     #[test]
     fn localhost() {
         let runtime = Runtime::new();
@@ -573,4 +1248,274 @@ mod tests {
 
         runtime.block_on(f).unwrap();
     }
+
+    #[test]
+    fn one_way_partition() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let pings = Arc::new(AtomicUsize::new(0));
+
+        // node2 replies to every heartbeat
+        let barrier_ = barrier.clone();
+        let pings_ = pings.clone();
+        node2.spawn(async move {
+            let net = Endpoint::bind(addr2).await.unwrap();
+            barrier_.wait().await;
+            loop {
+                let mut buf = [0; 8];
+                let (len, from) = net.recv_from(1, &mut buf).await.unwrap();
+                pings_.fetch_add(1, Ordering::SeqCst);
+                net.send_to(from, 2, &buf[..len]).await.unwrap();
+            }
+        });
+
+        // node1 sends a heartbeat every second,
+        // and suspects node2 is down after missing 3 acks in a row
+        let f = node1.spawn(async move {
+            let net = Endpoint::bind(addr1).await.unwrap();
+            barrier.wait().await;
+            let t0 = Instant::now();
+            let mut missed = 0;
+            let mut suspected = vec![];
+            for i in 0..12u64 {
+                sleep_until(t0 + Duration::from_secs(i)).await;
+                net.send_to(addr2, 1, &i.to_le_bytes()).await.unwrap();
+                let mut buf = [0; 8];
+                let ack = timeout(Duration::from_millis(500), net.recv_from(2, &mut buf)).await;
+                missed = if ack.is_ok() { 0 } else { missed + 1 };
+                suspected.push(missed >= 3);
+            }
+            suspected
+        });
+
+        let (id1, id2) = (node1.id(), node2.id());
+        runtime.block_on(async move {
+            // drop the replies from node2 to node1 during 3.5s ~ 8.5s
+            sleep(Duration::from_millis(3500)).await;
+            simulator::<NetSim>().clog_link(id2, id1);
+            sleep(Duration::from_secs(5)).await;
+            simulator::<NetSim>().unclog_link(id2, id1);
+
+            let suspected = f.await.unwrap();
+            assert_eq!(
+                suspected,
+                [false, false, false, false, false, false, true, true, true, false, false, false]
+            );
+        });
+        // node2 never notices the partition: all heartbeats are received
+        assert_eq!(pings.load(Ordering::SeqCst), 12);
+    }
+
+    #[test]
+    fn clog_link_buffered() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+
+        let barrier_ = barrier.clone();
+        node1.spawn(async move {
+            let net = Endpoint::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            for i in 1..=3 {
+                net.send_to(addr2, 1, &[i]).await.unwrap();
+            }
+        });
+
+        let (id1, id2) = (node1.id(), node2.id());
+        let f = node2.spawn(async move {
+            let net = Endpoint::bind(addr2).await.unwrap();
+            simulator::<NetSim>().clog_link_buffered(id1, id2, 2);
+            barrier.wait().await;
+            let mut buf = vec![0; 0x10];
+
+            // messages are held while the link is clogged
+            sleep(Duration::from_secs(1)).await;
+            let err = net.try_recv_from(1, &mut buf).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+            simulator::<NetSim>().unclog_link(id1, id2);
+            // the oldest one is dropped due to the buffer limit
+            // and the rest arrive in order after healing
+            for i in 2..=3 {
+                let (len, from) = net.recv_from(1, &mut buf).await.unwrap();
+                assert_eq!(&buf[..len], &[i]);
+                assert_eq!(from, addr1);
+            }
+            sleep(Duration::from_secs(1)).await;
+            let err = net.try_recv_from(1, &mut buf).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        });
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn clog_link_buffered_no_loss() {
+        let mut config = crate::Config::default();
+        config.net.packet_loss_rate = 0.5;
+        let runtime = Runtime::with_seed_and_config(0, config);
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+
+        let barrier_ = barrier.clone();
+        node1.spawn(async move {
+            let net = Endpoint::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            for i in 0..20 {
+                net.send_to(addr2, 1, &[i]).await.unwrap();
+            }
+        });
+
+        let (id1, id2) = (node1.id(), node2.id());
+        let f = node2.spawn(async move {
+            let net = Endpoint::bind(addr2).await.unwrap();
+            simulator::<NetSim>().clog_link_buffered(id1, id2, 20);
+            barrier.wait().await;
+            sleep(Duration::from_secs(1)).await;
+
+            // the held packets were taken by the link and are not lost
+            simulator::<NetSim>().unclog_link(id1, id2);
+            let mut buf = vec![0; 0x10];
+            let mut received = vec![];
+            for _ in 0..20 {
+                let (len, _) = net.recv_from(1, &mut buf).await.unwrap();
+                received.extend_from_slice(&buf[..len]);
+            }
+            received.sort();
+            assert_eq!(received, (0..20).collect::<Vec<u8>>());
+        });
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn latency_distribution() {
+        /// Returns the sorted latencies of 1000 messages.
+        fn sample(distribution: LatencyDistribution) -> Vec<Duration> {
+            let runtime = Runtime::new();
+            let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+            let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+            let node1 = runtime.create_node().ip(addr1.ip()).build();
+            let node2 = runtime.create_node().ip(addr2.ip()).build();
+            let barrier = Arc::new(Barrier::new(2));
+
+            let barrier_ = barrier.clone();
+            node1.spawn(async move {
+                simulator::<NetSim>().update_config(|cfg| {
+                    cfg.send_latency = Duration::from_millis(1)..Duration::from_millis(10);
+                    cfg.latency_distribution = distribution;
+                });
+                let net = Endpoint::bind(addr1).await.unwrap();
+                barrier_.wait().await;
+                for _ in 0..1000 {
+                    let sent = Box::new(Instant::now());
+                    net.send_to_raw(addr2, 1, sent).await.unwrap();
+                }
+            });
+
+            let f = node2.spawn(async move {
+                let net = Endpoint::bind(addr2).await.unwrap();
+                barrier.wait().await;
+                let mut latencies = vec![];
+                for _ in 0..1000 {
+                    let (sent, _) = net.recv_from_raw(1).await.unwrap();
+                    latencies.push(sent.downcast::<Instant>().unwrap().elapsed());
+                }
+                latencies.sort();
+                latencies
+            });
+            runtime.block_on(f).unwrap()
+        }
+        let ms = Duration::from_millis;
+        // the measured latency also includes the random delay before sending
+        // and the time advanced by polling tasks
+        let jitter = ms(1);
+
+        let latencies = sample(LatencyDistribution::Uniform);
+        assert!(latencies[0] >= ms(1));
+        assert!(latencies[999] < ms(10) + jitter);
+        // median is around 5.5ms
+        assert!(latencies[500] > ms(5) && latencies[500] < ms(6));
+
+        let latencies = sample(LatencyDistribution::Exponential { mean: ms(5) });
+        assert!(latencies[0] >= ms(1));
+        // median is around 1 + 5 * ln(2) = 4.5ms
+        assert!(latencies[500] > ms(4) && latencies[500] < ms(5));
+        // P(X > 15ms) = e^(-14/5) = 6%
+        let tail = latencies.iter().filter(|&&l| l > ms(15)).count();
+        assert!((30..100).contains(&tail), "tail: {tail}");
+
+        let latencies = sample(LatencyDistribution::Empirical {
+            latencies: vec![ms(3), ms(20)],
+        });
+        assert!(latencies[0] >= ms(3));
+        assert!(latencies[400] < ms(3) + jitter);
+        assert!(latencies[600] >= ms(20));
+        assert!(latencies[999] < ms(20) + jitter);
+    }
+
+    #[test]
+    fn delivery_log() {
+        fn run(seed: u64) -> Vec<DeliveryRecord> {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+            let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+            let addr3 = "10.0.0.3:1".parse::<SocketAddr>().unwrap();
+            let node1 = runtime.create_node().ip(addr1.ip()).build();
+            let node2 = runtime.create_node().ip(addr2.ip()).build();
+            let node3 = runtime.create_node().ip(addr3.ip()).build();
+            runtime.block_on(async move {
+                let net = simulator::<NetSim>();
+                net.enable_delivery_log();
+                let ep2 = node2.spawn(Endpoint::bind(addr2)).await.unwrap().unwrap();
+                let ep3 = node3.spawn(Endpoint::bind(addr3)).await.unwrap().unwrap();
+                // echo server
+                node3.spawn(async move {
+                    let (tx, mut rx, _) = ep3.accept1().await.unwrap();
+                    let msg = rx.recv().await.unwrap();
+                    tx.send(msg).await.unwrap();
+                });
+                node1
+                    .spawn(async move {
+                        let ep1 = Endpoint::bind(addr1).await.unwrap();
+                        for i in 0..10 {
+                            ep1.send_to(addr2, i, &[i as u8]).await.unwrap();
+                        }
+                        let (tx, mut rx) = ep1.connect1(addr3).await.unwrap();
+                        tx.send(Box::new(1u32)).await.unwrap();
+                        rx.recv().await.unwrap();
+                    })
+                    .await
+                    .unwrap();
+                for i in 0..10 {
+                    ep2.recv_from(i, &mut []).await.unwrap();
+                }
+                net.take_delivery_log()
+            })
+        }
+
+        let log = run(1);
+        assert_eq!(log.len(), 12);
+        assert!(log.iter().enumerate().all(|(i, r)| r.seq == i as u64));
+        assert!(log.windows(2).all(|w| w[0].time <= w[1].time));
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let addr3 = "10.0.0.3:1".parse::<SocketAddr>().unwrap();
+        let datagrams = log.iter().filter(|r| (r.src, r.dst) == (addr1, addr2));
+        assert_eq!(datagrams.count(), 10);
+        let request = log.iter().position(|r| r.dst == addr3).unwrap();
+        let response = log.iter().position(|r| r.src == addr3).unwrap();
+        assert!(request < response);
+
+        // the order is stable for the same seed
+        assert_eq!(run(1), log);
+    }
 }
@@ -34,12 +34,20 @@
 //!
 //! runtime.block_on(f);
 //! ```
+//!
+//! # Ephemeral ports
+//!
+//! Binding port 0 assigns the lowest free port of the node (at least 1024 on
+//! an unprivileged node). Ports are allocated per node, and concurrent binds
+//! on the same node are ordered by the scheduler, so the assignment is
+//! reproducible given the same seed.
 
 use bytes::Bytes;
 use spin::Mutex;
 use std::{
     any::Any,
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
+    future::Future,
     io,
     net::{IpAddr, SocketAddr},
     sync::Arc,
@@ -51,12 +59,15 @@ use crate::{
     plugin,
     rand::{GlobalRng, Rng},
     task::{NodeId, NodeInfo, Spawner},
-    time::{Duration, TimeHandle},
+    time::{Duration, Instant, TimeHandle},
 };
 
 mod addr;
+mod bandwidth;
+mod dns;
 mod endpoint;
 mod network;
+mod rate;
 #[cfg(feature = "rpc")]
 #[cfg_attr(docsrs, doc(cfg(feature = "rpc")))]
 pub mod rpc;
@@ -65,9 +76,13 @@ mod udp;
 pub mod unix;
 
 pub use self::addr::{lookup_host, ToSocketAddrs};
-pub use self::endpoint::{Endpoint, Receiver, Sender};
-pub use self::network::{Config, Stat};
-use self::network::{Direction, IpProtocol, Network, Socket};
+pub use self::dns::SrvRecord;
+pub use self::endpoint::{ConnectionRejected, Endpoint, Receiver, Sender};
+pub use self::network::{
+    BufferedLink, Config, FragmentationNeeded, IpProtocol, LatencyDistribution, NodeTopology, Stat,
+    Topology,
+};
+use self::network::{BufferedPacket, Direction, Network, Socket};
 pub use self::tcp::{TcpListener, TcpStream};
 pub use self::udp::UdpSocket;
 pub use self::unix::{UnixDatagram, UnixListener, UnixStream};
@@ -81,6 +96,49 @@ pub struct NetSim {
     task: Spawner,
     hooks_req: Mutex<HashMap<NodeId, MsgHookFn>>,
     hooks_rsp: Mutex<HashMap<NodeId, MsgHookFn>>,
+    delivery_log: Arc<DeliveryLog>,
+    tcp_config: tcp::TcpConfig,
+    tcp_pending: tcp::PendingConnections,
+    dns: Mutex<dns::Dns>,
+    links: bandwidth::Links,
+    rates: rate::PacketRates,
+}
+
+/// A message delivery recorded by [`NetSim::enable_delivery_log`].
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DeliveryRecord {
+    /// The position of the delivery in the global order, starting from 0.
+    pub seq: u64,
+    /// The simulated time since the start of the simulation.
+    pub time: Duration,
+    /// The address of the sender, as seen by the receiver.
+    pub src: SocketAddr,
+    /// The address of the receiver.
+    pub dst: SocketAddr,
+    /// The protocol of the message.
+    pub protocol: IpProtocol,
+}
+
+/// The log of message deliveries.
+struct DeliveryLog {
+    time: TimeHandle,
+    records: Mutex<Option<Vec<DeliveryRecord>>>,
+}
+
+impl DeliveryLog {
+    fn record(&self, src: SocketAddr, dst: SocketAddr, protocol: IpProtocol) {
+        let mut records = self.records.lock();
+        if let Some(records) = records.as_mut() {
+            records.push(DeliveryRecord {
+                seq: records.len() as u64,
+                time: self.time.elapsed(),
+                src,
+                dst,
+                protocol,
+            });
+        }
+    }
 }
 
 /// Message sent to a network socket.
@@ -89,6 +147,9 @@ pub type Payload = Box<dyn Any + Send + Sync>;
 type PayloadSender = mpsc::UnboundedSender<Payload>;
 type PayloadReceiver = mpsc::UnboundedReceiver<Payload>;
 type MsgHookFn = Arc<dyn Fn(&Payload) -> bool + Send + Sync>;
+/// A message on a bandwidth-limited link: the signal of the end of transmission,
+/// the latency and the message.
+type Transmission = (Option<oneshot::Receiver<()>>, Duration, Payload);
 
 impl plugin::Simulator for NetSim {
     fn new(_rand: &GlobalRng, _time: &TimeHandle, _config: &crate::Config) -> Self {
@@ -103,6 +164,15 @@ impl plugin::Simulator for NetSim {
             task: task.clone(),
             hooks_req: Default::default(),
             hooks_rsp: Default::default(),
+            delivery_log: Arc::new(DeliveryLog {
+                time: time.clone(),
+                records: Mutex::new(None),
+            }),
+            tcp_config: config.tcp.clone(),
+            tcp_pending: Default::default(),
+            dns: Default::default(),
+            links: bandwidth::Links::new(time.clone(), task.clone()),
+            rates: Default::default(),
         }
     }
 
@@ -127,24 +197,318 @@ impl NetSim {
         self.network.lock().stat().clone()
     }
 
+    /// Runs the future returned by `f`, and returns its output with the
+    /// statistics of the messages sent in the meantime.
+    ///
+    /// Messages sent by other tasks running concurrently are counted too.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{net::{Endpoint, NetSim}, runtime::Runtime};
+    ///
+    /// let runtime = Runtime::new();
+    /// runtime.block_on(async move {
+    ///     let ep = Endpoint::bind("127.0.0.1:1").await.unwrap();
+    ///     let (_, stat) = NetSim::current()
+    ///         .count_messages(|| async {
+    ///             ep.send_to("127.0.0.1:1", 0, b"ping").await.unwrap();
+    ///         })
+    ///         .await;
+    ///     assert_eq!(stat.msg_count, 1);
+    /// });
+    /// ```
+    pub async fn count_messages<F, Fut>(&self, f: F) -> (Fut::Output, Stat)
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future,
+    {
+        let before = self.stat();
+        let output = f().await;
+        (output, self.stat().since(&before))
+    }
+
+    /// Take a snapshot of the network topology.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{net::NetSim, runtime::Runtime};
+    ///
+    /// let runtime = Runtime::new();
+    /// let node1 = runtime.create_node().ip([10, 0, 0, 1].into()).build();
+    /// let node2 = runtime.create_node().ip([10, 0, 0, 2].into()).build();
+    /// runtime.block_on(async move {
+    ///     let net = NetSim::current();
+    ///     net.clog_link(node1.id(), node2.id());
+    ///     let topology = net.topology();
+    ///     assert_eq!(topology.clogged_links, [(node1.id(), node2.id())]);
+    /// });
+    /// ```
+    pub fn topology(&self) -> Topology {
+        self.network.lock().topology()
+    }
+
+    /// Start recording all message deliveries in the simulation.
+    ///
+    /// Every message delivered to a socket, including those on connections,
+    /// is recorded in a global order. The order is deterministic given the same seed,
+    /// so the history can be fed into an external checker after the run.
+    /// Calling this again discards the records so far.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{net::{Endpoint, NetSim}, runtime::Runtime};
+    /// use std::net::SocketAddr;
+    ///
+    /// let runtime = Runtime::new();
+    /// let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+    /// let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+    /// let node1 = runtime.create_node().ip(addr1.ip()).build();
+    /// let node2 = runtime.create_node().ip(addr2.ip()).build();
+    ///
+    /// runtime.block_on(async move {
+    ///     NetSim::current().enable_delivery_log();
+    ///     let ep2 = node2.spawn(Endpoint::bind(addr2)).await.unwrap().unwrap();
+    ///     node1
+    ///         .spawn(async move {
+    ///             let ep1 = Endpoint::bind(addr1).await.unwrap();
+    ///             ep1.send_to(addr2, 1, &[1]).await.unwrap();
+    ///         })
+    ///         .await
+    ///         .unwrap();
+    ///     ep2.recv_from(1, &mut []).await.unwrap();
+    ///
+    ///     let log = NetSim::current().take_delivery_log();
+    ///     assert_eq!(log.len(), 1);
+    ///     assert_eq!((log[0].src, log[0].dst), (addr1, addr2));
+    /// });
+    /// ```
+    pub fn enable_delivery_log(&self) {
+        *self.delivery_log.records.lock() = Some(vec![]);
+    }
+
+    /// Stop recording message deliveries and return the records in the global order.
+    ///
+    /// Returns an empty list if recording is not enabled.
+    pub fn take_delivery_log(&self) -> Vec<DeliveryRecord> {
+        self.delivery_log.records.lock().take().unwrap_or_default()
+    }
+
     /// Update network configurations.
     pub fn update_config(&self, f: impl FnOnce(&mut Config)) {
         let mut network = self.network.lock();
         network.update_config(f);
     }
 
+    /// Whether RPC messages are sent serialized.
+    #[cfg(feature = "rpc")]
+    pub(crate) fn serialize_rpc(&self) -> bool {
+        self.network.lock().config().serialize_rpc
+    }
+
     /// Reset a node.
     ///
     /// All connections will be closed.
     pub fn reset_node(&self, id: NodeId) {
         let mut network = self.network.lock();
         network.reset_node(id);
+        self.dns.lock().reset_node(id);
     }
 
     /// Set IP address of a node.
+    ///
+    /// This replaces all existing IP addresses of the node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the address is used by another node.
+    /// Use [`try_set_ip`](Self::try_set_ip) to handle the conflict.
     pub fn set_ip(&self, node: NodeId, ip: IpAddr) {
+        self.try_set_ip(node, ip).expect("failed to set IP");
+    }
+
+    /// Set IP address of a node, or return an `AddrInUse` error if the
+    /// address is used by another node.
+    ///
+    /// The node keeps its addresses on error.
+    pub fn try_set_ip(&self, node: NodeId, ip: IpAddr) -> io::Result<()> {
+        let mut network = self.network.lock();
+        network.set_ip(node, ip)
+    }
+
+    /// Set IP address of a node, and migrate its connections to it.
+    ///
+    /// Unlike [`set_ip`](Self::set_ip), the established connections of the
+    /// node are not cut off: their peers send to the new address from now on,
+    /// as with QUIC connection migration, so streams in flight continue. This
+    /// applies to the connections of sockets bound to the unspecified address.
+    /// New connections to the old address are refused. If the old address is
+    /// used again, the connections to it no longer follow the node.
+    pub fn migrate_ip(&self, node: NodeId, ip: IpAddr) -> io::Result<()> {
+        self.network.lock().migrate_ip(node, ip)
+    }
+
+    /// Add an IP address to a node.
+    ///
+    /// A node can have multiple IP addresses. Sockets bound to the unspecified
+    /// address receive packets sent to any of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the address is used by another node.
+    /// Use [`try_add_ip`](Self::try_add_ip) to handle the conflict.
+    pub fn add_ip(&self, node: NodeId, ip: IpAddr) {
+        self.try_add_ip(node, ip).expect("failed to add IP");
+    }
+
+    /// Add an IP address to a node, or return an `AddrInUse` error if the
+    /// address is used by another node.
+    pub fn try_add_ip(&self, node: NodeId, ip: IpAddr) -> io::Result<()> {
         let mut network = self.network.lock();
-        network.set_ip(node, ip);
+        network.add_ip(node, ip)
+    }
+
+    /// Returns an error if the IP address is used by any node.
+    pub(crate) fn check_ip(&self, ip: IpAddr) -> io::Result<()> {
+        self.network.lock().check_ip(None, ip)
+    }
+
+    /// Set the addresses of a hostname in the simulated DNS.
+    ///
+    /// This replaces the existing record of the hostname. Nodes cache the
+    /// result of a lookup for `ttl` on the simulated clock, so they keep
+    /// using the old addresses until it expires. A zero `ttl` disables
+    /// caching. The cache of a node is cleared when the node is reset.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{net::{lookup_host, NetSim}, runtime::Runtime, time::sleep};
+    /// use std::{net::SocketAddr, time::Duration};
+    ///
+    /// let runtime = Runtime::new();
+    /// runtime.block_on(async move {
+    ///     let net = NetSim::current();
+    ///     let ttl = Duration::from_secs(30);
+    ///     net.set_dns_record("server", ["10.0.0.1".parse().unwrap()], ttl);
+    ///     let addr: SocketAddr = "10.0.0.1:80".parse().unwrap();
+    ///     assert_eq!(lookup_host("server:80").await.unwrap().next(), Some(addr));
+    ///
+    ///     net.set_dns_record("server", ["10.0.0.2".parse().unwrap()], ttl);
+    ///     assert_eq!(lookup_host("server:80").await.unwrap().next(), Some(addr));
+    ///
+    ///     sleep(ttl).await;
+    ///     let addr: SocketAddr = "10.0.0.2:80".parse().unwrap();
+    ///     assert_eq!(lookup_host("server:80").await.unwrap().next(), Some(addr));
+    /// });
+    /// ```
+    pub fn set_dns_record(&self, host: &str, ips: impl IntoIterator<Item = IpAddr>, ttl: Duration) {
+        let ips = ips.into_iter().collect();
+        self.dns.lock().set(host, ips, ttl);
+    }
+
+    /// Remove the record of a hostname from the simulated DNS.
+    ///
+    /// Nodes that have cached the record can still resolve it until the TTL
+    /// expires.
+    pub fn remove_dns_record(&self, host: &str) {
+        self.dns.lock().remove(host);
+    }
+
+    /// Resolve a hostname on the current node.
+    pub(crate) fn resolve(&self, host: &str) -> Option<Vec<IpAddr>> {
+        let node = crate::context::current_task().node.id;
+        let now = self.time.now_instant();
+        self.dns.lock().resolve(node, host, now)
+    }
+
+    /// Set the SRV records of a service, like `_http._tcp.example.com`, in
+    /// the simulated DNS.
+    ///
+    /// This replaces the existing records of the service. Like the addresses
+    /// of hostnames, the records are cached by nodes for `ttl`.
+    /// They are looked up with [`NetSim::lookup_srv`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{net::{NetSim, SrvRecord}, runtime::Runtime};
+    /// use std::time::Duration;
+    ///
+    /// let runtime = Runtime::new();
+    /// runtime.block_on(async move {
+    ///     let net = NetSim::current();
+    ///     let record = |priority, target: &str| SrvRecord {
+    ///         priority,
+    ///         weight: 1,
+    ///         target: target.into(),
+    ///         port: 2379,
+    ///     };
+    ///     let records = [record(10, "backup"), record(0, "primary")];
+    ///     net.set_srv_record("_etcd._tcp.cluster", records, Duration::from_secs(30));
+    ///
+    ///     let records = net.lookup_srv("_etcd._tcp.cluster").unwrap();
+    ///     assert_eq!(records[0].target, "primary");
+    ///     assert_eq!(records[1].target, "backup");
+    /// });
+    /// ```
+    pub fn set_srv_record(
+        &self,
+        name: &str,
+        records: impl IntoIterator<Item = SrvRecord>,
+        ttl: Duration,
+    ) {
+        let records = records.into_iter().collect();
+        self.dns.lock().set_srv(name, records, ttl);
+    }
+
+    /// Remove the SRV records of a service from the simulated DNS.
+    ///
+    /// Nodes that have cached the records can still resolve them until the
+    /// TTL expires.
+    pub fn remove_srv_record(&self, name: &str) {
+        self.dns.lock().remove_srv(name);
+    }
+
+    /// Look up the SRV records of a service, like `_http._tcp.example.com`,
+    /// on the current node.
+    ///
+    /// The records are returned in the order they should be tried: by priority,
+    /// and by a weighted random order among records of the same priority, as
+    /// described in RFC 2782. The random order is determined by the seed.
+    pub fn lookup_srv(&self, name: &str) -> io::Result<Vec<SrvRecord>> {
+        let node = crate::context::current_task().node.id;
+        let now = self.time.now_instant();
+        let mut dns = self.dns.lock();
+        let records = self.rand.with(|rng| dns.resolve_srv(node, name, now, rng));
+        records.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("failed to lookup SRV record: {name}"),
+            )
+        })
+    }
+
+    /// Set whether a node is allowed to bind ports below 1024.
+    ///
+    /// Nodes are privileged by default. Binding a privileged port on an
+    /// unprivileged node fails with `PermissionDenied`.
+    pub fn set_privileged(&self, node: NodeId, privileged: bool) {
+        let mut network = self.network.lock();
+        network.set_privileged(node, privileged);
+    }
+
+    /// Make the next `count` binds of `port` on a node fail with `kind`.
+    ///
+    /// The error is returned before any other check, so it can simulate e.g.
+    /// `AddrInUse` from a port held by another process. Port 0 matches binds
+    /// requesting an ephemeral port. The injection survives node restarts, and
+    /// a `count` of 0 removes it.
+    pub fn inject_bind_error(&self, node: NodeId, port: u16, kind: io::ErrorKind, count: usize) {
+        let mut network = self.network.lock();
+        network.inject_bind_error(node, port, kind, count);
     }
 
     /// Connect a node to the network.
@@ -192,14 +556,54 @@ impl NetSim {
     /// Connect a pair of nodes.
     #[deprecated(since = "0.3.0", note = "call `unclog_link` twice instead")]
     pub fn connect2(&self, node1: NodeId, node2: NodeId) {
-        let mut network = self.network.lock();
-        network.unclog_link(node1, node2);
-        network.unclog_link(node2, node1);
+        self.unclog_link(node1, node2);
+        self.unclog_link(node2, node1);
     }
 
     /// Unclog the link from `src` to `dst`.
+    ///
+    /// The link from `dst` to `src` is not affected.
+    ///
+    /// If the link was clogged by [`clog_link_buffered`](NetSim::clog_link_buffered),
+    /// the held packets are delivered in order.
     pub fn unclog_link(&self, src: NodeId, dst: NodeId) {
-        self.network.lock().unclog_link(src, dst);
+        let mut network = self.network.lock();
+        let packets = network.unclog_link(src, dst);
+        if packets.is_empty() {
+            return;
+        }
+        // deliver all held packets at once to keep them in order
+        let mut max_latency = Duration::ZERO;
+        let mut deliveries = vec![];
+        for packet in packets {
+            if let Some((ip, dst_node, socket, latency)) = network.release(src, &packet) {
+                max_latency = max_latency.max(latency);
+                let from = SocketAddr::from((ip, packet.src.port()));
+                deliveries.push((
+                    dst_node,
+                    socket,
+                    from,
+                    packet.dst,
+                    packet.protocol,
+                    packet.msg,
+                ));
+            }
+        }
+        drop(network);
+        trace!(?max_latency, count = deliveries.len(), "flush link buffer");
+        let hooks_rsp = self.hooks_rsp.lock().clone();
+        let log = self.delivery_log.clone();
+        self.time.add_timer(max_latency, move || {
+            for (dst_node, socket, from, to, protocol, msg) in deliveries {
+                if let Some(hook) = hooks_rsp.get(&dst_node) {
+                    if !hook(&msg) {
+                        continue;
+                    }
+                }
+                log.record(from, to, protocol);
+                socket.deliver(from, to, msg);
+            }
+        });
     }
 
     /// Disconnect a pair of nodes.
@@ -211,10 +615,90 @@ impl NetSim {
     }
 
     /// Clog the link from `src` to `dst`.
+    ///
+    /// The link is one-way: packets from `src` to `dst` are dropped, while
+    /// packets from `dst` to `src` are still delivered. This can be used to
+    /// simulate an asymmetric partition. Call it twice with swapped arguments
+    /// to disconnect a pair of nodes completely.
+    ///
+    /// Messages on established connections (e.g. TCP) are not dropped, but
+    /// held until the link is unclogged.
     pub fn clog_link(&self, src: NodeId, dst: NodeId) {
         self.network.lock().clog_link(src, dst);
     }
 
+    /// Limit the bandwidth of the link from `src` to `dst` in bytes per second.
+    ///
+    /// Messages on connections (e.g. TCP) take time to be transmitted on a
+    /// limited link, in addition to the latency. When multiple connections
+    /// share the link, each backlogged connection gets a fair share of the
+    /// bandwidth, regardless of the size of its messages.
+    ///
+    /// Pass `None` to remove the limit. The link from `dst` to `src` is not
+    /// affected.
+    pub fn set_link_bandwidth(&self, src: NodeId, dst: NodeId, bandwidth: Option<u64>) {
+        self.links.set_bandwidth(src, dst, bandwidth);
+    }
+
+    /// Limit the number of packets a node sends per second.
+    ///
+    /// Packets beyond the limit are queued on the node and depart at evenly
+    /// spaced intervals, in addition to the latency. This applies to all
+    /// messages from the node, including datagrams and messages on
+    /// connections. The limit survives node restarts.
+    ///
+    /// Pass `None` to remove the limit.
+    pub fn set_packet_rate(&self, node: NodeId, rate: Option<u64>) {
+        self.rates.set(node, rate, self.time.now_instant());
+    }
+
+    /// Set the MTU of the link from `src` to `dst`, or remove it.
+    ///
+    /// This models a path whose MTU is lower than that of its endpoints, with
+    /// "don't fragment" set on every datagram. The first datagram larger than
+    /// the MTU is silently dropped, and a "fragmentation needed" error travels
+    /// back to the sender. Once it arrives, sending a datagram larger than the
+    /// MTU to that address fails with `InvalidInput` and [`FragmentationNeeded`]
+    /// as the inner error. The sender forgets the path MTU after 10 minutes, as
+    /// Linux does, and probes it again. The MTU counts payload bytes and only
+    /// applies to datagrams sent by [`Endpoint::send_to`] and [`UdpSocket`].
+    pub fn set_link_mtu(&self, src: NodeId, dst: NodeId, mtu: Option<usize>) {
+        self.network.lock().set_link_mtu(src, dst, mtu);
+    }
+
+    /// Checks a datagram against the path MTU. Returns `false` if it's dropped.
+    pub(crate) fn check_mtu(&self, node: NodeId, dst: SocketAddr, len: usize) -> io::Result<bool> {
+        let now = self.time.now_instant();
+        self.network.lock().check_mtu(node, dst, len, now)
+    }
+
+    /// Samples the round-trip time of a new TCP connection.
+    ///
+    /// Returns zero if TCP congestion control is disabled.
+    pub(crate) fn tcp_rtt(&self) -> Duration {
+        match self.tcp_config.congestion_control {
+            Some(_) => self.network.lock().sample_rtt(),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Reserves the departure of a packet from a node and returns its queuing delay.
+    fn packet_delay(&self, node: NodeId) -> Duration {
+        self.rates.delay(node, self.time.now_instant())
+    }
+
+    /// Clog the link from `src` to `dst`, but hold the packets instead of dropping them.
+    ///
+    /// This simulates a link that is stalled but not lossy. Up to `capacity` packets
+    /// are held, and when the buffer is full the oldest one is dropped. The held packets
+    /// are delivered in order when [`unclog_link`](NetSim::unclog_link) is called.
+    ///
+    /// Other clogs, such as [`clog_link`](NetSim::clog_link) and [`clog_node`](NetSim::clog_node),
+    /// still drop packets on this link.
+    pub fn clog_link_buffered(&self, src: NodeId, dst: NodeId, capacity: usize) {
+        self.network.lock().clog_link_buffered(src, dst, capacity);
+    }
+
     /// Add a hook function for RPC requests.
     ///
     /// If the hook function returns `false`, the request will be dropped.
@@ -269,11 +753,11 @@ impl NetSim {
         Ok(())
     }
 
-    /// Send a message to the destination.
+    /// Send a message from the socket bound at `src` to the destination.
     pub(crate) async fn send(
         &self,
         node: NodeId,
-        port: u16,
+        src: SocketAddr,
         dst: SocketAddr,
         protocol: IpProtocol,
         msg: Payload,
@@ -284,18 +768,82 @@ impl NetSim {
                 return Ok(());
             }
         }
+        let packet = BufferedPacket {
+            src,
+            dst,
+            protocol,
+            msg,
+        };
+        let msg = match self.network.lock().try_buffer(node, packet) {
+            Some(packet) => packet.msg,
+            // held on the clogged link
+            None => return Ok(()),
+        };
         if let Some((ip, dst_node, socket, latency)) =
-            self.network.lock().try_send(node, dst, protocol)
+            self.network.lock().try_send(node, src.ip(), dst, protocol)
         {
+            let latency = self.packet_delay(node) + latency;
             trace!(?latency, "delay");
             let hook = self.hooks_rsp.lock().get(&dst_node).cloned();
+            let log = self.delivery_log.clone();
             self.time.add_timer(latency, move || {
                 if let Some(hook) = hook {
                     if !hook(&msg) {
                         return;
                     }
                 }
-                socket.deliver((ip, port).into(), dst, msg);
+                let src = (ip, src.port()).into();
+                log.record(src, dst, protocol);
+                socket.deliver(src, dst, msg);
+            });
+        }
+        Ok(())
+    }
+
+    /// Sends messages to many destinations at once.
+    ///
+    /// This is equivalent to calling [`send`](Self::send) for each destination
+    /// concurrently, but only waits for one random delay and locks the network once.
+    pub(crate) async fn send_many(
+        &self,
+        node: NodeId,
+        src: SocketAddr,
+        dsts: &[SocketAddr],
+        protocol: IpProtocol,
+        mut msg: impl FnMut() -> Payload,
+    ) -> io::Result<()> {
+        self.rand_delay().await?;
+        let hook = self.hooks_req.lock().get(&node).cloned();
+        let mut packets = Vec::with_capacity(dsts.len());
+        for &dst in dsts {
+            let msg = msg();
+            if let Some(hook) = &hook {
+                if !hook(&msg) {
+                    continue;
+                }
+            }
+            packets.push(BufferedPacket {
+                src,
+                dst,
+                protocol,
+                msg,
+            });
+        }
+        let deliveries = self.network.lock().try_send_many(node, src.ip(), packets);
+        let hooks_rsp = self.hooks_rsp.lock().clone();
+        for (packet, ip, dst_node, socket, latency) in deliveries {
+            let latency = self.packet_delay(node) + latency;
+            let hook = hooks_rsp.get(&dst_node).cloned();
+            let log = self.delivery_log.clone();
+            self.time.add_timer(latency, move || {
+                if let Some(hook) = hook {
+                    if !hook(&packet.msg) {
+                        return;
+                    }
+                }
+                let src = (ip, packet.src.port()).into();
+                log.record(src, packet.dst, packet.protocol);
+                socket.deliver(src, packet.dst, packet.msg);
             });
         }
         Ok(())
@@ -306,18 +854,22 @@ impl NetSim {
     pub(crate) async fn connect1(
         self: &Arc<Self>,
         node: NodeId,
-        port: u16,
+        src: SocketAddr,
         dst: SocketAddr,
         protocol: IpProtocol,
     ) -> io::Result<(PayloadSender, PayloadReceiver, SocketAddr)> {
         self.rand_delay().await?;
-        let (ip, dst_node, socket, latency) = (self.network.lock().try_send(node, dst, protocol))
+        let (ip, dst_node, socket, latency) = self
+            .network
+            .lock()
+            .try_send(node, src.ip(), dst, protocol)
             .ok_or_else(|| {
-            io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused")
-        })?;
-        let src = (ip, port).into();
-        let (tx1, rx1) = self.channel(node, dst, protocol);
-        let (tx2, rx2) = self.channel(dst_node, src, protocol);
+                io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused")
+            })?;
+        let src = (ip, src.port()).into();
+        let (tx1, rx1) = self.channel(node, src, dst, protocol);
+        let (tx2, rx2) = self.channel(dst_node, dst, src, protocol);
+        let latency = self.packet_delay(node) + latency;
         trace!(?latency, "delay");
         self.time.add_timer(latency, move || {
             socket.new_connection(src, dst, tx2, rx1);
@@ -326,37 +878,79 @@ impl NetSim {
     }
 
     /// Create a reliable, ordered channel between two endpoints.
-    fn channel<T: Send + 'static>(
+    fn channel(
         self: &Arc<Self>,
         node: NodeId,
+        src: SocketAddr,
         dst: SocketAddr,
         protocol: IpProtocol,
-    ) -> (mpsc::UnboundedSender<T>, mpsc::UnboundedReceiver<T>) {
-        let (tx1, mut rx1) = mpsc::unbounded_channel::<T>();
-        let (tx2, rx2) = mpsc::unbounded_channel::<T>();
+    ) -> (PayloadSender, PayloadReceiver) {
+        let src_ip = src.ip();
+        let (tx1, mut rx1) = mpsc::unbounded_channel::<Payload>();
+        let (tx2, rx2) = mpsc::unbounded_channel::<Payload>();
         let net = self.clone();
+        let log = self.delivery_log.clone();
+        let flow = self.links.new_flow();
         let handle = self.task.spawn(async move {
+            let mut dst = dst;
+            // once the channel has used a bandwidth-limited link, all messages
+            // are delivered by a separate task to keep them in order.
+            let mut limited: Option<mpsc::UnboundedSender<Transmission>> = None;
             while let Some(msg) = rx1.recv().await {
+                // wait for the turn of the message on a rate-limited node
+                let delay = net.packet_delay(node);
+                if !delay.is_zero() {
+                    net.time.sleep(delay).await;
+                }
                 // wait for link available
                 let mut wait = Duration::from_millis(1);
-                loop {
-                    let res = net.network.lock().try_send(node, dst, protocol);
-                    match res {
-                        Some((_, _, _, latency)) => {
-                            net.time.sleep(latency).await;
-                            break;
+                let (dst_node, latency) = loop {
+                    let res = {
+                        let mut network = net.network.lock();
+                        // follow the peer to its new address
+                        if let Some(ip) = network.migrated_ip(dst.ip()) {
+                            dst.set_ip(ip);
                         }
+                        network.try_send(node, src_ip, dst, protocol)
+                    };
+                    match res {
+                        Some((_, dst_node, _, latency)) => break (dst_node, latency),
                         None => {
                             net.time.sleep(wait).await;
                             // backoff
                             wait = (wait * 2).min(Duration::from_secs(10));
                         }
                     }
+                };
+                let size = msg.downcast_ref::<Bytes>().map_or(0, |data| data.len());
+                let sent = net.links.transmit(node, dst_node, flow, size);
+                if sent.is_some() && limited.is_none() {
+                    let (tx, rx) = mpsc::unbounded_channel();
+                    let handle = net.task.spawn(deliver(
+                        net.time.clone(),
+                        rx,
+                        tx2.clone(),
+                        log.clone(),
+                        src,
+                        dst,
+                        protocol,
+                    ));
+                    net.network.lock().abort_task_on_reset(node, handle);
+                    limited = Some(tx);
                 }
+                if let Some(limited) = &limited {
+                    // the next message can be queued while this one is being transmitted
+                    if limited.send((sent, latency, msg)).is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                net.time.sleep(latency).await;
                 // receiver is closed. propagate the close to the sender.
                 if tx2.send(msg).is_err() {
                     return;
                 }
+                log.record(src, dst, protocol);
             }
             // sender is closed. propagate the close to the receiver.
         });
@@ -365,6 +959,45 @@ impl NetSim {
     }
 }
 
+/// Delivers the messages of a channel once they are transmitted on the link.
+async fn deliver(
+    time: TimeHandle,
+    mut rx: mpsc::UnboundedReceiver<Transmission>,
+    tx: PayloadSender,
+    log: Arc<DeliveryLog>,
+    src: SocketAddr,
+    dst: SocketAddr,
+    protocol: IpProtocol,
+) {
+    // messages propagating on the link, in the order of arrival
+    let in_flight = Arc::new(Mutex::new(VecDeque::<(Instant, Payload)>::new()));
+    let mut last_arrival = time.now_instant();
+    while let Some((sent, latency, msg)) = rx.recv().await {
+        if let Some(sent) = sent {
+            let _ = sent.await;
+        }
+        if tx.is_closed() {
+            return;
+        }
+        // don't overtake the messages in flight
+        let arrival = last_arrival.max(time.now_instant() + latency);
+        last_arrival = arrival;
+        in_flight.lock().push_back((arrival, msg));
+        let in_flight = in_flight.clone();
+        let tx = tx.clone();
+        let log = log.clone();
+        time.add_timer_at(arrival, move || {
+            let mut in_flight = in_flight.lock();
+            while in_flight.front().is_some_and(|(t, _)| *t <= arrival) {
+                let (_, msg) = in_flight.pop_front().unwrap();
+                if tx.send(msg).is_ok() {
+                    log.record(src, dst, protocol);
+                }
+            }
+        });
+    }
+}
+
 /// An RAII structure used to release the bound port.
 pub(crate) struct BindGuard {
     net: Arc<NetSim>,
@@ -7,16 +7,22 @@ use async_task::FallibleTask;
 use serde::{Deserialize, Serialize};
 use std::{
     any::Any,
-    collections::{hash_map::Entry, HashMap, HashSet},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque},
     hash::{Hash, Hasher},
     io,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     ops::Range,
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tracing::*;
 
+/// Ports below this number can only be bound by privileged nodes.
+const PRIVILEGED_PORTS_END: u16 = 1024;
+
+/// How long a learned path MTU is kept, as `net.ipv4.route.mtu_expires` on Linux.
+const PATH_MTU_EXPIRES: Duration = Duration::from_secs(600);
+
 /// A simulated network.
 ///
 /// This object manages the links and address resolution.
@@ -31,25 +37,78 @@ pub(crate) struct Network {
     clogged_node_in: HashSet<NodeId>,
     clogged_node_out: HashSet<NodeId>,
     clogged_link: HashSet<(NodeId, NodeId)>,
+    /// Clogged links that buffer packets instead of dropping them.
+    buffered_link: HashMap<(NodeId, NodeId), LinkBuffer>,
+    /// The MTU of links smaller than the MTU of their endpoints.
+    link_mtu: HashMap<(NodeId, NodeId), usize>,
+    /// The new IP of each migrated IP, followed by the established connections.
+    migrated: HashMap<IpAddr, IpAddr>,
+}
+
+/// Packets held on a clogged link.
+struct LinkBuffer {
+    capacity: usize,
+    packets: VecDeque<BufferedPacket>,
+}
+
+/// A packet held on a clogged link.
+pub(crate) struct BufferedPacket {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub protocol: IpProtocol,
+    pub msg: Payload,
 }
 
 /// A node in the network.
 #[derive(Default)]
 struct Node {
-    /// IP address of the node.
+    /// IP addresses of the node.
     ///
-    /// NOTE: now a node can have at most one IP address.
-    ip: Option<IpAddr>,
+    /// The first one is the primary address, which is used as the source IP
+    /// when the socket is not bound to a specific address.
+    ips: Vec<IpAddr>,
     /// Sockets in the node.
     sockets: HashMap<(SocketAddr, IpProtocol), Arc<dyn Socket>>,
     /// Used to close channels when the node is reset.
     tasks: Vec<FallibleTask<()>>,
+    /// Whether the node is forbidden to bind ports below 1024.
+    unprivileged: bool,
+    /// Injected errors for the next binds of each port, and how many are left.
+    bind_errors: HashMap<u16, (io::ErrorKind, usize)>,
+    /// The path MTU to each destination learned from "fragmentation needed"
+    /// errors, and when the error arrives.
+    path_mtu: HashMap<IpAddr, (usize, Instant)>,
+}
+
+/// The error of sending a datagram larger than the path MTU.
+///
+/// It is returned as the inner error of an [`io::Error`] of kind
+/// [`InvalidInput`](io::ErrorKind::InvalidInput) once the sender has learned
+/// the path MTU, like `EMSGSIZE` with `IP_PMTUDISC_DO`.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentationNeeded {
+    /// The MTU of the path to the destination.
+    pub mtu: usize,
 }
 
+impl std::fmt::Display for FragmentationNeeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "message too long: path MTU is {}", self.mtu)
+    }
+}
+
+impl std::error::Error for FragmentationNeeded {}
+
+/// The transport protocol of a socket.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
 #[non_exhaustive]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum IpProtocol {
+    /// TCP.
     Tcp,
+    /// UDP.
     Udp,
 }
 
@@ -79,6 +138,21 @@ pub struct Config {
     /// The latency range of sending packets.
     #[serde(default = "default_send_latency")]
     pub send_latency: Range<Duration>,
+    /// The distribution of latency over `send_latency`.
+    #[serde(default)]
+    pub latency_distribution: LatencyDistribution,
+    /// Pass the messages of [`Endpoint::call`] and its variants through real
+    /// serialization instead of as typed values.
+    ///
+    /// This surfaces incompatible message schemas, e.g. between two versions of
+    /// a service, at the cost of speed. A request the handler can't decode
+    /// fails the call with `InvalidData`. RPC hooks don't see serialized
+    /// messages. Other protocols built on the network, such as gRPC in
+    /// `madsim-tonic`, are not affected.
+    ///
+    /// [`Endpoint::call`]: super::Endpoint::call
+    #[serde(default)]
+    pub serialize_rpc: bool,
 }
 
 impl Default for Config {
@@ -86,6 +160,8 @@ impl Default for Config {
         Config {
             packet_loss_rate: 0.0,
             send_latency: default_send_latency(),
+            latency_distribution: LatencyDistribution::default(),
+            serialize_rpc: false,
         }
     }
 }
@@ -99,15 +175,144 @@ impl Hash for Config {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.packet_loss_rate.to_bits().hash(state);
         self.send_latency.hash(state);
+        // keep the hash of configs without them unchanged
+        if self.latency_distribution != LatencyDistribution::default() {
+            self.latency_distribution.hash(state);
+        }
+        if self.serialize_rpc {
+            self.serialize_rpc.hash(state);
+        }
+    }
+}
+
+/// The distribution of packet latency.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LatencyDistribution {
+    /// Uniformly distributed in `send_latency`.
+    #[default]
+    Uniform,
+    /// `send_latency.start` plus an exponentially distributed delay with the given mean.
+    ///
+    /// `send_latency.end` is ignored, so there is a long tail.
+    Exponential {
+        /// The mean of the extra delay.
+        mean: Duration,
+    },
+    /// Uniformly chosen from the given latencies, e.g. measured from a real network.
+    ///
+    /// `send_latency` is ignored unless the list is empty.
+    Empirical {
+        /// The latencies to choose from.
+        latencies: Vec<Duration>,
+    },
+}
+
+impl LatencyDistribution {
+    /// Samples a latency.
+    fn sample(&self, rng: &mut GlobalRng, range: &Range<Duration>) -> Duration {
+        match self {
+            Self::Uniform => rng.gen_range(range.clone()),
+            Self::Exponential { mean } => {
+                // inverse transform sampling
+                let u: f64 = rng.gen();
+                range.start + mean.mul_f64(-(1.0 - u).ln())
+            }
+            Self::Empirical { latencies } if latencies.is_empty() => rng.gen_range(range.clone()),
+            Self::Empirical { latencies } => latencies[rng.gen_range(0..latencies.len())],
+        }
     }
 }
 
+/// A packet ready to be delivered, with its source IP, destination node, socket and latency.
+pub(crate) type Delivery = (BufferedPacket, IpAddr, NodeId, Arc<dyn Socket>, Duration);
+
 /// Network statistics.
 #[cfg_attr(docsrs, doc(cfg(madsim)))]
 #[derive(Debug, Default, Clone)]
 pub struct Stat {
     /// Total number of messages.
     pub msg_count: u64,
+    /// Number of messages on each link, by `(src, dst)`.
+    pub link_msg_count: BTreeMap<(NodeId, NodeId), u64>,
+}
+
+impl Stat {
+    /// Returns the number of messages sent or received by a node.
+    pub fn node_msg_count(&self, node: NodeId) -> u64 {
+        self.link_msg_count
+            .iter()
+            .filter(|((src, dst), _)| *src == node || *dst == node)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Returns the statistics of the messages sent since `earlier`.
+    pub fn since(&self, earlier: &Stat) -> Stat {
+        let link_msg_count = self
+            .link_msg_count
+            .iter()
+            .map(|(link, count)| {
+                let before = earlier.link_msg_count.get(link).copied().unwrap_or(0);
+                (*link, count - before)
+            })
+            .filter(|(_, count)| *count != 0)
+            .collect();
+        Stat {
+            msg_count: self.msg_count - earlier.msg_count,
+            link_msg_count,
+        }
+    }
+}
+
+/// A point-in-time snapshot of the network topology.
+///
+/// All lists are sorted, so snapshots of the same topology compare equal and
+/// serialize to the same output.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Topology {
+    /// Network configurations.
+    pub config: Config,
+    /// Nodes in the network.
+    pub nodes: Vec<NodeTopology>,
+    /// Nodes that can not receive packets.
+    pub clogged_in: Vec<NodeId>,
+    /// Nodes that can not send packets.
+    pub clogged_out: Vec<NodeId>,
+    /// Links that are clogged in the direction of `(src, dst)`.
+    pub clogged_links: Vec<(NodeId, NodeId)>,
+    /// Clogged links that hold packets instead of dropping them.
+    pub buffered_links: Vec<BufferedLink>,
+}
+
+/// A node in the [`Topology`].
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeTopology {
+    /// Node ID.
+    pub id: NodeId,
+    /// IP addresses of the node. The first one is the primary address.
+    pub ips: Vec<IpAddr>,
+    /// Bound sockets.
+    pub sockets: Vec<(SocketAddr, IpProtocol)>,
+    /// Whether the node is forbidden to bind ports below 1024.
+    pub unprivileged: bool,
+}
+
+/// A clogged link that holds packets in the [`Topology`].
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BufferedLink {
+    /// Source node.
+    pub src: NodeId,
+    /// Destination node.
+    pub dst: NodeId,
+    /// The maximum number of packets held.
+    pub capacity: usize,
+    /// The number of packets held.
+    pub len: usize,
 }
 
 /// Direction of a link.
@@ -129,6 +334,9 @@ impl Network {
             clogged_node_in: HashSet::new(),
             clogged_node_out: HashSet::new(),
             clogged_link: HashSet::new(),
+            buffered_link: HashMap::new(),
+            link_mtu: HashMap::new(),
+            migrated: HashMap::new(),
         }
     }
 
@@ -136,10 +344,58 @@ impl Network {
         f(&mut self.config);
     }
 
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
     pub fn stat(&self) -> &Stat {
         &self.stat
     }
 
+    pub fn topology(&self) -> Topology {
+        let mut nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|(&id, node)| {
+                let mut sockets: Vec<_> = node.sockets.keys().copied().collect();
+                sockets.sort();
+                NodeTopology {
+                    id,
+                    ips: node.ips.clone(),
+                    sockets,
+                    unprivileged: node.unprivileged,
+                }
+            })
+            .collect();
+        nodes.sort_by_key(|node| node.id);
+        let sorted = |set: &HashSet<NodeId>| {
+            let mut ids: Vec<_> = set.iter().copied().collect();
+            ids.sort();
+            ids
+        };
+        let mut clogged_links: Vec<_> = self.clogged_link.iter().copied().collect();
+        clogged_links.sort();
+        let mut buffered_links: Vec<_> = self
+            .buffered_link
+            .iter()
+            .map(|(&(src, dst), buffer)| BufferedLink {
+                src,
+                dst,
+                capacity: buffer.capacity,
+                len: buffer.packets.len(),
+            })
+            .collect();
+        buffered_links.sort_by_key(|link| (link.src, link.dst));
+        Topology {
+            config: self.config.clone(),
+            nodes,
+            clogged_in: sorted(&self.clogged_node_in),
+            clogged_out: sorted(&self.clogged_node_out),
+            clogged_links,
+            buffered_links,
+        }
+    }
+
     pub fn insert_node(&mut self, id: NodeId) {
         debug!(%id, "insert_node");
         self.nodes.insert(id, Default::default());
@@ -151,21 +407,141 @@ impl Network {
         // close all sockets
         node.sockets.clear();
         node.tasks.clear();
+        node.path_mtu.clear();
+    }
+
+    pub fn set_link_mtu(&mut self, src: NodeId, dst: NodeId, mtu: Option<usize>) {
+        assert!(self.nodes.contains_key(&src), "node not found");
+        assert!(self.nodes.contains_key(&dst), "node not found");
+        debug!(?src, ?dst, ?mtu, "set_link_mtu");
+        match mtu {
+            Some(mtu) => self.link_mtu.insert((src, dst), mtu),
+            None => self.link_mtu.remove(&(src, dst)),
+        };
+    }
+
+    /// Checks a datagram of `len` bytes from `node` to `dst` against the path MTU.
+    ///
+    /// Returns an error if the node has learned that the path MTU is smaller.
+    /// Otherwise returns `false` if the datagram exceeds the MTU of the link.
+    /// Such a datagram is dropped, and the sender learns the path MTU after
+    /// the "fragmentation needed" error travels back. A learned path MTU is
+    /// forgotten after [`PATH_MTU_EXPIRES`].
+    pub fn check_mtu(
+        &mut self,
+        node: NodeId,
+        dst: SocketAddr,
+        len: usize,
+        now: Instant,
+    ) -> io::Result<bool> {
+        let node0 = self.nodes.get_mut(&node).expect("node not found");
+        if let Some(&(mtu, known_at)) = node0.path_mtu.get(&dst.ip()) {
+            if now >= known_at + PATH_MTU_EXPIRES {
+                node0.path_mtu.remove(&dst.ip());
+            } else if len > mtu && now >= known_at {
+                let error = FragmentationNeeded { mtu };
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+            }
+        }
+        let dst_node = match self.resolve_dest_node(node, dst, IpProtocol::Udp) {
+            Some(dst_node) => dst_node,
+            None => return Ok(true),
+        };
+        let mtu = match self.link_mtu.get(&(node, dst_node)) {
+            Some(&mtu) if len > mtu => mtu,
+            _ => return Ok(true),
+        };
+        let config = &self.config;
+        let latency = config
+            .latency_distribution
+            .sample(&mut self.rand, &config.send_latency);
+        trace!(%dst, len, mtu, "fragmentation needed");
+        let node0 = self.nodes.get_mut(&node).unwrap();
+        node0.path_mtu.insert(dst.ip(), (mtu, now + latency));
+        Ok(false)
+    }
+
+    /// Samples the round-trip time of a connection, as the sum of two latencies.
+    pub fn sample_rtt(&mut self) -> Duration {
+        let config = &self.config;
+        let dist = &config.latency_distribution;
+        dist.sample(&mut self.rand, &config.send_latency)
+            + dist.sample(&mut self.rand, &config.send_latency)
+    }
+
+    pub fn set_privileged(&mut self, id: NodeId, privileged: bool) {
+        let node = self.nodes.get_mut(&id).expect("node not found");
+        node.unprivileged = !privileged;
+    }
+
+    pub fn inject_bind_error(&mut self, id: NodeId, port: u16, kind: io::ErrorKind, count: usize) {
+        let node = self.nodes.get_mut(&id).expect("node not found");
+        if count == 0 {
+            node.bind_errors.remove(&port);
+        } else {
+            node.bind_errors.insert(port, (kind, count));
+        }
     }
 
-    pub fn set_ip(&mut self, id: NodeId, ip: IpAddr) {
+    pub fn set_ip(&mut self, id: NodeId, ip: IpAddr) -> io::Result<()> {
         debug!(%id, ?ip, "set_node_ip");
+        self.check_ip(Some(id), ip)?;
         let node = self.nodes.get_mut(&id).expect("node not found");
-        if let Some(old_ip) = node.ip.replace(ip) {
+        for old_ip in node.ips.drain(..) {
             self.addr_to_node.remove(&old_ip);
         }
-        let old_node = self.addr_to_node.insert(ip, id);
-        if let Some(old_node) = old_node {
-            panic!("IP conflict: {ip} {old_node}");
-        }
+        self.add_ip(id, ip)
         // TODO: what if we change the IP when there are opening sockets?
     }
 
+    pub fn add_ip(&mut self, id: NodeId, ip: IpAddr) -> io::Result<()> {
+        debug!(%id, ?ip, "add_node_ip");
+        self.check_ip(Some(id), ip)?;
+        let node = self.nodes.get_mut(&id).expect("node not found");
+        if node.ips.contains(&ip) {
+            return Ok(());
+        }
+        self.addr_to_node.insert(ip, id);
+        node.ips.push(ip);
+        // the connections to a reused IP no longer follow its previous owner
+        self.migrated.remove(&ip);
+        Ok(())
+    }
+
+    /// Sets the IP address of a node, migrating its connections to it.
+    pub fn migrate_ip(&mut self, id: NodeId, ip: IpAddr) -> io::Result<()> {
+        let old_ips = self.nodes.get(&id).expect("node not found").ips.clone();
+        self.set_ip(id, ip)?;
+        debug!(%id, ?old_ips, ?ip, "migrate_ip");
+        for new_ip in self.migrated.values_mut() {
+            if old_ips.contains(new_ip) {
+                *new_ip = ip;
+            }
+        }
+        for old_ip in old_ips {
+            if old_ip != ip {
+                self.migrated.insert(old_ip, ip);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the IP that the connections to `ip` have migrated to.
+    pub fn migrated_ip(&self, ip: IpAddr) -> Option<IpAddr> {
+        self.migrated.get(&ip).copied()
+    }
+
+    /// Returns an error if the IP address is used by a node other than `id`.
+    pub fn check_ip(&self, id: Option<NodeId>, ip: IpAddr) -> io::Result<()> {
+        match self.addr_to_node.get(&ip) {
+            Some(&node) if Some(node) != id => Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("IP conflict: {ip} is used by node {node}"),
+            )),
+            _ => Ok(()),
+        }
+    }
+
     pub fn clog_node(&mut self, id: NodeId, direction: Direction) {
         assert!(self.nodes.contains_key(&id), "node not found");
         debug!(%id, ?direction, "clog_node");
@@ -195,20 +571,72 @@ impl Network {
         self.clogged_link.insert((src, dst));
     }
 
-    pub fn unclog_link(&mut self, src: NodeId, dst: NodeId) {
+    /// Clog the link from `src` to `dst`, holding up to `capacity` packets until it is unclogged.
+    pub fn clog_link_buffered(&mut self, src: NodeId, dst: NodeId, capacity: usize) {
+        assert!(self.nodes.contains_key(&src), "node not found");
+        assert!(self.nodes.contains_key(&dst), "node not found");
+        debug!(?src, ?dst, capacity, "clog_link_buffered");
+        let buffer = self.buffered_link.entry((src, dst)).or_insert(LinkBuffer {
+            capacity,
+            packets: VecDeque::new(),
+        });
+        buffer.capacity = capacity;
+        buffer.packets.truncate(capacity);
+    }
+
+    /// Unclog the link from `src` to `dst`.
+    ///
+    /// Returns the packets held on the link in the order they were sent.
+    pub fn unclog_link(&mut self, src: NodeId, dst: NodeId) -> Vec<BufferedPacket> {
         assert!(self.nodes.contains_key(&src), "node not found");
         assert!(self.nodes.contains_key(&dst), "node not found");
         debug!(?src, ?dst, "unclog_link");
         self.clogged_link.remove(&(src, dst));
+        match self.buffered_link.remove(&(src, dst)) {
+            Some(buffer) => buffer.packets.into(),
+            None => vec![],
+        }
     }
 
     /// Returns whether the link from `src` to `dst` is clogged.
     pub fn link_clogged(&self, src: NodeId, dst: NodeId) -> bool {
+        self.link_clogged_lossy(src, dst) || self.buffered_link.contains_key(&(src, dst))
+    }
+
+    /// Returns whether the link from `src` to `dst` is clogged and drops packets.
+    fn link_clogged_lossy(&self, src: NodeId, dst: NodeId) -> bool {
         self.clogged_node_out.contains(&src)
             || self.clogged_node_in.contains(&dst)
             || self.clogged_link.contains(&(src, dst))
     }
 
+    /// Holds the packet if the link from `node` to `dst` is clogged with buffering.
+    ///
+    /// Returns the packet back if it is not held.
+    /// When the buffer is full, the oldest packet is dropped.
+    pub fn try_buffer(&mut self, node: NodeId, packet: BufferedPacket) -> Option<BufferedPacket> {
+        if self.buffered_link.is_empty() {
+            return Some(packet);
+        }
+        let dst_node = match self.resolve_dest_node(node, packet.dst, packet.protocol) {
+            Some(dst_node) if !self.link_clogged_lossy(node, dst_node) => dst_node,
+            _ => return Some(packet),
+        };
+        let buffer = match self.buffered_link.get_mut(&(node, dst_node)) {
+            Some(buffer) => buffer,
+            None => return Some(packet),
+        };
+        if buffer.capacity == 0 {
+            return None;
+        }
+        if buffer.packets.len() == buffer.capacity {
+            trace!(src = ?node, dst = ?dst_node, "link buffer full, drop the oldest packet");
+            buffer.packets.pop_front();
+        }
+        buffer.packets.push_back(packet);
+        None
+    }
+
     /// Bind a socket to the specified address.
     pub fn bind(
         &mut self,
@@ -218,19 +646,43 @@ impl Network {
         socket: Arc<dyn Socket>,
     ) -> io::Result<SocketAddr> {
         let node = self.nodes.get_mut(&node_id).expect("node not found");
+        // fail with injected error
+        if let Entry::Occupied(mut o) = node.bind_errors.entry(addr.port()) {
+            let (kind, count) = o.get_mut();
+            let kind = *kind;
+            *count -= 1;
+            if *count == 0 {
+                o.remove();
+            }
+            debug!(node = %node_id, ?addr, ?kind, "bind error injected");
+            return Err(io::Error::new(kind, format!("injected bind error: {addr}")));
+        }
         // check IP address
         if !addr.ip().is_unspecified()
             && !addr.ip().is_loopback()
-            && matches!(node.ip, Some(ip) if addr.ip() != ip)
+            && !node.ips.is_empty()
+            && !node.ips.contains(&addr.ip())
         {
             return Err(io::Error::new(
                 io::ErrorKind::AddrNotAvailable,
                 format!("invalid address: {addr}"),
             ));
         }
+        // check privileged port
+        if node.unprivileged && addr.port() != 0 && addr.port() < PRIVILEGED_PORTS_END {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("permission denied: {addr}"),
+            ));
+        }
         // resolve port if unspecified
         if addr.port() == 0 {
-            let port = (1..=u16::MAX)
+            let first = if node.unprivileged {
+                PRIVILEGED_PORTS_END
+            } else {
+                1
+            };
+            let port = (first..=u16::MAX)
                 .find(|&port| {
                     !node
                         .sockets
@@ -269,12 +721,21 @@ impl Network {
         if self.link_clogged(src, dst) || self.rand.gen_bool(self.config.packet_loss_rate) {
             None
         } else {
-            self.stat.msg_count += 1;
-            // TODO: special value for loopback
-            Some(self.rand.gen_range(self.config.send_latency.clone()))
+            Some(self.sample_latency(src, dst))
         }
     }
 
+    /// Counts a message on the link and returns its latency.
+    fn sample_latency(&mut self, src: NodeId, dst: NodeId) -> Duration {
+        self.stat.msg_count += 1;
+        *self.stat.link_msg_count.entry((src, dst)).or_default() += 1;
+        // TODO: special value for loopback
+        let config = &self.config;
+        config
+            .latency_distribution
+            .sample(&mut self.rand, &config.send_latency)
+    }
+
     /// Resolve destination node from IP address.
     pub fn resolve_dest_node(
         &self,
@@ -283,9 +744,12 @@ impl Network {
         protocol: IpProtocol,
     ) -> Option<NodeId> {
         let node0 = self.nodes.get(&node).expect("node not found");
-        if dst.ip().is_loopback() || node0.sockets.contains_key(&(dst, protocol)) {
+        if dst.ip().is_loopback()
+            || node0.ips.contains(&dst.ip())
+            || node0.sockets.contains_key(&(dst, protocol))
+        {
             Some(node)
-        } else if node0.ip.is_none() {
+        } else if node0.ips.is_empty() {
             warn!("ip not set: {node}");
             None
         } else if let Some(x) = self.addr_to_node.get(&dst.ip()) {
@@ -298,25 +762,122 @@ impl Network {
 
     /// Try sending a message to the destination.
     ///
+    /// `src_ip` is the IP address that the sending socket is bound to.
+    ///
     /// If destination is not found or packet loss, returns `None`.
     /// Otherwise returns the source IP, socket and latency.
     pub fn try_send(
         &mut self,
         node: NodeId,
+        src_ip: IpAddr,
         dst: SocketAddr,
         protocol: IpProtocol,
     ) -> Option<(IpAddr, NodeId, Arc<dyn Socket>, Duration)> {
-        let dst_node = self.resolve_dest_node(node, dst, protocol)?;
-        let latency = self.test_link(node, dst_node)?;
-        let sockets = &self.nodes.get(&dst_node)?.sockets;
-        let ep = (sockets.get(&(dst, protocol)))
-            .or_else(|| sockets.get(&((Ipv4Addr::UNSPECIFIED, dst.port()).into(), protocol)))?;
-        let src_ip = if dst.ip().is_loopback() {
+        let (dst_node, socket, latency) = self.route(node, dst, protocol)?;
+        let src_ip = self.source_ip(node, src_ip, dst);
+        Some((src_ip, dst_node, socket, latency))
+    }
+
+    /// Resolves a packet held on a clogged link from `node` after the link is unclogged.
+    ///
+    /// The packet is not subject to packet loss, since it was taken by the link.
+    /// If destination is not found or the link is clogged again, returns `None`.
+    /// Otherwise returns the source IP, socket and latency.
+    pub fn release(
+        &mut self,
+        node: NodeId,
+        packet: &BufferedPacket,
+    ) -> Option<(IpAddr, NodeId, Arc<dyn Socket>, Duration)> {
+        let dst_node = self.resolve_dest_node(node, packet.dst, packet.protocol)?;
+        if self.link_clogged(node, dst_node) {
+            return None;
+        }
+        let latency = self.sample_latency(node, dst_node);
+        let socket = self.socket(dst_node, packet.dst, packet.protocol)?;
+        let src_ip = self.source_ip(node, packet.src.ip(), packet.dst);
+        Some((src_ip, dst_node, socket, latency))
+    }
+
+    /// Returns the source IP of a packet from the socket bound to `src_ip`.
+    fn source_ip(&self, node: NodeId, src_ip: IpAddr, dst: SocketAddr) -> IpAddr {
+        if dst.ip().is_loopback() {
             IpAddr::V4(Ipv4Addr::LOCALHOST)
+        } else if !src_ip.is_unspecified() && !src_ip.is_loopback() {
+            src_ip
         } else {
-            self.nodes.get(&node).expect("node not found").ip.unwrap()
+            self.nodes.get(&node).expect("node not found").ips[0]
+        }
+    }
+
+    /// Try sending a batch of packets from the same socket.
+    ///
+    /// Packets on clogged links with buffering are held. For the others, this
+    /// is equivalent to calling [`try_send`](Self::try_send) on each of them,
+    /// but the source IP is resolved only once.
+    /// Returns the packets to be delivered with their source IP, socket and latency.
+    pub fn try_send_many(
+        &mut self,
+        node: NodeId,
+        src_ip: IpAddr,
+        packets: Vec<BufferedPacket>,
+    ) -> Vec<Delivery> {
+        // the source IP of non-loopback destinations
+        let primary_ip = if !src_ip.is_unspecified() && !src_ip.is_loopback() {
+            Some(src_ip)
+        } else {
+            self.nodes
+                .get(&node)
+                .expect("node not found")
+                .ips
+                .first()
+                .copied()
         };
-        Some((src_ip, dst_node, ep.clone(), latency))
+        let mut deliveries = Vec::with_capacity(packets.len());
+        for packet in packets {
+            let packet = match self.try_buffer(node, packet) {
+                Some(packet) => packet,
+                None => continue,
+            };
+            let (dst_node, socket, latency) = match self.route(node, packet.dst, packet.protocol) {
+                Some(route) => route,
+                None => continue,
+            };
+            let src_ip = if packet.dst.ip().is_loopback() {
+                IpAddr::V4(Ipv4Addr::LOCALHOST)
+            } else {
+                primary_ip.expect("node has no IP address")
+            };
+            deliveries.push((packet, src_ip, dst_node, socket, latency));
+        }
+        deliveries
+    }
+
+    /// Resolves the destination socket and samples the latency of the link.
+    ///
+    /// Returns `None` if the destination is not found or the packet is lost.
+    fn route(
+        &mut self,
+        node: NodeId,
+        dst: SocketAddr,
+        protocol: IpProtocol,
+    ) -> Option<(NodeId, Arc<dyn Socket>, Duration)> {
+        let dst_node = self.resolve_dest_node(node, dst, protocol)?;
+        let latency = self.test_link(node, dst_node)?;
+        let socket = self.socket(dst_node, dst, protocol)?;
+        Some((dst_node, socket, latency))
+    }
+
+    /// Returns the socket on `node` that receives packets to `dst`.
+    fn socket(
+        &self,
+        node: NodeId,
+        dst: SocketAddr,
+        protocol: IpProtocol,
+    ) -> Option<Arc<dyn Socket>> {
+        let sockets = &self.nodes.get(&node)?.sockets;
+        let socket = (sockets.get(&(dst, protocol)))
+            .or_else(|| sockets.get(&((Ipv4Addr::UNSPECIFIED, dst.port()).into(), protocol)))?;
+        Some(socket.clone())
     }
 
     pub fn abort_task_on_reset(&mut self, node: NodeId, handle: JoinHandle<()>) {
@@ -0,0 +1,55 @@
+use crate::{task::NodeId, time::Instant};
+use spin::Mutex;
+use std::{collections::HashMap, time::Duration};
+
+/// Limits on the rate of packets sent by each node.
+///
+/// Packets of a limited node depart one by one at fixed intervals. A packet
+/// sent while the previous ones are still waiting is queued behind them, so the
+/// delay grows with the backlog instead of packets being dropped.
+#[derive(Default)]
+pub(crate) struct PacketRates {
+    pacers: Mutex<HashMap<NodeId, Pacer>>,
+}
+
+struct Pacer {
+    /// The interval between two packets.
+    interval: Duration,
+    /// The earliest departure time of the next packet.
+    next: Instant,
+}
+
+impl PacketRates {
+    /// Sets the packets per second of `node`, or removes the limit.
+    pub fn set(&self, node: NodeId, rate: Option<u64>, now: Instant) {
+        let mut pacers = self.pacers.lock();
+        match rate {
+            Some(rate) => {
+                assert!(rate > 0, "packet rate must be positive");
+                let interval = Duration::from_nanos(1_000_000_000 / rate);
+                let pacer = pacers.entry(node).or_insert(Pacer {
+                    interval,
+                    next: now,
+                });
+                pacer.interval = interval;
+            }
+            None => {
+                pacers.remove(&node);
+            }
+        }
+    }
+
+    /// Reserves the departure of a packet sent by `node` at `now`.
+    ///
+    /// Returns how long the packet waits in the queue.
+    pub fn delay(&self, node: NodeId, now: Instant) -> Duration {
+        let mut pacers = self.pacers.lock();
+        let pacer = match pacers.get_mut(&node) {
+            Some(pacer) => pacer,
+            None => return Duration::ZERO,
+        };
+        let departure = pacer.next.max(now);
+        pacer.next = departure + pacer.interval;
+        departure - now
+    }
+}
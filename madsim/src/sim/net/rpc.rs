@@ -120,14 +120,27 @@ impl Endpoint {
         let req_tag = R::ID;
         let rsp_tag = random::<u64>();
         let data = Bytes::copy_from_slice(data);
-        self.send_to_raw(dst, req_tag, Box::new((rsp_tag, request, data)))
-            .await?;
+        let req: Payload = if plugin::simulator::<NetSim>().serialize_rpc() {
+            Box::new((rsp_tag, encode(&request), data))
+        } else {
+            Box::new((rsp_tag, request, data))
+        };
+        self.send_to_raw(dst, req_tag, req).await?;
         let (rsp, from) = self.recv_from_raw(rsp_tag).await?;
         assert_eq!(from, dst);
-        let (rsp, data) = *rsp
-            .downcast::<(R::Response, Bytes)>()
-            .expect("message type mismatch");
-        Ok((rsp, data))
+        let rsp = match rsp.downcast::<(R::Response, Bytes)>() {
+            Ok(rsp) => *rsp,
+            Err(rsp) => match rsp.downcast::<(Serialized, Bytes)>() {
+                Ok(rsp) => (decode(&rsp.0)?, rsp.1),
+                Err(rsp) => {
+                    let error = rsp
+                        .downcast::<DecodeError>()
+                        .expect("message type mismatch");
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, error.0));
+                }
+            },
+        };
+        Ok(rsp)
     }
 
     /// Add a RPC handler.
@@ -150,18 +163,170 @@ impl Endpoint {
         crate::task::spawn(async move {
             loop {
                 let (data, from) = net.recv_from_raw(req_tag).await.unwrap();
-                let (rsp_tag, req, data) = *data
-                    .downcast::<(u64, R, Bytes)>()
-                    .expect("message type mismatch");
+                let (rsp_tag, req, data, serialized) = match data.downcast::<(u64, R, Bytes)>() {
+                    Ok(msg) => (msg.0, msg.1, msg.2, false),
+                    Err(data) => {
+                        let (rsp_tag, req, data) = *data
+                            .downcast::<(u64, Serialized, Bytes)>()
+                            .expect("message type mismatch");
+                        match decode::<R>(&req) {
+                            Ok(req) => (rsp_tag, req, data, true),
+                            Err(e) => {
+                                // tell the caller instead of dropping the request silently
+                                let error = Box::new(DecodeError(e.to_string()));
+                                net.send_to_raw(from, rsp_tag, error).await.unwrap();
+                                continue;
+                            }
+                        }
+                    }
+                };
                 let rsp_future = f(req, data);
                 let net = net.clone();
                 crate::task::spawn(async move {
                     let (rsp, data) = rsp_future.await;
-                    net.send_to_raw(from, rsp_tag, Box::new((rsp, Bytes::from(data))))
-                        .await
-                        .unwrap();
+                    let rsp: Payload = if serialized {
+                        Box::new((encode(&rsp), Bytes::from(data)))
+                    } else {
+                        Box::new((rsp, Bytes::from(data)))
+                    };
+                    net.send_to_raw(from, rsp_tag, rsp).await.unwrap();
                 });
             }
         });
     }
 }
+
+/// A message in its serialized form. See [`Config::serialize_rpc`].
+struct Serialized(Vec<u8>);
+
+/// The response when the handler fails to decode a serialized request.
+struct DecodeError(String);
+
+fn encode<T: Serialize>(msg: &T) -> Serialized {
+    Serialized(bincode::serialize(msg).expect("failed to serialize RPC message"))
+}
+
+fn decode<T: DeserializeOwned>(msg: &Serialized) -> io::Result<T> {
+    bincode::deserialize(&msg.0).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Runtime;
+
+    #[derive(Serialize, Deserialize)]
+    struct Echo {
+        value: u32,
+        #[serde(skip)]
+        local: u32,
+    }
+
+    impl Request for Echo {
+        const ID: u64 = 1;
+        type Response = (u32, u32);
+    }
+
+    /// A newer version of `Echo` with an extra field.
+    #[derive(Serialize, Deserialize)]
+    struct EchoV2 {
+        value: u32,
+        name: String,
+    }
+
+    impl Request for EchoV2 {
+        const ID: u64 = 1;
+        type Response = (u32, u32);
+    }
+
+    #[test]
+    fn serialize_rpc() {
+        let mut config = crate::Config::default();
+        config.net.serialize_rpc = true;
+        let runtime = Runtime::with_seed_and_config(0, config);
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let addr3 = "10.0.0.3:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let node3 = runtime.create_node().ip(addr3.ip()).build();
+
+        let f1 = node1.spawn(async move {
+            let ep = Endpoint::bind(addr1).await.unwrap();
+            ep.add_rpc_handler(|req: Echo| async move { (req.value, req.local) });
+            ep
+        });
+        let f2 = node2.spawn(async move {
+            let ep = Endpoint::bind(addr2).await.unwrap();
+            ep.add_rpc_handler(|req: EchoV2| async move { (req.value, req.name.len() as u32) });
+            ep
+        });
+        let _ep1 = runtime.block_on(f1).unwrap();
+        let _ep2 = runtime.block_on(f2).unwrap();
+
+        let f3 = node3.spawn(async move {
+            let ep = Endpoint::bind(addr3).await.unwrap();
+            // the skipped field does not survive the round trip
+            let rsp = ep.call(addr1, Echo { value: 1, local: 2 }).await.unwrap();
+            assert_eq!(rsp, (1, 0));
+            // the old request can not be decoded by the new handler
+            let err = ep
+                .call(addr2, Echo { value: 1, local: 2 })
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        });
+        runtime.block_on(f3).unwrap();
+    }
+
+    #[test]
+    fn count_messages() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let addr3 = "10.0.0.3:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let node3 = runtime.create_node().ip(addr3.ip()).build();
+        let (id1, id2, id3) = (node1.id(), node2.id(), node3.id());
+
+        let f1 = node1.spawn(async move {
+            let ep = Endpoint::bind(addr1).await.unwrap();
+            ep.add_rpc_handler(|req: Echo| async move { (req.value, req.local) });
+            ep
+        });
+        let _ep1 = runtime.block_on(f1).unwrap();
+
+        let f2 = node2.spawn(async move {
+            let ep = Endpoint::bind(addr2).await.unwrap();
+            // warm up
+            ep.call(addr1, Echo { value: 0, local: 0 }).await.unwrap();
+
+            let net = NetSim::current();
+            let (rsp, stat) = net
+                .count_messages(|| async {
+                    ep.call(addr1, Echo { value: 1, local: 2 }).await.unwrap()
+                })
+                .await;
+            assert_eq!(rsp, (1, 2));
+            // a request and a response
+            assert_eq!(stat.msg_count, 2);
+            assert_eq!(stat.link_msg_count[&(id2, id1)], 1);
+            assert_eq!(stat.link_msg_count[&(id1, id2)], 1);
+            assert_eq!(stat.node_msg_count(id1), 2);
+            assert_eq!(stat.node_msg_count(id3), 0);
+
+            let (_, stat) = net
+                .count_messages(|| async {
+                    for i in 0..3 {
+                        ep.call(addr1, Echo { value: i, local: 0 }).await.unwrap();
+                    }
+                })
+                .await;
+            assert_eq!(stat.msg_count, 6);
+            // the total is still counted
+            assert_eq!(net.stat().link_msg_count[&(id2, id1)], 5);
+        });
+        runtime.block_on(f2).unwrap();
+    }
+}
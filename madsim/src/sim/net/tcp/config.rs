@@ -1,13 +1,92 @@
-use std::hash::{Hash, Hasher};
-
 use serde::{Deserialize, Serialize};
+use std::{
+    hash::{Hash, Hasher},
+    time::Duration,
+};
 
 /// tcp configurations.
 #[cfg_attr(docsrs, doc(cfg(madsim)))]
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
-pub struct TcpConfig {}
+#[serde(default)]
+pub struct TcpConfig {
+    /// The maximum number of bytes a sender can have in flight before the
+    /// receiver reads them.
+    ///
+    /// Writes beyond the window return `Pending` until the peer reads.
+    /// `None` means unlimited.
+    pub send_window: Option<usize>,
+    /// Reset the connection if a write is blocked by a full window for this
+    /// long.
+    ///
+    /// Both ends then see `ConnectionReset`. `None` means never. Only takes
+    /// effect with `send_window`.
+    pub stall_timeout: Option<Duration>,
+    /// Split received data into reads of random sizes.
+    ///
+    /// By default, a read returns as much of a received payload as fits in
+    /// the buffer. Enable this to exercise readers against short reads.
+    pub partial_read: bool,
+    /// Detect a peer whose node has been killed after this long.
+    ///
+    /// A killed node never sends a FIN, so its connections become half-open:
+    /// reads on the other end block until this timeout and then return
+    /// `TimedOut`, and writes are silently lost. `None` means the connections
+    /// of a killed node are closed as if it had dropped them, so the other end
+    /// reads EOF immediately.
+    pub dead_peer_timeout: Option<Duration>,
+    /// Limit the bytes in flight of a connection to a congestion window.
+    ///
+    /// The window starts small and grows by the bytes acknowledged, doubling
+    /// every round trip as in TCP slow start, up to a maximum. So a short
+    /// transfer over a link of high latency is bound by the round trips, and
+    /// a long one by the window over the round-trip time, whatever the
+    /// bandwidth. Data is acknowledged one round trip after it is sent, with
+    /// the round-trip time of a connection sampled from `send_latency` when it
+    /// is opened. `None` means no congestion control.
+    pub congestion_control: Option<CongestionControl>,
+}
 
-#[allow(clippy::derive_hash_xor_eq)]
+/// Only non-default settings are hashed, so that the hash of a config doesn't
+/// change when a new setting is added.
 impl Hash for TcpConfig {
-    fn hash<H: Hasher>(&self, _state: &mut H) {}
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let default = Self::default();
+        if self.send_window != default.send_window {
+            ("send_window", self.send_window).hash(state);
+        }
+        if self.stall_timeout != default.stall_timeout {
+            ("stall_timeout", self.stall_timeout).hash(state);
+        }
+        if self.partial_read != default.partial_read {
+            ("partial_read", self.partial_read).hash(state);
+        }
+        if self.dead_peer_timeout != default.dead_peer_timeout {
+            ("dead_peer_timeout", self.dead_peer_timeout).hash(state);
+        }
+        if self.congestion_control != default.congestion_control {
+            ("congestion_control", &self.congestion_control).hash(state);
+        }
+    }
+}
+
+/// The congestion window of [`TcpConfig::congestion_control`].
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[serde(default)]
+pub struct CongestionControl {
+    /// The window of a new connection in bytes. Defaults to 10 segments of
+    /// 1460 bytes, as in RFC 6928.
+    pub initial_window: usize,
+    /// The largest window in bytes, like the receive window of the peer.
+    /// Defaults to 64 KiB.
+    pub max_window: usize,
+}
+
+impl Default for CongestionControl {
+    fn default() -> Self {
+        CongestionControl {
+            initial_window: 10 * 1460,
+            max_window: 64 * 1024,
+        }
+    }
 }
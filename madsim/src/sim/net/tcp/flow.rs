@@ -0,0 +1,155 @@
+use super::CongestionControl;
+use spin::Mutex;
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Arc,
+    task::Waker,
+};
+
+/// Flow-control state shared by the two ends of a TCP connection.
+#[derive(Default)]
+pub(crate) struct Connection {
+    /// Flows of both directions, indexed by the sending [`Side`].
+    flows: [Mutex<Flow>; 2],
+    /// Whether the connection has been reset.
+    reset: AtomicBool,
+    /// Whether each [`Side`] has vanished without closing the connection.
+    vanished: [AtomicBool; 2],
+}
+
+/// One direction of a connection.
+#[derive(Default)]
+struct Flow {
+    /// The number of bytes written but not yet read by the peer.
+    unread: usize,
+    /// The number of bytes written but not yet acknowledged.
+    in_flight: usize,
+    /// The congestion window, set on the first write.
+    cwnd: Option<usize>,
+    /// The writer waiting for the window to open.
+    writer: Option<Waker>,
+}
+
+/// The end of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Side {
+    Connector = 0,
+    Acceptor = 1,
+}
+
+impl Side {
+    fn peer(self) -> Side {
+        match self {
+            Side::Connector => Side::Acceptor,
+            Side::Acceptor => Side::Connector,
+        }
+    }
+}
+
+impl Connection {
+    /// Reserves up to `len` bytes of the window for the writer on `side`.
+    ///
+    /// The bytes are limited by both the send window and the congestion
+    /// window if set. Returns the number of bytes reserved. If a window is
+    /// full, the waker is registered and 0 is returned.
+    pub fn reserve(
+        &self,
+        side: Side,
+        len: usize,
+        window: Option<usize>,
+        congestion: Option<&CongestionControl>,
+        waker: &Waker,
+    ) -> usize {
+        let mut flow = self.flows[side as usize].lock();
+        let mut len = match window {
+            Some(window) => len.min(window.saturating_sub(flow.unread)),
+            None => len,
+        };
+        if let Some(cc) = congestion {
+            let cwnd = *flow.cwnd.get_or_insert(cc.initial_window);
+            len = len.min(cwnd.saturating_sub(flow.in_flight));
+            flow.in_flight += len;
+        }
+        if len == 0 {
+            flow.writer = Some(waker.clone());
+        }
+        flow.unread += len;
+        len
+    }
+
+    /// Acknowledges `len` bytes sent by the writer on `side`, growing its
+    /// congestion window and waking it.
+    pub fn ack(&self, side: Side, len: usize, max_window: usize) {
+        let mut flow = self.flows[side as usize].lock();
+        flow.in_flight = flow.in_flight.saturating_sub(len);
+        if let Some(cwnd) = &mut flow.cwnd {
+            // slow start
+            *cwnd = (*cwnd + len).min(max_window);
+        }
+        if let Some(waker) = flow.writer.take() {
+            waker.wake();
+        }
+    }
+
+    /// Releases `len` bytes read by the reader on `side`, waking the writer.
+    pub fn consume(&self, side: Side, len: usize) {
+        let mut flow = self.flows[side.peer() as usize].lock();
+        flow.unread = flow.unread.saturating_sub(len);
+        if let Some(waker) = flow.writer.take() {
+            waker.wake();
+        }
+    }
+
+    /// Marks the connection as reset and wakes up both writers.
+    pub fn reset(&self) {
+        self.reset.store(true, Ordering::Relaxed);
+        for flow in &self.flows {
+            if let Some(waker) = flow.lock().writer.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Marks the end on `side` as vanished, e.g. because its node was killed.
+    pub fn vanish(&self, side: Side) {
+        self.vanished[side as usize].store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if the peer of the end on `side` has vanished.
+    pub fn is_peer_vanished(&self, side: Side) -> bool {
+        self.vanished[side.peer() as usize].load(Ordering::Relaxed)
+    }
+
+    /// Returns an error if the connection has been reset.
+    pub fn check_reset(&self) -> io::Result<()> {
+        if self.reset.load(Ordering::Relaxed) {
+            return Err(reset_error());
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn reset_error() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "connection reset by peer")
+}
+
+/// Connections whose accepting end has not been created yet.
+#[derive(Default)]
+pub(crate) struct PendingConnections {
+    conns: Mutex<HashMap<(SocketAddr, SocketAddr), Arc<Connection>>>,
+}
+
+impl PendingConnections {
+    /// Registers a connection from `src` to `dst`.
+    pub fn insert(&self, src: SocketAddr, dst: SocketAddr, conn: Arc<Connection>) {
+        self.conns.lock().insert((src, dst), conn);
+    }
+
+    /// Takes the connection from `src` to `dst`.
+    pub fn take(&self, src: SocketAddr, dst: SocketAddr) -> Arc<Connection> {
+        self.conns.lock().remove(&(src, dst)).unwrap_or_default()
+    }
+}
@@ -1,7 +1,12 @@
 use std::{fmt, io::Result, net::SocketAddr, sync::Arc};
 use tracing::instrument;
 
-use crate::net::{IpProtocol::Tcp, *};
+use super::flow::Side;
+use crate::{
+    net::{IpProtocol::Tcp, *},
+    plugin,
+    task::NodeInfo,
+};
 
 /// A TCP socket server, listening for connections.
 #[cfg_attr(docsrs, doc(cfg(madsim)))]
@@ -35,7 +40,9 @@ impl TcpListener {
     pub async fn bind<A: ToSocketAddrs>(addr: A) -> Result<TcpListener> {
         // TODO: simulate backlog
         let (tx, rx) = async_channel::unbounded();
-        let guard = BindGuard::bind(addr, Tcp, Arc::new(TcpListenerSocket { tx })).await?;
+        let node = crate::context::current_task().node.clone();
+        let socket = Arc::new(TcpListenerSocket { tx, node });
+        let guard = BindGuard::bind(addr, Tcp, socket).await?;
 
         Ok(TcpListener {
             guard: Arc::new(guard),
@@ -72,6 +79,8 @@ impl TcpListener {
 /// Socket registered in the [`Network`].
 struct TcpListenerSocket {
     tx: async_channel::Sender<TcpStream>,
+    /// The node owning the listener.
+    node: Arc<NodeInfo>,
 }
 
 impl Socket for TcpListenerSocket {
@@ -82,14 +91,23 @@ impl Socket for TcpListenerSocket {
         tx: PayloadSender,
         rx: PayloadReceiver,
     ) {
+        let net = plugin::simulator::<NetSim>();
         let stream = TcpStream {
             guard: None,
+            node: self.node.clone(),
             addr,
             peer,
             write_buf: Default::default(),
             read_buf: Default::default(),
             tx,
             rx,
+            conn: net.tcp_pending.take(peer, addr),
+            side: Side::Acceptor,
+            config: net.tcp_config.clone(),
+            stall: None,
+            dead_peer: None,
+            linger: Mutex::new(None),
+            rtt: net.tcp_rtt(),
         };
         let _ = self.tx.try_send(stream);
     }
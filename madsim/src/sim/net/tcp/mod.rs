@@ -47,17 +47,24 @@ use std::any::Any;
 pub type Payload = Box<dyn Any + Send + Sync>;
 
 mod config;
+mod flow;
 mod listener;
 mod stream;
 
 pub use self::config::*;
+pub(crate) use self::flow::PendingConnections;
 pub use self::listener::*;
 pub use self::stream::*;
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{net::NetSim, plugin, runtime::Runtime, time::timeout};
+    use crate::{
+        net::{NetSim, SrvRecord},
+        plugin,
+        runtime::Runtime,
+        time::timeout,
+    };
     use std::{io::ErrorKind, net::SocketAddr, sync::Arc, time::Duration};
     use tokio::{
         io::{AsyncReadExt, AsyncWriteExt},
@@ -207,6 +214,48 @@ mod tests {
         runtime.block_on(f2).unwrap();
     }
 
+    #[test]
+    fn linger_close() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime
+            .create_node()
+            .ip("10.0.0.2".parse().unwrap())
+            .build();
+
+        node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            for abort in [false, true] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                stream.write_all(b"hello").await.unwrap();
+                stream.flush().await.unwrap();
+                if abort {
+                    stream.set_linger(Some(Duration::ZERO)).unwrap();
+                }
+                assert_eq!(stream.linger().unwrap(), abort.then_some(Duration::ZERO));
+            }
+        });
+
+        let f = node2.spawn(async move {
+            for abort in [false, true] {
+                let mut stream = TcpStream::connect(addr1).await.unwrap();
+                // the data arrives before the peer closes, but is not read yet
+                crate::time::sleep(Duration::from_secs(1)).await;
+                let mut buf = vec![];
+                let res = stream.read_to_end(&mut buf).await;
+                if abort {
+                    assert_eq!(res.unwrap_err().kind(), ErrorKind::ConnectionReset);
+                    assert!(buf.is_empty());
+                } else {
+                    assert_eq!(res.unwrap(), 5);
+                    assert_eq!(buf, b"hello");
+                }
+            }
+        });
+        runtime.block_on(f).unwrap();
+    }
+
     #[test]
     fn ip_resolve() {
         let runtime = Runtime::new();
@@ -248,4 +297,513 @@ mod tests {
         });
         runtime.block_on(f1).unwrap();
     }
+
+    #[test]
+    fn reset_on_stall() {
+        let mut config = crate::Config::default();
+        config.tcp.send_window = Some(1024);
+        config.tcp.stall_timeout = Some(Duration::from_secs(1));
+        let runtime = Runtime::with_seed_and_config(0, config);
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            // a reading peer receives everything through the window
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 10000];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert!(buf.iter().all(|&b| b == 1));
+
+            // a peer that never reads gets the connection reset
+            let (mut stream, _) = listener.accept().await.unwrap();
+            crate::time::sleep(Duration::from_secs(5)).await;
+            let err = stream.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+        });
+
+        let f2 = node2.spawn(async move {
+            barrier.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            stream.write_all(&[1; 10000]).await.unwrap();
+            stream.flush().await.unwrap();
+
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            let start = crate::time::Instant::now();
+            let err = stream.write_all(&[2; 10000]).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+            assert!(start.elapsed() >= Duration::from_secs(1));
+            assert!(start.elapsed() < Duration::from_secs(2));
+            let err = stream.read(&mut [0; 10]).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+        });
+
+        runtime.block_on(f1).unwrap();
+        runtime.block_on(f2).unwrap();
+    }
+
+    #[test]
+    fn half_open_on_kill() {
+        let mut config = crate::Config::default();
+        config.tcp.dead_peer_timeout = Some(Duration::from_secs(3));
+        let runtime = Runtime::with_seed_and_config(0, config);
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+
+            // the peer is killed at 1s, but no EOF arrives
+            let err = stream.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::TimedOut);
+            // writes are lost without an error
+            stream.write_all(b"world").await.unwrap();
+            stream.flush().await.unwrap();
+            crate::time::Instant::now()
+        });
+
+        node2.spawn(async move {
+            barrier.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            stream.flush().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        runtime.block_on(async move {
+            let t0 = crate::time::Instant::now();
+            crate::time::sleep(Duration::from_secs(1)).await;
+            crate::runtime::Handle::current().kill(node2.id());
+            let timed_out = f1.await.unwrap();
+            assert!(timed_out - t0 >= Duration::from_secs(4));
+            assert!(timed_out - t0 < Duration::from_secs(5));
+        });
+
+        // the same holds when the server's node is killed
+        let mut config = crate::Config::default();
+        config.tcp.dead_peer_timeout = Some(Duration::from_secs(3));
+        let runtime = Runtime::with_seed_and_config(0, config);
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            stream.flush().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let f2 = node2.spawn(async move {
+            barrier.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+            // never accepted by the server
+            let mut pending = TcpStream::connect(addr1).await.unwrap();
+
+            // the server is killed at 1s, but no EOF arrives
+            let err = stream.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::TimedOut);
+            let timed_out = crate::time::Instant::now();
+            let err = pending.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::TimedOut);
+            timed_out
+        });
+
+        runtime.block_on(async move {
+            let t0 = crate::time::Instant::now();
+            crate::time::sleep(Duration::from_secs(1)).await;
+            crate::runtime::Handle::current().kill(node1.id());
+            let timed_out = f2.await.unwrap();
+            assert!(timed_out - t0 >= Duration::from_secs(4));
+            assert!(timed_out - t0 < Duration::from_secs(5));
+        });
+    }
+
+    #[test]
+    fn partial_read() {
+        let mut config = crate::Config::default();
+        config.tcp.partial_read = true;
+        let runtime = Runtime::with_seed_and_config(0, config);
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // a length-prefixed frame in a single write
+            let body = b"hello world, this is a framed message";
+            let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+            frame.extend_from_slice(body);
+            stream.write_all(&frame).await.unwrap();
+            stream.flush().await.unwrap();
+            stream
+        });
+
+        let f2 = node2.spawn(async move {
+            barrier.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            let mut received = vec![];
+            let mut reads = 0;
+            let mut buf = [0; 64];
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                received.extend_from_slice(&buf[..n]);
+                reads += 1;
+                if received.len() >= 4 {
+                    let len = u32::from_be_bytes(received[..4].try_into().unwrap()) as usize;
+                    if received.len() == 4 + len {
+                        break;
+                    }
+                }
+            }
+            assert_eq!(&received[4..], b"hello world, this is a framed message");
+            assert!(reads > 1, "the frame should arrive across several reads");
+        });
+
+        runtime.block_on(f1).unwrap();
+        runtime.block_on(f2).unwrap();
+    }
+
+    #[test]
+    fn peek() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // sniff the protocol by the first byte
+            let mut magic = [0; 1];
+            assert_eq!(stream.peek(&mut magic).await.unwrap(), 1);
+            assert_eq!(&magic, b"\x16");
+            // the peeked byte is read again
+            let mut buf = vec![];
+            stream.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"\x16hello");
+            // EOF
+            assert_eq!(stream.peek(&mut magic).await.unwrap(), 0);
+        });
+
+        let f2 = node2.spawn(async move {
+            barrier.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            stream.write_all(b"\x16hello").await.unwrap();
+            stream.flush().await.unwrap();
+        });
+
+        runtime.block_on(f2).unwrap();
+        runtime.block_on(f1).unwrap();
+    }
+
+    #[test]
+    fn reconnect_after_dns_ttl() {
+        let runtime = Runtime::new();
+        let ip1 = "10.0.0.1".parse().unwrap();
+        let ip2 = "10.0.0.2".parse().unwrap();
+        let server1 = runtime.create_node().ip(ip1).build();
+        let server2 = runtime.create_node().ip(ip2).build();
+        let client = runtime.create_node().ip([10, 0, 0, 3].into()).build();
+        let ttl = Duration::from_secs(10);
+
+        for server in [server1, server2] {
+            server.spawn(async move {
+                let listener = TcpListener::bind("0.0.0.0:80").await.unwrap();
+                loop {
+                    listener.accept().await.unwrap();
+                }
+            });
+        }
+
+        let f = client.spawn(async move {
+            let net = NetSim::current();
+            net.set_dns_record("server", [ip1], ttl);
+            crate::time::sleep(Duration::from_secs(1)).await;
+            let stream = TcpStream::connect("server:80").await.unwrap();
+            assert_eq!(stream.peer_addr().unwrap().ip(), ip1);
+
+            // the server migrates, but the old address is still cached
+            net.set_dns_record("server", [ip2], ttl);
+            let stream = TcpStream::connect("server:80").await.unwrap();
+            assert_eq!(stream.peer_addr().unwrap().ip(), ip1);
+
+            crate::time::sleep(ttl).await;
+            let stream = TcpStream::connect("server:80").await.unwrap();
+            assert_eq!(stream.peer_addr().unwrap().ip(), ip2);
+        });
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn srv_discovery() {
+        fn run(seed: u64) -> Vec<String> {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            let names = ["a", "b", "backup"];
+            for (i, name) in names.into_iter().enumerate() {
+                let ip = [10, 0, 0, i as u8 + 1].into();
+                runtime.create_node().ip(ip).build().spawn(async move {
+                    NetSim::current().set_dns_record(name, [ip], Duration::from_secs(60));
+                    let listener = TcpListener::bind(("0.0.0.0", 2000 + i as u16))
+                        .await
+                        .unwrap();
+                    loop {
+                        listener.accept().await.unwrap();
+                    }
+                });
+            }
+            let client = runtime.create_node().ip([10, 0, 0, 9].into()).build();
+            let f = client.spawn(async move {
+                crate::time::sleep(Duration::from_secs(1)).await;
+                let record = |priority, weight, target: &str, port| SrvRecord {
+                    priority,
+                    weight,
+                    target: target.into(),
+                    port,
+                };
+                let records = [
+                    record(1, 0, "backup", 2002),
+                    record(0, 3, "a", 2000),
+                    record(0, 1, "b", 2001),
+                ];
+                let net = NetSim::current();
+                net.set_srv_record("_kv._tcp.cluster", records, Duration::ZERO);
+
+                let mut firsts = vec![];
+                for _ in 0..100 {
+                    let records = net.lookup_srv("_kv._tcp.cluster").unwrap();
+                    assert_eq!(records.len(), 3);
+                    // the backup is only tried after the others
+                    assert_eq!(records[2].target, "backup");
+                    let first = &records[0];
+                    let stream = TcpStream::connect((first.target.as_str(), first.port))
+                        .await
+                        .unwrap();
+                    let ip = stream.peer_addr().unwrap().ip();
+                    assert_eq!(net.resolve(&first.target).unwrap(), [ip]);
+                    firsts.push(first.target.clone());
+                }
+                // chosen by weight
+                let a = firsts.iter().filter(|t| *t == "a").count();
+                assert!((60..90).contains(&a), "{a}");
+
+                net.remove_srv_record("_kv._tcp.cluster");
+                let err = net.lookup_srv("_kv._tcp.cluster").unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::NotFound);
+                firsts
+            });
+            runtime.block_on(f).unwrap()
+        }
+        // the order is determined by the seed
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+
+    #[test]
+    fn fair_bandwidth() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        const TOTAL: usize = 1 << 20;
+
+        let receiver = node2.spawn(async move {
+            let listener = TcpListener::bind(addr2).await.unwrap();
+            let mut tasks = vec![];
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tasks.push(crate::task::spawn(async move {
+                    let start = crate::time::Instant::now();
+                    let mut buf = vec![0; TOTAL];
+                    stream.read_exact(&mut buf).await.unwrap();
+                    start.elapsed()
+                }));
+            }
+            let mut elapsed = vec![];
+            for task in tasks {
+                elapsed.push(task.await.unwrap());
+            }
+            elapsed
+        });
+
+        runtime.block_on(async move {
+            plugin::simulator::<NetSim>().set_link_bandwidth(
+                node1.id(),
+                node2.id(),
+                Some(TOTAL as u64),
+            );
+            node1.spawn(async move {
+                crate::time::sleep(Duration::from_secs(1)).await;
+                // the first transfer writes large chunks, the second small ones
+                for chunk in [64 << 10, 4 << 10] {
+                    let mut stream = TcpStream::connect(addr2).await.unwrap();
+                    crate::task::spawn(async move {
+                        for _ in 0..TOTAL / chunk {
+                            stream.write_all(&vec![0; chunk]).await.unwrap();
+                            stream.flush().await.unwrap();
+                        }
+                        stream
+                    });
+                }
+            });
+            let elapsed = receiver.await.unwrap();
+            // both transfers share the link and finish at about the same time
+            for e in &elapsed {
+                assert!(
+                    (Duration::from_millis(1800)..Duration::from_millis(2300)).contains(e),
+                    "{elapsed:?}"
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn congestion_control() {
+        /// Returns the time to transfer `size` bytes on a fast link of 50ms latency.
+        fn transfer(congestion_control: bool, size: usize) -> Duration {
+            let mut config = crate::Config::default();
+            config.net.send_latency = Duration::from_millis(50)..Duration::from_millis(51);
+            if congestion_control {
+                config.tcp.congestion_control = Some(CongestionControl::default());
+            }
+            let runtime = Runtime::with_seed_and_config(0, config);
+            let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+            let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+            let node1 = runtime.create_node().ip(addr1.ip()).build();
+            let node2 = runtime.create_node().ip(addr2.ip()).build();
+            let receiver = node2.spawn(async move {
+                let listener = TcpListener::bind(addr2).await.unwrap();
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; size];
+                stream.read_exact(&mut buf).await.unwrap();
+                crate::time::Instant::now()
+            });
+            runtime.block_on(async move {
+                // 100 MB/s
+                plugin::simulator::<NetSim>().set_link_bandwidth(
+                    node1.id(),
+                    node2.id(),
+                    Some(100 << 20),
+                );
+                let sender = node1.spawn(async move {
+                    crate::time::sleep(Duration::from_secs(1)).await;
+                    let mut stream = TcpStream::connect(addr2).await.unwrap();
+                    let start = crate::time::Instant::now();
+                    stream.write_all(&vec![0; size]).await.unwrap();
+                    stream.flush().await.unwrap();
+                    // keep the connection open until the data is read
+                    crate::time::sleep(Duration::from_secs(10)).await;
+                    start
+                });
+                let end = receiver.await.unwrap();
+                end - sender.await.unwrap()
+            })
+        }
+
+        // a short transfer fits in the initial window and takes one trip
+        let small = transfer(true, 10_000);
+        assert!(small < Duration::from_millis(60), "{small:?}");
+
+        // a long one ramps up and is then bound by the window over the round trip
+        let large = transfer(true, 1 << 20);
+        let min = Duration::from_millis(100) * ((1 << 20) / (64 << 10));
+        assert!(large > min && large < min * 3 / 2, "{large:?}");
+
+        // without congestion control, it is only bound by the bandwidth
+        let large = transfer(false, 1 << 20);
+        assert!(large < Duration::from_millis(100), "{large:?}");
+    }
+
+    #[test]
+    fn migrate_ip() {
+        /// Returns the bytes received by a client changing its IP mid-stream,
+        /// and what the server received back.
+        fn run(migrate: bool) -> (Vec<u8>, Option<u8>) {
+            let runtime = Runtime::new();
+            let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+            let node1 = runtime.create_node().ip(addr1.ip()).build();
+            let node2 = runtime.create_node().ip([10, 0, 0, 2].into()).build();
+            let server = node1.spawn(async move {
+                let listener = TcpListener::bind(addr1).await.unwrap();
+                let (mut stream, _) = listener.accept().await.unwrap();
+                for i in 0..20 {
+                    stream.write_all(&[i]).await.unwrap();
+                    stream.flush().await.unwrap();
+                    crate::time::sleep(Duration::from_millis(100)).await;
+                }
+                let mut buf = [0];
+                let res = timeout(Duration::from_secs(1), stream.read_exact(&mut buf)).await;
+                res.ok().map(|_| buf[0])
+            });
+            let client = node2.spawn(async move {
+                crate::time::sleep(Duration::from_secs(1)).await;
+                let mut stream = TcpStream::connect(addr1).await.unwrap();
+                let mut received = vec![];
+                let mut buf = [0];
+                while received.len() < 20 {
+                    let res = timeout(Duration::from_secs(1), stream.read_exact(&mut buf)).await;
+                    if res.is_err() {
+                        break;
+                    }
+                    received.push(buf[0]);
+                }
+                stream.write_all(&[42]).await.unwrap();
+                stream.flush().await.unwrap();
+                received
+            });
+            runtime.block_on(async move {
+                crate::time::sleep(Duration::from_millis(2050)).await;
+                let net = plugin::simulator::<NetSim>();
+                let ip = [10, 0, 0, 3].into();
+                if migrate {
+                    net.migrate_ip(node2.id(), ip).unwrap();
+                } else {
+                    net.set_ip(node2.id(), ip);
+                }
+                let received = client.await.unwrap();
+                (received, server.await.unwrap())
+            })
+        }
+
+        // the stream continues on the new IP
+        let (received, reply) = run(true);
+        assert_eq!(received, (0..20).collect::<Vec<u8>>());
+        assert_eq!(reply, Some(42));
+
+        // without migration, the server can no longer reach the client
+        let (received, _) = run(false);
+        assert!(received.len() < 20, "{received:?}");
+    }
 }
@@ -1,12 +1,21 @@
+use super::{
+    flow::{reset_error, Connection, Side},
+    TcpConfig,
+};
 use crate::{
     net::{IpProtocol::Tcp, *},
     plugin,
+    rand::Rng,
+    task::NodeInfo,
+    time::{sleep, Duration, Sleep, TimeHandle},
 };
 use bytes::{Buf, Bytes, BytesMut};
+use spin::Mutex;
 #[cfg(unix)]
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::{
     fmt,
+    future::Future,
     io::Result,
     net::SocketAddr,
     pin::Pin,
@@ -19,6 +28,8 @@ use tracing::*;
 /// A TCP stream between a local and a remote socket.
 pub struct TcpStream {
     pub(super) guard: Option<Arc<BindGuard>>,
+    /// The node owning the stream.
+    pub(super) node: Arc<NodeInfo>,
     pub(super) addr: SocketAddr,
     pub(super) peer: SocketAddr,
     /// Buffer write data to be flushed.
@@ -26,6 +37,17 @@ pub struct TcpStream {
     pub(super) read_buf: Bytes,
     pub(super) tx: PayloadSender,
     pub(super) rx: PayloadReceiver,
+    pub(super) conn: Arc<Connection>,
+    pub(super) side: Side,
+    pub(super) config: TcpConfig,
+    /// The timer started when a write is blocked by a full window.
+    pub(super) stall: Option<Pin<Box<Sleep>>>,
+    /// The timer started when reading from a vanished peer.
+    pub(super) dead_peer: Option<Pin<Box<Sleep>>>,
+    /// The `SO_LINGER` option.
+    pub(super) linger: Mutex<Option<Duration>>,
+    /// The round-trip time under congestion control.
+    pub(super) rtt: Duration,
 }
 
 impl fmt::Debug for TcpStream {
@@ -75,10 +97,12 @@ impl TcpStream {
         // send a request to listener and wait for TcpStream
         // FIXME: the port it uses should not be exclusive
         let guard = BindGuard::bind("0.0.0.0:0", Tcp, Arc::new(TcpStreamSocket)).await?;
-        let (tx, rx, local_addr) = net
-            .connect1(plugin::node(), guard.addr.port(), addr, Tcp)
-            .await?;
+        let (tx, rx, local_addr) = net.connect1(plugin::node(), guard.addr, addr, Tcp).await?;
+        // the listener takes it when the connection arrives
+        let conn = Arc::new(Connection::default());
+        net.tcp_pending.insert(local_addr, addr, conn.clone());
         let stream = TcpStream {
+            node: guard.node.clone(),
             guard: Some(Arc::new(guard)),
             addr: local_addr,
             peer: addr,
@@ -86,6 +110,13 @@ impl TcpStream {
             read_buf: Default::default(),
             tx,
             rx,
+            conn,
+            side: Side::Connector,
+            config: net.tcp_config.clone(),
+            stall: None,
+            dead_peer: None,
+            linger: Mutex::new(None),
+            rtt: net.tcp_rtt(),
         };
         Ok(stream)
     }
@@ -96,6 +127,21 @@ impl TcpStream {
         Ok(())
     }
 
+    /// Sets the value of the `SO_LINGER` option on this socket.
+    ///
+    /// With a zero timeout, dropping the stream aborts the connection: the
+    /// peer gets `ConnectionReset` and the data it has not read is discarded.
+    /// Otherwise, the buffered data is sent and the peer reads EOF after it.
+    pub fn set_linger(&self, dur: Option<Duration>) -> Result<()> {
+        *self.linger.lock() = dur;
+        Ok(())
+    }
+
+    /// Reads the linger duration for this socket by getting the `SO_LINGER` option.
+    pub fn linger(&self) -> Result<Option<Duration>> {
+        Ok(*self.linger.lock())
+    }
+
     /// Returns the socket address of the local half of this TCP connection.
     pub fn local_addr(&self) -> Result<SocketAddr> {
         Ok(self.addr)
@@ -105,6 +151,104 @@ impl TcpStream {
     pub fn peer_addr(&self) -> Result<SocketAddr> {
         Ok(self.peer)
     }
+
+    /// Receives data on the socket without removing it from the queue.
+    ///
+    /// The next read returns the same data. Returns 0 at EOF.
+    ///
+    /// Unlike tokio, this requires `&mut self`.
+    pub async fn peek(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut buf = ReadBuf::new(buf);
+        futures_util::future::poll_fn(|cx| self.poll_peek(cx, &mut buf)).await
+    }
+
+    /// Attempts to receive data on the socket without removing it from the queue.
+    pub fn poll_peek(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<usize>> {
+        self.conn.check_reset()?;
+        while self.read_buf.is_empty() {
+            match self.rx.poll_recv(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(data)) => self.read_buf = *data.downcast::<Bytes>().unwrap(),
+                Poll::Ready(None) => return self.poll_closed(cx).map_ok(|_| 0),
+            }
+        }
+        let len = self.read_buf.len().min(buf.remaining());
+        buf.put_slice(&self.read_buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    /// Polls the end of the received data.
+    ///
+    /// Returns EOF if the peer closed the connection. If the peer vanished,
+    /// waits for the dead peer timeout and returns `TimedOut`.
+    fn poll_closed(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.conn.check_reset()?;
+        let timeout = match self.config.dead_peer_timeout {
+            Some(timeout) if self.conn.is_peer_vanished(self.side) => timeout,
+            _ => return Poll::Ready(Ok(())),
+        };
+        let timer = self
+            .dead_peer
+            .get_or_insert_with(|| Box::pin(sleep(timeout)));
+        if timer.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        debug!(addr = %self.addr, peer = %self.peer, "connection timed out");
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "connection timed out",
+        )))
+    }
+
+    /// Sends the buffered data to the peer.
+    fn send_buffered(&mut self) -> Result<()> {
+        let data = self.write_buf.split().freeze();
+        if self.config.dead_peer_timeout.is_some() && self.conn.is_peer_vanished(self.side) {
+            // lost in the void
+            return Ok(());
+        }
+        // the data is acknowledged after a round trip
+        if let (Some(cc), Some(time)) = (&self.config.congestion_control, TimeHandle::try_current())
+        {
+            let (conn, side, len, max) = (self.conn.clone(), self.side, data.len(), cc.max_window);
+            time.add_timer(self.rtt, move || conn.ack(side, len, max));
+        }
+        self.tx
+            .send(Box::new(data))
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionReset, e))
+    }
+
+    /// Resets the connection, closing both directions.
+    fn reset(&mut self) {
+        debug!(addr = %self.addr, peer = %self.peer, "connection reset on stall");
+        self.conn.reset();
+        self.rx.close();
+        // drop the sender so that the peer's reader wakes up
+        self.tx = tokio::sync::mpsc::unbounded_channel().0;
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        if self.node.is_killed() && self.config.dead_peer_timeout.is_some() {
+            // the node vanished without closing the connection
+            debug!(addr = %self.addr, peer = %self.peer, "connection half-open on kill");
+            self.conn.vanish(self.side);
+        } else if *self.linger.get_mut() == Some(Duration::ZERO) {
+            // abortive close
+            debug!(addr = %self.addr, peer = %self.peer, "connection reset on close");
+            self.conn.reset();
+        } else if !self.write_buf.is_empty() {
+            // nothing waits for the acknowledgement, and no timer can be added
+            // if the node is killed in a timer callback
+            self.config.congestion_control = None;
+            let _ = self.send_buffered();
+        }
+    }
 }
 
 #[cfg(unix)]
@@ -120,11 +264,16 @@ impl AsyncRead for TcpStream {
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<()>> {
+        self.conn.check_reset()?;
         // read the buffer if not empty
         if !self.read_buf.is_empty() {
-            let len = self.read_buf.len().min(buf.remaining());
+            let mut len = self.read_buf.len().min(buf.remaining());
+            if self.config.partial_read && len > 1 {
+                len = crate::rand::thread_rng().gen_range(1..=len);
+            }
             buf.put_slice(&self.read_buf[..len]);
             self.read_buf.advance(len);
+            self.conn.consume(self.side, len);
             return Poll::Ready(Ok(()));
         }
         // otherwise wait on channel
@@ -137,7 +286,7 @@ impl AsyncRead for TcpStream {
             // ref: https://man7.org/linux/man-pages/man2/recv.2.html
             // > When a stream socket peer has performed an orderly shutdown, the
             // > return value will be 0 (the traditional "end-of-file" return).
-            Poll::Ready(None) => Poll::Ready(Ok(())),
+            Poll::Ready(None) => self.poll_closed(cx),
         }
     }
 }
@@ -145,21 +294,39 @@ impl AsyncRead for TcpStream {
 impl AsyncWrite for TcpStream {
     fn poll_write(
         mut self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
+        cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize>> {
-        self.write_buf.extend_from_slice(buf);
-        // TODO: simulate buffer full, partial write
-        Poll::Ready(Ok(buf.len()))
+        self.conn.check_reset()?;
+        let len = self.conn.reserve(
+            self.side,
+            buf.len(),
+            self.config.send_window,
+            self.config.congestion_control.as_ref(),
+            cx.waker(),
+        );
+        if len == 0 && !buf.is_empty() {
+            // the window is full. send what we have so that the peer can make progress.
+            if !self.write_buf.is_empty() {
+                self.send_buffered()?;
+            }
+            if let Some(timeout) = self.config.stall_timeout {
+                let stall = self.stall.get_or_insert_with(|| Box::pin(sleep(timeout)));
+                if stall.as_mut().poll(cx).is_ready() {
+                    self.reset();
+                    return Poll::Ready(Err(reset_error()));
+                }
+            }
+            return Poll::Pending;
+        }
+        self.stall = None;
+        self.write_buf.extend_from_slice(&buf[..len]);
+        Poll::Ready(Ok(len))
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
-        // send data
-        let data = self.write_buf.split().freeze();
-        self.tx
-            .send(Box::new(data))
-            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionReset, e))?;
-        Poll::Ready(Ok(()))
+        self.conn.check_reset()?;
+        Poll::Ready(self.send_buffered())
     }
 
     fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
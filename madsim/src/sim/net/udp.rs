@@ -58,6 +58,13 @@ impl UdpSocket {
         self.ep.recv_from(0, buf).await
     }
 
+    /// Tries to receive a single datagram message on the socket without waiting.
+    ///
+    /// If no message is queued, returns an error of kind [`std::io::ErrorKind::WouldBlock`].
+    pub fn try_recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        self.ep.try_recv_from(0, buf)
+    }
+
     /// Sends data on the socket to the remote address that the socket is connected to.
     #[instrument]
     pub async fn send(&self, buf: &[u8]) -> Result<()> {
@@ -70,4 +77,11 @@ impl UdpSocket {
     pub async fn recv(&self, buf: &mut [u8]) -> Result<usize> {
         self.ep.recv(0, buf).await
     }
+
+    /// Tries to receive a single datagram message from the connected remote address without waiting.
+    ///
+    /// If no message is queued, returns an error of kind [`std::io::ErrorKind::WouldBlock`].
+    pub fn try_recv(&self, buf: &mut [u8]) -> Result<usize> {
+        self.ep.try_recv(0, buf)
+    }
 }
@@ -4,12 +4,15 @@
 
 use rand::{
     distributions::Standard,
-    prelude::{Distribution, SmallRng},
+    prelude::{Distribution, IteratorRandom, SliceRandom, SmallRng},
 };
 
 use spin::Mutex;
 use std::cell::Cell;
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
 #[doc(no_inline)]
 pub use rand::{distributions, seq, CryptoRng, Error, Fill, Rng, RngCore, SeedableRng};
@@ -29,13 +32,30 @@ pub mod prelude {
 #[derive(Clone)]
 pub struct GlobalRng {
     inner: Arc<Mutex<Inner>>,
+    /// Whether the trace is enabled, checked without taking the lock.
+    tracing: Arc<AtomicBool>,
 }
 
 struct Inner {
+    /// The seed the RNG was created with.
+    initial_seed: u64,
+    /// The seed the RNG is running with, which changes at the branch point.
     seed: u64,
     rng: SmallRng,
     log: Option<Vec<u8>>,
     check: Option<(Vec<u8>, usize)>,
+    /// The number of calls to `with`.
+    draws: u64,
+    /// Reseed the RNG after the given number of draws.
+    branch: Option<Branch>,
+    /// The trace of scheduler decisions, if enabled.
+    trace: Option<Vec<String>>,
+}
+
+struct Branch {
+    draws: u64,
+    elapsed: std::time::Duration,
+    seed: u64,
 }
 
 impl GlobalRng {
@@ -50,20 +70,44 @@ impl GlobalRng {
         }
 
         let inner = Inner {
+            initial_seed: seed,
             seed,
             rng: SeedableRng::seed_from_u64(seed),
             log: None,
             check: None,
+            draws: 0,
+            branch: None,
+            trace: None,
         };
         GlobalRng {
             inner: Arc::new(Mutex::new(inner)),
+            tracing: Arc::new(AtomicBool::new(false)),
         }
     }
 
     /// Call function on the inner RNG.
     pub(crate) fn with<T>(&self, f: impl FnOnce(&mut SmallRng) -> T) -> T {
         let mut lock = self.inner.lock();
+        if matches!(&lock.branch, Some(b) if b.draws == lock.draws) {
+            let branch = lock.branch.take().unwrap();
+            let elapsed = crate::time::TimeHandle::try_current().map(|t| t.elapsed());
+            if elapsed != Some(branch.elapsed) {
+                panic!(
+                    "non-determinism detected: snapshot was taken at {:?}, but replayed to {:?}",
+                    branch.elapsed, elapsed
+                );
+            }
+            lock.rng = SeedableRng::seed_from_u64(branch.seed);
+            lock.seed = branch.seed;
+        }
+        lock.draws += 1;
         let ret = f(&mut lock.rng);
+        if lock.trace.is_some() {
+            // identify the draw by the state of the RNG after it
+            let state = lock.rng.clone().gen::<u64>();
+            let event = format!("rand #{} {:016x}", lock.draws, state);
+            lock.trace.as_mut().unwrap().push(with_time(event));
+        }
         // log or check
         if lock.log.is_some() || lock.check.is_some() {
             let t = crate::time::TimeHandle::try_current().map(|t| t.elapsed());
@@ -87,11 +131,51 @@ impl GlobalRng {
         ret
     }
 
+    /// Returns the seed the RNG is running with.
     pub(crate) fn seed(&self) -> u64 {
         let lock = self.inner.lock();
         lock.seed
     }
 
+    /// Returns the seed the RNG was created with, which replays the execution.
+    pub(crate) fn initial_seed(&self) -> u64 {
+        self.inner.lock().initial_seed
+    }
+
+    /// Returns the number of random draws so far.
+    pub(crate) fn draws(&self) -> u64 {
+        self.inner.lock().draws
+    }
+
+    /// Reseed the RNG with `seed` once `draws` random numbers have been drawn.
+    ///
+    /// Panics if the time elapsed at that point is not `elapsed`.
+    pub(crate) fn branch_at(&self, draws: u64, elapsed: std::time::Duration, seed: u64) {
+        let mut lock = self.inner.lock();
+        assert!(lock.draws <= draws, "the branch point has passed");
+        lock.branch = Some(Branch {
+            draws,
+            elapsed,
+            seed,
+        });
+    }
+
+    /// Panics if the branch point set by [`branch_at`](Self::branch_at) has not
+    /// been reached.
+    pub(crate) fn check_branch_reached(&self) {
+        let lock = self.inner.lock();
+        if let Some(branch) = &lock.branch {
+            if lock.draws < branch.draws {
+                let elapsed = branch.elapsed;
+                drop(lock);
+                panic!(
+                    "non-determinism detected: snapshot was taken at {:?}, but the run ended before reaching it",
+                    elapsed
+                );
+            }
+        }
+    }
+
     pub(crate) fn enable_check(&self, log: Log) {
         let mut lock = self.inner.lock();
         lock.check = Some((log.0, 0));
@@ -102,6 +186,28 @@ impl GlobalRng {
         lock.log = Some(Vec::new());
     }
 
+    pub(crate) fn enable_trace(&self) {
+        self.inner.lock().trace = Some(Vec::new());
+        self.tracing.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn take_trace(&self) -> Option<Vec<String>> {
+        self.tracing.store(false, Ordering::Relaxed);
+        self.inner.lock().trace.take()
+    }
+
+    /// Records a scheduler decision in the trace if it is enabled.
+    pub(crate) fn trace(&self, event: impl FnOnce() -> String) {
+        // this is called on every poll, so don't take the lock unless tracing
+        if !self.tracing.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut lock = self.inner.lock();
+        if let Some(trace) = &mut lock.trace {
+            trace.push(with_time(event()));
+        }
+    }
+
     pub(crate) fn take_log(&self) -> Option<Log> {
         let mut lock = self.inner.lock();
         lock.log
@@ -111,11 +217,31 @@ impl GlobalRng {
     }
 }
 
+/// Prefixes a trace event with the elapsed time.
+fn with_time(event: String) -> String {
+    let elapsed = crate::time::TimeHandle::try_current().map(|t| t.elapsed());
+    format!("[{:?}] {}", elapsed.unwrap_or_default(), event)
+}
+
 /// Retrieve the deterministic random number generator from the current madsim context.
 pub fn thread_rng() -> GlobalRng {
     crate::context::current(|h| h.rand.clone())
 }
 
+/// Returns the random seed of the current simulation.
+///
+/// This can be used to derive seeds for other deterministic random streams.
+/// It is the same as [`Handle::seed`](crate::runtime::Handle::seed).
+///
+/// On a runtime created by [`Runtime::from_snapshot`], this is the seed of
+/// the snapshot until the execution reaches it, and the seed of the branch
+/// after that.
+///
+/// [`Runtime::from_snapshot`]: crate::runtime::Runtime::from_snapshot
+pub fn current_seed() -> u64 {
+    crate::context::current(|h| h.rand.seed())
+}
+
 impl RngCore for GlobalRng {
     fn next_u32(&mut self) -> u32 {
         self.with(|rng| rng.next_u32())
@@ -143,6 +269,92 @@ where
     thread_rng().gen()
 }
 
+/// Returns a random element from the collection, or `None` if it is empty.
+///
+/// # Example
+///
+/// ```
+/// # madsim::runtime::Runtime::new().block_on(async {
+/// let replicas = std::collections::HashSet::from([1, 2, 3]);
+/// let leader = madsim::rand::choose(&replicas).unwrap();
+/// assert!(replicas.contains(leader));
+/// # });
+/// ```
+pub fn choose<I: IntoIterator>(iter: I) -> Option<I::Item> {
+    iter.into_iter().choose(&mut thread_rng())
+}
+
+/// Shuffles the slice in place.
+pub fn shuffle<T>(slice: &mut [T]) {
+    slice.shuffle(&mut thread_rng());
+}
+
+/// Returns `amount` distinct elements chosen at random from the collection.
+///
+/// If the collection has fewer than `amount` elements, all of them are returned.
+/// The order of the returned elements is not random.
+pub fn sample<I: IntoIterator>(iter: I, amount: usize) -> Vec<I::Item> {
+    iter.into_iter().choose_multiple(&mut thread_rng(), amount)
+}
+
+/// Fills the buffer with entropy.
+///
+/// Inside a madsim context, the bytes come from the global deterministic RNG.
+/// Otherwise, they come from the operating system.
+///
+/// Libraries that read entropy through the libc `getrandom` or `getentropy`
+/// functions are routed here automatically. Those that issue the system call
+/// directly, such as the `getrandom` crate on Linux, are not. `getrandom` 0.3
+/// and later can be pointed here with [`getrandom_custom_backend!`].
+///
+/// [`getrandom_custom_backend!`]: crate::getrandom_custom_backend
+pub fn fill_entropy(dest: &mut [u8]) {
+    let mut filled = 0;
+    while filled < dest.len() {
+        let ret = unsafe { getrandom(dest[filled..].as_mut_ptr(), dest.len() - filled, 0) };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            assert_eq!(
+                err.kind(),
+                std::io::ErrorKind::Interrupted,
+                "getrandom: {err}"
+            );
+            continue;
+        }
+        filled += ret as usize;
+    }
+}
+
+/// Routes the `getrandom` crate to [`fill_entropy`] through its custom backend.
+///
+/// This works with `getrandom` 0.3 and later. Invoke it once in the test
+/// binary, which must depend on `getrandom`, and build with
+/// `--cfg getrandom_backend="custom"`:
+///
+/// ```ignore
+/// madsim::getrandom_custom_backend!();
+///
+/// #[madsim::test]
+/// async fn uuid() {
+///     // deterministic with the same seed
+///     let id = uuid::Uuid::new_v4();
+/// }
+/// ```
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[macro_export]
+macro_rules! getrandom_custom_backend {
+    () => {
+        #[no_mangle]
+        unsafe extern "Rust" fn __getrandom_v03_custom(
+            dest: *mut u8,
+            len: usize,
+        ) -> ::core::result::Result<(), ::getrandom::Error> {
+            $crate::rand::fill_entropy(::core::slice::from_raw_parts_mut(dest, len));
+            ::core::result::Result::Ok(())
+        }
+    };
+}
+
 /// Random log for determinism check.
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) struct Log(Vec<u8>);
@@ -265,6 +477,26 @@ mod tests {
         assert_eq!(seqs.len(), 3);
     }
 
+    crate::getrandom_custom_backend!();
+
+    #[test]
+    fn deterministic_getrandom() {
+        extern "Rust" {
+            fn __getrandom_v03_custom(dest: *mut u8, len: usize) -> Result<(), getrandom::Error>;
+        }
+        let bytes = |seed| {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            runtime.block_on(async {
+                // what `getrandom::fill` calls with the custom backend
+                let mut buf = [0u8; 100];
+                unsafe { __getrandom_v03_custom(buf.as_mut_ptr(), buf.len()) }.unwrap();
+                buf.to_vec()
+            })
+        };
+        assert_eq!(bytes(1), bytes(1));
+        assert_ne!(bytes(1), bytes(2));
+    }
+
     #[test]
     fn deterministic_std_hashmap() {
         let mut seqs = BTreeSet::new();
@@ -282,4 +514,33 @@ mod tests {
         }
         assert_eq!(seqs.len(), 3, "hashmap is not deterministic");
     }
+
+    #[test]
+    fn deterministic_selection() {
+        let mut results = BTreeSet::new();
+        for i in 0..9 {
+            let result = std::thread::spawn(move || {
+                let runtime = Runtime::with_seed_and_config(i / 3, crate::Config::default());
+                runtime.block_on(async {
+                    let set = (0..100).collect::<std::collections::HashSet<u32>>();
+                    let chosen = *super::choose(&set).unwrap();
+                    let mut sampled = super::sample(&set, 5)
+                        .into_iter()
+                        .copied()
+                        .collect::<Vec<_>>();
+                    sampled.sort();
+                    let mut shuffled = (0..10).collect::<Vec<_>>();
+                    super::shuffle(&mut shuffled);
+                    assert_eq!(super::choose(&[] as &[u32]), None);
+                    assert_eq!(super::sample(0..3, 5).len(), 3);
+                    (chosen, sampled, shuffled)
+                })
+            })
+            .join()
+            .unwrap();
+            results.insert(result);
+        }
+        // same selections with the same seed, different selections across seeds
+        assert_eq!(results.len(), 3);
+    }
 }
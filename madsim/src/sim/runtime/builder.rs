@@ -1,6 +1,8 @@
 use super::{Config, Runtime};
+use crate::rand::GlobalRng;
 use futures_util::{stream, StreamExt};
 use std::future::Future;
+use std::path::PathBuf;
 use std::time::{Duration, SystemTime};
 
 /// Builds Madsim Runtime with custom configuration values.
@@ -17,6 +19,10 @@ pub struct Builder {
     pub time_limit: Option<Duration>,
     /// Enable determinism check.
     pub check: bool,
+    /// The wall-clock time when the simulation starts.
+    pub epoch: Option<SystemTime>,
+    /// The file to write the trace of scheduler decisions to.
+    pub trace: Option<PathBuf>,
 }
 
 impl Builder {
@@ -52,6 +58,17 @@ impl Builder {
     ///     If any non-determinism detected, it will panic as soon as possible.
     ///
     ///     By default, it is disabled.
+    ///
+    /// - `MADSIM_TEST_EPOCH`: Set the wall-clock time when the simulation starts,
+    ///   in seconds since the Unix epoch.
+    ///
+    ///     By default, the simulation starts at a random time in 2022.
+    ///
+    /// - `MADSIM_TEST_TRACE`: Write the trace of scheduler decisions to a file.
+    ///
+    ///     See [`Runtime::enable_trace`]. With multiple tests, the seed is appended.
+    ///
+    ///     By default, it is disabled.
     pub fn from_env() -> Self {
         let seed: u64 = if let Ok(seed_str) = std::env::var("MADSIM_TEST_SEED") {
             seed_str
@@ -93,9 +110,18 @@ impl Builder {
             )
         });
         let check = std::env::var("MADSIM_TEST_CHECK_DETERMINISM").is_ok();
+        let epoch = std::env::var("MADSIM_TEST_EPOCH").ok().map(|secs_str| {
+            SystemTime::UNIX_EPOCH
+                + Duration::from_secs(
+                    secs_str
+                        .parse()
+                        .expect("MADSIM_TEST_EPOCH should be an integer"),
+                )
+        });
         if check {
             count = count.max(2);
         }
+        let trace = std::env::var_os("MADSIM_TEST_TRACE").map(PathBuf::from);
         Builder {
             seed,
             count,
@@ -103,6 +129,8 @@ impl Builder {
             config,
             time_limit,
             check,
+            epoch,
+            trace,
         }
     }
 
@@ -118,6 +146,11 @@ impl Builder {
         let mut stream = stream::iter(self.seed..self.seed + self.count)
             .map(|seed| {
                 let config = self.config.clone();
+                // one trace file per seed if there are multiple runs
+                let trace = self.trace.as_ref().map(|path| match self.count {
+                    1 => path.clone(),
+                    _ => PathBuf::from(format!("{}.{seed}", path.display())),
+                });
                 async move {
                     let (tx, rx) = tokio::sync::oneshot::channel();
                     let handle = std::thread::spawn(move || {
@@ -125,6 +158,16 @@ impl Builder {
                         if let Some(limit) = self.time_limit {
                             rt.set_time_limit(limit);
                         }
+                        if let Some(epoch) = self.epoch {
+                            rt.set_epoch(epoch);
+                        }
+                        let _trace = trace.map(|path| {
+                            rt.enable_trace();
+                            TraceWriter {
+                                rand: rt.rand.clone(),
+                                path,
+                            }
+                        });
                         let ret = rt.block_on(f());
                         tx.send(()).unwrap();
                         ret
@@ -147,3 +190,19 @@ impl Builder {
         return_value.unwrap()
     }
 }
+
+/// Writes the trace to a file when dropped, even if the simulation panics.
+struct TraceWriter {
+    rand: GlobalRng,
+    path: PathBuf,
+}
+
+impl Drop for TraceWriter {
+    fn drop(&mut self) {
+        let mut trace = self.rand.take_trace().unwrap_or_default().join("\n");
+        trace.push('\n');
+        if let Err(e) = std::fs::write(&self.path, trace) {
+            eprintln!("failed to write trace to {}: {e}", self.path.display());
+        }
+    }
+}
@@ -2,20 +2,24 @@
 
 use super::*;
 use crate::task::{JoinHandle, NodeId, ToNodeId};
+use serde::{Deserialize, Serialize};
 use spin::Mutex;
 use std::{
     any::{Any, TypeId},
     collections::HashMap,
     future::Future,
     net::IpAddr,
+    ops::Range,
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 mod builder;
 pub(crate) mod context;
+mod nemesis;
 
 pub use self::builder::Builder;
+pub use self::nemesis::{Nemesis, NemesisEvent, NemesisHandle};
 
 /// The madsim runtime.
 ///
@@ -47,10 +51,17 @@ impl Runtime {
     }
 
     /// Create a new runtime instance with given seed and config.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the config is invalid, e.g. a rate is out of `[0, 1]`.
     pub fn with_seed_and_config(seed: u64, config: Config) -> Self {
+        if let Err(e) = config.time.validate() {
+            panic!("invalid config: {e}");
+        }
         let rand = rand::GlobalRng::new_with_seed(seed);
         let sims = Arc::new(Mutex::new(HashMap::new()));
-        let task = task::Executor::new(rand.clone(), sims.clone());
+        let task = task::Executor::new(rand.clone(), sims.clone(), &config.time);
         let handle = Handle {
             rand: rand.clone(),
             time: task.time_handle().clone(),
@@ -64,6 +75,43 @@ impl Runtime {
         rt
     }
 
+    /// Create a new runtime that restores the simulation state from a snapshot.
+    ///
+    /// The runtime replays the execution from the beginning with the seed and
+    /// config of the snapshot. Therefore the same future must be run on it as
+    /// the one that took the snapshot. Once the execution reaches the point where
+    /// the snapshot was taken, the random number generator is reseeded with `seed`,
+    /// so the rest of the execution explores an alternative future.
+    ///
+    /// Branching from the same snapshot with the same `seed` always results in the same execution.
+    ///
+    /// # Panics
+    ///
+    /// The runtime panics if the replay diverges before reaching the snapshot,
+    /// or ends without reaching it, i.e. the simulation is not deterministic.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{rand::random, runtime::{Handle, Runtime}};
+    ///
+    /// let f = || async {
+    ///     let a = random::<u64>();
+    ///     let snapshot = Handle::current().snapshot();
+    ///     let b = random::<u64>();
+    ///     (a, b, snapshot)
+    /// };
+    /// let (a0, b0, snapshot) = Runtime::new().block_on(f());
+    /// let (a1, b1, _) = Runtime::from_snapshot(&snapshot, 1).block_on(f());
+    /// assert_eq!(a0, a1);
+    /// assert_ne!(b0, b1);
+    /// ```
+    pub fn from_snapshot(snapshot: &Snapshot, seed: u64) -> Self {
+        let rt = Self::with_seed_and_config(snapshot.seed, snapshot.config.clone());
+        rt.rand.branch_at(snapshot.draws, snapshot.elapsed, seed);
+        rt
+    }
+
     /// Register a simulator.
     pub fn add_simulator<S: plugin::Simulator>(&self) {
         let mut sims = self.handle.sims.lock();
@@ -121,7 +169,9 @@ impl Runtime {
     /// ```
     pub fn block_on<F: Future>(&self, future: F) -> F::Output {
         let _guard = crate::context::enter(self.handle.clone());
-        self.task.block_on(future)
+        let output = self.task.block_on(future);
+        self.rand.check_branch_reached();
+        output
     }
 
     /// Set a time limit of the execution.
@@ -144,6 +194,63 @@ impl Runtime {
         self.task.set_time_limit(limit);
     }
 
+    /// Set the wall-clock time when the simulation starts.
+    ///
+    /// By default, the simulation starts at a random time in 2022.
+    /// This should be called before [`block_on`](Self::block_on).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{runtime::Runtime, time::{sleep, Duration}};
+    /// use std::time::SystemTime;
+    ///
+    /// let epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    /// let mut rt = Runtime::new();
+    /// rt.set_epoch(epoch);
+    ///
+    /// rt.block_on(async move {
+    ///     sleep(Duration::from_secs(10)).await;
+    ///     let elapsed = SystemTime::now().duration_since(epoch).unwrap();
+    ///     assert!(elapsed >= Duration::from_secs(10));
+    ///     assert!(elapsed < Duration::from_secs(11));
+    /// });
+    /// ```
+    pub fn set_epoch(&mut self, epoch: SystemTime) {
+        self.handle.time.set_epoch(epoch);
+    }
+
+    /// Record every scheduler decision in a trace.
+    ///
+    /// The trace lists the tasks polled, the timers fired and the random numbers
+    /// drawn, in order. Runs with the same seed and config produce identical
+    /// traces, so when a simulation is not deterministic, diffing the traces of
+    /// two runs points to the first divergence. Tracing slows down the simulation.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{runtime::Runtime, time::{sleep, Duration}};
+    ///
+    /// let rt = Runtime::new();
+    /// rt.enable_trace();
+    /// rt.block_on(async {
+    ///     sleep(Duration::from_secs(1)).await;
+    /// });
+    /// let trace = rt.take_trace().unwrap();
+    /// assert!(trace.iter().any(|event| event.contains("timer")));
+    /// ```
+    pub fn enable_trace(&self) {
+        self.rand.enable_trace();
+    }
+
+    /// Takes the trace recorded since [`enable_trace`](Self::enable_trace).
+    ///
+    /// Returns `None` if tracing is not enabled.
+    pub fn take_trace(&self) -> Option<Vec<String>> {
+        self.rand.take_trace()
+    }
+
     /// Check determinism of the future.
     ///
     /// # Example
@@ -229,6 +336,9 @@ impl Handle {
 
     /// Returns the random seed of the current runtime.
     ///
+    /// See [`rand::current_seed`](crate::rand::current_seed) for runtimes
+    /// created from a snapshot.
+    ///
     /// ```
     /// use madsim::{Config, runtime::Runtime};
     ///
@@ -239,19 +349,125 @@ impl Handle {
         self.rand.seed()
     }
 
+    /// Takes a snapshot of the simulation state.
+    ///
+    /// See [`Snapshot`] for what is captured.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            seed: self.rand.initial_seed(),
+            config: self.config.clone(),
+            draws: self.rand.draws(),
+            elapsed: self.time.elapsed(),
+        }
+    }
+
+    /// Takes a snapshot of the network topology.
+    ///
+    /// This is a shortcut for [`NetSim::topology`](net::NetSim::topology).
+    pub fn topology(&self) -> net::Topology {
+        let sims = self.sims.lock();
+        let net = sims
+            .get(&TypeId::of::<net::NetSim>())
+            .and_then(|sim| sim.downcast_ref::<net::NetSim>())
+            .expect("network simulator not found");
+        net.topology()
+    }
+
     /// Kill a node.
     ///
     /// - All tasks spawned on this node will be killed immediately.
     /// - All data that has not been flushed to the disk will be lost.
+    /// - All pending timers of the node will never fire.
     pub fn kill(&self, id: impl ToNodeId) {
         self.task.kill(&id);
     }
 
+    /// Kill a node and return the timers its tasks were waiting on.
+    ///
+    /// The timers are cancelled like those of [`kill`](Self::kill), and are
+    /// returned ordered by deadline. Useful for finding out which timeouts
+    /// were still pending when a node crashed.
+    pub fn kill_and_report(&self, id: impl ToNodeId) -> Vec<time::CancelledTimer> {
+        self.task.kill_and_report(&id)
+    }
+
     /// Restart a node。
     pub fn restart(&self, id: impl ToNodeId) {
         self.task.restart(&id);
     }
 
+    /// Create a [`Nemesis`] killing random nodes of `nodes`.
+    pub fn nemesis<I>(&self, nodes: impl IntoIterator<Item = I>) -> Nemesis
+    where
+        I: ToNodeId,
+    {
+        let nodes = nodes
+            .into_iter()
+            .map(|id| id.to_node_id(&self.task))
+            .collect();
+        Nemesis::new(self.clone(), nodes)
+    }
+
+    /// Run the simulation until it becomes idle.
+    ///
+    /// The returned future completes once no other task can make progress:
+    /// no task is ready to run and no timer is pending. The clock is advanced
+    /// only as far as needed to fire the timers on the way, so this is a
+    /// precise replacement for a long `sleep` that lets background work
+    /// settle.
+    ///
+    /// Timers of futures that have completed early, such as a [`timeout`]
+    /// whose inner future finished, still fire at their deadlines. It never
+    /// completes if a task keeps setting timers, e.g. with an [`interval`].
+    ///
+    /// [`timeout`]: crate::time::timeout
+    /// [`interval`]: crate::time::interval
+    pub async fn run_until_idle(&self) {
+        self.task.wait_idle().await;
+    }
+
+    /// Force an interleaving of named tasks.
+    ///
+    /// Each entry in `order` lets the task of that name, as given by
+    /// [`task::Builder::name`], run until its next await point. Tasks named in
+    /// `order` are held back until their turn, while all other tasks are
+    /// scheduled as usual. Once `order` is exhausted, the named tasks are
+    /// scheduled as usual too. Calling this again replaces the remaining order.
+    ///
+    /// The order must be feasible: if the task whose turn it is can never be
+    /// woken up, e.g. because it has finished or waits for a held task, the
+    /// tasks after it in the order are never polled again.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{runtime::Handle, task};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// # madsim::runtime::Runtime::new().block_on(async {
+    /// let log = Arc::new(Mutex::new(vec![]));
+    /// Handle::current().interleave(["b", "a", "b"]);
+    /// let mut handles = vec![];
+    /// for name in ["a", "b"] {
+    ///     let log = log.clone();
+    ///     handles.push(task::Builder::new().name(name).spawn(async move {
+    ///         for _ in 0..2 {
+    ///             log.lock().unwrap().push(name);
+    ///             task::yield_now().await;
+    ///         }
+    ///     }));
+    /// }
+    /// for handle in handles {
+    ///     handle.await.unwrap();
+    /// }
+    /// assert_eq!(*log.lock().unwrap(), ["b", "a", "b", "a"]);
+    /// # });
+    /// ```
+    pub fn interleave<S: Into<String>>(&self, order: impl IntoIterator<Item = S>) {
+        self.task
+            .interleave(order.into_iter().map(Into::into).collect());
+    }
+
     /// Pause the execution of a node.
     pub fn pause(&self, id: impl ToNodeId) {
         self.task.pause(id);
@@ -262,6 +478,40 @@ impl Handle {
         self.task.resume(id);
     }
 
+    /// Step the wall clock of a node immediately.
+    ///
+    /// Only the [`SystemTime`](std::time::SystemTime) observed by the node is changed.
+    /// [`Instant`](time::Instant), sleeps and timeouts are unaffected.
+    /// Steps accumulate and survive restarts of the node.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use madsim::{runtime::Handle, time::{ClockStep, Duration}};
+    /// use std::time::SystemTime;
+    ///
+    /// # madsim::runtime::Runtime::new().block_on(async {
+    /// let handle = Handle::current();
+    /// let node = handle.create_node().build();
+    /// let t0 = SystemTime::now();
+    /// handle.step_clock(node.id(), ClockStep::Backward(Duration::from_secs(60)));
+    /// let t1 = node.spawn(async { SystemTime::now() }).await.unwrap();
+    /// assert!(t1 < t0);
+    /// # });
+    /// ```
+    pub fn step_clock(&self, id: impl ToNodeId, step: time::ClockStep) {
+        let id = id.to_node_id(&self.task);
+        self.time.step_clock(id, step);
+    }
+
+    /// Step the wall clock of a node when `deadline` is reached.
+    ///
+    /// See [`step_clock`](Self::step_clock) for details.
+    pub fn step_clock_at(&self, id: impl ToNodeId, deadline: time::Instant, step: time::ClockStep) {
+        let id = id.to_node_id(&self.task);
+        self.time.step_clock_at(id, deadline, step);
+    }
+
     /// Create a node which will be bound to the specified address.
     pub fn create_node(&self) -> NodeBuilder<'_> {
         NodeBuilder::new(self)
@@ -273,14 +523,53 @@ impl Handle {
     }
 }
 
+/// A snapshot of the simulation state, used to explore alternative futures from a branch point.
+///
+/// The states of tasks, such as local variables in futures, can not be captured.
+/// Instead, a snapshot only records the deterministic inputs of the simulation:
+/// the seed, the config, and the position in the random sequence, along with the
+/// simulated time. Given the same future, these determine the whole state of the
+/// simulation at the snapshot, including the clock, the network and all nodes.
+///
+/// The state is restored by [`Runtime::from_snapshot`] which replays the execution
+/// from the beginning. This has some limitations:
+///
+/// - Restoring costs as much time as running up to the snapshot.
+/// - Any nondeterminism, e.g. reading real time or interacting with the outside world,
+///   breaks the replay.
+/// - Changes made outside the future, e.g. by the code before [`Runtime::block_on`],
+///   must be made again on the restored runtime.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    seed: u64,
+    config: Config,
+    draws: u64,
+    elapsed: Duration,
+}
+
+impl Snapshot {
+    /// Returns the random seed of the simulation.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the simulated time elapsed when the snapshot was taken.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
 /// Builds a node with custom configurations.
 pub struct NodeBuilder<'a> {
     handle: &'a Handle,
     name: Option<String>,
-    ip: Option<IpAddr>,
+    ips: Vec<IpAddr>,
     cores: Option<usize>,
     init: Option<task::InitFn>,
     restart_on_panic: bool,
+    unprivileged: bool,
+    packet_rate: Option<u64>,
+    startup_delay: Option<Range<Duration>>,
 }
 
 impl<'a> NodeBuilder<'a> {
@@ -288,10 +577,13 @@ impl<'a> NodeBuilder<'a> {
         NodeBuilder {
             handle,
             name: None,
-            ip: None,
+            ips: vec![],
             cores: None,
             init: None,
             restart_on_panic: false,
+            unprivileged: false,
+            packet_rate: None,
+            startup_delay: None,
         }
     }
 
@@ -324,9 +616,43 @@ impl<'a> NodeBuilder<'a> {
         self
     }
 
+    /// Run the node without root privilege.
+    ///
+    /// Binding ports below 1024 on this node will fail with `PermissionDenied`.
+    pub fn unprivileged(mut self) -> Self {
+        self.unprivileged = true;
+        self
+    }
+
+    /// Limit the number of packets the node sends per second.
+    ///
+    /// See [`NetSim::set_packet_rate`](crate::net::NetSim::set_packet_rate).
+    pub fn packet_rate(mut self, rate: u64) -> Self {
+        assert_ne!(rate, 0, "packet rate must be greater than 0");
+        self.packet_rate = Some(rate);
+        self
+    }
+
+    /// Delay the start of the node by a random duration in `range`.
+    ///
+    /// The node is paused until the delay elapses, so neither its initial task
+    /// nor the tasks spawned on it run before. A new delay is drawn on every
+    /// restart. The delays are determined by the seed.
+    ///
+    /// This can be used to model the staggered boot of cluster members.
+    /// By default the node starts immediately.
+    pub fn startup_delay(mut self, range: Range<Duration>) -> Self {
+        assert!(range.start <= range.end, "invalid startup delay range");
+        self.startup_delay = Some(range);
+        self
+    }
+
     /// Set one IP address of the node.
+    ///
+    /// This can be called multiple times to create a multi-homed node.
+    /// The first address is the primary one.
     pub fn ip(mut self, ip: IpAddr) -> Self {
-        self.ip = Some(ip);
+        self.ips.push(ip);
         self
     }
 
@@ -340,22 +666,55 @@ impl<'a> NodeBuilder<'a> {
     }
 
     /// Build a node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an IP address of the node is used by another node.
+    /// Use [`try_build`](Self::try_build) to handle the conflict.
     pub fn build(self) -> NodeHandle {
-        let task =
-            self.handle
-                .task
-                .create_node(self.name, self.init, self.cores, self.restart_on_panic);
+        self.try_build().expect("failed to build node")
+    }
+
+    /// Build a node, or return an `AddrInUse` error if an IP address of the
+    /// node is used by another node.
+    ///
+    /// No node is created on error.
+    pub fn try_build(self) -> std::io::Result<NodeHandle> {
+        if let Some(net) = self
+            .handle
+            .sims
+            .lock()
+            .values()
+            .find_map(|sim| sim.downcast_ref::<net::NetSim>())
+        {
+            for &ip in &self.ips {
+                net.check_ip(ip)?;
+            }
+        }
+        let task = self.handle.task.create_node(
+            self.name,
+            self.init,
+            self.cores,
+            self.restart_on_panic,
+            self.startup_delay,
+        );
         let sims = self.handle.sims.lock();
         let values = sims.values();
         for sim in values {
             sim.create_node(task.node_id());
-            if let Some(ip) = self.ip {
-                if let Some(net) = sim.downcast_ref::<net::NetSim>() {
-                    net.set_ip(task.node_id(), ip)
+            if let Some(net) = sim.downcast_ref::<net::NetSim>() {
+                for &ip in &self.ips {
+                    net.add_ip(task.node_id(), ip);
+                }
+                if self.unprivileged {
+                    net.set_privileged(task.node_id(), false);
+                }
+                if self.packet_rate.is_some() {
+                    net.set_packet_rate(task.node_id(), self.packet_rate);
                 }
             }
         }
-        NodeHandle { task }
+        Ok(NodeHandle { task })
     }
 }
 
@@ -387,3 +746,358 @@ pub fn init_logger() {
     static LOGGER_INIT: Once = Once::new();
     LOGGER_INIT.call_once(tracing_subscriber::fmt::init);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Handle, NemesisEvent, Runtime, Snapshot};
+    use crate::{
+        net::{Endpoint, IpProtocol, NetSim, Topology},
+        rand,
+        time::{sleep, timeout, Instant},
+        Config,
+    };
+    use spin::Mutex;
+    use std::{
+        collections::HashSet,
+        net::{IpAddr, SocketAddr},
+        sync::Arc,
+        time::Duration,
+    };
+
+    #[test]
+    fn snapshot_restore() {
+        fn run(rt: Runtime) -> (Vec<u64>, Snapshot) {
+            rt.block_on(async {
+                let node = Handle::current().create_node().build();
+                node.spawn(async {
+                    let mut values = vec![];
+                    let mut snapshot = None;
+                    for i in 0..10 {
+                        sleep(Duration::from_secs(1)).await;
+                        if i == 5 {
+                            snapshot = Some(Handle::current().snapshot());
+                        }
+                        values.push(rand::random::<u64>());
+                    }
+                    (values, snapshot.unwrap())
+                })
+                .await
+                .unwrap()
+            })
+        }
+        let (values0, snapshot) = run(Runtime::new());
+        assert_eq!(snapshot.elapsed().as_secs(), 6);
+
+        let (values1, snapshot1) = run(Runtime::from_snapshot(&snapshot, 1));
+        // the state before the snapshot is the same
+        assert_eq!(snapshot1, snapshot);
+        assert_eq!(values1[..5], values0[..5]);
+        // the future diverges
+        assert_ne!(values1[5..], values0[5..]);
+
+        // the same branch is deterministic
+        let (values2, _) = run(Runtime::from_snapshot(&snapshot, 1));
+        assert_eq!(values2, values1);
+        let (values3, _) = run(Runtime::from_snapshot(&snapshot, 2));
+        assert_ne!(values3[5..], values1[5..]);
+
+        // the seed changes to the one of the branch at the snapshot
+        let seeds = Runtime::from_snapshot(&snapshot, 7).block_on(async {
+            let node = Handle::current().create_node().build();
+            node.spawn(async {
+                let mut seeds = vec![];
+                for _ in 0..10 {
+                    sleep(Duration::from_secs(1)).await;
+                    rand::random::<u64>();
+                    seeds.push(rand::current_seed());
+                }
+                seeds
+            })
+            .await
+            .unwrap()
+        });
+        assert_eq!(seeds[..5], [0; 5]);
+        assert_eq!(seeds[5..], [7; 5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "non-determinism detected")]
+    fn snapshot_diverge() {
+        let snapshot = Runtime::new().block_on(async {
+            sleep(Duration::from_secs(1)).await;
+            Handle::current().snapshot()
+        });
+        // replay a different future
+        Runtime::from_snapshot(&snapshot, 1).block_on(async {
+            sleep(Duration::from_secs(2)).await;
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "the run ended before reaching it")]
+    fn snapshot_unreached() {
+        let snapshot = Runtime::new().block_on(async {
+            sleep(Duration::from_secs(1)).await;
+            Handle::current().snapshot()
+        });
+        // replay a future that ends before the snapshot
+        Runtime::from_snapshot(&snapshot, 1).block_on(async {});
+    }
+
+    #[test]
+    fn trace() {
+        fn run(seed: u64) -> Vec<String> {
+            let rt = Runtime::with_seed_and_config(seed, crate::Config::default());
+            rt.enable_trace();
+            rt.block_on(async {
+                let node = Handle::current().create_node().build();
+                let tasks: Vec<_> = (0..3)
+                    .map(|_| {
+                        node.spawn(async {
+                            for _ in 0..3 {
+                                let ms = rand::random::<u64>() % 100;
+                                sleep(Duration::from_millis(ms)).await;
+                            }
+                        })
+                    })
+                    .collect();
+                for task in tasks {
+                    task.await.unwrap();
+                }
+            });
+            rt.take_trace().unwrap()
+        }
+        let trace = run(1);
+        for event in ["poll task", "timer", "rand"] {
+            assert!(trace.iter().any(|e| e.contains(event)), "no {event}");
+        }
+        assert_eq!(run(1), trace);
+        assert_ne!(run(2), trace);
+    }
+
+    #[test]
+    fn topology() {
+        let runtime = Runtime::new();
+        let node1 = runtime.create_node().ip([10, 0, 0, 1].into()).build();
+        let node2 = runtime.create_node().ip([10, 0, 0, 2].into()).build();
+        let node3 = runtime.create_node().ip([10, 0, 0, 3].into()).build();
+        let (id1, id2, id3) = (node1.id(), node2.id(), node3.id());
+        runtime.block_on(async move {
+            let _ep = node1
+                .spawn(async { Endpoint::bind("10.0.0.1:80").await.unwrap() })
+                .await
+                .unwrap();
+            let handle = Handle::current();
+            let before = handle.topology();
+            assert!(before.clogged_links.is_empty());
+
+            // partition node1 from the others
+            let net = NetSim::current();
+            for id in [id2, id3] {
+                net.clog_link(id1, id);
+                net.clog_link(id, id1);
+            }
+            let topology = handle.topology();
+            let mut links = vec![(id1, id2), (id1, id3), (id2, id1), (id3, id1)];
+            links.sort();
+            assert_eq!(topology.clogged_links, links);
+            assert_ne!(topology, before);
+
+            let node = topology.nodes.iter().find(|n| n.id == id1).unwrap();
+            assert_eq!(node.ips, ["10.0.0.1".parse::<IpAddr>().unwrap()]);
+            assert_eq!(
+                node.sockets,
+                [("10.0.0.1:80".parse().unwrap(), IpProtocol::Udp)]
+            );
+
+            // a snapshot can be saved and loaded
+            // through a value, which puts plain values before tables as TOML requires
+            let text = toml::Value::try_from(&topology).unwrap().to_string();
+            let loaded: Topology = toml::from_str(&text).unwrap();
+            assert_eq!(loaded, topology);
+
+            // the partition is healed
+            for id in [id2, id3] {
+                net.unclog_link(id1, id);
+                net.unclog_link(id, id1);
+            }
+            assert_eq!(handle.topology(), before);
+        });
+    }
+
+    #[test]
+    fn ip_conflict() {
+        let runtime = Runtime::new();
+        let ip1 = [10, 0, 0, 1].into();
+        let ip2 = [10, 0, 0, 2].into();
+        let node1 = runtime.create_node().ip(ip1).build();
+
+        // provisioning falls back to another IP on conflict
+        let err = runtime.create_node().ip(ip1).try_build().err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+        let node2 = runtime.create_node().ip(ip2).try_build().unwrap();
+
+        runtime.block_on(async move {
+            let net = NetSim::current();
+            let err = net.try_set_ip(node2.id(), ip1).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+            // the addresses are unchanged
+            let topology = Handle::current().topology();
+            let ips = |id| {
+                topology
+                    .nodes
+                    .iter()
+                    .find(|n| n.id == id)
+                    .unwrap()
+                    .ips
+                    .clone()
+            };
+            assert_eq!(ips(node1.id()), [ip1]);
+            assert_eq!(ips(node2.id()), [ip2]);
+            // setting its own address is fine
+            net.set_ip(node1.id(), ip1);
+        });
+    }
+
+    #[test]
+    fn staggered_startup() {
+        fn run(seed: u64) -> Vec<Duration> {
+            let runtime = Runtime::with_seed_and_config(seed, Config::default());
+            let boots = Arc::new(Mutex::new(vec![]));
+            let joined = Arc::new(Mutex::new(vec![]));
+            let seed_addr = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+            for i in 1..=5u8 {
+                let boots = boots.clone();
+                let joined = joined.clone();
+                runtime
+                    .create_node()
+                    .ip([10, 0, 0, i].into())
+                    .startup_delay(Duration::ZERO..Duration::from_secs(10))
+                    .init(move || {
+                        let boots = boots.clone();
+                        let joined = joined.clone();
+                        async move {
+                            boots.lock().push((i, Instant::now()));
+                            let addr = SocketAddr::new([10, 0, 0, i].into(), 1);
+                            let ep = Endpoint::bind(addr).await.unwrap();
+                            if i == 1 {
+                                // the seed node acknowledges joins
+                                let mut members = HashSet::new();
+                                while members.len() < 4 {
+                                    let (_, from) = ep.recv_from(0, &mut []).await.unwrap();
+                                    members.insert(from);
+                                    ep.send_to(from, 1, &[]).await.unwrap();
+                                }
+                                return;
+                            }
+                            // retry joining until the seed node is up
+                            loop {
+                                ep.send_to(seed_addr, 0, &[]).await.unwrap();
+                                let ack = ep.recv_from(1, &mut []);
+                                if timeout(Duration::from_secs(1), ack).await.is_ok() {
+                                    joined.lock().push(i);
+                                    break;
+                                }
+                            }
+                        }
+                    })
+                    .build();
+            }
+            runtime.block_on(async move {
+                let t0 = Instant::now();
+                sleep(Duration::from_secs(20)).await;
+                let mut boots = boots.lock().clone();
+                boots.sort();
+                assert_eq!(boots.len(), 5);
+                // the cluster converges
+                joined.lock().sort();
+                assert_eq!(*joined.lock(), [2, 3, 4, 5]);
+                boots.iter().map(|(_, t)| *t - t0).collect()
+            })
+        }
+        let boots = run(1);
+        // members start at distinct times
+        let mut sorted = boots.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), 5, "{boots:?}");
+        assert!(boots.iter().all(|t| *t < Duration::from_secs(10)));
+        // the delays are determined by the seed
+        assert_eq!(run(1), boots);
+        assert_ne!(run(2), boots);
+    }
+
+    #[test]
+    fn nemesis() {
+        fn run(seed: u64) -> Vec<NemesisEvent> {
+            let runtime = Runtime::with_seed_and_config(seed, Config::default());
+            runtime.block_on(async move {
+                let handle = Handle::current();
+                // a cluster of echo servers
+                let nodes: Vec<_> = (1..=5u8)
+                    .map(|i| {
+                        let addr = SocketAddr::new([10, 0, 0, i].into(), 1);
+                        handle
+                            .create_node()
+                            .ip(addr.ip())
+                            .init(move || async move {
+                                let ep = Endpoint::bind(addr).await.unwrap();
+                                loop {
+                                    let (_, from) = ep.recv_from(0, &mut []).await.unwrap();
+                                    ep.send_to(from, 1, &[]).await.unwrap();
+                                }
+                            })
+                            .build()
+                            .id()
+                    })
+                    .collect();
+                let nemesis = handle
+                    .nemesis(nodes)
+                    .interval(Duration::from_secs(2))
+                    .restart_after(Duration::from_secs(5))
+                    .min_alive(3)
+                    .start();
+
+                let client = handle.create_node().ip([10, 0, 1, 1].into()).build();
+                client
+                    .spawn(async move {
+                        let ep = Endpoint::bind("10.0.1.1:1").await.unwrap();
+                        for _ in 0..100 {
+                            for i in 1..=5u8 {
+                                let addr = SocketAddr::new([10, 0, 0, i].into(), 1);
+                                ep.send_to(addr, 0, &[]).await.unwrap();
+                            }
+                            // a quorum always replies
+                            let mut replies = HashSet::new();
+                            while let Ok(Ok((_, from))) =
+                                timeout(Duration::from_millis(500), ep.recv_from(1, &mut [])).await
+                            {
+                                replies.insert(from);
+                            }
+                            assert!(replies.len() >= 3, "replies: {replies:?}");
+                        }
+                    })
+                    .await
+                    .unwrap();
+                nemesis.stop();
+
+                let events: Vec<_> = nemesis.events().into_iter().map(|(_, e)| e).collect();
+                // at most 2 nodes are down at the same time
+                let mut down = HashSet::new();
+                for event in &events {
+                    match event {
+                        NemesisEvent::Kill(id) => assert!(down.insert(*id)),
+                        NemesisEvent::Restart(id) => assert!(down.remove(id)),
+                    }
+                    assert!(down.len() <= 2);
+                }
+                events
+            })
+        }
+        let events = run(1);
+        assert!(events.len() >= 10, "{events:?}");
+        // the victims are determined by the seed
+        assert_eq!(run(1), events);
+        assert_ne!(run(2), events);
+    }
+}
@@ -0,0 +1,172 @@
+use super::Handle;
+use crate::{
+    rand::Rng,
+    task::{JoinHandle, NodeId},
+    time::{self, Instant},
+};
+use spin::Mutex;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use tracing::info;
+
+/// A chaos process that kills random nodes of a set at regular intervals.
+///
+/// The victims are chosen by the random generator of the runtime, so a run is
+/// reproducible from its seed. Every fault is logged and recorded in the
+/// [`NemesisHandle`].
+///
+/// Created by [`Handle::nemesis`].
+///
+/// # Example
+///
+/// ```
+/// use madsim::runtime::Handle;
+/// use std::time::Duration;
+///
+/// # madsim::runtime::Runtime::new().block_on(async {
+/// let handle = Handle::current();
+/// let nodes: Vec<_> = (0..3).map(|_| handle.create_node().build().id()).collect();
+/// let nemesis = handle
+///     .nemesis(nodes)
+///     .interval(Duration::from_secs(5))
+///     .restart_after(Duration::from_secs(2))
+///     .min_alive(2)
+///     .start();
+/// madsim::time::sleep(Duration::from_secs(60)).await;
+/// nemesis.stop();
+/// assert!(!nemesis.events().is_empty());
+/// # });
+/// ```
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[must_use = "a nemesis does nothing unless started"]
+pub struct Nemesis {
+    handle: Handle,
+    nodes: Vec<NodeId>,
+    interval: Duration,
+    restart_after: Option<Duration>,
+    min_alive: usize,
+}
+
+/// A fault injected by a [`Nemesis`].
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NemesisEvent {
+    /// The node was killed.
+    Kill(NodeId),
+    /// The node was restarted, either after being killed or right away.
+    Restart(NodeId),
+}
+
+/// A handle to a running [`Nemesis`].
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+pub struct NemesisHandle {
+    events: Arc<Mutex<Vec<(Instant, NemesisEvent)>>>,
+    task: JoinHandle<()>,
+}
+
+impl Nemesis {
+    pub(super) fn new(handle: Handle, nodes: Vec<NodeId>) -> Self {
+        Nemesis {
+            handle,
+            nodes,
+            interval: Duration::from_secs(5),
+            restart_after: None,
+            min_alive: 0,
+        }
+    }
+
+    /// Sets the interval between kills. Defaults to 5 seconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "interval must be positive");
+        self.interval = interval;
+        self
+    }
+
+    /// Restarts the killed nodes after `downtime`.
+    ///
+    /// With a zero downtime, nodes are restarted right away as by
+    /// [`Handle::restart`]. Killed nodes stay down if not set.
+    pub fn restart_after(mut self, downtime: Duration) -> Self {
+        self.restart_after = Some(downtime);
+        self
+    }
+
+    /// Keeps at least `n` nodes of the set alive, e.g. a quorum.
+    ///
+    /// No node is killed when it would leave fewer alive. Only the nodes
+    /// killed by this nemesis are counted as down.
+    pub fn min_alive(mut self, n: usize) -> Self {
+        self.min_alive = n;
+        self
+    }
+
+    /// Starts killing nodes, the first one after an interval.
+    ///
+    /// The nemesis runs on the main node until stopped.
+    pub fn start(self) -> NemesisHandle {
+        let events = Arc::new(Mutex::new(vec![]));
+        let events0 = events.clone();
+        let main = self.handle.task.get_node(NodeId::zero()).unwrap();
+        let task = main.spawn(async move {
+            let down = Arc::new(Mutex::new(HashSet::new()));
+            let record = move |event| {
+                info!(?event, "nemesis");
+                events0.lock().push((Instant::now(), event));
+            };
+            loop {
+                time::sleep(self.interval).await;
+                let alive: Vec<_> = {
+                    let down = down.lock();
+                    self.nodes
+                        .iter()
+                        .filter(|id| !down.contains(*id))
+                        .copied()
+                        .collect()
+                };
+                if alive.len() <= self.min_alive {
+                    continue;
+                }
+                let i = self.handle.rand.with(|rng| rng.gen_range(0..alive.len()));
+                let victim = alive[i];
+                match self.restart_after {
+                    Some(downtime) if downtime.is_zero() => {
+                        self.handle.restart(victim);
+                        record(NemesisEvent::Restart(victim));
+                    }
+                    Some(downtime) => {
+                        record(NemesisEvent::Kill(victim));
+                        self.handle.kill(victim);
+                        down.lock().insert(victim);
+                        let (handle, down, record) =
+                            (self.handle.clone(), down.clone(), record.clone());
+                        crate::task::spawn(async move {
+                            time::sleep(downtime).await;
+                            handle.restart(victim);
+                            down.lock().remove(&victim);
+                            record(NemesisEvent::Restart(victim));
+                        });
+                    }
+                    None => {
+                        record(NemesisEvent::Kill(victim));
+                        self.handle.kill(victim);
+                        down.lock().insert(victim);
+                    }
+                }
+            }
+        });
+        NemesisHandle { events, task }
+    }
+}
+
+impl NemesisHandle {
+    /// Returns the faults injected so far, with the time they happened.
+    pub fn events(&self) -> Vec<(Instant, NemesisEvent)> {
+        self.events.lock().clone()
+    }
+
+    /// Stops injecting faults.
+    ///
+    /// Killed nodes waiting for a restart are still restarted.
+    pub fn stop(&self) {
+        self.task.abort();
+    }
+}
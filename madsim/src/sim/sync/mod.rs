@@ -0,0 +1,294 @@
+//! Synchronization primitives for use in simulation.
+//!
+//! # Examples
+//!
+//! ```
+//! use madsim::{runtime::Runtime, sync::Barrier};
+//! use std::sync::Arc;
+//!
+//! let runtime = Runtime::new();
+//! let barrier = Arc::new(Barrier::new(3));
+//! let mut handles = vec![];
+//! for _ in 0..3 {
+//!     let node = runtime.create_node().build();
+//!     let barrier = barrier.clone();
+//!     handles.push(node.spawn(async move { barrier.wait().await }));
+//! }
+//! runtime.block_on(async move {
+//!     let mut leaders = 0;
+//!     for handle in handles {
+//!         if handle.await.unwrap().is_leader() {
+//!             leaders += 1;
+//!         }
+//!     }
+//!     assert_eq!(leaders, 1);
+//! });
+//! ```
+
+use futures_util::future::poll_fn;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    task::{Poll, Waker},
+};
+
+pub mod mpsc;
+mod mutex;
+mod raw;
+mod rwlock;
+
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Synchronization configurations.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[serde(default)]
+pub struct Config {
+    /// Hand [`Mutex`] and [`RwLock`] to waiters in a random order instead of
+    /// the arrival order.
+    ///
+    /// The order is chosen by the seeded random generator, so different seeds
+    /// explore different contention orderings.
+    pub random_lock_order: bool,
+}
+
+/// Error returned from the [`Mutex::try_lock`], [`RwLock::try_read`] and
+/// [`RwLock::try_write`] functions.
+#[derive(Debug)]
+pub struct TryLockError(());
+
+impl fmt::Display for TryLockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "operation would block")
+    }
+}
+
+impl std::error::Error for TryLockError {}
+
+/// A barrier enables multiple tasks to synchronize the beginning of some computation.
+///
+/// Tasks can be on the same node or on different nodes.
+/// All waiters are woken up at the same time once the last one arrives,
+/// and the scheduler resumes them in an order determined by the random seed.
+///
+/// This is a drop-in replacement for [`tokio::sync::Barrier`].
+#[derive(Debug)]
+pub struct Barrier {
+    n: usize,
+    state: spin::Mutex<BarrierState>,
+}
+
+#[derive(Debug)]
+struct BarrierState {
+    /// Wakers of the arrived tasks in the current generation.
+    waiters: Vec<Option<Waker>>,
+    /// Increased by 1 whenever the barrier is released.
+    generation: usize,
+}
+
+impl Barrier {
+    /// Creates a new barrier that can block a given number of tasks.
+    ///
+    /// A barrier will block `n`-1 tasks which call [`Barrier::wait`] and then wake up all
+    /// tasks at once when the `n`th task calls `wait`.
+    pub fn new(n: usize) -> Self {
+        Barrier {
+            // a barrier of 0 behaves the same as a barrier of 1
+            n: n.max(1),
+            state: spin::Mutex::new(BarrierState {
+                waiters: Vec::with_capacity(n),
+                generation: 0,
+            }),
+        }
+    }
+
+    /// Does not resolve until all tasks have rendezvoused here.
+    ///
+    /// Barriers are re-usable after all tasks have rendezvoused once, and can
+    /// be used continuously.
+    ///
+    /// A single (arbitrary) future will receive a [`BarrierWaitResult`] that returns `true` from
+    /// [`BarrierWaitResult::is_leader`] when returning from this function, and all other tasks
+    /// will receive a result that will return `false` from `is_leader`.
+    ///
+    /// Note that a task is counted once it calls `wait`, even if the future is dropped
+    /// before the barrier is released.
+    pub async fn wait(&self) -> BarrierWaitResult {
+        let (generation, slot) = {
+            let mut state = self.state.lock();
+            if state.waiters.len() + 1 == self.n {
+                // the last one arrives, release all
+                state.generation = state.generation.wrapping_add(1);
+                for waker in state.waiters.drain(..).flatten() {
+                    waker.wake();
+                }
+                return BarrierWaitResult(true);
+            }
+            state.waiters.push(None);
+            (state.generation, state.waiters.len() - 1)
+        };
+        poll_fn(|cx| {
+            let mut state = self.state.lock();
+            if state.generation != generation {
+                return Poll::Ready(BarrierWaitResult(false));
+            }
+            state.waiters[slot] = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// A `BarrierWaitResult` is returned by `wait` when all tasks in the `Barrier` have rendezvoused.
+#[derive(Debug, Clone)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Returns `true` if this task from wait is the "leader task".
+    ///
+    /// Only one task will have `true` returned from their result, all other tasks will have
+    /// `false` returned.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{runtime::Runtime, time::*};
+    use std::sync::Arc;
+
+    #[test]
+    fn barrier() {
+        let runtime = Runtime::new();
+        let barrier = Arc::new(Barrier::new(4));
+        let arrived = Arc::new(spin::Mutex::new(vec![]));
+        let mut handles = vec![];
+        for i in 0..4u64 {
+            let node = runtime.create_node().build();
+            let barrier = barrier.clone();
+            let arrived = arrived.clone();
+            handles.push(node.spawn(async move {
+                // reuse the barrier for multiple rounds
+                for round in 0..3u64 {
+                    sleep(Duration::from_secs(i + round)).await;
+                    arrived.lock().push((round, i));
+                    let t0 = Instant::now();
+                    let res = barrier.wait().await;
+                    // all tasks should have arrived in this round
+                    let arrived = arrived.lock();
+                    assert_eq!(arrived.iter().filter(|(r, _)| *r == round).count(), 4);
+                    drop(arrived);
+                    // the last one to arrive is the leader and does not wait
+                    assert_eq!(res.is_leader(), i == 3);
+                    if i == 3 {
+                        assert_eq!(Instant::now(), t0);
+                    }
+                }
+            }));
+        }
+        runtime.block_on(async move {
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn barrier_of_one() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let barrier = Barrier::new(0);
+            assert!(barrier.wait().await.is_leader());
+            assert!(barrier.wait().await.is_leader());
+        });
+    }
+
+    /// Returns the order in which contending tasks acquire a mutex.
+    fn lock_order(seed: u64, random: bool) -> Vec<usize> {
+        let mut config = crate::Config::default();
+        config.sync.random_lock_order = random;
+        let runtime = Runtime::with_seed_and_config(seed, config);
+        runtime.block_on(async {
+            let lock = Arc::new(Mutex::new(vec![]));
+            let guard = lock.lock().await;
+            let mut handles = vec![];
+            for i in 0..10 {
+                let lock = lock.clone();
+                handles.push(crate::task::spawn(async move {
+                    // arrive in order
+                    sleep(Duration::from_millis(i as u64)).await;
+                    lock.lock().await.push(i);
+                }));
+            }
+            sleep(Duration::from_secs(1)).await;
+            drop(guard);
+            for handle in handles {
+                handle.await.unwrap();
+            }
+            Arc::try_unwrap(lock).unwrap().into_inner()
+        })
+    }
+
+    #[test]
+    fn mutex_fifo() {
+        for seed in 0..3 {
+            assert_eq!(lock_order(seed, false), (0..10).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn mutex_random_order() {
+        let order = lock_order(1, true);
+        assert_eq!(order, lock_order(1, true));
+        assert_ne!(order, lock_order(2, true));
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rwlock() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let lock = Arc::new(RwLock::new(0));
+            let r1 = lock.read().await;
+            let r2 = lock.try_read().unwrap();
+            assert!(lock.try_write().is_err());
+
+            // a queued writer blocks later readers
+            let lock1 = lock.clone();
+            let writer = crate::task::spawn(async move {
+                *lock1.write().await += 1;
+            });
+            sleep(Duration::from_millis(1)).await;
+            assert!(lock.try_read().is_err());
+            let lock1 = lock.clone();
+            let reader = crate::task::spawn(async move { *lock1.read().await });
+
+            drop(r1);
+            drop(r2);
+            writer.await.unwrap();
+            assert_eq!(reader.await.unwrap(), 1);
+            assert_eq!(*lock.try_write().unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn cancel_waiting() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let lock = Arc::new(Mutex::new(0));
+            let guard = lock.lock().await;
+            // a cancelled waiter does not keep the lock
+            timeout(Duration::from_secs(1), lock.lock())
+                .await
+                .unwrap_err();
+            drop(guard);
+            *lock.lock().await += 1;
+            assert_eq!(*lock.try_lock().unwrap(), 1);
+        });
+    }
+}
@@ -0,0 +1,325 @@
+//! A multi-producer, single-consumer queue for sending values between tasks.
+//!
+//! Unlike [`tokio::sync::mpsc`], the wakeups of this channel are driven only by
+//! the simulation scheduler, so the order in which blocked tasks resume is
+//! determined by the random seed.
+//!
+//! # Examples
+//!
+//! ```
+//! use madsim::{runtime::Runtime, sync::mpsc};
+//!
+//! Runtime::new().block_on(async {
+//!     let (tx, mut rx) = mpsc::bounded(1);
+//!     madsim::task::spawn(async move {
+//!         for i in 0..3 {
+//!             // blocks until the receiver takes the previous value
+//!             tx.send(i).await.unwrap();
+//!         }
+//!     });
+//!     assert_eq!(rx.recv().await, Some(0));
+//!     assert_eq!(rx.recv().await, Some(1));
+//!     assert_eq!(rx.recv().await, Some(2));
+//!     assert_eq!(rx.recv().await, None);
+//! });
+//! ```
+
+use futures_util::future::poll_fn;
+use spin::Mutex;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+
+pub use tokio::sync::mpsc::error::{SendError, TryRecvError, TrySendError};
+
+/// Creates a bounded mpsc channel for communicating between asynchronous tasks
+/// with backpressure.
+///
+/// The channel holds at most `cap` values. Once the buffer is full,
+/// [`Sender::send`] waits until the receiver takes a value out.
+///
+/// # Panics
+///
+/// Panics if `cap` is 0.
+pub fn bounded<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(cap > 0, "mpsc bounded channel requires capacity > 0");
+    let shared = Arc::new(Shared {
+        cap,
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(cap),
+            senders: 1,
+            closed: false,
+            recv_waker: None,
+            send_wakers: Vec::new(),
+        }),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+struct Shared<T> {
+    cap: usize,
+    state: Mutex<State<T>>,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    /// The number of living senders.
+    senders: usize,
+    /// Whether the receiver is closed or dropped.
+    closed: bool,
+    recv_waker: Option<Waker>,
+    /// Wakers of the senders waiting for capacity.
+    send_wakers: Vec<Waker>,
+}
+
+impl<T> State<T> {
+    /// Wakes up all waiting senders.
+    ///
+    /// They race for the free slots, and the winner is decided by the scheduler.
+    fn wake_senders(&mut self) {
+        for waker in self.send_wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Sends values to the associated [`Receiver`].
+///
+/// Instances are created by the [`bounded`] function.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends a value, waiting until there is capacity.
+    ///
+    /// Returns an error if the receiver has been closed or dropped,
+    /// along with the value.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut value = Some(value);
+        poll_fn(|cx| self.poll_send(cx, &mut value)).await
+    }
+
+    fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        value: &mut Option<T>,
+    ) -> Poll<Result<(), SendError<T>>> {
+        let mut state = self.shared.state.lock();
+        if state.closed {
+            return Poll::Ready(Err(SendError(value.take().unwrap())));
+        }
+        if state.queue.len() < self.shared.cap {
+            state.queue.push_back(value.take().unwrap());
+            if let Some(waker) = state.recv_waker.take() {
+                waker.wake();
+            }
+            return Poll::Ready(Ok(()));
+        }
+        if !state.send_wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            state.send_wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    /// Attempts to immediately send a value on this channel.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.shared.state.lock();
+        if state.closed {
+            return Err(TrySendError::Closed(value));
+        }
+        if state.queue.len() >= self.shared.cap {
+            return Err(TrySendError::Full(value));
+        }
+        state.queue.push_back(value);
+        if let Some(waker) = state.recv_waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Checks if the channel has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.shared.state.lock().closed
+    }
+
+    /// Returns the current capacity of the channel.
+    pub fn capacity(&self) -> usize {
+        self.shared.cap - self.shared.state.lock().queue.len()
+    }
+
+    /// Returns the maximum buffer capacity of the channel.
+    pub fn max_capacity(&self) -> usize {
+        self.shared.cap
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            if let Some(waker) = state.recv_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("cap", &self.shared.cap)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Receives values from the associated [`Sender`].
+///
+/// Instances are created by the [`bounded`] function.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receives the next value for this receiver.
+    ///
+    /// Returns `None` if the channel has been closed and there are no remaining values.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Polls to receive the next value on this channel.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.shared.state.lock();
+        if let Some(value) = state.queue.pop_front() {
+            state.wake_senders();
+            return Poll::Ready(Some(value));
+        }
+        if state.closed || state.senders == 0 {
+            return Poll::Ready(None);
+        }
+        state.recv_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Tries to receive the next value for this receiver.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.state.lock();
+        if let Some(value) = state.queue.pop_front() {
+            state.wake_senders();
+            return Ok(value);
+        }
+        if state.closed || state.senders == 0 {
+            return Err(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    /// Closes the receiving half of a channel without dropping it.
+    ///
+    /// This prevents any further values from being sent on the channel while
+    /// still enabling the receiver to drain values that are buffered.
+    pub fn close(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.closed = true;
+        state.wake_senders();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("cap", &self.shared.cap)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{runtime::Runtime, time::*};
+
+    #[test]
+    fn backpressure() {
+        let runtime = Runtime::new();
+        let (tx, mut rx) = bounded::<u32>(2);
+        let sent = Arc::new(Mutex::new(vec![]));
+
+        let sent_ = sent.clone();
+        let producer = runtime.create_node().build().spawn(async move {
+            let t0 = Instant::now();
+            for i in 0..5 {
+                tx.send(i).await.unwrap();
+                sent_.lock().push((i, t0.elapsed()));
+            }
+        });
+        let consumer = runtime.create_node().build().spawn(async move {
+            let mut received = vec![];
+            while let Some(i) = rx.recv().await {
+                // slow consumer
+                sleep(Duration::from_secs(1)).await;
+                received.push(i);
+            }
+            received
+        });
+        runtime.block_on(async move {
+            producer.await.unwrap();
+            assert_eq!(consumer.await.unwrap(), vec![0, 1, 2, 3, 4]);
+        });
+        // the first 3 values are sent immediately: 1 taken by the consumer and 2 buffered.
+        // then each one has to wait for the consumer to take a value.
+        let secs: Vec<_> = sent.lock().iter().map(|(_, t)| t.as_secs()).collect();
+        assert_eq!(secs, [0, 0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn closed() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let (tx, mut rx) = bounded(1);
+            tx.try_send(1).unwrap();
+            assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+            assert_eq!(tx.capacity(), 0);
+
+            // blocked sender is woken up when the receiver is closed
+            let tx1 = tx.clone();
+            let handle = crate::task::spawn(async move { tx1.send(3).await });
+            sleep(Duration::from_secs(1)).await;
+            rx.close();
+            assert_eq!(handle.await.unwrap(), Err(SendError(3)));
+            assert!(tx.is_closed());
+
+            // buffered values can still be received
+            assert_eq!(rx.recv().await, Some(1));
+            assert_eq!(rx.recv().await, None);
+
+            let (tx, mut rx) = bounded::<u32>(1);
+            drop(tx);
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+        });
+    }
+}
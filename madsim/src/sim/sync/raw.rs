@@ -0,0 +1,183 @@
+use crate::{context, rand::Rng};
+use spin::Mutex;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+/// A raw reader-writer lock that hands ownership to waiters in a
+/// deterministic order.
+///
+/// Waiters are granted in FIFO order, or in a random order chosen by the
+/// global random generator if [`Config::random_lock_order`] is set.
+///
+/// [`Config::random_lock_order`]: super::Config::random_lock_order
+#[derive(Debug, Default)]
+pub(super) struct RawLock {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    readers: usize,
+    writer: bool,
+    /// Waiters in arrival order, including the granted ones not yet polled.
+    waiters: Vec<Waiter>,
+    next_id: u64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    exclusive: bool,
+    granted: bool,
+    waker: Option<Waker>,
+}
+
+impl State {
+    fn is_compatible(&self, exclusive: bool) -> bool {
+        match exclusive {
+            true => !self.writer && self.readers == 0,
+            false => !self.writer,
+        }
+    }
+
+    fn take(&mut self, exclusive: bool) {
+        match exclusive {
+            true => self.writer = true,
+            false => self.readers += 1,
+        }
+    }
+
+    fn has_pending(&self) -> bool {
+        self.waiters.iter().any(|w| !w.granted)
+    }
+
+    /// Grants the lock to waiters as long as possible.
+    fn grant(&mut self) {
+        let random = context::try_current(|h| h.config.sync.random_lock_order).unwrap_or(false);
+        loop {
+            let pending: Vec<usize> = self
+                .waiters
+                .iter()
+                .enumerate()
+                .filter(|(_, w)| !w.granted)
+                .map(|(i, _)| i)
+                .collect();
+            if pending.is_empty() {
+                return;
+            }
+            let i = match random {
+                true => pending[crate::rand::thread_rng().gen_range(0..pending.len())],
+                false => pending[0],
+            };
+            let waiter = &self.waiters[i];
+            if !self.is_compatible(waiter.exclusive) {
+                return;
+            }
+            self.take(waiter.exclusive);
+            let waiter = &mut self.waiters[i];
+            waiter.granted = true;
+            if let Some(waker) = waiter.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl RawLock {
+    /// Acquires the lock.
+    pub fn acquire(&self, exclusive: bool) -> Acquire<'_> {
+        Acquire {
+            lock: self,
+            exclusive,
+            id: None,
+        }
+    }
+
+    /// Tries to acquire the lock without waiting.
+    pub fn try_acquire(&self, exclusive: bool) -> bool {
+        let mut state = self.state.lock();
+        if state.has_pending() || !state.is_compatible(exclusive) {
+            return false;
+        }
+        state.take(exclusive);
+        true
+    }
+
+    /// Releases the lock.
+    pub fn release(&self, exclusive: bool) {
+        let mut state = self.state.lock();
+        match exclusive {
+            true => state.writer = false,
+            false => state.readers -= 1,
+        }
+        state.grant();
+    }
+}
+
+/// Future returned by [`RawLock::acquire`].
+pub(super) struct Acquire<'a> {
+    lock: &'a RawLock,
+    exclusive: bool,
+    /// The waiter ID if queued.
+    id: Option<u64>,
+}
+
+impl Future for Acquire<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let lock = self.lock;
+        let mut state = lock.state.lock();
+        let id = match self.id {
+            Some(id) => id,
+            None => {
+                // queued waiters go first for fairness
+                if !state.has_pending() && state.is_compatible(self.exclusive) {
+                    state.take(self.exclusive);
+                    return Poll::Ready(());
+                }
+                let id = state.next_id;
+                state.next_id += 1;
+                state.waiters.push(Waiter {
+                    id,
+                    exclusive: self.exclusive,
+                    granted: false,
+                    waker: None,
+                });
+                self.id = Some(id);
+                id
+            }
+        };
+        let i = state.waiters.iter().position(|w| w.id == id).unwrap();
+        if state.waiters[i].granted {
+            state.waiters.remove(i);
+            self.id = None;
+            return Poll::Ready(());
+        }
+        state.waiters[i].waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let id = match self.id {
+            Some(id) => id,
+            None => return,
+        };
+        let mut state = self.lock.state.lock();
+        let i = state.waiters.iter().position(|w| w.id == id).unwrap();
+        let waiter = state.waiters.remove(i);
+        drop(state);
+        // pass the lock on if it was granted to us
+        if waiter.granted {
+            self.lock.release(waiter.exclusive);
+        } else {
+            // a waiter behind us may be able to proceed now
+            self.lock.state.lock().grant();
+        }
+    }
+}
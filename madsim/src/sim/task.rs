@@ -1,9 +1,21 @@
 //! Asynchronous tasks executor.
+//!
+//! # Concurrent futures
+//!
+//! Futures awaited concurrently within a task, e.g. with
+//! [`futures::future::join_all`] or [`FuturesUnordered`], are polled in the
+//! order they are woken up. Since timers, network deliveries and task
+//! scheduling are all driven by the seeded random generator, the completion
+//! order of such a fan-out is reproducible for a given seed, and no special
+//! combinator is needed.
+//!
+//! [`futures::future::join_all`]: https://docs.rs/futures/0.3/futures/future/fn.join_all.html
+//! [`FuturesUnordered`]: https://docs.rs/futures/0.3/futures/stream/struct.FuturesUnordered.html
 
 use super::{
     rand::GlobalRng,
     runtime::Simulators,
-    time::{TimeHandle, TimeRuntime},
+    time::{self, TimeHandle, TimeRuntime},
     utils::mpsc,
 };
 #[doc(hidden)]
@@ -11,21 +23,23 @@ pub use async_task::FallibleTask;
 use async_task::Runnable;
 use futures_util::FutureExt;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use spin::Mutex;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     fmt,
     future::Future,
     io,
-    ops::Deref,
+    ops::{Deref, Range},
     pin::Pin,
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
     task::{Context, Poll, Waker},
-    time::Duration,
+    time::{Duration, Instant},
 };
+use tokio::sync::oneshot;
 use tracing::*;
 
 pub use tokio::task::yield_now;
@@ -36,11 +50,17 @@ pub(crate) struct Executor {
     rand: GlobalRng,
     time: TimeRuntime,
     time_limit: Option<Duration>,
+    clock_stall_rate: f64,
+    /// Task numbers in the order of their first poll, for the trace.
+    ///
+    /// Task IDs are unique in the process, so they differ between runs.
+    trace_ids: Mutex<HashMap<Id, usize>>,
 }
 
 /// A unique identifier for a node.
 #[cfg_attr(docsrs, doc(cfg(madsim)))]
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct NodeId(u64);
 
 impl fmt::Display for NodeId {
@@ -57,7 +77,7 @@ impl NodeId {
 
 pub(crate) struct TaskInfo {
     pub id: Id,
-    // name: Option<String>,
+    pub name: Option<String>,
     pub node: Arc<NodeInfo>,
     /// The span of this task.
     pub span: Span,
@@ -71,6 +91,8 @@ pub(crate) struct NodeInfo {
     cores: usize,
     /// A flag indicating that the task should be paused.
     paused: AtomicBool,
+    /// The time the node has spent paused.
+    pause_time: Mutex<PauseTime>,
     /// A flag indicating that the task should no longer be executed.
     killed: AtomicBool,
     /// Whether to restart the node on panic.
@@ -81,6 +103,15 @@ pub(crate) struct NodeInfo {
     ///
     /// When being killed, all spawned tasks will be woken up.
     wakers: Mutex<Vec<Waker>>,
+    /// The free CPU cores, taken by [`consume_cpu`].
+    cpus: tokio::sync::Semaphore,
+}
+
+#[derive(Default)]
+struct PauseTime {
+    total: Duration,
+    /// When the current pause began.
+    since: Option<Instant>,
 }
 
 impl NodeInfo {
@@ -90,7 +121,7 @@ impl NodeInfo {
         Arc::new(TaskInfo {
             span: error_span!(parent: &self.span, "task", %id, name),
             id,
-            // name,
+            name,
             node: self.clone(),
         })
     }
@@ -98,11 +129,31 @@ impl NodeInfo {
     pub(crate) fn is_killed(&self) -> bool {
         self.killed.load(Ordering::Relaxed)
     }
+
+    fn pause(&self, now: Instant) {
+        self.paused.store(true, Ordering::Relaxed);
+        self.pause_time.lock().since.get_or_insert(now);
+    }
+
+    fn resume(&self, now: Instant) {
+        self.paused.store(false, Ordering::Relaxed);
+        let mut time = self.pause_time.lock();
+        if let Some(since) = time.since.take() {
+            time.total += now - since;
+        }
+    }
+
+    /// Returns the total time the node has been paused until `now`.
+    pub(crate) fn paused_time(&self, now: Instant) -> Duration {
+        let time = self.pause_time.lock();
+        time.total + time.since.map_or(Duration::ZERO, |since| now - since)
+    }
 }
 
 impl Executor {
-    pub fn new(rand: GlobalRng, sims: Arc<Simulators>) -> Self {
+    pub fn new(rand: GlobalRng, sims: Arc<Simulators>, config: &time::Config) -> Self {
         let (sender, queue) = mpsc::channel();
+        let time = TimeRuntime::new(&rand);
         Executor {
             queue,
             handle: TaskHandle {
@@ -113,17 +164,25 @@ impl Executor {
                     id: NodeId::zero(),
                     name: Some("main".into()),
                     cores: 1,
+                    cpus: tokio::sync::Semaphore::new(1),
                     paused: AtomicBool::new(false),
+                    pause_time: Mutex::new(PauseTime::default()),
                     killed: AtomicBool::new(false),
                     restart_on_panic: false,
                     span: error_span!("node", id = %NodeId::zero(), name = "main"),
                     wakers: Mutex::new(vec![]),
                 }),
                 sims,
+                interleaving: Default::default(),
+                time: time.handle().clone(),
+                idle_waiters: Default::default(),
+                rand: rand.clone(),
             },
-            time: TimeRuntime::new(&rand),
+            time,
             rand,
             time_limit: None,
+            clock_stall_rate: config.clock_stall_rate,
+            trace_ids: Default::default(),
         }
     }
 
@@ -160,6 +219,9 @@ impl Executor {
             if let Poll::Ready(val) = task.poll_unpin(&mut cx) {
                 return val;
             }
+            if self.wake_idle_waiters() {
+                continue;
+            }
             let going = self.time.advance_to_next_event();
             assert!(going, "no events, all tasks will block forever");
             if let Some(limit) = self.time_limit {
@@ -172,6 +234,21 @@ impl Executor {
         }
     }
 
+    /// Wakes up the tasks waiting for idle if no task is ready and no timer is pending.
+    ///
+    /// Returns true if any task is woken up.
+    fn wake_idle_waiters(&self) -> bool {
+        if self.time.has_pending_timers() {
+            return false;
+        }
+        let waiters = std::mem::take(&mut *self.idle_waiters.lock());
+        let mut woken = false;
+        for waiter in waiters {
+            woken |= waiter.send(()).is_ok();
+        }
+        woken
+    }
+
     /// Drain all tasks from ready queue and run them.
     fn run_all_ready(&self) {
         while let Ok((runnable, info)) = self.queue.try_recv_random(&self.rand) {
@@ -183,6 +260,24 @@ impl Executor {
                 (self.nodes.lock().get_mut(&info.node.id).unwrap().paused).push((runnable, info));
                 continue;
             }
+            // tasks in a forced interleaving wait for their turn
+            let turn = {
+                let mut interleaving = self.interleaving.lock();
+                if interleaving.is_waiting(&info) {
+                    interleaving.held.push((runnable, info));
+                    continue;
+                }
+                interleaving.is_turn(&info)
+            };
+            self.rand.trace(|| {
+                let mut ids = self.trace_ids.lock();
+                let len = ids.len();
+                let id = *ids.entry(info.id).or_insert(len);
+                match &info.name {
+                    Some(name) => format!("poll task {id} ({name}) on node {}", info.node.id),
+                    None => format!("poll task {id} on node {}", info.node.id),
+                }
+            });
             // run the task
             if info.node.restart_on_panic {
                 let node_id = info.node.id;
@@ -208,7 +303,16 @@ impl Executor {
                 let _guard = crate::context::enter_task(info);
                 runnable.run();
             }
+            if turn {
+                self.interleaving.lock().next(&self.sender);
+            }
 
+            // the clock may stall if configured
+            if self.clock_stall_rate > 0.0
+                && self.rand.with(|rng| rng.gen_bool(self.clock_stall_rate))
+            {
+                continue;
+            }
             // advance time: 50-100ns
             let dur = Duration::from_nanos(self.rand.with(|rng| rng.gen_range(50..100)));
             self.time.advance(dur);
@@ -233,6 +337,43 @@ pub struct TaskHandle {
     /// Info of the main node.
     main_info: Arc<NodeInfo>,
     sims: Arc<Simulators>,
+    interleaving: Arc<Mutex<Interleaving>>,
+    time: TimeHandle,
+    /// Tasks waiting for the simulation to become idle.
+    idle_waiters: Arc<Mutex<Vec<oneshot::Sender<()>>>>,
+    rand: GlobalRng,
+}
+
+/// A forced order of polls among named tasks.
+#[derive(Default)]
+struct Interleaving {
+    /// Names of the tasks to poll, one entry per poll.
+    order: VecDeque<String>,
+    /// Tasks held back until their turn.
+    held: Vec<(Runnable, Arc<TaskInfo>)>,
+}
+
+impl Interleaving {
+    /// Returns true if the task is in the order but it's not its turn.
+    fn is_waiting(&self, info: &TaskInfo) -> bool {
+        match &info.name {
+            Some(name) => self.order.contains(name) && !self.is_turn(info),
+            None => false,
+        }
+    }
+
+    /// Returns true if it's the turn of the task.
+    fn is_turn(&self, info: &TaskInfo) -> bool {
+        info.name.is_some() && self.order.front() == info.name.as_ref()
+    }
+
+    /// Moves to the next turn and reschedules the held tasks.
+    fn next(&mut self, sender: &mpsc::Sender<(Runnable, Arc<TaskInfo>)>) {
+        self.order.pop_front();
+        for task in self.held.drain(..) {
+            sender.send(task).unwrap();
+        }
+    }
 }
 
 struct Node {
@@ -240,6 +381,8 @@ struct Node {
     paused: Vec<(Runnable, Arc<TaskInfo>)>,
     /// A function to spawn the initial task.
     init: Option<InitFn>,
+    /// The range of the random delay before the node starts.
+    startup_delay: Option<Range<Duration>>,
 }
 
 pub(crate) type InitFn = Arc<dyn Fn(&Spawner) + Send + Sync>;
@@ -252,7 +395,16 @@ impl TaskHandle {
         self.kill_id(id);
     }
 
-    fn kill_id(&self, id: NodeId) {
+    /// Kill all tasks of the node and return the timers they were waiting on.
+    pub fn kill_and_report(&self, id: impl ToNodeId) -> Vec<time::CancelledTimer> {
+        debug!(node = %id, "kill");
+        let id = id.to_node_id(self);
+        self.kill_id(id)
+    }
+
+    fn kill_id(&self, id: NodeId) -> Vec<time::CancelledTimer> {
+        // cancel timers before the futures of the node are dropped
+        let cancelled = self.time.cancel_node_timers(id);
         let mut nodes = self.nodes.lock();
         let node = nodes.get_mut(&id).expect("node not found");
         node.paused.clear();
@@ -260,7 +412,9 @@ impl TaskHandle {
             id,
             name: node.info.name.clone(),
             cores: node.info.cores,
+            cpus: tokio::sync::Semaphore::new(node.info.cores),
             paused: AtomicBool::new(false),
+            pause_time: Mutex::new(PauseTime::default()),
             killed: AtomicBool::new(false),
             restart_on_panic: node.info.restart_on_panic,
             span: error_span!(parent: None, "node", %id, name = &node.info.name),
@@ -273,6 +427,7 @@ impl TaskHandle {
         for sim in self.sims.lock().values() {
             sim.reset_node(id);
         }
+        cancelled
     }
 
     /// Kill all tasks of the node and restart the initial task.
@@ -282,6 +437,9 @@ impl TaskHandle {
         self.kill_id(id);
         let nodes = self.nodes.lock();
         let node = nodes.get(&id).expect("node not found");
+        if let Some(range) = &node.startup_delay {
+            self.delay_startup(&node.info, range.clone());
+        }
         if let Some(init) = &node.init {
             init(&Spawner {
                 sender: self.sender.clone(),
@@ -290,13 +448,30 @@ impl TaskHandle {
         }
     }
 
+    /// Waits until no task is ready and no timer is pending.
+    pub async fn wait_idle(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.idle_waiters.lock().push(tx);
+        let _ = rx.await;
+    }
+
+    /// Force the order in which named tasks are polled.
+    pub fn interleave(&self, order: Vec<String>) {
+        debug!(?order, "interleave");
+        let mut interleaving = self.interleaving.lock();
+        interleaving.order = order.into();
+        for task in interleaving.held.drain(..) {
+            self.sender.send(task).unwrap();
+        }
+    }
+
     /// Pause all tasks of the node.
     pub fn pause(&self, id: impl ToNodeId) {
         debug!(node = %id, "pause");
         let id = id.to_node_id(self);
         let nodes = self.nodes.lock();
         let node = nodes.get(&id).expect("node not found");
-        node.info.paused.store(true, Ordering::Relaxed);
+        node.info.pause(self.time.now_instant());
     }
 
     /// Resume the execution of the address.
@@ -305,7 +480,11 @@ impl TaskHandle {
         let id = id.to_node_id(self);
         let mut nodes = self.nodes.lock();
         let node = nodes.get_mut(&id).expect("node not found");
-        node.info.paused.store(false, Ordering::Relaxed);
+        self.resume_node(node);
+    }
+
+    fn resume_node(&self, node: &mut Node) {
+        node.info.resume(self.time.now_instant());
 
         // take paused tasks from waiting list and push them to ready queue
         for (runnable, info) in node.paused.drain(..) {
@@ -313,6 +492,37 @@ impl TaskHandle {
         }
     }
 
+    /// Pauses a booting node until a random delay in `range` elapses.
+    fn delay_startup(&self, info: &Arc<NodeInfo>, range: Range<Duration>) {
+        let delay = match range.is_empty() {
+            true => range.start,
+            false => self.rand.with(|rng| rng.gen_range(range)),
+        };
+        if delay.is_zero() {
+            return;
+        }
+        debug!(node = %info.id, ?delay, "delay startup");
+        info.pause(self.time.now_instant());
+        // resume from a task on the main node, because a restart may happen in
+        // a timer callback where no timer can be added
+        let handle = self.clone();
+        let info = info.clone();
+        let sleep = self.time.sleep(delay);
+        let main = Spawner {
+            sender: self.sender.clone(),
+            info: self.main_info.clone(),
+        };
+        main.spawn(async move {
+            sleep.await;
+            let mut nodes = handle.nodes.lock();
+            let node = nodes.get_mut(&info.id).expect("node not found");
+            // the node may have been killed since
+            if Arc::ptr_eq(&node.info, &info) {
+                handle.resume_node(node);
+            }
+        });
+    }
+
     /// Create a new node.
     pub fn create_node(
         &self,
@@ -320,6 +530,7 @@ impl TaskHandle {
         init: Option<InitFn>,
         cores: Option<usize>,
         restart_on_panic: bool,
+        startup_delay: Option<Range<Duration>>,
     ) -> Spawner {
         let id = NodeId(self.next_node_id.fetch_add(1, Ordering::Relaxed));
         debug!(node = %id, name, "create");
@@ -328,7 +539,9 @@ impl TaskHandle {
             id,
             name,
             cores: cores.unwrap_or(1),
+            cpus: tokio::sync::Semaphore::new(cores.unwrap_or(1)),
             paused: AtomicBool::new(false),
+            pause_time: Mutex::new(PauseTime::default()),
             killed: AtomicBool::new(false),
             restart_on_panic,
             wakers: Mutex::new(vec![]),
@@ -337,6 +550,9 @@ impl TaskHandle {
             sender: self.sender.clone(),
             info: info.clone(),
         };
+        if let Some(range) = &startup_delay {
+            self.delay_startup(&info, range.clone());
+        }
         if let Some(init) = &init {
             init(&handle);
         }
@@ -344,6 +560,7 @@ impl TaskHandle {
             info,
             paused: vec![],
             init,
+            startup_delay,
         };
         self.nodes.lock().insert(id, node);
         handle
@@ -495,6 +712,39 @@ where
     Spawner::current().spawn_local(future)
 }
 
+/// Occupies a CPU core of the current node for `duration` of simulated time.
+///
+/// This models CPU-bound work so that it competes for the cores of a node
+/// (see [`NodeBuilder::cores`](crate::runtime::NodeBuilder::cores)).
+/// The work runs on the core that becomes free first. If all cores are busy,
+/// it has to wait for them, so tasks on a busy node lag behind while other
+/// nodes are not affected. Dropping the future frees its core at once.
+///
+/// Only the work declared through this function is accounted.
+///
+/// # Example
+///
+/// ```
+/// use madsim::{runtime::Runtime, task, time::{Duration, Instant}};
+///
+/// let runtime = Runtime::new();
+/// runtime.block_on(async {
+///     let t0 = Instant::now();
+///     let a = task::spawn(task::consume_cpu(Duration::from_secs(1)));
+///     let b = task::spawn(task::consume_cpu(Duration::from_secs(1)));
+///     a.await.unwrap();
+///     b.await.unwrap();
+///     // the main node has only one core
+///     assert!(t0.elapsed() >= Duration::from_secs(2));
+/// });
+/// ```
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+pub async fn consume_cpu(duration: Duration) {
+    let info = crate::context::current_task();
+    let _core = info.node.cpus.acquire().await.unwrap();
+    crate::time::sleep(duration).await;
+}
+
 /// Runs the provided closure on a thread where blocking is acceptable.
 #[deprecated(
     since = "0.3.0",
@@ -733,6 +983,72 @@ mod tests {
     };
     use std::{collections::HashSet, sync::atomic::AtomicUsize, time::Duration};
 
+    #[test]
+    fn interleave_lost_update() {
+        // a read-modify-write with an await point in between
+        async fn increment(counter: Arc<AtomicUsize>) {
+            let value = counter.load(Ordering::SeqCst);
+            yield_now().await;
+            counter.store(value + 1, Ordering::SeqCst);
+        }
+
+        for seed in 0..10 {
+            let runtime = Runtime::with_seed_and_config(seed, crate::Config::default());
+            let (racy, serial) = runtime.block_on(async {
+                let mut results = vec![];
+                for order in [["a", "b", "a", "b"], ["a", "a", "b", "b"]] {
+                    Handle::current().interleave(order);
+                    let counter = Arc::new(AtomicUsize::new(0));
+                    let a = Builder::new().name("a").spawn(increment(counter.clone()));
+                    let b = Builder::new().name("b").spawn(increment(counter.clone()));
+                    a.await.unwrap();
+                    b.await.unwrap();
+                    results.push(counter.load(Ordering::SeqCst));
+                }
+                (results[0], results[1])
+            });
+            assert_eq!(racy, 1, "the update of one task should be lost");
+            assert_eq!(serial, 2);
+        }
+    }
+
+    #[test]
+    fn run_until_idle() {
+        let runtime = Runtime::new();
+        let node = runtime.create_node().build();
+        let steps = Arc::new(AtomicUsize::new(0));
+
+        let steps_ = steps.clone();
+        node.spawn(async move {
+            for i in 1..=3 {
+                time::sleep(Duration::from_secs(i)).await;
+                steps_.fetch_add(1, Ordering::Relaxed);
+                // hand off to another task
+                let steps = steps_.clone();
+                spawn(async move {
+                    time::sleep(Duration::from_millis(100)).await;
+                    steps.fetch_add(10, Ordering::Relaxed);
+                })
+                .await
+                .unwrap();
+            }
+        });
+
+        runtime.block_on(async move {
+            let t0 = time::Instant::now();
+            Handle::current().run_until_idle().await;
+            assert_eq!(steps.load(Ordering::Relaxed), 33);
+            // the clock stops at the last timer
+            let elapsed = t0.elapsed();
+            assert!(elapsed >= Duration::from_millis(6300), "{elapsed:?}");
+            assert!(elapsed < Duration::from_millis(6310), "{elapsed:?}");
+
+            // returns immediately when already idle
+            Handle::current().run_until_idle().await;
+            assert!(t0.elapsed() < Duration::from_millis(6310));
+        });
+    }
+
     #[test]
     fn spawn_in_block_on() {
         let runtime = Runtime::new();
@@ -782,6 +1098,43 @@ mod tests {
         });
     }
 
+    #[test]
+    fn kill_and_report_timers() {
+        let runtime = Runtime::new();
+        let node = runtime.create_node().build();
+        let fired = Arc::new(AtomicUsize::new(0));
+
+        let fired_ = fired.clone();
+        node.spawn(async move {
+            for (name, secs) in [("a", 5), ("b", 3), ("c", 7)] {
+                let fired = fired_.clone();
+                Builder::new().name(name).spawn(async move {
+                    time::sleep(Duration::from_secs(secs)).await;
+                    fired.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+        });
+
+        runtime.block_on(async move {
+            let t0 = time::Instant::now();
+            time::sleep(Duration::from_secs(1)).await;
+            let cancelled = Handle::current().kill_and_report(node.id());
+            let report: Vec<_> = cancelled
+                .iter()
+                .map(|t| (t.deadline - t0, t.task.as_deref()))
+                .collect();
+            assert_eq!(report.len(), 3);
+            assert_eq!(report[0].1, Some("b"));
+            assert_eq!(report[1].1, Some("a"));
+            assert_eq!(report[2].1, Some("c"));
+            assert!(report[0].0 < report[1].0 && report[1].0 < report[2].0);
+
+            time::sleep(Duration::from_secs(10)).await;
+            assert_eq!(fired.load(Ordering::Relaxed), 0);
+            assert!(Handle::current().kill_and_report(node.id()).is_empty());
+        });
+    }
+
     #[test]
     fn restart() {
         let runtime = Runtime::new();
@@ -922,6 +1275,61 @@ mod tests {
         runtime.block_on(f2).unwrap();
     }
 
+    #[test]
+    fn consume_cpu_contention() {
+        let runtime = Runtime::new();
+        // run 3 CPU-bound tasks on each node and return their completion times
+        let run = |node: crate::runtime::NodeHandle| {
+            node.spawn(async move {
+                let t0 = time::Instant::now();
+                let tasks: Vec<_> = (0..3)
+                    .map(|_| {
+                        spawn(async move {
+                            consume_cpu(Duration::from_secs(1)).await;
+                            t0.elapsed().as_secs()
+                        })
+                    })
+                    .collect();
+                let mut elapsed = vec![];
+                for task in tasks {
+                    elapsed.push(task.await.unwrap());
+                }
+                elapsed.sort();
+                elapsed
+            })
+        };
+        let busy = run(runtime.create_node().build());
+        let idle = run(runtime.create_node().cores(4).build());
+        let dual = run(runtime.create_node().cores(2).build());
+        // tasks on a single core are serialized
+        assert_eq!(runtime.block_on(busy).unwrap(), [1, 2, 3]);
+        // tasks on other nodes are not delayed by the busy node
+        assert_eq!(runtime.block_on(idle).unwrap(), [1, 1, 1]);
+        assert_eq!(runtime.block_on(dual).unwrap(), [1, 1, 2]);
+    }
+
+    #[test]
+    fn consume_cpu_cancel() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let t0 = time::Instant::now();
+            let cancelled = spawn(time::timeout(
+                Duration::from_secs(1),
+                consume_cpu(Duration::from_secs(10)),
+            ));
+            time::sleep(Duration::from_millis(1)).await;
+            let next = spawn(async move {
+                consume_cpu(Duration::from_secs(1)).await;
+                t0.elapsed()
+            });
+            cancelled.await.unwrap().unwrap_err();
+            // the core is freed as soon as the first work is cancelled
+            let elapsed = next.await.unwrap();
+            assert!(elapsed >= Duration::from_secs(2), "{elapsed:?}");
+            assert!(elapsed < Duration::from_millis(2100), "{elapsed:?}");
+        });
+    }
+
     #[test]
     #[should_panic]
     fn forbid_creating_system_thread() {
@@ -35,6 +35,11 @@ use std::task::{Context, Poll};
 use std::{convert::TryInto, future::Future};
 
 /// Creates new [`Interval`] that yields with interval of `period`.
+///
+/// The first tick completes immediately. Ticks are scheduled on the simulated
+/// clock, so their timing is reproducible. Missed ticks are handled according
+/// to [`MissedTickBehavior`], which is [`Burst`](MissedTickBehavior::Burst) by
+/// default.
 pub fn interval(period: Duration) -> Interval {
     assert!(period > Duration::new(0, 0), "`period` must be non-zero.");
     internal_interval_at(Instant::now(), period)
@@ -58,13 +63,27 @@ fn internal_interval_at(start: Instant, period: Duration) -> Interval {
 }
 
 /// Defines the behavior of an [`Interval`] when it misses a tick.
+///
+/// A tick is missed when [`Interval::tick`] is called more than 5ms after the
+/// tick was due, e.g. because the task was busy or its node was paused. The
+/// examples below use a period of 100ms and a task that calls `tick` at 0ms,
+/// 100ms, 200ms, and then not until 560ms.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MissedTickBehavior {
     /// Ticks as fast as possible until caught up.
+    ///
+    /// The missed ticks at 300ms, 400ms and 500ms all complete at 560ms, then
+    /// the interval continues at 600ms, 700ms, ... The average rate is kept.
     Burst,
     /// Tick at multiples of `period` from when `tick` was called, rather than from `start`.
+    ///
+    /// The tick at 300ms completes at 560ms, then the interval continues at
+    /// 660ms, 760ms, ... Ticks are never less than `period` apart.
     Delay,
     /// Skips missed ticks and tick on the next multiple of `period` from `start`.
+    ///
+    /// The tick at 300ms completes at 560ms, the ones at 400ms and 500ms are
+    /// dropped, then the interval continues at 600ms, 700ms, ...
     Skip,
 }
 
@@ -2,13 +2,24 @@
 //!
 //!
 
-use crate::rand::{GlobalRng, Rng};
+use crate::{
+    rand::{GlobalRng, Rng},
+    task::NodeId,
+};
 use futures_util::{select_biased, FutureExt};
 use naive_timer::Timer;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 use spin::Mutex;
 #[doc(no_inline)]
 pub use std::time::{Duration, Instant};
-use std::{future::Future, sync::Arc, time::SystemTime};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    hash::{Hash, Hasher},
+    pin::pin,
+    sync::Arc,
+    time::SystemTime,
+};
 
 pub mod error;
 mod interval;
@@ -17,6 +28,52 @@ mod system_time;
 
 pub use self::interval::{interval, interval_at, Interval, MissedTickBehavior};
 pub use self::sleep::{sleep, sleep_until, Sleep};
+pub use crate::instant::InstantExt;
+
+/// Time configurations.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(default)]
+pub struct Config {
+    /// Possibility that the clock does not advance after a task is polled.
+    ///
+    /// The clock normally advances a little every time a task is polled, so
+    /// two [`Instant::now`] calls separated by an `.await` never return the
+    /// same value. Set this to flush out code that breaks on a zero
+    /// `elapsed()`, such as a rate computed by dividing by it.
+    ///
+    /// Must be in `[0, 1]`.
+    #[serde(deserialize_with = "deserialize_clock_stall_rate")]
+    pub clock_stall_rate: f64,
+}
+
+impl Config {
+    /// Checks that the settings are valid.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.clock_stall_rate) {
+            return Err(format!(
+                "clock_stall_rate must be in [0, 1], got {}",
+                self.clock_stall_rate
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn deserialize_clock_stall_rate<'de, D: Deserializer<'de>>(d: D) -> Result<f64, D::Error> {
+    let config = Config {
+        clock_stall_rate: f64::deserialize(d)?,
+    };
+    config.validate().map_err(D::Error::custom)?;
+    Ok(config.clock_stall_rate)
+}
+
+#[allow(clippy::derived_hash_with_manual_eq)]
+impl Hash for Config {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.clock_stall_rate.to_bits().hash(state);
+    }
+}
 
 pub(crate) struct TimeRuntime {
     handle: TimeHandle,
@@ -33,6 +90,8 @@ impl TimeRuntime {
         let handle = TimeHandle {
             timer: Arc::new(Mutex::new(Timer::default())),
             clock: Arc::new(Clock::new(base_time)),
+            rand: rand.clone(),
+            sleeps: Default::default(),
         };
         TimeRuntime { handle }
     }
@@ -59,6 +118,11 @@ impl TimeRuntime {
         }
     }
 
+    /// Returns true if any timer is pending.
+    pub fn has_pending_timers(&self) -> bool {
+        self.handle.timer.lock().next().is_some()
+    }
+
     /// Advances time.
     pub fn advance(&self, duration: Duration) {
         self.handle.clock.advance(duration);
@@ -78,6 +142,35 @@ impl TimeRuntime {
 pub struct TimeHandle {
     timer: Arc<Mutex<Timer>>,
     clock: Arc<Clock>,
+    /// For tracing timer events.
+    rand: GlobalRng,
+    /// Pending sleeps of tasks.
+    sleeps: Arc<Mutex<PendingSleeps>>,
+}
+
+/// The sleeps that tasks are waiting on, by registration order.
+#[derive(Default)]
+struct PendingSleeps {
+    next_id: u64,
+    sleeps: BTreeMap<u64, PendingSleep>,
+}
+
+struct PendingSleep {
+    node: NodeId,
+    task: Option<String>,
+    deadline: Instant,
+}
+
+/// A pending timer cancelled by killing its node.
+///
+/// See [`Handle::kill_and_report`](crate::runtime::Handle::kill_and_report).
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CancelledTimer {
+    /// The instant at which the timer would have fired.
+    pub deadline: Instant,
+    /// The name of the task waiting on the timer.
+    pub task: Option<String>,
 }
 
 impl TimeHandle {
@@ -96,9 +189,29 @@ impl TimeHandle {
         self.clock.now_instant()
     }
 
-    /// Return the current time.
+    /// Return the current wall-clock time of the current node.
+    ///
+    /// Unlike [`now_instant`](Self::now_instant), this may go backwards
+    /// if the clock of the node has been stepped by [`ClockStep`].
     pub fn now_time(&self) -> SystemTime {
-        self.clock.now_time()
+        let node = crate::context::try_current_task().map(|task| task.node.id);
+        self.clock.now_time(node)
+    }
+
+    /// Sets the wall-clock time when the simulation starts.
+    pub(crate) fn set_epoch(&self, epoch: SystemTime) {
+        self.clock.set_base_time(epoch);
+    }
+
+    /// Steps the wall clock of a node.
+    pub(crate) fn step_clock(&self, node: NodeId, step: ClockStep) {
+        self.clock.step(node, step);
+    }
+
+    /// Steps the wall clock of a node when `deadline` is reached.
+    pub(crate) fn step_clock_at(&self, node: NodeId, deadline: Instant, step: ClockStep) {
+        let clock = self.clock.clone();
+        self.add_timer_at(deadline, move || clock.step(node, step));
     }
 
     /// Returns the amount of time elapsed since this handle was created.
@@ -116,21 +229,92 @@ impl TimeHandle {
         Sleep {
             handle: self.clone(),
             deadline,
+            id: None,
         }
     }
 
+    /// Registers a sleep polled by the current task, or updates its deadline.
+    ///
+    /// Returns `None` if not called from a task.
+    fn track_sleep(&self, id: Option<u64>, deadline: Instant) -> Option<u64> {
+        let mut sleeps = self.sleeps.lock();
+        if let Some(sleep) = id.and_then(|id| sleeps.sleeps.get_mut(&id)) {
+            sleep.deadline = deadline;
+            return id;
+        }
+        let task = crate::context::try_current_task()?;
+        let id = sleeps.next_id;
+        sleeps.next_id += 1;
+        sleeps.sleeps.insert(
+            id,
+            PendingSleep {
+                node: task.node.id,
+                task: task.name.clone(),
+                deadline,
+            },
+        );
+        Some(id)
+    }
+
+    fn untrack_sleep(&self, id: u64) {
+        self.sleeps.lock().sleeps.remove(&id);
+    }
+
+    /// Cancels the pending sleeps of a node, so they never wake up their tasks.
+    ///
+    /// Returns the cancelled timers ordered by deadline, then by the order in
+    /// which they were first polled.
+    pub(crate) fn cancel_node_timers(&self, node: NodeId) -> Vec<CancelledTimer> {
+        let mut sleeps = self.sleeps.lock();
+        let ids: Vec<u64> = sleeps
+            .sleeps
+            .iter()
+            .filter(|(_, sleep)| sleep.node == node)
+            .map(|(id, _)| *id)
+            .collect();
+        let mut cancelled: Vec<_> = ids
+            .into_iter()
+            .map(|id| (id, sleeps.sleeps.remove(&id).unwrap()))
+            .collect();
+        cancelled.sort_by_key(|(id, sleep)| (sleep.deadline, *id));
+        cancelled
+            .into_iter()
+            .map(|(_, sleep)| CancelledTimer {
+                deadline: sleep.deadline,
+                task: sleep.task,
+            })
+            .collect()
+    }
+
     /// Require a `Future` to complete before the specified duration has elapsed.
+    ///
+    /// In the simulation, the time the node of the current task is paused
+    /// doesn't count, so a paused task doesn't time out before it runs again.
     // TODO: make it Send
     pub fn timeout<T: Future>(
         &self,
         duration: Duration,
         future: T,
     ) -> impl Future<Output = Result<T::Output, error::Elapsed>> {
-        let timeout = self.sleep(duration);
+        let handle = self.clone();
+        let node = crate::context::try_current_task().map(|task| task.node.clone());
+        let paused_time = move |now| node.as_ref().map_or(Duration::ZERO, |n| n.paused_time(now));
+        let start = self.now_instant();
+        let paused0 = paused_time(start);
         async move {
-            select_biased! {
-                res = future.fuse() => Ok(res),
-                _ = timeout.fuse() => Err(error::Elapsed),
+            let mut future = pin!(future.fuse());
+            let mut deadline = start + duration;
+            loop {
+                select_biased! {
+                    res = future => return Ok(res),
+                    _ = handle.sleep_until(deadline).fuse() => {}
+                }
+                // extend the deadline by the time paused since the start
+                let now = handle.now_instant();
+                deadline = start + duration + (paused_time(now) - paused0);
+                if deadline <= now {
+                    return Err(error::Elapsed);
+                }
             }
         }
     }
@@ -141,7 +325,12 @@ impl TimeHandle {
         callback: impl FnOnce() + Send + Sync + 'static,
     ) {
         let mut timer = self.timer.lock();
-        timer.add(deadline - self.clock.base_instant(), |_| callback());
+        let due = deadline - self.clock.base_instant();
+        let rand = self.rand.clone();
+        timer.add(due, move |_| {
+            rand.trace(|| format!("timer {due:?}"));
+            callback()
+        });
     }
 
     pub(crate) fn add_timer(&self, dur: Duration, callback: impl FnOnce() + Send + Sync + 'static) {
@@ -158,6 +347,18 @@ pub fn timeout<T: Future>(
     handle.timeout(duration, future)
 }
 
+/// A step of the wall clock, as if it was set by NTP or an operator.
+///
+/// It changes the [`SystemTime`] of a node, while [`Instant`] and timers are unaffected.
+#[cfg_attr(docsrs, doc(cfg(madsim)))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockStep {
+    /// Step the clock forward.
+    Forward(Duration),
+    /// Step the clock backward.
+    Backward(Duration),
+}
+
 struct Clock {
     inner: Mutex<ClockInner>,
 }
@@ -169,6 +370,8 @@ struct ClockInner {
     base_instant: std::time::Instant,
     /// The amount of mock time which has elapsed.
     advance: Duration,
+    /// The offset of the wall clock of each node in nanoseconds.
+    wall_offsets: HashMap<NodeId, i64>,
 }
 
 impl Clock {
@@ -177,6 +380,7 @@ impl Clock {
             base_time,
             base_instant: unsafe { std::mem::zeroed() },
             advance: Duration::default(),
+            wall_offsets: HashMap::new(),
         };
         Clock {
             inner: Mutex::new(clock),
@@ -208,9 +412,28 @@ impl Clock {
         inner.base_instant + inner.advance
     }
 
-    fn now_time(&self) -> SystemTime {
+    fn set_base_time(&self, time: SystemTime) {
+        self.inner.lock().base_time = time;
+    }
+
+    fn now_time(&self, node: Option<NodeId>) -> SystemTime {
         let inner = self.inner.lock();
-        inner.base_time + inner.advance
+        let time = inner.base_time + inner.advance;
+        match node.and_then(|node| inner.wall_offsets.get(&node)) {
+            Some(&offset) if offset >= 0 => time + Duration::from_nanos(offset as u64),
+            Some(&offset) => time - Duration::from_nanos(offset.unsigned_abs()),
+            None => time,
+        }
+    }
+
+    fn step(&self, node: NodeId, step: ClockStep) {
+        tracing::debug!(%node, ?step, "step clock");
+        let mut inner = self.inner.lock();
+        let offset = inner.wall_offsets.entry(node).or_default();
+        match step {
+            ClockStep::Forward(d) => *offset += d.as_nanos() as i64,
+            ClockStep::Backward(d) => *offset -= d.as_nanos() as i64,
+        }
     }
 }
 
@@ -243,4 +466,174 @@ mod tests {
             );
         });
     }
+
+    #[test]
+    fn missed_tick_behavior() {
+        let run = |behavior| {
+            let runtime = Runtime::new();
+            runtime.block_on(async move {
+                let handle = crate::runtime::Handle::current();
+                let node = handle.create_node().build();
+                let start = Instant::now();
+                let task = node.spawn(async move {
+                    let mut interval = interval(Duration::from_millis(100));
+                    interval.set_missed_tick_behavior(behavior);
+                    let mut ticks = vec![];
+                    for _ in 0..7 {
+                        let tick = interval.tick().await;
+                        ticks.push((
+                            (tick - start).as_millis(),
+                            (Instant::now() - start).as_millis(),
+                        ));
+                    }
+                    ticks
+                });
+                // miss the ticks at 300ms, 400ms and 500ms
+                sleep(Duration::from_millis(250)).await;
+                handle.pause(node.id());
+                sleep(Duration::from_millis(310)).await;
+                handle.resume(node.id());
+                task.await.unwrap()
+            })
+        };
+        let before = [(0, 0), (100, 100), (200, 200)];
+        let after = [(300, 560), (400, 560), (500, 560), (600, 600)];
+        assert_eq!(
+            run(MissedTickBehavior::Burst),
+            [&before[..], &after].concat()
+        );
+        let after = [(300, 560), (660, 660), (760, 760), (860, 860)];
+        assert_eq!(
+            run(MissedTickBehavior::Delay),
+            [&before[..], &after].concat()
+        );
+        let after = [(300, 560), (600, 600), (700, 700), (800, 800)];
+        assert_eq!(
+            run(MissedTickBehavior::Skip),
+            [&before[..], &after].concat()
+        );
+    }
+
+    #[test]
+    fn timeout_paused() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let handle = crate::runtime::Handle::current();
+            let node = handle.create_node().build();
+            let t0 = Instant::now();
+            let task = node.spawn(async move {
+                let res = timeout(Duration::from_secs(5), sleep(Duration::from_secs(60))).await;
+                assert!(res.is_err());
+                t0.elapsed()
+            });
+            // pause the node for 4s after 2s of the timeout
+            sleep(Duration::from_secs(2)).await;
+            handle.pause(node.id());
+            sleep(Duration::from_secs(4)).await;
+            handle.resume(node.id());
+            // the remaining 3s run after the resume
+            let elapsed = task.await.unwrap();
+            assert!(elapsed >= Duration::from_secs(9), "{elapsed:?}");
+            assert!(elapsed < Duration::from_millis(9001), "{elapsed:?}");
+        });
+    }
+
+    #[test]
+    fn clock_stall() {
+        // a throughput meter that must not divide by a zero duration
+        fn rate(bytes: u64, elapsed: Duration) -> Option<u64> {
+            bytes.checked_div(elapsed.as_nanos() as u64)
+        }
+
+        let mut config = crate::Config::default();
+        config.time.clock_stall_rate = 0.5;
+        let runtime = Runtime::with_seed_and_config(0, config);
+        runtime.block_on(async {
+            let mut stalls = 0;
+            for _ in 0..100 {
+                let t0 = Instant::now();
+                crate::task::yield_now().await;
+                if rate(1000, t0.elapsed()).is_none() {
+                    stalls += 1;
+                }
+            }
+            assert!(stalls > 0 && stalls < 100, "stalls: {stalls}");
+        });
+
+        // the clock always advances by default
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            for _ in 0..100 {
+                let t0 = Instant::now();
+                crate::task::yield_now().await;
+                assert!(rate(1000, t0.elapsed()).is_some());
+            }
+        });
+    }
+
+    #[test]
+    fn invalid_clock_stall_rate() {
+        let err = "[time]\nclock_stall_rate = 1.5"
+            .parse::<crate::Config>()
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("clock_stall_rate must be in [0, 1], got 1.5"),
+            "{err}"
+        );
+
+        let mut config = crate::Config::default();
+        config.time.clock_stall_rate = -0.1;
+        let err = std::panic::catch_unwind(|| Runtime::with_seed_and_config(0, config))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<String>().unwrap(),
+            "invalid config: clock_stall_rate must be in [0, 1], got -0.1"
+        );
+    }
+
+    #[test]
+    fn step_clock_backward() {
+        let runtime = Runtime::new();
+        runtime.block_on(async {
+            let handle = crate::runtime::Handle::current();
+            let node = handle.create_node().build();
+            let other = handle.create_node().build();
+            let step = Duration::from_secs(3600);
+            handle.step_clock_at(
+                node.id(),
+                Instant::now() + Duration::from_secs(1),
+                ClockStep::Backward(step),
+            );
+
+            let (before, after) = node
+                .spawn(async move {
+                    let t0 = Instant::now();
+                    let before = SystemTime::now();
+                    sleep(Duration::from_secs(2)).await;
+                    let after = SystemTime::now();
+                    // the monotonic clock and timers are unaffected
+                    assert!(t0.elapsed() >= Duration::from_secs(2));
+                    assert!(
+                        timeout(Duration::from_secs(1), sleep(Duration::from_secs(2)))
+                            .await
+                            .is_err()
+                    );
+                    (before, after)
+                })
+                .await
+                .unwrap();
+            assert!(after < before);
+            let back = before.duration_since(after).unwrap();
+            assert!(back <= step - Duration::from_secs(2));
+            assert!(back > step - Duration::from_secs(3));
+
+            // other nodes keep the global wall clock
+            let now = SystemTime::now();
+            let other_now = other.spawn(async { SystemTime::now() }).await.unwrap();
+            assert!(other_now >= now);
+            assert!(other_now.duration_since(now).unwrap() < Duration::from_secs(1));
+        });
+    }
 }
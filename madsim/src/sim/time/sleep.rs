@@ -20,6 +20,8 @@ pub fn sleep_until(deadline: Instant) -> Sleep {
 pub struct Sleep {
     pub(super) handle: TimeHandle,
     pub(super) deadline: Instant,
+    /// The registration of the sleep, once polled by a task.
+    pub(super) id: Option<u64>,
 }
 
 impl Sleep {
@@ -44,16 +46,36 @@ impl Sleep {
 impl Future for Sleep {
     type Output = ();
 
-    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
         if self.is_elapsed() {
             return Poll::Ready(());
         }
+        self.id = self.handle.track_sleep(self.id, self.deadline);
         let waker = cx.waker().clone();
-        self.handle.add_timer_at(self.deadline, || waker.wake());
+        match self.id {
+            Some(id) => {
+                let sleeps = self.handle.sleeps.clone();
+                self.handle.add_timer_at(self.deadline, move || {
+                    // the timer is cancelled if its node has been killed
+                    if sleeps.lock().sleeps.contains_key(&id) {
+                        waker.wake();
+                    }
+                });
+            }
+            None => self.handle.add_timer_at(self.deadline, || waker.wake()),
+        }
         Poll::Pending
     }
 }
 
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.handle.untrack_sleep(id);
+        }
+    }
+}
+
 impl fmt::Debug for Sleep {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Sleep")
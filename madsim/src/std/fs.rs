@@ -57,6 +57,12 @@ impl File {
         self.inner.sync_all().await
     }
 
+    /// This function is similar to `sync_all`, except that it may not synchronize file metadata
+    /// to the filesystem.
+    pub async fn sync_data(&self) -> Result<()> {
+        self.inner.sync_data().await
+    }
+
     /// Queries metadata about the underlying file.
     pub async fn metadata(&self) -> Result<Metadata> {
         self.inner.metadata().await
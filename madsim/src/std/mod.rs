@@ -1,7 +1,15 @@
 pub mod fs;
 pub mod net;
+pub mod rand;
+pub mod sync;
 pub mod time;
 
-pub use rand;
 pub use std::collections;
 pub use tokio::{main, task, test};
+
+/// Returns `true` if the current thread is running inside a simulation runtime.
+///
+/// Always `false` when built without `--cfg madsim`.
+pub fn is_simulated() -> bool {
+    false
+}
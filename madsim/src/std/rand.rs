@@ -0,0 +1,23 @@
+//! Utilities for random number generation.
+
+pub use rand::*;
+
+use rand::seq::{IteratorRandom, SliceRandom};
+
+/// Returns a random element from the collection, or `None` if it is empty.
+pub fn choose<I: IntoIterator>(iter: I) -> Option<I::Item> {
+    iter.into_iter().choose(&mut thread_rng())
+}
+
+/// Shuffles the slice in place.
+pub fn shuffle<T>(slice: &mut [T]) {
+    slice.shuffle(&mut thread_rng());
+}
+
+/// Returns `amount` distinct elements chosen at random from the collection.
+///
+/// If the collection has fewer than `amount` elements, all of them are returned.
+/// The order of the returned elements is not random.
+pub fn sample<I: IntoIterator>(iter: I, amount: usize) -> Vec<I::Item> {
+    iter.into_iter().choose_multiple(&mut thread_rng(), amount)
+}
@@ -0,0 +1,25 @@
+//! Synchronization primitives.
+
+pub use tokio::sync::{
+    Barrier, BarrierWaitResult, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    TryLockError,
+};
+
+pub mod mpsc {
+    //! A multi-producer, single-consumer queue for sending values between tasks.
+
+    pub use tokio::sync::mpsc::{
+        error::{SendError, TryRecvError, TrySendError},
+        Receiver, Sender,
+    };
+
+    /// Creates a bounded mpsc channel for communicating between asynchronous tasks
+    /// with backpressure.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cap` is 0.
+    pub fn bounded<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+        tokio::sync::mpsc::channel(cap)
+    }
+}
@@ -1,3 +1,5 @@
 //! Utilities for tracking time.
 
 pub use tokio::time::{error, sleep, sleep_until, timeout, Duration, Instant};
+
+pub use crate::instant::InstantExt;
@@ -0,0 +1,50 @@
+#![cfg(madsim)]
+
+use madsim::{net::Endpoint, runtime::Handle, time::timeout, Config};
+use std::{net::SocketAddr, time::Duration};
+
+fn lossy() -> Config {
+    let mut config = Config::default();
+    config.net.packet_loss_rate = 0.5;
+    config
+}
+
+#[madsim::test(config = "lossy")]
+async fn start_with_packet_loss() {
+    let handle = Handle::current();
+    let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+    let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+    let node1 = handle.create_node().ip(addr1.ip()).build();
+    let node2 = handle.create_node().ip(addr2.ip()).build();
+
+    // echo server
+    node1.spawn(async move {
+        let ep = Endpoint::bind(addr1).await.unwrap();
+        let mut buf = [0; 1];
+        loop {
+            let (_, from) = ep.recv_from(1, &mut buf).await.unwrap();
+            ep.send_to(from, 2, &buf).await.unwrap();
+        }
+    });
+
+    let attempts = node2
+        .spawn(async move {
+            let ep = Endpoint::bind(addr2).await.unwrap();
+            let mut attempts = 0;
+            for i in 0..10u8 {
+                loop {
+                    attempts += 1;
+                    ep.send_to(addr1, 1, &[i]).await.unwrap();
+                    let mut buf = [0; 1];
+                    let res = timeout(Duration::from_millis(100), ep.recv_from(2, &mut buf)).await;
+                    if res.is_ok() && buf[0] == i {
+                        break;
+                    }
+                }
+            }
+            attempts
+        })
+        .await
+        .unwrap();
+    assert!(attempts > 10, "attempts: {attempts}");
+}
@@ -0,0 +1,53 @@
+#![cfg(madsim)]
+
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use madsim::{rand::Rng, runtime::Runtime, time::sleep, Config};
+use std::time::Duration;
+
+/// Returns the order in which delayed futures complete.
+fn completion_order(seed: u64) -> Vec<usize> {
+    let runtime = Runtime::with_seed_and_config(seed, Config::default());
+    runtime.block_on(async {
+        let mut futures = (0..50)
+            .map(|i| async move {
+                let delay = madsim::rand::thread_rng().gen_range(0..10);
+                sleep(Duration::from_millis(delay)).await;
+                i
+            })
+            .collect::<FuturesUnordered<_>>();
+        let mut order = vec![];
+        while let Some(i) = futures.next().await {
+            order.push(i);
+        }
+        order
+    })
+}
+
+#[test]
+fn futures_unordered_reproducible() {
+    let order = completion_order(1);
+    assert_eq!(order, completion_order(1));
+    assert_ne!(order, completion_order(2));
+}
+
+#[test]
+fn join_all_spawned_reproducible() {
+    fn finish_times(seed: u64) -> Vec<Duration> {
+        let runtime = Runtime::with_seed_and_config(seed, Config::default());
+        runtime.block_on(async {
+            let start = madsim::time::Instant::now();
+            let tasks = (0..50).map(|_| {
+                madsim::task::spawn(async move {
+                    let delay = madsim::rand::thread_rng().gen_range(0..10);
+                    sleep(Duration::from_millis(delay)).await;
+                    start.elapsed()
+                })
+            });
+            let results = futures_util::future::join_all(tasks).await;
+            results.into_iter().map(|r| r.unwrap()).collect()
+        })
+    }
+    let times = finish_times(1);
+    assert_eq!(times, finish_times(1));
+    assert_ne!(times, finish_times(2));
+}
@@ -0,0 +1,13 @@
+#![cfg(madsim)]
+
+use madsim::runtime::{Builder, Handle};
+
+#[test]
+fn current_seed_from_env() {
+    std::env::set_var("MADSIM_TEST_SEED", "2333");
+    let seed = Builder::from_env().run(|| async {
+        assert_eq!(madsim::rand::current_seed(), Handle::current().seed());
+        madsim::rand::current_seed()
+    });
+    assert_eq!(seed, 2333);
+}
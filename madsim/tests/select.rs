@@ -0,0 +1,66 @@
+use std::future::{pending, ready};
+
+#[madsim::test]
+async fn take_ready_branch() {
+    let value = madsim::select! {
+        _ = pending::<()>() => unreachable!(),
+        v = ready(1) => v + 1,
+    };
+    assert_eq!(value, 2);
+}
+
+#[madsim::test]
+async fn disabled_branches() {
+    // mismatched patterns and false preconditions disable the branch
+    let value = madsim::select! {
+        Some(v) = ready(None::<i32>) => v,
+        v = ready(2), if false => v,
+        v = ready(3) => v,
+    };
+    assert_eq!(value, 3);
+
+    let value = madsim::select! {
+        Some(v) = ready(None::<i32>) => v,
+        else => 0,
+    };
+    assert_eq!(value, 0);
+}
+
+#[madsim::test]
+async fn biased() {
+    for _ in 0..10 {
+        let value = madsim::select! {
+            biased;
+            v = ready(1) => v,
+            v = ready(2) => v,
+        };
+        assert_eq!(value, 1);
+    }
+}
+
+#[cfg(madsim)]
+#[test]
+fn branch_choice_reproducible() {
+    use madsim::{runtime::Runtime, Config};
+
+    fn choices(seed: u64) -> Vec<u32> {
+        let runtime = Runtime::with_seed_and_config(seed, Config::default());
+        runtime.block_on(async {
+            let mut choices = vec![];
+            for _ in 0..100 {
+                choices.push(madsim::select! {
+                    v = ready(0) => v,
+                    v = ready(1) => v,
+                    v = ready(2) => v,
+                });
+            }
+            choices
+        })
+    }
+    let a = choices(1);
+    assert_eq!(a, choices(1));
+    assert_ne!(a, choices(2));
+    for i in 0..3 {
+        assert!(a.contains(&i), "branch {i} is never taken");
+    }
+}
@@ -0,0 +1,9 @@
+#[madsim::test]
+async fn is_simulated() {
+    assert_eq!(madsim::is_simulated(), cfg!(madsim));
+}
+
+#[test]
+fn not_simulated_outside_runtime() {
+    assert!(!madsim::is_simulated());
+}
@@ -7,6 +7,7 @@ use std::time::Duration;
 use async_stream::try_stream;
 use futures_core::Stream;
 use madsim::time::sleep;
+use prost::Message;
 use tonic::{transport::Server, Request, Response, Status, Streaming};
 
 use hello_world::another_greeter_server::{AnotherGreeter, AnotherGreeterServer};
@@ -46,6 +47,23 @@ impl Greeter for MyGreeter {
         if name == "error" {
             return Err(Status::invalid_argument("error!"));
         }
+        if name == "details" {
+            // attach a typed message as error details
+            let details = HelloReply {
+                message: "invalid name".into(),
+            };
+            let mut metadata = tonic::metadata::MetadataMap::new();
+            metadata.insert("x-error-source", "greeter".parse().unwrap());
+            return Err(Status::with_details_and_metadata(
+                tonic::Code::InvalidArgument,
+                "error with details",
+                details.encode_to_vec().into(),
+                metadata,
+            ));
+        }
+        if name == "slow" {
+            sleep(Duration::from_secs(10)).await;
+        }
         let reply = HelloReply {
             message: format!("Hello {}! ({})", name, remote_addr.ip()),
         };
@@ -60,6 +78,10 @@ impl Greeter for MyGreeter {
     ) -> Result<Response<Self::LotsOfRepliesStream>, Status> {
         println!("Got a request: {:?}", request);
         let remote_addr = request.remote_addr().expect("no remote address");
+        if request.get_ref().name == "error" {
+            // rejected before sending any message
+            return Err(Status::permission_denied("rejected"));
+        }
         let stream = try_stream! {
             let name = request.into_inner().name;
             for i in 0..3 {
@@ -70,7 +92,11 @@ impl Greeter for MyGreeter {
             }
             Err(Status::unknown("EOF"))?;
         };
-        Ok(Response::new(Box::pin(stream)))
+        let mut response = Response::new(Box::pin(stream) as Self::LotsOfRepliesStream);
+        response
+            .metadata_mut()
+            .insert("x-reply-count", "3".parse().unwrap());
+        Ok(response)
     }
 
     async fn lots_of_greetings(
@@ -135,9 +161,16 @@ mod tests {
     use madsim::{
         rand::{thread_rng, Rng},
         runtime::Handle,
-        time::sleep,
+        time::{sleep, Instant},
     };
-    use std::net::SocketAddr;
+    use std::{
+        net::SocketAddr,
+        sync::atomic::{AtomicUsize, Ordering},
+        sync::{Arc, Mutex},
+    };
+    use tonic::simulation::{Faults, PeerCerts, PropagateHeaders, WaitForReady};
+    use tonic::testing::collect_stream;
+    use tonic::transport::{ClientTlsConfig, Identity};
 
     use super::*;
 
@@ -183,6 +216,17 @@ mod tests {
             });
             let response = client.say_hello(request).await.unwrap_err();
             assert_eq!(response.code(), tonic::Code::InvalidArgument);
+
+            // error details and metadata are kept
+            let request = tonic::Request::new(HelloRequest {
+                name: "details".into(),
+            });
+            let status = client.say_hello(request).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            assert_eq!(status.message(), "error with details");
+            let details = HelloReply::decode(status.details()).unwrap();
+            assert_eq!(details.message, "invalid name");
+            assert_eq!(status.metadata().get("x-error-source").unwrap(), "greeter");
         });
 
         // another service
@@ -208,6 +252,7 @@ mod tests {
                 name: "Tonic".into(),
             });
             let response = client.lots_of_replies(request).await.unwrap();
+            assert_eq!(response.metadata().get("x-reply-count").unwrap(), "3");
             let mut stream = response.into_inner();
             for i in 0..3 {
                 let reply = stream.message().await.unwrap().unwrap();
@@ -366,6 +411,153 @@ mod tests {
             .unwrap();
     }
 
+    #[madsim::test]
+    async fn trailers_only() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+        node0.spawn(async move {
+            Server::builder()
+                .add_service(GreeterServer::new(MyGreeter::default()))
+                .serve(addr0)
+                .await
+                .unwrap();
+        });
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client1").ip(ip1).build();
+        node1
+            .spawn(async move {
+                let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                    .await
+                    .unwrap();
+                let request = tonic::Request::new(HelloRequest {
+                    name: "error".into(),
+                });
+                // the call itself fails instead of yielding an erroneous stream
+                let status = client.lots_of_replies(request).await.unwrap_err();
+                assert_eq!(status.code(), tonic::Code::PermissionDenied);
+                assert_eq!(status.message(), "rejected");
+            })
+            .await
+            .unwrap();
+    }
+
+    #[madsim::test]
+    async fn collect_replies() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+        node0.spawn(async move {
+            Server::builder()
+                .add_service(GreeterServer::new(MyGreeter::default()))
+                .serve(addr0)
+                .await
+                .unwrap();
+        });
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client1").ip(ip1).build();
+        node1
+            .spawn(async move {
+                let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                    .await
+                    .unwrap();
+                let request = |name: &str| tonic::Request::new(HelloRequest { name: name.into() });
+
+                // all replies, then the error ending the stream
+                let (replies, status) = collect_stream(
+                    client.lots_of_replies(request("Tonic")),
+                    Duration::from_secs(10),
+                )
+                .await;
+                let messages: Vec<_> = replies.into_iter().map(|r| r.message).collect();
+                assert_eq!(
+                    messages,
+                    [
+                        "0: Hello Tonic! (10.0.0.2)",
+                        "1: Hello Tonic! (10.0.0.2)",
+                        "2: Hello Tonic! (10.0.0.2)",
+                    ]
+                );
+                assert_eq!(status.unwrap_err().code(), tonic::Code::Unknown);
+
+                // the call is rejected before any reply
+                let (replies, status) = collect_stream(
+                    client.lots_of_replies(request("error")),
+                    Duration::from_secs(10),
+                )
+                .await;
+                assert!(replies.is_empty());
+                assert_eq!(status.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+                // the replies received before the timeout are kept
+                let (replies, status) = collect_stream(
+                    client.lots_of_replies(request("Tonic")),
+                    Duration::from_millis(1500),
+                )
+                .await;
+                assert_eq!(replies.len(), 2);
+                assert_eq!(status.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+            })
+            .await
+            .unwrap();
+    }
+
+    #[madsim::test]
+    async fn buffer_size() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+        node0.spawn(async move {
+            Server::builder()
+                .add_service(GreeterServer::new(MyGreeter::default()))
+                .serve(addr0)
+                .await
+                .unwrap();
+        });
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client").ip(ip1).build();
+        node1
+            .spawn(async move {
+                let channel = tonic::transport::Endpoint::from_static("http://10.0.0.1:50051")
+                    .buffer_size(2)
+                    .connect()
+                    .await
+                    .unwrap();
+                let client = GreeterClient::new(channel);
+
+                // flood the slow server with 5 calls
+                let t0 = Instant::now();
+                let calls: Vec<_> = (0..5)
+                    .map(|_| {
+                        let mut client = client.clone();
+                        madsim::task::spawn(async move {
+                            let request = tonic::Request::new(HelloRequest {
+                                name: "slow".into(),
+                            });
+                            client.say_hello(request).await.unwrap();
+                            t0.elapsed().as_secs()
+                        })
+                    })
+                    .collect();
+
+                // the calls beyond the buffer wait for the earlier ones
+                let mut done = vec![];
+                for call in calls {
+                    done.push(call.await.unwrap());
+                }
+                done.sort();
+                assert_eq!(done, [10, 10, 20, 20, 30]);
+            })
+            .await
+            .unwrap();
+    }
+
     #[madsim::test]
     async fn server_crash() {
         let handle = Handle::current();
@@ -403,4 +595,835 @@ mod tests {
             .await
             .unwrap();
     }
+
+    #[madsim::test]
+    async fn server_crash_mid_stream() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+        node0.spawn(async move {
+            Server::builder()
+                .add_service(GreeterServer::new(MyGreeter::default()))
+                .serve(addr0)
+                .await
+                .unwrap();
+        });
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client1").ip(ip1).build();
+        node1
+            .spawn(async move {
+                let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                    .await
+                    .unwrap();
+                let request = tonic::Request::new(HelloRequest {
+                    name: "Tonic".into(),
+                });
+                let response = client.lots_of_replies(request).await.unwrap();
+                let mut stream = response.into_inner();
+                stream.message().await.unwrap().unwrap();
+
+                Handle::current().kill(node0.id());
+
+                // the broken stream is not mistaken for a complete one
+                let error = stream.message().await.unwrap_err();
+                assert_eq!(error.code(), tonic::Code::Unavailable);
+            })
+            .await
+            .unwrap();
+    }
+
+    /// Forwards requests to the greeter at `addr`.
+    struct Proxy {
+        addr: &'static str,
+        /// The elapsed time and the result code of the last forwarded call.
+        last_call: Arc<Mutex<Option<(Duration, tonic::Code)>>>,
+    }
+
+    #[tonic::async_trait]
+    impl AnotherGreeter for Proxy {
+        async fn say_hello(
+            &self,
+            request: Request<HelloRequest>,
+        ) -> Result<Response<HelloReply>, Status> {
+            // do some work before calling downstream
+            sleep(Duration::from_millis(500)).await;
+            let mut client = GreeterClient::connect(self.addr).await.unwrap();
+            let t0 = Instant::now();
+            // no timeout is set on the inner request
+            let request = tonic::Request::new(request.into_inner());
+            let result = client.say_hello(request).await;
+            let code = result
+                .as_ref()
+                .map_or_else(|e| e.code(), |_| tonic::Code::Ok);
+            *self.last_call.lock().unwrap() = Some((t0.elapsed(), code));
+            result
+        }
+    }
+
+    #[madsim::test]
+    async fn deadline_propagation() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let addr1 = "10.0.0.2:50051".parse::<SocketAddr>().unwrap();
+        let ip2 = "10.0.0.3".parse().unwrap();
+        let last_call = Arc::new(Mutex::new(None));
+
+        handle
+            .create_node()
+            .name("server")
+            .ip(addr0.ip())
+            .build()
+            .spawn(async move {
+                Server::builder()
+                    .add_service(GreeterServer::new(MyGreeter::default()))
+                    .serve(addr0)
+                    .await
+                    .unwrap();
+            });
+        let proxy = Proxy {
+            addr: "http://10.0.0.1:50051",
+            last_call: last_call.clone(),
+        };
+        handle
+            .create_node()
+            .name("proxy")
+            .ip(addr1.ip())
+            .build()
+            .spawn(async move {
+                Server::builder()
+                    .add_service(AnotherGreeterServer::new(proxy))
+                    .serve(addr1)
+                    .await
+                    .unwrap();
+            });
+        sleep(Duration::from_secs(1)).await;
+
+        let node2 = handle.create_node().name("client").ip(ip2).build();
+        node2
+            .spawn(async move {
+                let mut client = AnotherGreeterClient::connect("http://10.0.0.2:50051")
+                    .await
+                    .unwrap();
+
+                // without deadline
+                let request = tonic::Request::new(HelloRequest {
+                    name: "Tonic".into(),
+                });
+                let response = client.say_hello(request).await.unwrap();
+                assert_eq!(response.into_inner().message, "Hello Tonic! (10.0.0.2)");
+
+                // the slow call exceeds the deadline
+                let mut request = tonic::Request::new(HelloRequest {
+                    name: "slow".into(),
+                });
+                request.set_timeout(Duration::from_secs(2));
+                let t0 = Instant::now();
+                let error = client.say_hello(request).await.unwrap_err();
+                assert_eq!(error.code(), tonic::Code::DeadlineExceeded);
+                assert!(t0.elapsed() < Duration::from_millis(2100));
+            })
+            .await
+            .unwrap();
+
+        // wait for the proxy to finish
+        sleep(Duration::from_secs(1)).await;
+        // the inner call is bounded by the remaining time of the outer request
+        let (elapsed, code) = last_call.lock().unwrap().unwrap();
+        assert_eq!(code, tonic::Code::DeadlineExceeded);
+        assert!(elapsed <= Duration::from_millis(1500), "{elapsed:?}");
+        assert!(elapsed > Duration::from_millis(1400), "{elapsed:?}");
+    }
+
+    #[madsim::test]
+    async fn keepalive_detects_partition() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+        node0.spawn(async move {
+            Server::builder()
+                .add_service(GreeterServer::new(MyGreeter::default()))
+                .serve(addr0)
+                .await
+                .unwrap();
+        });
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client").ip(ip1).build();
+        let server_id = node0.id();
+        node1
+            .spawn(async move {
+                let channel = tonic::transport::Endpoint::from_static("http://10.0.0.1:50051")
+                    .http2_keep_alive_interval(Duration::from_secs(1))
+                    .keep_alive_timeout(Duration::from_secs(1))
+                    .connect()
+                    .await
+                    .unwrap();
+                let mut client = GreeterClient::new(channel);
+
+                // pings are acknowledged while the server is reachable
+                let request = tonic::Request::new(HelloRequest {
+                    name: "slow".into(),
+                });
+                client.say_hello(request).await.unwrap();
+
+                // the slow call takes 10s, and its response would never arrive
+                let t0 = Instant::now();
+                let call = madsim::task::spawn(async move {
+                    let request = tonic::Request::new(HelloRequest {
+                        name: "slow".into(),
+                    });
+                    client.say_hello(request).await
+                });
+                sleep(Duration::from_millis(1500)).await;
+                madsim::net::NetSim::current().clog_node(server_id);
+
+                let error = call.await.unwrap().unwrap_err();
+                assert_eq!(error.code(), tonic::Code::Unavailable);
+                // detected by the ping after the partition
+                let elapsed = t0.elapsed();
+                assert!(elapsed < Duration::from_millis(3100), "{elapsed:?}");
+                assert!(elapsed > Duration::from_secs(2), "{elapsed:?}");
+            })
+            .await
+            .unwrap();
+    }
+
+    #[madsim::test]
+    async fn transport_error_codes() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        let node0 = handle
+            .create_node()
+            .name("server")
+            .ip(addr0.ip())
+            .init(move || async move {
+                Server::builder()
+                    .add_service(GreeterServer::new(MyGreeter::default()))
+                    .serve(addr0)
+                    .await
+                    .unwrap();
+            })
+            .build();
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client").ip(ip1).build();
+        let server_id = node0.id();
+        let client_id = node1.id();
+        node1
+            .spawn(async move {
+                let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                    .await
+                    .unwrap();
+                let request = |name: &str| {
+                    tonic::Request::new(HelloRequest {
+                        name: name.to_string(),
+                    })
+                };
+
+                // deadline
+                let mut req = request("slow");
+                req.set_timeout(Duration::from_secs(1));
+                let error = client.say_hello(req).await.unwrap_err();
+                assert_eq!(error.code(), tonic::Code::DeadlineExceeded);
+
+                // connection reset by a crash during the call
+                let mut call_client = client.clone();
+                let call =
+                    madsim::task::spawn(
+                        async move { call_client.say_hello(request("slow")).await },
+                    );
+                sleep(Duration::from_secs(1)).await;
+                Handle::current().kill(server_id);
+                let error = call.await.unwrap().unwrap_err();
+                assert_eq!(error.code(), tonic::Code::Unavailable);
+
+                // connection refused by the crashed server
+                let error = client.say_hello(request("Tonic")).await.unwrap_err();
+                assert_eq!(error.code(), tonic::Code::Unavailable);
+
+                // partitioned from the restarted server
+                Handle::current().restart(server_id);
+                sleep(Duration::from_secs(1)).await;
+                client.say_hello(request("Tonic")).await.unwrap();
+                let net = madsim::net::NetSim::current();
+                net.clog_link(client_id, server_id);
+                let error = client.say_hello(request("Tonic")).await.unwrap_err();
+                assert_eq!(error.code(), tonic::Code::Unavailable);
+                net.unclog_link(client_id, server_id);
+                client.say_hello(request("Tonic")).await.unwrap();
+            })
+            .await
+            .unwrap();
+    }
+
+    #[madsim::test]
+    async fn connect_timeout() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        let node0 = handle.create_node().name("server").ip(addr0.ip()).build();
+        node0.spawn(async move {
+            Server::builder()
+                .add_service(GreeterServer::new(MyGreeter::default()))
+                .serve(addr0)
+                .await
+                .unwrap();
+        });
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client").ip(ip1).build();
+        let server_id = node0.id();
+        node1
+            .spawn(async move {
+                let endpoint = tonic::transport::Endpoint::from_static("http://10.0.0.1:50051")
+                    .connect_timeout(Duration::from_secs(2));
+
+                // connecting to a partitioned server times out
+                madsim::net::NetSim::current().clog_node(server_id);
+                let t0 = Instant::now();
+                endpoint.connect().await.unwrap_err();
+                let elapsed = t0.elapsed();
+                assert!(elapsed >= Duration::from_secs(2), "{elapsed:?}");
+                assert!(elapsed < Duration::from_millis(2010), "{elapsed:?}");
+
+                // the connection is established once the partition heals
+                madsim::task::spawn(async move {
+                    sleep(Duration::from_millis(500)).await;
+                    madsim::net::NetSim::current().unclog_node(server_id);
+                });
+                let channel = endpoint.connect().await.unwrap();
+                let request = tonic::Request::new(HelloRequest {
+                    name: "Tonic".into(),
+                });
+                GreeterClient::new(channel)
+                    .say_hello(request)
+                    .await
+                    .unwrap();
+            })
+            .await
+            .unwrap();
+    }
+
+    #[madsim::test]
+    async fn wait_for_ready() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        let node0 = handle
+            .create_node()
+            .name("server")
+            .ip(addr0.ip())
+            .init(move || async move {
+                Server::builder()
+                    .add_service(GreeterServer::new(MyGreeter::default()))
+                    .serve(addr0)
+                    .await
+                    .unwrap();
+            })
+            .build();
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client").ip(ip1).build();
+        let channel = node1
+            .spawn(async move {
+                tonic::transport::Endpoint::from_static("http://10.0.0.1:50051")
+                    .connect()
+                    .await
+                    .unwrap()
+            })
+            .await
+            .unwrap();
+
+        // the server crashes and restarts after the calls are made
+        handle.kill(node0.id());
+        let t0 = Instant::now();
+        let client = node1.spawn(async move {
+            let mut client = GreeterClient::new(channel);
+            let request = || {
+                let mut request = tonic::Request::new(HelloRequest {
+                    name: "Tonic".into(),
+                });
+                request.extensions_mut().insert(WaitForReady);
+                request
+            };
+
+            // the call times out if the server doesn't come up in time
+            let mut req = request();
+            req.set_timeout(Duration::from_secs(1));
+            let status = client.say_hello(req).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+
+            // otherwise it waits for the server and succeeds
+            client.say_hello(request()).await.unwrap();
+            t0.elapsed()
+        });
+        sleep(Duration::from_secs(5)).await;
+        handle.restart(node0.id());
+        let elapsed = client.await.unwrap();
+        assert!(elapsed >= Duration::from_secs(5), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(7), "{elapsed:?}");
+    }
+
+    /// Only greets the clients with an authorized identity.
+    struct Authorizer;
+
+    #[tonic::async_trait]
+    impl AnotherGreeter for Authorizer {
+        async fn say_hello(
+            &self,
+            request: Request<HelloRequest>,
+        ) -> Result<Response<HelloReply>, Status> {
+            let certs = request.extensions().get::<PeerCerts>();
+            match certs.and_then(|certs| certs.0.first()) {
+                Some(cert) if cert.get_ref() == b"admin" => Ok(Response::new(HelloReply {
+                    message: "Hi admin!".into(),
+                })),
+                _ => Err(Status::permission_denied("unauthorized")),
+            }
+        }
+    }
+
+    #[madsim::test]
+    async fn encode_failure() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        let faults = Faults::default();
+        faults.fail_encode("/helloworld.Greeter/SayHello", 1);
+        faults.fail_encode("/helloworld.Greeter/LotsOfReplies", 1);
+        let server_faults = faults.clone();
+        handle
+            .create_node()
+            .name("server")
+            .ip(addr0.ip())
+            .build()
+            .spawn(async move {
+                let svc = GreeterServer::new(MyGreeter::default());
+                Server::builder()
+                    .add_service(server_faults.service(svc))
+                    .serve(addr0)
+                    .await
+                    .unwrap();
+            });
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client").ip(ip1).build();
+        node1
+            .spawn(async move {
+                let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                    .await
+                    .unwrap();
+                let request = || {
+                    tonic::Request::new(HelloRequest {
+                        name: "Tonic".into(),
+                    })
+                };
+
+                // only the next call fails
+                let status = client.say_hello(request()).await.unwrap_err();
+                assert_eq!(status.code(), tonic::Code::Internal);
+                client.say_hello(request()).await.unwrap();
+
+                // a stream fails at its first message
+                let (replies, status) =
+                    collect_stream(client.lots_of_replies(request()), Duration::from_secs(10))
+                        .await;
+                assert!(replies.is_empty());
+                assert_eq!(status.unwrap_err().code(), tonic::Code::Internal);
+                let (replies, status) =
+                    collect_stream(client.lots_of_replies(request()), Duration::from_secs(10))
+                        .await;
+                assert_eq!(replies.len(), 3);
+                assert_eq!(status.unwrap_err().code(), tonic::Code::Unknown);
+
+                // faults can be injected while the server is running
+                faults.fail_encode("/helloworld.Greeter/SayHello", 1);
+                let status = client.say_hello(request()).await.unwrap_err();
+                assert_eq!(status.code(), tonic::Code::Internal);
+                client.say_hello(request()).await.unwrap();
+            })
+            .await
+            .unwrap();
+    }
+
+    struct Health;
+
+    #[tonic::async_trait]
+    impl hello_world::health_server::Health for Health {
+        async fn check(&self, _request: Request<()>) -> Result<Response<HelloReply>, Status> {
+            Ok(Response::new(HelloReply {
+                message: "ok".into(),
+            }))
+        }
+    }
+
+    #[madsim::test]
+    async fn empty_request() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        handle
+            .create_node()
+            .name("server")
+            .ip(addr0.ip())
+            .build()
+            .spawn(async move {
+                Server::builder()
+                    .add_service(hello_world::health_server::HealthServer::new(Health))
+                    .serve(addr0)
+                    .await
+                    .unwrap();
+            });
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client").ip(ip1).build();
+        node1
+            .spawn(async move {
+                let mut client =
+                    hello_world::health_client::HealthClient::connect("http://10.0.0.1:50051")
+                        .await
+                        .unwrap();
+                let reply = client.check(()).await.unwrap();
+                assert_eq!(reply.into_inner().message, "ok");
+            })
+            .await
+            .unwrap();
+    }
+
+    #[madsim::test]
+    async fn peer_identity() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        handle
+            .create_node()
+            .name("server")
+            .ip(addr0.ip())
+            .build()
+            .spawn(async move {
+                Server::builder()
+                    .add_service(AnotherGreeterServer::new(Authorizer))
+                    .serve(addr0)
+                    .await
+                    .unwrap();
+            });
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client").ip(ip1).build();
+        node1
+            .spawn(async move {
+                let endpoint = tonic::transport::Endpoint::from_static("http://10.0.0.1:50051");
+                for (identity, authorized) in
+                    [(Some("admin"), true), (Some("guest"), false), (None, false)]
+                {
+                    let endpoint = match identity {
+                        Some(identity) => {
+                            let identity = Identity::from_pem(identity, "key");
+                            let tls_config = ClientTlsConfig::new().identity(identity);
+                            endpoint.clone().tls_config(tls_config).unwrap()
+                        }
+                        None => endpoint.clone(),
+                    };
+                    let mut client = AnotherGreeterClient::new(endpoint.connect().await.unwrap());
+                    let request = tonic::Request::new(HelloRequest {
+                        name: "Tonic".into(),
+                    });
+                    match client.say_hello(request).await {
+                        Ok(reply) => {
+                            assert!(authorized, "{identity:?}");
+                            assert_eq!(reply.into_inner().message, "Hi admin!");
+                        }
+                        Err(status) => {
+                            assert!(!authorized, "{identity:?}");
+                            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+                        }
+                    }
+                }
+            })
+            .await
+            .unwrap();
+    }
+
+    /// Records the start time of each call and the peak number of running calls.
+    #[derive(Default)]
+    struct Busy {
+        starts: Mutex<Vec<(String, Instant)>>,
+        running: Mutex<(usize, usize)>,
+    }
+
+    #[tonic::async_trait]
+    impl AnotherGreeter for Arc<Busy> {
+        async fn say_hello(
+            &self,
+            request: Request<HelloRequest>,
+        ) -> Result<Response<HelloReply>, Status> {
+            let name = request.into_inner().name;
+            self.starts
+                .lock()
+                .unwrap()
+                .push((name.clone(), Instant::now()));
+            {
+                let mut running = self.running.lock().unwrap();
+                running.0 += 1;
+                running.1 = running.1.max(running.0);
+            }
+            sleep(Duration::from_secs(1)).await;
+            self.running.lock().unwrap().0 -= 1;
+            Ok(Response::new(HelloReply { message: name }))
+        }
+    }
+
+    #[madsim::test]
+    async fn max_concurrent_streams() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        let busy = Arc::new(Busy::default());
+        let busy0 = busy.clone();
+        handle
+            .create_node()
+            .name("server")
+            .ip(addr0.ip())
+            .build()
+            .spawn(async move {
+                Server::builder()
+                    .max_concurrent_streams(2)
+                    .add_service(AnotherGreeterServer::new(busy0))
+                    .serve(addr0)
+                    .await
+                    .unwrap();
+            });
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client").ip(ip1).build();
+        let t0 = Instant::now();
+        node1
+            .spawn(async move {
+                let client = AnotherGreeterClient::connect("http://10.0.0.1:50051")
+                    .await
+                    .unwrap();
+                let mut tasks = vec![];
+                for i in 0..5 {
+                    let mut client = client.clone();
+                    tasks.push(madsim::task::spawn(async move {
+                        let request = tonic::Request::new(HelloRequest {
+                            name: i.to_string(),
+                        });
+                        client.say_hello(request).await.unwrap()
+                    }));
+                    // make the calls arrive in order
+                    sleep(Duration::from_millis(10)).await;
+                }
+                for (i, task) in tasks.into_iter().enumerate() {
+                    assert_eq!(task.await.unwrap().into_inner().message, i.to_string());
+                }
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(busy.running.lock().unwrap().1, 2);
+        // the queued calls start in the order they arrived, as slots free up
+        let starts = busy.starts.lock().unwrap();
+        let names: Vec<_> = starts.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["0", "1", "2", "3", "4"]);
+        let secs: Vec<_> = starts
+            .iter()
+            .map(|(_, t)| t.duration_since(t0).as_secs())
+            .collect();
+        assert_eq!(secs, [0, 0, 1, 1, 2]);
+    }
+
+    /// Forwards the request to the next service, or replies with the request
+    /// ID if it is the last one.
+    struct Forwarder {
+        next: Option<&'static str>,
+    }
+
+    #[tonic::async_trait]
+    impl AnotherGreeter for Forwarder {
+        async fn say_hello(
+            &self,
+            request: Request<HelloRequest>,
+        ) -> Result<Response<HelloReply>, Status> {
+            let next = match self.next {
+                Some(next) => next,
+                None => {
+                    let id = request.metadata().get("x-request-id");
+                    let message = id.map_or("none", |id| id.to_str().unwrap()).to_string();
+                    return Ok(Response::new(HelloReply { message }));
+                }
+            };
+            let mut client = AnotherGreeterClient::connect(next).await.unwrap();
+            // the metadata is not copied explicitly
+            let request = tonic::Request::new(request.into_inner());
+            client.say_hello(request).await
+        }
+    }
+
+    #[madsim::test]
+    async fn propagate_request_id() {
+        let handle = Handle::current();
+        let services = [
+            ("10.0.0.1:50051", Some("http://10.0.0.2:50051")),
+            ("10.0.0.2:50051", None),
+        ];
+        for (addr, next) in services {
+            let addr = addr.parse::<SocketAddr>().unwrap();
+            handle
+                .create_node()
+                .ip(addr.ip())
+                .build()
+                .spawn(async move {
+                    let svc = AnotherGreeterServer::new(Forwarder { next });
+                    Server::builder()
+                        .add_service(PropagateHeaders::new(svc, &["x-request-id"]))
+                        .serve(addr)
+                        .await
+                        .unwrap();
+                });
+        }
+        sleep(Duration::from_secs(1)).await;
+
+        let node = handle
+            .create_node()
+            .name("client")
+            .ip("10.0.1.1".parse().unwrap())
+            .build();
+        node.spawn(async move {
+            let mut client = AnotherGreeterClient::connect("http://10.0.0.1:50051")
+                .await
+                .unwrap();
+            let mut request = tonic::Request::new(HelloRequest {
+                name: "Tonic".into(),
+            });
+            request
+                .metadata_mut()
+                .insert("x-request-id", "req-42".parse().unwrap());
+            let reply = client.say_hello(request).await.unwrap();
+            assert_eq!(reply.into_inner().message, "req-42");
+
+            // nothing to propagate
+            let request = tonic::Request::new(HelloRequest {
+                name: "Tonic".into(),
+            });
+            let reply = client.say_hello(request).await.unwrap();
+            assert_eq!(reply.into_inner().message, "none");
+        })
+        .await
+        .unwrap();
+    }
+
+    /// Streams replies as fast as it can, counting the replies produced.
+    #[derive(Default)]
+    struct Flood {
+        produced: Arc<AtomicUsize>,
+    }
+
+    #[tonic::async_trait]
+    impl Greeter for Flood {
+        async fn say_hello(
+            &self,
+            _: Request<HelloRequest>,
+        ) -> Result<Response<HelloReply>, Status> {
+            Err(Status::unimplemented("say_hello"))
+        }
+
+        type LotsOfRepliesStream = Pin<Box<dyn Stream<Item = Result<HelloReply, Status>> + Send>>;
+
+        async fn lots_of_replies(
+            &self,
+            _: Request<HelloRequest>,
+        ) -> Result<Response<Self::LotsOfRepliesStream>, Status> {
+            let produced = self.produced.clone();
+            let stream = try_stream! {
+                for i in 0..20 {
+                    produced.fetch_add(1, Ordering::Relaxed);
+                    yield HelloReply { message: i.to_string() };
+                }
+            };
+            Ok(Response::new(Box::pin(stream)))
+        }
+
+        async fn lots_of_greetings(
+            &self,
+            _: Request<Streaming<HelloRequest>>,
+        ) -> Result<Response<HelloReply>, Status> {
+            Err(Status::unimplemented("lots_of_greetings"))
+        }
+
+        type BidiHelloStream = Pin<Box<dyn Stream<Item = Result<HelloReply, Status>> + Send>>;
+
+        async fn bidi_hello(
+            &self,
+            _: Request<Streaming<HelloRequest>>,
+        ) -> Result<Response<Self::BidiHelloStream>, Status> {
+            Err(Status::unimplemented("bidi_hello"))
+        }
+    }
+
+    #[madsim::test]
+    async fn stream_flow_control() {
+        let handle = Handle::current();
+        let addr0 = "10.0.0.1:50051".parse::<SocketAddr>().unwrap();
+        let ip1 = "10.0.0.2".parse().unwrap();
+        let flood = Flood::default();
+        let produced = flood.produced.clone();
+        handle
+            .create_node()
+            .name("server")
+            .ip(addr0.ip())
+            .build()
+            .spawn(async move {
+                Server::builder()
+                    .add_service(GreeterServer::new(flood))
+                    .serve(addr0)
+                    .await
+                    .unwrap();
+            });
+        sleep(Duration::from_secs(1)).await;
+
+        let node1 = handle.create_node().name("client").ip(ip1).build();
+        node1
+            .spawn(async move {
+                let request = || tonic::Request::new(HelloRequest { name: "".into() });
+
+                // without a window, the server streams all replies at once
+                let mut client = GreeterClient::connect("http://10.0.0.1:50051")
+                    .await
+                    .unwrap();
+                let _stream = client.lots_of_replies(request()).await.unwrap();
+                sleep(Duration::from_secs(1)).await;
+                assert_eq!(produced.swap(0, Ordering::Relaxed), 20);
+
+                // a slow client backpressures the server
+                let channel = tonic::transport::Endpoint::from_static("http://10.0.0.1:50051")
+                    .initial_stream_window_size(4)
+                    .connect()
+                    .await
+                    .unwrap();
+                let mut client = GreeterClient::new(channel);
+                let mut stream = client
+                    .lots_of_replies(request())
+                    .await
+                    .unwrap()
+                    .into_inner();
+                sleep(Duration::from_secs(1)).await;
+                // the window is full, and the server waits at the next reply
+                assert_eq!(produced.load(Ordering::Relaxed), 5);
+                for i in 0..20 {
+                    let reply = stream.message().await.unwrap().unwrap();
+                    assert_eq!(reply.message, i.to_string());
+                    sleep(Duration::from_millis(100)).await;
+                    let ahead = produced.load(Ordering::Relaxed) - (i + 1);
+                    assert!(ahead <= 5, "{ahead} replies ahead");
+                }
+                assert!(stream.message().await.unwrap().is_none());
+            })
+            .await
+            .unwrap();
+    }
 }